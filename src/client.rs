@@ -0,0 +1,1215 @@
+//! Instruction builders for off-chain Rust clients.
+//!
+//! Each builder returns the raw instruction data (discriminator included) and
+//! the account list in the exact order the on-chain handler destructures it.
+//! The data layouts mirror the `*Args` parsers used by the handlers.
+
+use solana_sdk::{instruction::AccountMeta, pubkey::Pubkey, sysvar};
+
+use crate::instructions::{
+    AdlArgs, CloseMarketArgs, ClosePositionArgs, DepositCollateralArgs, DepositInsuranceArgs, InitializeMarketArgs, LiquidateArgs, OpenPositionArgs, PauseMarketArgs, PerpetualInstructions, SetMarketParamsArgs,
+    SetDelegateArgs, SetTradingHaltedArgs, UpdateMaxLeverageArgs, WithdrawCollateralArgs,
+};
+
+fn program_id() -> Pubkey {
+    Pubkey::new_from_array(crate::ID)
+}
+
+fn system_program_id() -> Pubkey {
+    Pubkey::new_from_array(pinocchio_system::ID)
+}
+
+fn token_program_id() -> Pubkey {
+    Pubkey::new_from_array(pinocchio_token::ID)
+}
+
+pub fn user_account_pda(user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"user_account", user.as_ref()], &program_id()).0
+}
+
+pub fn market_account_pda(authority: &Pubkey, market_id: u64) -> Pubkey {
+    Pubkey::find_program_address(&[b"market_account", authority.as_ref(), &market_id.to_le_bytes()], &program_id()).0
+}
+
+/// Vaults seed on the market account, so markets sharing a mint and id under
+/// different authorities never share a vault.
+pub fn collateral_vault_pda(market_account: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"collateral_vault", market_account.as_ref()], &program_id()).0
+}
+
+pub fn insurance_vault_pda(market_account: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"insurance_vault", market_account.as_ref()], &program_id()).0
+}
+
+pub fn global_config_pda() -> Pubkey {
+    Pubkey::find_program_address(&[b"global_config"], &program_id()).0
+}
+
+pub fn delegation_pda(user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"delegation", user.as_ref()], &program_id()).0
+}
+
+/// Temporary wSOL account a SOL-funded open creates and closes for `owner`,
+/// the instruction's signer. Passed as the user token account.
+pub fn wsol_account_pda(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"wsol", owner.as_ref()], &program_id()).0
+}
+
+pub fn position_pda(user: &Pubkey, market_id: u64) -> Pubkey {
+    Pubkey::find_program_address(&[b"position", user.as_ref(), &market_id.to_le_bytes()], &program_id()).0
+}
+
+pub fn init_market_ix(
+    authority: &Pubkey,
+    collateral_mint: &Pubkey,
+    args: &InitializeMarketArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + InitializeMarketArgs::LEN_WITH_FEED_ID);
+    data.push(PerpetualInstructions::InitializeMarket as u8);
+    data.extend_from_slice(&args.market_id.to_le_bytes());
+    data.extend_from_slice(&args.market_symbol);
+    data.extend_from_slice(&args.max_leverage.to_le_bytes());
+    data.extend_from_slice(&args.max_publish_gap.to_le_bytes());
+    data.extend_from_slice(&args.withdraw_delay.to_le_bytes());
+    data.extend_from_slice(&args.close_fee_discount.to_le_bytes());
+    data.extend_from_slice(&args.min_margin.to_le_bytes());
+    data.extend_from_slice(&args.max_open_interest.to_le_bytes());
+    data.extend_from_slice(&args.tick_size.to_le_bytes());
+    data.push(args.allow_ema_fallback as u8);
+    data.extend_from_slice(&args.max_slot_lag.to_le_bytes());
+    data.extend_from_slice(&args.liquidation_fee.to_le_bytes());
+    data.extend_from_slice(&args.max_funding_rate.to_le_bytes());
+    for byte in args.feed_id {
+        data.extend_from_slice(format!("{:02x}", byte).as_bytes());
+    }
+    data.extend_from_slice(&args.max_conf_bps.to_le_bytes());
+    data.push(args.use_ema as u8);
+    data.extend_from_slice(&args.max_price_age.to_le_bytes());
+    data.push(args.min_verification_level);
+    data.push(args.oracle_kind);
+
+    let market = market_account_pda(authority, args.market_id);
+    let accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new_readonly(*collateral_mint, false),
+        AccountMeta::new(market, false),
+        AccountMeta::new(collateral_vault_pda(&market), false),
+        AccountMeta::new(insurance_vault_pda(&market), false),
+        AccountMeta::new_readonly(system_program_id(), false),
+        AccountMeta::new_readonly(token_program_id(), false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn init_user_ix(user: &Pubkey) -> (Vec<u8>, Vec<AccountMeta>) {
+    let data = vec![PerpetualInstructions::InitializeUser as u8];
+
+    let accounts = vec![
+        AccountMeta::new(*user, true),
+        AccountMeta::new(user_account_pda(user), false),
+        AccountMeta::new_readonly(system_program_id(), false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn open_position_ix(
+    user: &Pubkey,
+    market_authority: &Pubkey,
+    collateral_mint: &Pubkey,
+    user_token_account: &Pubkey,
+    pyth_price_account: &Pubkey,
+    other_positions: &[Pubkey],
+    args: &OpenPositionArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + OpenPositionArgs::LEN_WITH_WRAP_SOL);
+    data.push(PerpetualInstructions::OpenPosition as u8);
+    data.extend_from_slice(&args.market_id.to_le_bytes());
+    data.extend_from_slice(&args.size.to_le_bytes());
+    data.extend_from_slice(&args.margin_amount.to_le_bytes());
+    // Trailing fields are positional, a later field needs every earlier one written
+    let wrap_sol = args.wrap_sol;
+    let partial = args.allow_partial || wrap_sol;
+    let flip = args.close_size > 0 || partial;
+    if args.post_only || args.auto_compound_funding || args.margin_in_quote || flip {
+        data.extend_from_slice(&args.target_price.to_le_bytes());
+        data.push(args.post_only as u8);
+    }
+    if args.auto_compound_funding || args.margin_in_quote || flip {
+        data.push(args.auto_compound_funding as u8);
+    }
+    if args.margin_in_quote || flip {
+        data.push(args.margin_in_quote as u8);
+    }
+    if flip {
+        data.extend_from_slice(&args.close_size.to_le_bytes());
+    }
+    if partial {
+        data.push(args.allow_partial as u8);
+    }
+    if wrap_sol {
+        data.push(1);
+    }
+
+    let market = market_account_pda(market_authority, args.market_id);
+    let mut accounts = vec![
+        AccountMeta::new(*user, true),
+        AccountMeta::new_readonly(*market_authority, false),
+        AccountMeta::new_readonly(*collateral_mint, false),
+        AccountMeta::new_readonly(*collateral_mint, false),
+        AccountMeta::new(market, false),
+        AccountMeta::new(user_account_pda(user), false),
+        AccountMeta::new(collateral_vault_pda(&market), false),
+        AccountMeta::new(*user_token_account, false),
+        AccountMeta::new(position_pda(user, args.market_id), false),
+        AccountMeta::new_readonly(*pyth_price_account, false),
+        AccountMeta::new_readonly(system_program_id(), false),
+        AccountMeta::new_readonly(token_program_id(), false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+        AccountMeta::new_readonly(global_config_pda(), false),
+        AccountMeta::new(*user, true),
+        AccountMeta::new_readonly(delegation_pda(user), false),
+        AccountMeta::new(insurance_vault_pda(&market), false),
+    ];
+    // The user's positions in other markets, needed to compute free margin
+    accounts.extend(other_positions.iter().map(|position| AccountMeta::new_readonly(*position, false)));
+
+    (data, accounts)
+}
+
+/// Like `open_position_ix`, signed by `delegate` for `user`. Moving collateral
+/// also needs an SPL token approval from the user to the delegate.
+#[allow(clippy::too_many_arguments)]
+pub fn open_position_as_delegate_ix(
+    delegate: &Pubkey,
+    user: &Pubkey,
+    market_authority: &Pubkey,
+    collateral_mint: &Pubkey,
+    user_token_account: &Pubkey,
+    pyth_price_account: &Pubkey,
+    other_positions: &[Pubkey],
+    args: &OpenPositionArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let (data, mut accounts) = open_position_ix(
+        user, market_authority, collateral_mint, user_token_account, pyth_price_account, other_positions, args
+    );
+    accounts[0] = AccountMeta::new_readonly(*user, false);
+    accounts[14] = AccountMeta::new(*delegate, true);
+
+    (data, accounts)
+}
+
+pub fn close_position_ix(
+    user: &Pubkey,
+    market_authority: &Pubkey,
+    collateral_mint: &Pubkey,
+    user_token_account: &Pubkey,
+    pyth_price_account: &Pubkey,
+    args: &ClosePositionArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + ClosePositionArgs::LEN_WITH_SIZE);
+    data.push(PerpetualInstructions::ClosePosition as u8);
+    data.extend_from_slice(&args.market_id.to_le_bytes());
+    if let Some(close_size) = args.close_size {
+        data.extend_from_slice(&close_size.to_le_bytes());
+    }
+
+    let market = market_account_pda(market_authority, args.market_id);
+    let accounts = vec![
+        AccountMeta::new(*user, true),
+        AccountMeta::new_readonly(*market_authority, false),
+        AccountMeta::new_readonly(*collateral_mint, false),
+        AccountMeta::new(market, false),
+        AccountMeta::new(user_account_pda(user), false),
+        AccountMeta::new(collateral_vault_pda(&market), false),
+        AccountMeta::new(*user_token_account, false),
+        AccountMeta::new(position_pda(user, args.market_id), false),
+        AccountMeta::new_readonly(*pyth_price_account, false),
+        AccountMeta::new_readonly(token_program_id(), false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+        AccountMeta::new(insurance_vault_pda(&market), false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn set_market_params_ix(
+    authority: &Pubkey,
+    market_account: &Pubkey,
+    args: &SetMarketParamsArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + SetMarketParamsArgs::LEN);
+    data.push(PerpetualInstructions::SetMarketParams as u8);
+    data.extend_from_slice(&args.params.initial_margin.to_le_bytes());
+    data.extend_from_slice(&args.params.maintenance_margin.to_le_bytes());
+    data.extend_from_slice(&args.params.fee_rate.to_le_bytes());
+    data.extend_from_slice(&args.params.max_leverage.to_le_bytes());
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*market_account, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn pause_market_ix(
+    authority: &Pubkey,
+    market_account: &Pubkey,
+    args: &PauseMarketArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let data = vec![PerpetualInstructions::PauseMarket as u8, args.status as u8];
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*market_account, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn resume_market_ix(authority: &Pubkey, market_account: &Pubkey) -> (Vec<u8>, Vec<AccountMeta>) {
+    let data = vec![PerpetualInstructions::ResumeMarket as u8];
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*market_account, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn update_max_leverage_ix(
+    authority: &Pubkey,
+    market_account: &Pubkey,
+    args: &UpdateMaxLeverageArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + UpdateMaxLeverageArgs::LEN);
+    data.push(PerpetualInstructions::UpdateMaxLeverage as u8);
+    data.extend_from_slice(&args.max_leverage.to_le_bytes());
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*market_account, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn transfer_authority_ix(
+    authority: &Pubkey,
+    market_account: &Pubkey,
+    new_authority: &Pubkey,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let data = vec![PerpetualInstructions::TransferMarketAuthority as u8];
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(*market_account, false),
+        AccountMeta::new_readonly(*new_authority, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn accept_authority_ix(new_authority: &Pubkey, market_account: &Pubkey) -> (Vec<u8>, Vec<AccountMeta>) {
+    let data = vec![PerpetualInstructions::AcceptMarketAuthority as u8];
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*new_authority, true),
+        AccountMeta::new(*market_account, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn close_market_ix(
+    authority: &Pubkey,
+    collateral_mint: &Pubkey,
+    fee_token_account: &Pubkey,
+    args: &CloseMarketArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + CloseMarketArgs::LEN);
+    data.push(PerpetualInstructions::CloseMarket as u8);
+    data.extend_from_slice(&args.market_id.to_le_bytes());
+
+    let market = market_account_pda(authority, args.market_id);
+    let accounts = vec![
+        AccountMeta::new(*authority, true),
+        AccountMeta::new(market, false),
+        AccountMeta::new(collateral_vault_pda(&market), false),
+        AccountMeta::new(insurance_vault_pda(&market), false),
+        AccountMeta::new_readonly(token_program_id(), false),
+        AccountMeta::new_readonly(*collateral_mint, false),
+        AccountMeta::new(*fee_token_account, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn withdraw_collateral_ix(
+    user: &Pubkey,
+    market_authority: &Pubkey,
+    collateral_mint: &Pubkey,
+    user_token_account: &Pubkey,
+    positions: &[Pubkey],
+    args: &WithdrawCollateralArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + WithdrawCollateralArgs::LEN);
+    data.push(PerpetualInstructions::WithdrawCollateral as u8);
+    data.extend_from_slice(&args.market_id.to_le_bytes());
+    data.extend_from_slice(&args.amount.to_le_bytes());
+
+    let market = market_account_pda(market_authority, args.market_id);
+    let mut accounts = vec![
+        AccountMeta::new(*user, true),
+        AccountMeta::new_readonly(*market_authority, false),
+        AccountMeta::new_readonly(*collateral_mint, false),
+        AccountMeta::new(market, false),
+        AccountMeta::new(user_account_pda(user), false),
+        AccountMeta::new(collateral_vault_pda(&market), false),
+        AccountMeta::new(*user_token_account, false),
+        AccountMeta::new_readonly(token_program_id(), false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+    ];
+    // Every open position of the user, needed to compute free margin
+    accounts.extend(positions.iter().map(|position| AccountMeta::new_readonly(*position, false)));
+
+    (data, accounts)
+}
+
+pub fn deposit_collateral_ix(
+    user: &Pubkey,
+    market_authority: &Pubkey,
+    collateral_mint: &Pubkey,
+    user_token_account: &Pubkey,
+    args: &DepositCollateralArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + DepositCollateralArgs::LEN);
+    data.push(PerpetualInstructions::DepositCollateral as u8);
+    data.extend_from_slice(&args.market_id.to_le_bytes());
+    data.extend_from_slice(&args.amount.to_le_bytes());
+
+    let market = market_account_pda(market_authority, args.market_id);
+    let accounts = vec![
+        AccountMeta::new(*user, true),
+        AccountMeta::new_readonly(*market_authority, false),
+        AccountMeta::new_readonly(*collateral_mint, false),
+        AccountMeta::new(market, false),
+        // Created by the deposit if the user has no account yet
+        AccountMeta::new(user_account_pda(user), false),
+        AccountMeta::new(collateral_vault_pda(&market), false),
+        AccountMeta::new(*user_token_account, false),
+        AccountMeta::new_readonly(system_program_id(), false),
+        AccountMeta::new_readonly(token_program_id(), false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn deposit_insurance_ix(
+    authority: &Pubkey,
+    collateral_mint: &Pubkey,
+    authority_token_account: &Pubkey,
+    args: &DepositInsuranceArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + DepositInsuranceArgs::LEN);
+    data.push(PerpetualInstructions::DepositInsurance as u8);
+    data.extend_from_slice(&args.market_id.to_le_bytes());
+    data.extend_from_slice(&args.amount.to_le_bytes());
+
+    let market = market_account_pda(authority, args.market_id);
+    let accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new_readonly(*collateral_mint, false),
+        AccountMeta::new_readonly(market, false),
+        AccountMeta::new(insurance_vault_pda(&market), false),
+        AccountMeta::new(*authority_token_account, false),
+        AccountMeta::new_readonly(token_program_id(), false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn settle_funding_ix(
+    market_account: &Pubkey,
+    user: &Pubkey,
+    position: &Pubkey,
+    pyth_price_account: &Pubkey,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let data = vec![PerpetualInstructions::SettleFunding as u8];
+
+    let accounts = vec![
+        // Writable: settling advances the market's funding index
+        AccountMeta::new(*market_account, false),
+        AccountMeta::new(user_account_pda(user), false),
+        AccountMeta::new(*position, false),
+        AccountMeta::new_readonly(*pyth_price_account, false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn get_withdrawable_margin_ix(
+    market_account: &Pubkey,
+    position: &Pubkey,
+    pyth_price_account: &Pubkey,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let data = vec![PerpetualInstructions::GetWithdrawableMargin as u8];
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*market_account, false),
+        AccountMeta::new_readonly(*position, false),
+        AccountMeta::new_readonly(*pyth_price_account, false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn set_trading_halted_ix(admin: &Pubkey, args: &SetTradingHaltedArgs) -> (Vec<u8>, Vec<AccountMeta>) {
+    let data = vec![PerpetualInstructions::SetTradingHalted as u8, args.halted as u8];
+
+    let accounts = vec![
+        AccountMeta::new(*admin, true),
+        AccountMeta::new(global_config_pda(), false),
+        AccountMeta::new_readonly(system_program_id(), false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn set_delegate_ix(user: &Pubkey, args: &SetDelegateArgs) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + SetDelegateArgs::LEN);
+    data.push(PerpetualInstructions::SetDelegate as u8);
+    data.extend_from_slice(&args.delegate);
+
+    let accounts = vec![
+        AccountMeta::new(*user, true),
+        AccountMeta::new(delegation_pda(user), false),
+        AccountMeta::new_readonly(system_program_id(), false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn liquidation_queue_pda(market_account: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"liquidation_queue", market_account.as_ref()], &program_id()).0
+}
+
+pub fn mark_liquidatable_ix(
+    keeper: &Pubkey,
+    market_account: &Pubkey,
+    pyth_price_account: &Pubkey,
+    positions: &[Pubkey],
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let data = vec![PerpetualInstructions::MarkLiquidatable as u8];
+
+    let mut accounts = vec![
+        AccountMeta::new(*keeper, true),
+        AccountMeta::new(*market_account, false),
+        AccountMeta::new(liquidation_queue_pda(market_account), false),
+        AccountMeta::new_readonly(*pyth_price_account, false),
+        AccountMeta::new_readonly(system_program_id(), false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+    ];
+    accounts.extend(positions.iter().map(|position| AccountMeta::new(*position, false)));
+
+    (data, accounts)
+}
+
+pub fn liquidate_from_queue_ix(
+    liquidator: &Pubkey,
+    market_account: &Pubkey,
+    collateral_mint: &Pubkey,
+    position_owner: &Pubkey,
+    position: &Pubkey,
+    pyth_price_account: &Pubkey,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let data = vec![PerpetualInstructions::LiquidateFromQueue as u8];
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*liquidator, true),
+        AccountMeta::new(*market_account, false),
+        AccountMeta::new(liquidation_queue_pda(market_account), false),
+        AccountMeta::new(user_account_pda(position_owner), false),
+        AccountMeta::new(*position, false),
+        AccountMeta::new_readonly(*pyth_price_account, false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+        AccountMeta::new_readonly(*collateral_mint, false),
+        AccountMeta::new(collateral_vault_pda(market_account), false),
+        AccountMeta::new(insurance_vault_pda(market_account), false),
+        AccountMeta::new_readonly(token_program_id(), false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn liquidate_ix(
+    liquidator: &Pubkey,
+    market_authority: &Pubkey,
+    collateral_mint: &Pubkey,
+    position_owner: &Pubkey,
+    liquidator_token_account: &Pubkey,
+    pyth_price_account: &Pubkey,
+    args: &LiquidateArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + LiquidateArgs::LEN);
+    data.push(PerpetualInstructions::Liquidate as u8);
+    data.extend_from_slice(&args.market_id.to_le_bytes());
+
+    let market = market_account_pda(market_authority, args.market_id);
+    let accounts = vec![
+        AccountMeta::new_readonly(*liquidator, true),
+        AccountMeta::new_readonly(*market_authority, false),
+        AccountMeta::new_readonly(*collateral_mint, false),
+        AccountMeta::new(market, false),
+        AccountMeta::new(user_account_pda(position_owner), false),
+        AccountMeta::new(collateral_vault_pda(&market), false),
+        AccountMeta::new(insurance_vault_pda(&market), false),
+        AccountMeta::new(*liquidator_token_account, false),
+        AccountMeta::new(position_pda(position_owner, args.market_id), false),
+        AccountMeta::new_readonly(*pyth_price_account, false),
+        AccountMeta::new_readonly(token_program_id(), false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+    ];
+
+    (data, accounts)
+}
+
+pub fn adl_ix(
+    authority: &Pubkey,
+    bankrupt_owner: &Pubkey,
+    counterparty_owner: &Pubkey,
+    pyth_price_account: &Pubkey,
+    args: &AdlArgs,
+) -> (Vec<u8>, Vec<AccountMeta>) {
+    let mut data = Vec::with_capacity(1 + AdlArgs::LEN);
+    data.push(PerpetualInstructions::Adl as u8);
+    data.extend_from_slice(&args.market_id.to_le_bytes());
+
+    let market = market_account_pda(authority, args.market_id);
+    let accounts = vec![
+        AccountMeta::new_readonly(*authority, true),
+        AccountMeta::new(market, false),
+        AccountMeta::new_readonly(insurance_vault_pda(&market), false),
+        AccountMeta::new(user_account_pda(bankrupt_owner), false),
+        AccountMeta::new(position_pda(bankrupt_owner, args.market_id), false),
+        AccountMeta::new(user_account_pda(counterparty_owner), false),
+        AccountMeta::new(position_pda(counterparty_owner, args.market_id), false),
+        AccountMeta::new_readonly(*pyth_price_account, false),
+        AccountMeta::new_readonly(sysvar::clock::ID, false),
+        AccountMeta::new_readonly(collateral_vault_pda(&market), false),
+    ];
+
+    (data, accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{instructions::{PriceUpdateV2, SOL_USD_FEED_ID}, states::{Market, OracleKind}};
+
+    const AUTHORITY: Pubkey = Pubkey::new_from_array([1u8; 32]);
+    const USER: Pubkey = Pubkey::new_from_array([2u8; 32]);
+    const COLLATERAL_MINT: Pubkey = Pubkey::new_from_array([3u8; 32]);
+    const USER_TOKEN_ACCOUNT: Pubkey = Pubkey::new_from_array([4u8; 32]);
+    const PYTH_PRICE_ACCOUNT: Pubkey = Pubkey::new_from_array([5u8; 32]);
+
+    #[test]
+    fn test_init_market_ix_round_trip() {
+        let args = InitializeMarketArgs {
+            market_id: 66,
+            market_symbol: *b"SOL-PERP\0\0\0\0\0\0\0\0",
+            max_leverage: 1000,
+            max_publish_gap: 30,
+            withdraw_delay: 60,
+            close_fee_discount: 5_000,
+            min_margin: 100,
+            max_open_interest: 1_000_000,
+            tick_size: 1_000_000,
+            allow_ema_fallback: true,
+            max_slot_lag: 25,
+            liquidation_fee: 1_000,
+            max_funding_rate: 50,
+            feed_id: PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap(),
+            max_conf_bps: 200,
+            use_ema: true,
+            max_price_age: 120,
+            min_verification_level: Market::FULL_VERIFICATION,
+            oracle_kind: OracleKind::Switchboard as u8,
+        };
+        let (data, accounts) = init_market_ix(&AUTHORITY, &COLLATERAL_MINT, &args);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::InitializeMarket)
+        ));
+        assert_eq!(InitializeMarketArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 7);
+        assert!(accounts[0].is_signer);
+    }
+
+    #[test]
+    fn test_same_id_and_mint_under_different_authorities_get_distinct_vaults() {
+        let args = InitializeMarketArgs {
+            market_id: 66,
+            market_symbol: *b"SOL-PERP\0\0\0\0\0\0\0\0",
+            max_leverage: 1000,
+            max_publish_gap: 0,
+            withdraw_delay: 0,
+            close_fee_discount: 0,
+            min_margin: 0,
+            max_open_interest: 0,
+            tick_size: 0,
+            allow_ema_fallback: false,
+            max_slot_lag: 0,
+            liquidation_fee: 0,
+            max_funding_rate: 0,
+            feed_id: PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap(),
+            max_conf_bps: 0,
+            use_ema: false,
+            max_price_age: 0,
+            min_verification_level: 0,
+            oracle_kind: OracleKind::Pyth as u8,
+        };
+        let other_authority = Pubkey::new_from_array([9u8; 32]);
+
+        let (_, first) = init_market_ix(&AUTHORITY, &COLLATERAL_MINT, &args);
+        let (_, second) = init_market_ix(&other_authority, &COLLATERAL_MINT, &args);
+
+        assert_ne!(first[2].pubkey, second[2].pubkey);
+        assert_ne!(first[3].pubkey, second[3].pubkey);
+        assert_eq!(first[3].pubkey, collateral_vault_pda(&first[2].pubkey));
+        assert_ne!(first[4].pubkey, second[4].pubkey);
+        assert_eq!(first[4].pubkey, insurance_vault_pda(&first[2].pubkey));
+    }
+
+    #[test]
+    fn test_open_targets_the_market_init_created() {
+        // Past u8 range, so a narrower id anywhere would derive another market
+        let market_id = 300;
+        let init_args = InitializeMarketArgs {
+            market_id,
+            market_symbol: *b"SOL-PERP\0\0\0\0\0\0\0\0",
+            max_leverage: 1000,
+            max_publish_gap: 0,
+            withdraw_delay: 0,
+            close_fee_discount: 0,
+            min_margin: 0,
+            max_open_interest: 0,
+            tick_size: 0,
+            allow_ema_fallback: false,
+            max_slot_lag: 0,
+            liquidation_fee: 0,
+            max_funding_rate: 0,
+            feed_id: PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap(),
+            max_conf_bps: 0,
+            use_ema: false,
+            max_price_age: 0,
+            min_verification_level: 0,
+            oracle_kind: OracleKind::Pyth as u8,
+        };
+        let open_args = OpenPositionArgs {
+            market_id,
+            size: 10,
+            margin_amount: 1000,
+            target_price: 0,
+            post_only: false,
+            auto_compound_funding: false,
+            margin_in_quote: false,
+            close_size: 0,
+            allow_partial: false,
+            wrap_sol: false,
+        };
+        let (init_data, init_accounts) = init_market_ix(&AUTHORITY, &COLLATERAL_MINT, &init_args);
+        let (open_data, open_accounts) = open_position_ix(
+            &USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &PYTH_PRICE_ACCOUNT, &[], &open_args
+        );
+
+        // Both handlers seed the market PDA on the market_id they parse
+        let seeded = |market_id: u64| Pubkey::find_program_address(
+            &[b"market_account", AUTHORITY.as_ref(), &market_id.to_le_bytes()],
+            &program_id(),
+        ).0;
+        let init_market = seeded(InitializeMarketArgs::try_from(&init_data[1..]).unwrap().market_id);
+        let open_market = seeded(OpenPositionArgs::try_from(&open_data[1..]).unwrap().market_id);
+
+        assert_eq!(init_market, open_market);
+        assert_eq!(init_accounts[2].pubkey, init_market);
+        assert_eq!(open_accounts[4].pubkey, init_market);
+        assert_eq!(open_accounts[6].pubkey, init_accounts[3].pubkey);
+    }
+
+    #[test]
+    fn test_init_user_ix_round_trip() {
+        let (data, accounts) = init_user_ix(&USER);
+
+        assert_eq!(data.len(), 1);
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::InitializeUser)
+        ));
+        assert_eq!(accounts[1].pubkey, user_account_pda(&USER));
+    }
+
+    #[test]
+    fn test_open_position_ix_round_trip() {
+        let args = OpenPositionArgs {
+            market_id: 66,
+            size: -10,
+            margin_amount: 1000,
+            target_price: 0,
+            post_only: false,
+            auto_compound_funding: false,
+            margin_in_quote: false,
+            close_size: 0,
+            allow_partial: false,
+            wrap_sol: false,
+        };
+        let other_position = position_pda(&USER, 7);
+        let (data, accounts) = open_position_ix(
+            &USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &PYTH_PRICE_ACCOUNT, &[other_position], &args
+        );
+
+        assert_eq!(data.len(), 1 + OpenPositionArgs::LEN);
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::OpenPosition)
+        ));
+        assert_eq!(OpenPositionArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 18);
+        assert_eq!(accounts[13].pubkey, global_config_pda());
+        assert_eq!(accounts[14].pubkey, USER);
+        assert_eq!(accounts[15].pubkey, delegation_pda(&USER));
+        assert_eq!(accounts[16].pubkey, insurance_vault_pda(&market_account_pda(&AUTHORITY, 66)));
+        assert_eq!(accounts[17].pubkey, other_position);
+        assert!(!accounts[17].is_writable);
+    }
+
+    #[test]
+    fn test_open_position_ix_flip_round_trip() {
+        let args = OpenPositionArgs {
+            market_id: 66,
+            size: -5,
+            margin_amount: 50,
+            target_price: 0,
+            post_only: false,
+            auto_compound_funding: false,
+            margin_in_quote: false,
+            close_size: 10,
+            allow_partial: false,
+            wrap_sol: false,
+        };
+        let (data, _) = open_position_ix(
+            &USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &PYTH_PRICE_ACCOUNT, &[], &args
+        );
+
+        assert_eq!(data.len(), 1 + OpenPositionArgs::LEN_WITH_FLIP);
+        assert_eq!(OpenPositionArgs::try_from(&data[1..]).unwrap(), args);
+    }
+
+    #[test]
+    fn test_open_position_ix_wrap_sol_round_trip() {
+        let args = OpenPositionArgs {
+            market_id: 66,
+            size: 10,
+            margin_amount: 1_000_000_000,
+            target_price: 0,
+            post_only: false,
+            auto_compound_funding: false,
+            margin_in_quote: false,
+            close_size: 0,
+            allow_partial: false,
+            wrap_sol: true,
+        };
+        let native_mint = Pubkey::new_from_array(crate::instructions::NATIVE_MINT);
+        let (data, accounts) = open_position_ix(
+            &USER, &AUTHORITY, &native_mint, &wsol_account_pda(&USER), &PYTH_PRICE_ACCOUNT, &[], &args
+        );
+
+        assert_eq!(data.len(), 1 + OpenPositionArgs::LEN_WITH_WRAP_SOL);
+        assert_eq!(OpenPositionArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts[7].pubkey, wsol_account_pda(&USER));
+        assert!(accounts[7].is_writable);
+    }
+
+    #[test]
+    fn test_open_position_ix_post_only_round_trip() {
+        let args = OpenPositionArgs {
+            market_id: 66,
+            size: 10,
+            margin_amount: 1000,
+            target_price: 150_00000000,
+            post_only: true,
+            auto_compound_funding: false,
+            margin_in_quote: false,
+            close_size: 0,
+            allow_partial: false,
+            wrap_sol: false,
+        };
+        let (data, _) = open_position_ix(
+            &USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &PYTH_PRICE_ACCOUNT, &[], &args
+        );
+
+        assert_eq!(data.len(), 1 + OpenPositionArgs::LEN_WITH_LIMIT);
+        assert_eq!(OpenPositionArgs::try_from(&data[1..]).unwrap(), args);
+    }
+
+    #[test]
+    fn test_close_position_ix_round_trip() {
+        let args = ClosePositionArgs { market_id: 66, close_size: None };
+        let (data, accounts) = close_position_ix(
+            &USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &PYTH_PRICE_ACCOUNT, &args
+        );
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::ClosePosition)
+        ));
+        assert_eq!(ClosePositionArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 12);
+        assert_eq!(accounts[11].pubkey, insurance_vault_pda(&market_account_pda(&AUTHORITY, 66)));
+    }
+
+    #[test]
+    fn test_partial_close_position_ix_round_trip() {
+        let args = ClosePositionArgs { market_id: 66, close_size: Some(-4) };
+        let (data, _) = close_position_ix(
+            &USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &PYTH_PRICE_ACCOUNT, &args
+        );
+
+        assert_eq!(data.len(), 1 + ClosePositionArgs::LEN_WITH_SIZE);
+        assert_eq!(ClosePositionArgs::try_from(&data[1..]).unwrap(), args);
+    }
+
+    #[test]
+    fn test_set_market_params_ix_round_trip() {
+        let args = SetMarketParamsArgs {
+            params: crate::events::MarketParams {
+                initial_margin: 1000,
+                maintenance_margin: 500,
+                fee_rate: 10,
+                max_leverage: 10,
+            },
+        };
+        let market = market_account_pda(&AUTHORITY, 66);
+        let (data, accounts) = set_market_params_ix(&AUTHORITY, &market, &args);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::SetMarketParams)
+        ));
+        assert_eq!(SetMarketParamsArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 2);
+    }
+
+    #[test]
+    fn test_pause_and_resume_market_ix_round_trip() {
+        let args = PauseMarketArgs { status: crate::states::MarketStatus::ReduceOnly };
+        let market = market_account_pda(&AUTHORITY, 66);
+        let (data, accounts) = pause_market_ix(&AUTHORITY, &market, &args);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::PauseMarket)
+        ));
+        assert_eq!(PauseMarketArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 2);
+
+        let (data, accounts) = resume_market_ix(&AUTHORITY, &market);
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::ResumeMarket)
+        ));
+        assert_eq!(accounts.len(), 2);
+    }
+
+    #[test]
+    fn test_update_max_leverage_ix_round_trip() {
+        let args = UpdateMaxLeverageArgs { max_leverage: 10 };
+        let market = market_account_pda(&AUTHORITY, 66);
+        let (data, accounts) = update_max_leverage_ix(&AUTHORITY, &market, &args);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::UpdateMaxLeverage)
+        ));
+        assert_eq!(UpdateMaxLeverageArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 2);
+    }
+
+    #[test]
+    fn test_transfer_and_accept_authority_ix_round_trip() {
+        let market = market_account_pda(&AUTHORITY, 66);
+        let (data, accounts) = transfer_authority_ix(&AUTHORITY, &market, &USER);
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::TransferMarketAuthority)
+        ));
+        assert_eq!(accounts[2].pubkey, USER);
+        assert!(!accounts[2].is_signer);
+
+        let (data, accounts) = accept_authority_ix(&USER, &market);
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::AcceptMarketAuthority)
+        ));
+        assert!(accounts[0].is_signer);
+    }
+
+    #[test]
+    fn test_close_market_ix_round_trip() {
+        let args = CloseMarketArgs { market_id: 66 };
+        let (data, accounts) = close_market_ix(&AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &args);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::CloseMarket)
+        ));
+        assert_eq!(CloseMarketArgs::try_from(&data[1..]).unwrap(), args);
+        let market = market_account_pda(&AUTHORITY, 66);
+        assert_eq!(accounts[1].pubkey, market);
+        assert_eq!(accounts[2].pubkey, collateral_vault_pda(&market));
+        assert_eq!(accounts[3].pubkey, insurance_vault_pda(&market));
+        assert_eq!(accounts[5].pubkey, COLLATERAL_MINT);
+        assert_eq!(accounts[6].pubkey, USER_TOKEN_ACCOUNT);
+        assert!(accounts[6].is_writable);
+    }
+
+    #[test]
+    fn test_withdraw_collateral_ix_round_trip() {
+        let args = WithdrawCollateralArgs { market_id: 66, amount: 5000 };
+        let position = position_pda(&USER, 66);
+        let (data, accounts) = withdraw_collateral_ix(
+            &USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &[position], &args
+        );
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::WithdrawCollateral)
+        ));
+        assert_eq!(WithdrawCollateralArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 10);
+        assert_eq!(accounts[9].pubkey, position);
+    }
+
+    #[test]
+    fn test_deposit_collateral_ix_round_trip() {
+        let args = DepositCollateralArgs { market_id: 66, amount: 5000 };
+        let (data, accounts) = deposit_collateral_ix(&USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &args);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::DepositCollateral)
+        ));
+        assert_eq!(DepositCollateralArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 10);
+        assert!(accounts[0].is_signer && accounts[0].is_writable);
+        assert_eq!(accounts[4].pubkey, user_account_pda(&USER));
+    }
+
+    #[test]
+    fn test_open_position_ix_auto_compound_round_trip() {
+        let args = OpenPositionArgs {
+            market_id: 66,
+            size: 10,
+            margin_amount: 1000,
+            target_price: 0,
+            post_only: false,
+            auto_compound_funding: true,
+            margin_in_quote: false,
+            close_size: 0,
+            allow_partial: false,
+            wrap_sol: false,
+        };
+        let (data, _) = open_position_ix(
+            &USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &PYTH_PRICE_ACCOUNT, &[], &args
+        );
+
+        assert_eq!(data.len(), 1 + OpenPositionArgs::LEN_WITH_FUNDING_OPTION);
+        assert_eq!(OpenPositionArgs::try_from(&data[1..]).unwrap(), args);
+    }
+
+    #[test]
+    fn test_open_position_ix_quote_margin_round_trip() {
+        let args = OpenPositionArgs {
+            market_id: 66,
+            size: 10,
+            margin_amount: 150_00000000,
+            target_price: 0,
+            post_only: false,
+            auto_compound_funding: false,
+            margin_in_quote: true,
+            close_size: 0,
+            allow_partial: false,
+            wrap_sol: false,
+        };
+        let (data, _) = open_position_ix(
+            &USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &PYTH_PRICE_ACCOUNT, &[], &args
+        );
+
+        assert_eq!(data.len(), 1 + OpenPositionArgs::LEN_WITH_QUOTE_MARGIN);
+        assert_eq!(OpenPositionArgs::try_from(&data[1..]).unwrap(), args);
+    }
+
+    #[test]
+    fn test_set_trading_halted_ix_round_trip() {
+        let args = SetTradingHaltedArgs { halted: true };
+        let (data, accounts) = set_trading_halted_ix(&AUTHORITY, &args);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::SetTradingHalted)
+        ));
+        assert_eq!(SetTradingHaltedArgs::try_from(&data[1..]).unwrap(), args);
+        assert!(accounts[0].is_signer);
+        assert_eq!(accounts[1].pubkey, global_config_pda());
+    }
+
+    #[test]
+    fn test_delegated_open_signed_by_delegate() {
+        let delegate = Pubkey::new_from_array([9u8; 32]);
+        let (data, _) = set_delegate_ix(&USER, &SetDelegateArgs { delegate: delegate.to_bytes() });
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::SetDelegate)
+        ));
+        assert_eq!(SetDelegateArgs::try_from(&data[1..]).unwrap().delegate, delegate.to_bytes());
+
+        let args = OpenPositionArgs {
+            market_id: 66,
+            size: 10,
+            margin_amount: 1000,
+            target_price: 0,
+            post_only: false,
+            auto_compound_funding: false,
+            margin_in_quote: false,
+            close_size: 0,
+            allow_partial: false,
+            wrap_sol: false,
+        };
+        let (_, accounts) = open_position_as_delegate_ix(
+            &delegate, &USER, &AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &PYTH_PRICE_ACCOUNT, &[], &args
+        );
+
+        // The position is still the user's, only the delegate signs
+        assert!(!accounts[0].is_signer);
+        assert_eq!(accounts[8].pubkey, position_pda(&USER, 66));
+        assert_eq!(accounts[14].pubkey, delegate);
+        assert!(accounts[14].is_signer);
+        assert_eq!(accounts.iter().filter(|meta| meta.is_signer).count(), 1);
+    }
+
+    #[test]
+    fn test_settle_funding_ix() {
+        let market = market_account_pda(&AUTHORITY, 66);
+        let position = position_pda(&USER, 66);
+        let (data, accounts) = settle_funding_ix(&market, &USER, &position, &PYTH_PRICE_ACCOUNT);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::SettleFunding)
+        ));
+        // Permissionless: nobody signs
+        assert!(accounts.iter().all(|meta| !meta.is_signer));
+        assert_eq!(accounts[1].pubkey, user_account_pda(&USER));
+    }
+
+    #[test]
+    fn test_get_withdrawable_margin_ix() {
+        let market = market_account_pda(&AUTHORITY, 66);
+        let position = position_pda(&USER, 66);
+        let (data, accounts) = get_withdrawable_margin_ix(&market, &position, &PYTH_PRICE_ACCOUNT);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::GetWithdrawableMargin)
+        ));
+        // A view: nothing signs or is written
+        assert!(accounts.iter().all(|meta| !meta.is_signer && !meta.is_writable));
+    }
+
+    #[test]
+    fn test_liquidation_queue_ixs() {
+        let market = market_account_pda(&AUTHORITY, 66);
+        let position = position_pda(&USER, 66);
+
+        let (data, accounts) = mark_liquidatable_ix(&AUTHORITY, &market, &PYTH_PRICE_ACCOUNT, &[position]);
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::MarkLiquidatable)
+        ));
+        assert_eq!(accounts[2].pubkey, liquidation_queue_pda(&market));
+        assert_eq!(accounts[6].pubkey, position);
+        // The scan accrues the market's funding and marks the positions
+        assert!(accounts[1].is_writable && accounts[6].is_writable);
+
+        let (data, accounts) = liquidate_from_queue_ix(&AUTHORITY, &market, &COLLATERAL_MINT, &USER, &position, &PYTH_PRICE_ACCOUNT);
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::LiquidateFromQueue)
+        ));
+        assert_eq!(accounts.len(), 11);
+        assert_eq!(accounts[3].pubkey, user_account_pda(&USER));
+        assert_eq!(accounts[9].pubkey, insurance_vault_pda(&market));
+        assert!(accounts[9].is_writable);
+    }
+
+    #[test]
+    fn test_liquidate_ix_round_trip() {
+        let liquidator = Pubkey::new_from_array([9u8; 32]);
+        let args = LiquidateArgs { market_id: 66 };
+        let (data, accounts) = liquidate_ix(
+            &liquidator, &AUTHORITY, &COLLATERAL_MINT, &USER, &USER_TOKEN_ACCOUNT, &PYTH_PRICE_ACCOUNT, &args
+        );
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::Liquidate)
+        ));
+        assert_eq!(LiquidateArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 12);
+        assert_eq!(accounts[6].pubkey, insurance_vault_pda(&accounts[3].pubkey));
+        // The liquidator signs, the position owner doesn't
+        assert!(accounts[0].is_signer && accounts[0].pubkey == liquidator);
+        assert!(accounts.iter().skip(1).all(|account| !account.is_signer));
+        assert_eq!(accounts[8].pubkey, position_pda(&USER, 66));
+    }
+
+    #[test]
+    fn test_deposit_insurance_ix_round_trip() {
+        let args = DepositInsuranceArgs { market_id: 66, amount: 25_000 };
+        let (data, accounts) = deposit_insurance_ix(&AUTHORITY, &COLLATERAL_MINT, &USER_TOKEN_ACCOUNT, &args);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::DepositInsurance)
+        ));
+        assert_eq!(DepositInsuranceArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 6);
+        assert!(accounts[0].is_signer && accounts[0].pubkey == AUTHORITY);
+        assert_eq!(accounts[3].pubkey, insurance_vault_pda(&accounts[2].pubkey));
+    }
+
+    #[test]
+    fn test_adl_ix_round_trip() {
+        let counterparty = Pubkey::new_from_array([9u8; 32]);
+        let args = AdlArgs { market_id: 66 };
+        let (data, accounts) = adl_ix(&AUTHORITY, &USER, &counterparty, &PYTH_PRICE_ACCOUNT, &args);
+
+        assert!(matches!(
+            PerpetualInstructions::try_from(&data[0]),
+            Ok(PerpetualInstructions::Adl)
+        ));
+        assert_eq!(AdlArgs::try_from(&data[1..]).unwrap(), args);
+        assert_eq!(accounts.len(), 10);
+        assert_eq!(accounts[9].pubkey, collateral_vault_pda(&market_account_pda(&AUTHORITY, 66)));
+        assert!(accounts[0].is_signer && accounts[0].pubkey == AUTHORITY);
+        assert_eq!(accounts[4].pubkey, position_pda(&USER, 66));
+        assert_eq!(accounts[6].pubkey, position_pda(&counterparty, 66));
+    }
+}
@@ -0,0 +1,63 @@
+use pinocchio::program_error::ProgramError;
+
+/// Program-specific errors, surfaced to clients as `ProgramError::Custom(code)`.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PerpError {
+    // Post-only order whose target price is not satisfied by the oracle price
+    OrderNotFillable = 0,
+    // Market is paused for new opens
+    MarketPaused = 1,
+    // Market has no initial margin configured, opening would allow free leverage
+    ZeroInitialMargin = 2,
+    // Oracle feed went too long between its previous and current update
+    OracleFeedGap = 3,
+    // Oracle price is non-positive or its exponent is outside the supported range
+    OracleInvalidPrice = 4,
+    // Oracle account publishes a different feed than the one configured on the market
+    OracleFeedMismatch = 5,
+    // Withdrawal attempted before the market's withdraw delay elapsed since the last deposit
+    WithdrawDelayActive = 6,
+    // Position margin would be below the market's absolute minimum
+    MarginBelowMinimum = 7,
+    // Position notional over margin exceeds the market's max_leverage
+    LeverageTooHigh = 8,
+    // Market symbol names a different asset than the configured oracle feed prices
+    SymbolFeedMismatch = 9,
+    // Global kill switch is set, no new opens in any market
+    TradingHalted = 10,
+    // Signer is neither the position owner nor the owner's current delegate
+    UnauthorizedDelegate = 11,
+    // Position account belongs to a different market than the instruction's market_id
+    WrongPositionForMarket = 12,
+    // Open would take the side's open interest over the market cap
+    OpenInterestCapExceeded = 13,
+    // Position hasn't stayed underwater across two oracle updates yet
+    LiquidationGracePeriod = 14,
+    // Collateral vault isn't the market's vault PDA or isn't the vault the market recorded
+    VaultMismatch = 15,
+    // Oracle price was published longer ago than the allowed max age
+    OracleStale = 16,
+    // Position's equity still covers its maintenance margin
+    PositionNotLiquidatable = 17,
+    // ADL needs unrecovered bad debt, an empty insurance vault, a bankrupt position and a profitable opposite one
+    AdlNotAllowed = 18,
+    // Oracle account has never been published to, its publish time is still zero
+    OracleNeverPublished = 19,
+    // Market is reduce-only, positions can only be closed
+    ReduceOnly = 20,
+    // Market still has open interest, unpaid bad debt, user collateral or an insurance fund
+    MarketNotEmpty = 21,
+    // Market's collateral mint differs from the one the user's margin balance is held in
+    CollateralMintMismatch = 22,
+    // Collateral mint isn't a $1 stablecoin and has no oracle to price it in dollars
+    UnpricedCollateral = 23,
+    // Collateral vault holds less than a payout owed from it
+    VaultInsolvent = 24,
+}
+
+impl From<PerpError> for ProgramError {
+    fn from(e: PerpError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
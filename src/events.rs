@@ -0,0 +1,441 @@
+//! Events emitted through `sol_log_data` for off-chain indexers.
+//!
+//! Every event is encoded as a one-byte discriminator followed by its fields
+//! in little-endian order, so clients can decode them without an IDL.
+
+use pinocchio::{log::sol_log_data, pubkey::Pubkey};
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_i64(data: &[u8], offset: usize) -> i64 {
+    i64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_i128(data: &[u8], offset: usize) -> i128 {
+    i128::from_le_bytes(data[offset..offset + 16].try_into().unwrap())
+}
+
+/// Risk parameters an authority can change on a market.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MarketParams {
+    pub initial_margin: u64,
+    pub maintenance_margin: u64,
+    pub fee_rate: u64,
+    pub max_leverage: u64,
+}
+
+impl MarketParams {
+    pub const LEN: usize = 4 * 8;
+
+    // Bits of MarketParamsUpdated::changed
+    pub const INITIAL_MARGIN: u8 = 1 << 0;
+    pub const MAINTENANCE_MARGIN: u8 = 1 << 1;
+    pub const FEE_RATE: u8 = 1 << 2;
+    pub const MAX_LEVERAGE: u8 = 1 << 3;
+
+    /// Bitmask of the fields that differ between `self` and `other`.
+    pub fn diff(&self, other: &MarketParams) -> u8 {
+        let mut changed = 0;
+        if self.initial_margin != other.initial_margin {
+            changed |= Self::INITIAL_MARGIN;
+        }
+        if self.maintenance_margin != other.maintenance_margin {
+            changed |= Self::MAINTENANCE_MARGIN;
+        }
+        if self.fee_rate != other.fee_rate {
+            changed |= Self::FEE_RATE;
+        }
+        if self.max_leverage != other.max_leverage {
+            changed |= Self::MAX_LEVERAGE;
+        }
+        changed
+    }
+
+    fn write(&self, out: &mut [u8]) {
+        out[0..8].copy_from_slice(&self.initial_margin.to_le_bytes());
+        out[8..16].copy_from_slice(&self.maintenance_margin.to_le_bytes());
+        out[16..24].copy_from_slice(&self.fee_rate.to_le_bytes());
+        out[24..32].copy_from_slice(&self.max_leverage.to_le_bytes());
+    }
+
+    fn read(data: &[u8]) -> Self {
+        Self {
+            initial_margin: read_u64(data, 0),
+            maintenance_margin: read_u64(data, 8),
+            fee_rate: read_u64(data, 16),
+            max_leverage: read_u64(data, 24),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MarketParamsUpdated {
+    pub market: Pubkey,
+    pub old: MarketParams,
+    pub new: MarketParams,
+    pub changed: u8, // Bitmask of MarketParams::* flags
+    pub authority: Pubkey,
+    pub timestamp: i64,
+}
+
+impl MarketParamsUpdated {
+    pub const DISCRIMINATOR: u8 = 0;
+    pub const LEN: usize = 1 + 32 + (2 * MarketParams::LEN) + 1 + 32 + 8;
+
+    pub fn new(market: Pubkey, old: MarketParams, new: MarketParams, authority: Pubkey, timestamp: i64) -> Self {
+        Self { market, old, new, changed: old.diff(&new), authority, timestamp }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut data = [0u8; Self::LEN];
+        data[0] = Self::DISCRIMINATOR;
+        data[1..33].copy_from_slice(&self.market);
+        self.old.write(&mut data[33..65]);
+        self.new.write(&mut data[65..97]);
+        data[97] = self.changed;
+        data[98..130].copy_from_slice(&self.authority);
+        data[130..138].copy_from_slice(&self.timestamp.to_le_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::LEN || data[0] != Self::DISCRIMINATOR {
+            return None;
+        }
+
+        Some(Self {
+            market: data[1..33].try_into().ok()?,
+            old: MarketParams::read(&data[33..65]),
+            new: MarketParams::read(&data[65..97]),
+            changed: data[97],
+            authority: data[98..130].try_into().ok()?,
+            timestamp: read_i64(data, 130),
+        })
+    }
+
+    pub fn emit(&self) {
+        sol_log_data(&[&self.to_bytes()]);
+    }
+}
+
+/// Settlement of a liquidated position. `penalty + insurance_contribution +
+/// owner_remainder` always equals the position's equity (margin plus PnL,
+/// floored at zero) at `settlement_price`. `liquidator_reward` is the part of
+/// the penalty paid out to the liquidator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionLiquidated {
+    pub position: Pubkey,
+    pub liquidator: Pubkey,
+    pub size_closed: i128,
+    pub settlement_price: u64,
+    pub penalty: u64,
+    pub insurance_contribution: u64,
+    pub owner_remainder: u64,
+    pub liquidator_reward: u64,
+}
+
+impl PositionLiquidated {
+    pub const DISCRIMINATOR: u8 = 1;
+    pub const LEN: usize = 1 + 32 + 32 + 16 + (5 * 8);
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut data = [0u8; Self::LEN];
+        data[0] = Self::DISCRIMINATOR;
+        data[1..33].copy_from_slice(&self.position);
+        data[33..65].copy_from_slice(&self.liquidator);
+        data[65..81].copy_from_slice(&self.size_closed.to_le_bytes());
+        data[81..89].copy_from_slice(&self.settlement_price.to_le_bytes());
+        data[89..97].copy_from_slice(&self.penalty.to_le_bytes());
+        data[97..105].copy_from_slice(&self.insurance_contribution.to_le_bytes());
+        data[105..113].copy_from_slice(&self.owner_remainder.to_le_bytes());
+        data[113..121].copy_from_slice(&self.liquidator_reward.to_le_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::LEN || data[0] != Self::DISCRIMINATOR {
+            return None;
+        }
+
+        Some(Self {
+            position: data[1..33].try_into().ok()?,
+            liquidator: data[33..65].try_into().ok()?,
+            size_closed: read_i128(data, 65),
+            settlement_price: read_u64(data, 81),
+            penalty: read_u64(data, 89),
+            insurance_contribution: read_u64(data, 97),
+            owner_remainder: read_u64(data, 105),
+            liquidator_reward: read_u64(data, 113),
+        })
+    }
+
+    pub fn emit(&self) {
+        sol_log_data(&[&self.to_bytes()]);
+    }
+}
+
+/// Funding settled on a position against the market's cumulative index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FundingSettled {
+    pub position: Pubkey,
+    pub payment: i64, // Received by the position, negative when paid
+    pub cumulative_funding_index: i128,
+}
+
+impl FundingSettled {
+    pub const DISCRIMINATOR: u8 = 2;
+    pub const LEN: usize = 1 + 32 + 8 + 16;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut data = [0u8; Self::LEN];
+        data[0] = Self::DISCRIMINATOR;
+        data[1..33].copy_from_slice(&self.position);
+        data[33..41].copy_from_slice(&self.payment.to_le_bytes());
+        data[41..57].copy_from_slice(&self.cumulative_funding_index.to_le_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::LEN || data[0] != Self::DISCRIMINATOR {
+            return None;
+        }
+
+        Some(Self {
+            position: data[1..33].try_into().ok()?,
+            payment: read_i64(data, 33),
+            cumulative_funding_index: read_i128(data, 41),
+        })
+    }
+
+    pub fn emit(&self) {
+        sol_log_data(&[&self.to_bytes()]);
+    }
+}
+
+/// Where an open's trading fee went, so clients can reconcile to the unit.
+/// `margin + trading_fee` is what the open deducted, and the fee's
+/// components sum to `trading_fee`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeBreakdown {
+    pub position: Pubkey,
+    pub margin: u64,
+    pub trading_fee: u64,
+    pub insurance_contribution: u64,
+    pub treasury_contribution: u64, // Kept in the collateral vault
+    pub maker_rebate: u64,
+}
+
+impl FeeBreakdown {
+    pub const DISCRIMINATOR: u8 = 3;
+    pub const LEN: usize = 1 + 32 + 5 * 8;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut data = [0u8; Self::LEN];
+        data[0] = Self::DISCRIMINATOR;
+        data[1..33].copy_from_slice(&self.position);
+        data[33..41].copy_from_slice(&self.margin.to_le_bytes());
+        data[41..49].copy_from_slice(&self.trading_fee.to_le_bytes());
+        data[49..57].copy_from_slice(&self.insurance_contribution.to_le_bytes());
+        data[57..65].copy_from_slice(&self.treasury_contribution.to_le_bytes());
+        data[65..73].copy_from_slice(&self.maker_rebate.to_le_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::LEN || data[0] != Self::DISCRIMINATOR {
+            return None;
+        }
+
+        Some(Self {
+            position: data[1..33].try_into().ok()?,
+            margin: read_u64(data, 33),
+            trading_fee: read_u64(data, 41),
+            insurance_contribution: read_u64(data, 49),
+            treasury_contribution: read_u64(data, 57),
+            maker_rebate: read_u64(data, 65),
+        })
+    }
+
+    pub fn emit(&self) {
+        sol_log_data(&[&self.to_bytes()]);
+    }
+}
+
+/// An open filled for less than requested because of the open interest cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialFill {
+    pub position: Pubkey,
+    pub requested_size: i128,
+    pub filled_size: i128,
+}
+
+impl PartialFill {
+    pub const DISCRIMINATOR: u8 = 4;
+    pub const LEN: usize = 1 + 32 + 16 + 16;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut data = [0u8; Self::LEN];
+        data[0] = Self::DISCRIMINATOR;
+        data[1..33].copy_from_slice(&self.position);
+        data[33..49].copy_from_slice(&self.requested_size.to_le_bytes());
+        data[49..65].copy_from_slice(&self.filled_size.to_le_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::LEN || data[0] != Self::DISCRIMINATOR {
+            return None;
+        }
+
+        Some(Self {
+            position: data[1..33].try_into().ok()?,
+            requested_size: read_i128(data, 33),
+            filled_size: read_i128(data, 49),
+        })
+    }
+
+    pub fn emit(&self) {
+        sol_log_data(&[&self.to_bytes()]);
+    }
+}
+
+/// A position's state after an open changed it, with the liquidation price
+/// clients display. Averaging in or reducing moves the liquidation price, so
+/// every open emits this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionUpdated {
+    pub position: Pubkey,
+    pub size: i128,
+    pub entry_price: u64,
+    pub margin: u64,
+    pub liquidation_price: u64, // 0 when no price liquidates the position
+}
+
+impl PositionUpdated {
+    pub const DISCRIMINATOR: u8 = 5;
+    pub const LEN: usize = 1 + 32 + 16 + 3 * 8;
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut data = [0u8; Self::LEN];
+        data[0] = Self::DISCRIMINATOR;
+        data[1..33].copy_from_slice(&self.position);
+        data[33..49].copy_from_slice(&self.size.to_le_bytes());
+        data[49..57].copy_from_slice(&self.entry_price.to_le_bytes());
+        data[57..65].copy_from_slice(&self.margin.to_le_bytes());
+        data[65..73].copy_from_slice(&self.liquidation_price.to_le_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() != Self::LEN || data[0] != Self::DISCRIMINATOR {
+            return None;
+        }
+
+        Some(Self {
+            position: data[1..33].try_into().ok()?,
+            size: read_i128(data, 33),
+            entry_price: read_u64(data, 49),
+            margin: read_u64(data, 57),
+            liquidation_price: read_u64(data, 65),
+        })
+    }
+
+    pub fn emit(&self) {
+        sol_log_data(&[&self.to_bytes()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FeeBreakdown, PartialFill, PositionUpdated, FundingSettled, MarketParams, MarketParamsUpdated, PositionLiquidated};
+
+    #[test]
+    fn test_market_params_updated_round_trip() {
+        let old = MarketParams {
+            initial_margin: 1000,
+            maintenance_margin: 500,
+            fee_rate: 10,
+            max_leverage: 10,
+        };
+        let new = MarketParams {
+            initial_margin: 2000,
+            fee_rate: 5,
+            ..old
+        };
+
+        let event = MarketParamsUpdated::new([7u8; 32], old, new, [9u8; 32], 1_700_000_000);
+        let decoded = MarketParamsUpdated::from_bytes(&event.to_bytes()).unwrap();
+
+        assert_eq!(decoded, event);
+        assert_eq!(decoded.old, old);
+        assert_eq!(decoded.new, new);
+        assert_eq!(decoded.changed, MarketParams::INITIAL_MARGIN | MarketParams::FEE_RATE);
+        assert_eq!(decoded.authority, [9u8; 32]);
+        assert_eq!(decoded.timestamp, 1_700_000_000);
+    }
+
+    #[test]
+    fn test_unchanged_params_have_empty_mask() {
+        let params = MarketParams { initial_margin: 1000, ..MarketParams::default() };
+        assert_eq!(params.diff(&params), 0);
+    }
+
+    #[test]
+    fn test_position_liquidated_round_trip() {
+        let event = PositionLiquidated {
+            position: [1u8; 32],
+            liquidator: [2u8; 32],
+            size_closed: -10,
+            settlement_price: 150_00000000,
+            penalty: 40,
+            insurance_contribution: 0,
+            owner_remainder: 0,
+            liquidator_reward: 4,
+        };
+
+        assert_eq!(PositionLiquidated::from_bytes(&event.to_bytes()), Some(event));
+        // Other events' bytes don't decode as a liquidation
+        assert!(PositionLiquidated::from_bytes(&[0u8; MarketParamsUpdated::LEN]).is_none());
+    }
+
+    #[test]
+    fn test_funding_settled_round_trip() {
+        let event = FundingSettled { position: [1u8; 32], payment: -250, cumulative_funding_index: 1_000_000 };
+        assert_eq!(FundingSettled::from_bytes(&event.to_bytes()), Some(event));
+    }
+
+    #[test]
+    fn test_fee_breakdown_round_trip() {
+        let event = FeeBreakdown {
+            position: [1u8; 32],
+            margin: 1_000,
+            trading_fee: 10,
+            insurance_contribution: 0,
+            treasury_contribution: 10,
+            maker_rebate: 0,
+        };
+        assert_eq!(FeeBreakdown::from_bytes(&event.to_bytes()), Some(event));
+        assert!(FeeBreakdown::from_bytes(&[0u8; FundingSettled::LEN]).is_none());
+    }
+
+    #[test]
+    fn test_partial_fill_round_trip() {
+        let event = PartialFill { position: [1u8; 32], requested_size: -100, filled_size: -40 };
+        assert_eq!(PartialFill::from_bytes(&event.to_bytes()), Some(event));
+    }
+
+    #[test]
+    fn test_position_updated_round_trip() {
+        let event = PositionUpdated {
+            position: [1u8; 32],
+            size: -10,
+            entry_price: 150_00000000,
+            margin: 150_000_000,
+            liquidation_price: 163_09523809,
+        };
+        assert_eq!(PositionUpdated::from_bytes(&event.to_bytes()), Some(event));
+        assert_eq!(PositionUpdated::from_bytes(&event.to_bytes()[..PositionUpdated::LEN - 1]), None);
+    }
+}
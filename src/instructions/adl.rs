@@ -0,0 +1,304 @@
+use pinocchio::{account_info::AccountInfo, program_error::ProgramError, sysvars::clock::Clock, *};
+use pinocchio_token::state::TokenAccount;
+
+use crate::{
+    errors::PerpError,
+    math::{self, RoundingMode},
+    instructions::{assert_vault_solvent, calculate_realized_pnl, check_market_pda, check_writable, get_price_for_feed, margin_share, not_enough_accounts, remove_open_interest, remove_position_from_user},
+    states::{Market, Position, UserAccount},
+};
+
+/// Instruction data: [market_id: u64]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdlArgs {
+    pub market_id: u64,
+}
+
+impl AdlArgs {
+    pub const LEN: usize = 8;
+}
+
+impl TryFrom<&[u8]> for AdlArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let market_id = u64::from_le_bytes(
+            data[0..8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+
+        Ok(Self { market_id })
+    }
+}
+
+/// Auto-deleveraging, the backstop once the insurance fund is exhausted. A
+/// bankrupt position is closed against a profitable position on the other
+/// side at the bankrupt position's bankruptcy price, so the counterparty gives
+/// up the profit past that price instead of the market taking on more bad debt.
+/// That forgone profit writes off bad debt, so ADL stops once there is none
+/// left. A bankrupt position larger than the counterparty keeps its remainder
+/// open, for another ADL or a liquidation. Only the market authority can pick
+/// the counterparty.
+pub fn process_adl(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+
+    let [
+        authority, // Market authority (must sign transaction)
+        market_account, // Market both positions trade on
+        insurance_vault, // Market's insurance fund, must be empty
+        bankrupt_user_account, // Trading account of the bankrupt position's owner
+        bankrupt_position_account, // Position whose equity is below zero
+        counterparty_user_account, // Trading account of the counterparty's owner
+        counterparty_position_account, // Profitable position on the opposite side
+        pyth_price_account, // Pyth oracle for price feeds
+        clock_sysvar, // Solana clock for timestamps
+        collateral_vault, // Vault holding all collateral, checked against the market's books
+        ] = accounts else {
+        return Err(not_enough_accounts(10, accounts.len()));
+    };
+
+    // ---- Basic checks ----
+    if !authority.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[market_account, bankrupt_user_account, bankrupt_position_account, counterparty_user_account, counterparty_position_account])?;
+    if !bankrupt_position_account.is_owned_by(&crate::ID) || !counterparty_position_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+    if bankrupt_position_account.key() == counterparty_position_account.key() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // ---- Parse instruction ----
+    let AdlArgs { market_id } = AdlArgs::try_from(instruction_data)?;
+
+    // ---- Validate market ----
+    let (oracle, collateral_decimals, bad_debt) = {
+        let market = Market::load_initialized_mut(market_account)?;
+        check_market_pda(&market, market_account, market_id)?;
+        if market.authority != *authority.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if market.insurance_vault != *insurance_vault.key() || market.collateral_vault != *collateral_vault.key() {
+            return Err(PerpError::VaultMismatch.into());
+        }
+        (market.oracle_config(), market.collateral_decimals, market.bad_debt)
+    };
+
+    let insurance_balance = TokenAccount::from_account_info(insurance_vault)?.amount();
+    check_adl_allowed(bad_debt, insurance_balance)?;
+
+    // ---- Sysvars / Oracle ----
+    let clock = Clock::from_account_info(clock_sysvar)?;
+    let current_price = get_price_for_feed(pyth_price_account, &clock, &oracle)?.price;
+
+    // ---- Deleverage ----
+    let mut bankrupt = Position::from_account_info_mut(bankrupt_position_account)?;
+    let mut counterparty = Position::from_account_info_mut(counterparty_position_account)?;
+    for (position, user_account) in [(&bankrupt, bankrupt_user_account), (&counterparty, counterparty_user_account)] {
+        if position.market != *market_account.key() || !position.is_open() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let (user_account_pda, _user_bump) = pubkey::find_program_address(
+            &[b"user_account", position.user.as_ref()],
+            &crate::ID
+        );
+        if *user_account.key() != user_account_pda {
+            return Err(ProgramError::InvalidAccountData);
+        }
+    }
+
+    // Funding owed either way is settled into margin first, so the bankruptcy
+    // price and the margin shares below reflect it
+    let funding_index = {
+        let mut market = Market::from_account_info_mut(market_account)?;
+        market.accrue_funding(current_price, clock.unix_timestamp)?;
+        market.cumulative_funding_index
+    };
+    let bankrupt_funding = bankrupt.settle_funding_index(funding_index, collateral_decimals)?;
+    let counterparty_funding = counterparty.settle_funding_index(funding_index, collateral_decimals)?;
+
+    if bankrupt.equity_with_funding(current_price, funding_index, collateral_decimals)? >= 0
+        || counterparty.unrealized_pnl_at(current_price, collateral_decimals)? <= 0
+    {
+        return Err(PerpError::AdlNotAllowed.into());
+    }
+
+    let bankruptcy_price = bankrupt.bankruptcy_price(collateral_decimals)?;
+    let mut market = Market::from_account_info_mut(market_account)?;
+    market.apply_margin_delta(bankrupt_funding)?;
+    market.apply_margin_delta(counterparty_funding)?;
+    let adl = deleverage(&mut bankrupt, &mut counterparty, &mut market, bankruptcy_price, current_price, collateral_decimals)?;
+
+    // ---- Update accounting ----
+    // A flattened position no longer takes one of its owner's slots
+    {
+        let mut bankrupt_user = UserAccount::from_account_info_mut(bankrupt_user_account)?;
+        bankrupt_user.apply_margin_delta(bankrupt_funding)?;
+        bankrupt_user.margin_balance = bankrupt_user.margin_balance.saturating_sub(adl.bankrupt_margin);
+        if !bankrupt.is_open() {
+            remove_position_from_user(&mut bankrupt_user, bankrupt_position_account.key())?;
+        }
+    }
+    {
+        let mut counterparty_user = UserAccount::from_account_info_mut(counterparty_user_account)?;
+        counterparty_user.apply_margin_delta(counterparty_funding)?;
+        counterparty_user.margin_balance = counterparty_user.margin_balance
+            .checked_add(adl.counterparty_pnl)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        if !counterparty.is_open() {
+            remove_position_from_user(&mut counterparty_user, counterparty_position_account.key())?;
+        }
+    }
+    assert_vault_solvent(&market, &*TokenAccount::from_account_info(collateral_vault)?)?;
+
+    println!("ADL size: {}", adl.size);
+    println!("ADL price: {}", bankruptcy_price);
+    println!("Bad debt cleared: {}", adl.bad_debt_cleared);
+
+    Ok(())
+}
+
+/// ADL only runs once liquidations have left bad debt the insurance fund can't cover.
+fn check_adl_allowed(bad_debt: u128, insurance_balance: u64) -> ProgramResult {
+    if bad_debt == 0 || insurance_balance > 0 {
+        return Err(PerpError::AdlNotAllowed.into());
+    }
+    Ok(())
+}
+
+/// What a deleverage moved, in contracts and collateral units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Deleverage {
+    pub size: u64, // Contracts taken off each position
+    pub bankrupt_margin: u64, // Bankrupt position's margin consumed by its loss
+    pub counterparty_pnl: u64, // Counterparty's profit realized at the bankruptcy price
+    pub bad_debt_cleared: u64, // Bad debt written off against the profit the counterparty gave up
+}
+
+/// Closes the overlap of two opposite positions at `bankruptcy_price`. At that
+/// price the bankrupt side loses exactly its margin share, the counterparty
+/// realizes its PnL and gets its closed margin share back as free balance.
+/// What it would have made between the bankruptcy price and `current_price`
+/// is written off the market's bad debt.
+fn deleverage(
+    bankrupt: &mut Position,
+    counterparty: &mut Position,
+    market: &mut Market,
+    bankruptcy_price: u64,
+    current_price: u64,
+    decimals: u8
+) -> Result<Deleverage, ProgramError> {
+    if bankrupt.size == 0 || bankrupt.size.signum() != -counterparty.size.signum() {
+        return Err(PerpError::AdlNotAllowed.into());
+    }
+
+    let size = bankrupt.size.unsigned_abs().min(counterparty.size.unsigned_abs());
+    let bankrupt_closed = size as i128 * bankrupt.size.signum();
+    let counterparty_closed = -bankrupt_closed;
+
+    let bankrupt_margin = margin_share(bankrupt.margin, bankrupt_closed, bankrupt.size)?;
+    let counterparty_margin = margin_share(counterparty.margin, counterparty_closed, counterparty.size)?;
+
+    let pnl = calculate_realized_pnl(counterparty_closed, counterparty.entry_price, bankruptcy_price)?;
+    let pnl = math::quote_to_collateral(pnl, decimals, RoundingMode::Down)?;
+    // A counterparty that isn't in profit at the bankruptcy price has nothing to give up
+    if pnl <= 0 {
+        return Err(PerpError::AdlNotAllowed.into());
+    }
+    let counterparty_pnl = u64::try_from(pnl).map_err(|_| ProgramError::ArithmeticOverflow)?;
+
+    let forgone = calculate_realized_pnl(counterparty_closed, bankruptcy_price, current_price)?;
+    let forgone = math::quote_to_collateral(forgone, decimals, RoundingMode::Down)?.max(0);
+    let bad_debt_cleared = market.clear_bad_debt(u64::try_from(forgone).map_err(|_| ProgramError::ArithmeticOverflow)?);
+
+    for (position, closed, margin) in [(&mut *bankrupt, bankrupt_closed, bankrupt_margin), (&mut *counterparty, counterparty_closed, counterparty_margin)] {
+        position.size -= closed;
+        position.margin -= margin;
+        if position.size == 0 {
+            position.is_active = 0;
+        }
+        remove_open_interest(market, closed);
+        market.release_margin(margin);
+    }
+
+    Ok(Deleverage {
+        size: u64::try_from(size).map_err(|_| ProgramError::ArithmeticOverflow)?,
+        bankrupt_margin,
+        counterparty_pnl,
+        bad_debt_cleared,
+    })
+}
+
+// =========================== TESTING process_adl ===========================
+
+#[cfg(test)]
+mod tests {
+    use super::{check_adl_allowed, deleverage, Deleverage};
+    use crate::{errors::PerpError, math::PRICE_DECIMALS, states::{Market, Position}};
+
+    #[test]
+    fn test_adl_only_once_insurance_is_exhausted() {
+        let not_allowed = Err(PerpError::AdlNotAllowed.into());
+        assert_eq!(check_adl_allowed(0, 0), not_allowed);
+        assert_eq!(check_adl_allowed(50, 1), not_allowed);
+        assert_eq!(check_adl_allowed(50, 0), Ok(()));
+    }
+
+    #[test]
+    fn test_counterparty_closed_at_bankruptcy_price() {
+        // 10 long at 100 with 100 margin goes bankrupt at 90. At 85 its equity is -50
+        let mut bankrupt = Position { size: 10, entry_price: 100, margin: 100, is_active: 1, ..Position::default() };
+        assert_eq!(bankrupt.equity_at(85, PRICE_DECIMALS).unwrap(), -50);
+        let bankruptcy_price = bankrupt.bankruptcy_price(PRICE_DECIMALS).unwrap();
+        assert_eq!(bankruptcy_price, 90);
+
+        // 4 short at 110 would make 100 at 85 but only gets 80 at 90
+        let mut counterparty = Position { size: -4, entry_price: 110, margin: 40, is_active: 1, ..Position::default() };
+        let mut market = Market { open_interest_long: 10, open_interest_short: 4, locked_margin: 140, bad_debt: 30, ..Market::default() };
+
+        let adl = deleverage(&mut bankrupt, &mut counterparty, &mut market, bankruptcy_price, 85, PRICE_DECIMALS).unwrap();
+        // The 20 it would have made from 90 to 85 comes off the bad debt
+        assert_eq!(adl, Deleverage { size: 4, bankrupt_margin: 40, counterparty_pnl: 80, bad_debt_cleared: 20 });
+        assert_eq!(market.bad_debt, 10);
+
+        assert!(bankrupt.is_open());
+        assert_eq!((bankrupt.size, bankrupt.margin), (6, 60));
+        assert!(!counterparty.is_open());
+        assert_eq!((counterparty.size, counterparty.margin), (0, 0));
+        assert_eq!((market.open_interest_long, market.open_interest_short, market.locked_margin), (6, 0, 60));
+    }
+
+    #[test]
+    fn test_same_side_or_unprofitable_counterparty_rejected() {
+        let bankrupt = Position { size: 10, entry_price: 100, margin: 100, is_active: 1, ..Position::default() };
+        let not_allowed = Err(PerpError::AdlNotAllowed.into());
+
+        let mut same_side = Position { size: 4, entry_price: 80, margin: 40, is_active: 1, ..Position::default() };
+        assert_eq!(deleverage(&mut bankrupt.clone(), &mut same_side, &mut Market::default(), 90, 85, PRICE_DECIMALS), not_allowed);
+
+        // Short from 85 is losing at the bankruptcy price of 90
+        let mut underwater = Position { size: -4, entry_price: 85, margin: 40, is_active: 1, ..Position::default() };
+        assert_eq!(deleverage(&mut bankrupt.clone(), &mut underwater, &mut Market::default(), 90, 85, PRICE_DECIMALS), not_allowed);
+        assert_eq!(underwater.size, -4);
+    }
+
+    #[test]
+    fn test_adl_stops_once_bad_debt_is_written_off() {
+        // Only 15 of bad debt left, the counterparty gives up 20 past the bankruptcy price
+        let mut bankrupt = Position { size: 10, entry_price: 100, margin: 100, is_active: 1, ..Position::default() };
+        let mut counterparty = Position { size: -4, entry_price: 110, margin: 40, is_active: 1, ..Position::default() };
+        let mut market = Market { open_interest_long: 10, open_interest_short: 4, locked_margin: 140, bad_debt: 15, ..Market::default() };
+        assert_eq!(check_adl_allowed(market.bad_debt, 0), Ok(()));
+
+        let adl = deleverage(&mut bankrupt, &mut counterparty, &mut market, 90, 85, PRICE_DECIMALS).unwrap();
+        assert_eq!(adl.bad_debt_cleared, 15);
+        assert_eq!(market.bad_debt, 0);
+
+        // Nothing left to socialize, the next ADL is refused
+        assert_eq!(check_adl_allowed(market.bad_debt, 0), Err(PerpError::AdlNotAllowed.into()));
+    }
+}
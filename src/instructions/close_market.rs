@@ -0,0 +1,170 @@
+use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, *};
+use pinocchio_token::instructions::{CloseAccount, TransferChecked};
+use pinocchio_token::state::TokenAccount;
+
+use crate::{
+    errors::PerpError,
+    instructions::{check_market_pda, check_writable, close_program_account, load_market_as_authority, not_enough_accounts},
+    states::Market,
+};
+
+/// Instruction data: [market_id: u64]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CloseMarketArgs {
+    pub market_id: u64,
+}
+
+impl CloseMarketArgs {
+    pub const LEN: usize = 8;
+}
+
+impl TryFrom<&[u8]> for CloseMarketArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let market_id = u64::from_le_bytes(
+            data[0..8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+
+        Ok(Self { market_id })
+    }
+}
+
+/// Closes a market nobody trades on any more, along with both its vaults,
+/// and returns all their rent to the authority. The fees left in the
+/// collateral vault are swept to the authority's token account first.
+/// Market authority only.
+pub fn process_close_market(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+
+    let [
+        authority, // Market authority (must sign transaction), receives the rent
+        market_account, // Market to close
+        collateral_vault, // Market's collateral vault, may only hold fees
+        insurance_vault, // Market's insurance fund, must be empty
+        token_program,
+        collateral_mint, // Market's collateral mint
+        fee_token_account, // Authority's token account receiving the collected fees
+        ] = accounts else {
+        return Err(not_enough_accounts(7, accounts.len()));
+    };
+
+    // ---- Basic checks ----
+    check_writable(&[authority, market_account, collateral_vault, insurance_vault, fee_token_account])?;
+    if *token_program.key() != pinocchio_token::ID {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let CloseMarketArgs { market_id } = CloseMarketArgs::try_from(instruction_data)?;
+
+    // ---- Validate market ----
+    let (seed_authority, market_bump, decimals) = {
+        let market = load_market_as_authority(&accounts[..2])?;
+        check_market_pda(&market, market_account, market_id)?;
+        if market.collateral_vault != *collateral_vault.key() || market.insurance_vault != *insurance_vault.key() {
+            return Err(PerpError::VaultMismatch.into());
+        }
+        if market.collateral_mint != *collateral_mint.key()
+            || *TokenAccount::from_account_info(fee_token_account)?.mint() != market.collateral_mint
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        check_market_empty(&market)?;
+        (market.seed_authority, market.bump, market.collateral_decimals)
+    };
+    // Insurance deposits aren't tracked on the market, the vault itself must be empty
+    if TokenAccount::from_account_info(insurance_vault)?.amount() != 0 {
+        return Err(PerpError::MarketNotEmpty.into());
+    }
+
+    // ---- Close both vaults while the market PDA can still sign for them ----
+    let market_id_bytes = market_id.to_le_bytes();
+    let bump_ref = &[market_bump];
+    let seeds = seeds!(
+        b"market_account",
+        seed_authority.as_ref(),
+        &market_id_bytes,
+        bump_ref
+    );
+
+    // ---- Sweep the collected fees, plus any untracked dust, to the authority ----
+    let sweep = TokenAccount::from_account_info(collateral_vault)?.amount();
+    if sweep > 0 {
+        TransferChecked {
+            from: collateral_vault,
+            to: fee_token_account,
+            authority: market_account,
+            mint: collateral_mint,
+            amount: sweep,
+            decimals,
+        }.invoke_signed(&[Signer::from(&seeds)])?;
+    }
+
+    for vault in [collateral_vault, insurance_vault] {
+        CloseAccount {
+            account: vault,
+            destination: authority,
+            authority: market_account,
+        }.invoke_signed(&[Signer::from(&seeds)])?;
+    }
+
+    // ---- Close the market itself last ----
+    close_program_account(market_account, authority)?;
+
+    println!("Market closed");
+    println!("Fees swept: {}", sweep);
+
+    Ok(())
+}
+
+/// A market can only be closed once nothing is open on it, no bad debt is
+/// left unpaid, and the collateral vault holds nothing but collected fees.
+fn check_market_empty(market: &Market) -> ProgramResult {
+    if market.open_interest_long != 0
+        || market.open_interest_short != 0
+        || market.bad_debt != 0
+        || market.total_collateral != market.fees_collected
+    {
+        return Err(PerpError::MarketNotEmpty.into());
+    }
+    Ok(())
+}
+
+// =========================== TESTING process_close_market ===========================
+
+#[cfg(test)]
+mod tests {
+    use super::check_market_empty;
+    use crate::{errors::PerpError, states::Market};
+
+    #[test]
+    fn test_only_an_empty_market_closes() {
+        assert!(check_market_empty(&Market::default()).is_ok());
+
+        let not_empty = Err(PerpError::MarketNotEmpty.into());
+        assert_eq!(check_market_empty(&Market { open_interest_long: 1, ..Market::default() }), not_empty);
+        assert_eq!(check_market_empty(&Market { open_interest_short: 1, ..Market::default() }), not_empty);
+        assert_eq!(check_market_empty(&Market { total_collateral: 1, ..Market::default() }), not_empty);
+        assert_eq!(check_market_empty(&Market { bad_debt: 1, ..Market::default() }), not_empty);
+    }
+
+    #[test]
+    fn test_market_with_only_fees_left_closes() {
+        // Open 1_000 margin plus a 10 fee, close for a 12 fee with no PnL
+        let mut market = Market::default();
+        market.credit_collateral(1_010).unwrap();
+        market.collect_fees(10).unwrap();
+        market.debit_collateral(1_000 - 12);
+        market.collect_fees(12).unwrap();
+
+        assert_eq!(market.total_collateral, 22);
+        assert!(check_market_empty(&market).is_ok());
+
+        // Margin still owed to a user keeps it open
+        market.credit_collateral(5).unwrap();
+        assert_eq!(check_market_empty(&market), Err(PerpError::MarketNotEmpty.into()));
+    }
+}
@@ -0,0 +1,676 @@
+use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, pubkey::Pubkey, sysvars::clock::Clock, *};
+use pinocchio_token::instructions::TransferChecked;
+use pinocchio_token::state::{Mint, TokenAccount};
+
+use crate::{
+    errors::PerpError,
+    math::{self, RoundingMode},
+    instructions::{assert_vault_solvent, close_program_account, cover_deficit, remove_position_from_user, notional_in_collateral, check_writable, calculate_trading_fee, check_collateral_vault, check_delegation, check_market_accounts, get_price_for_feed, not_enough_accounts, record_oracle_snapshot, remove_open_interest},
+    states::{Market, UserAccount, Position},
+};
+
+/// Instruction data: [market_id: u64] optionally followed by [close_size: i128]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClosePositionArgs {
+    pub market_id: u64,
+    // Contracts to close, signed against the position. None closes it in full.
+    pub close_size: Option<i128>,
+}
+
+impl ClosePositionArgs {
+    pub const LEN: usize = 8;
+    pub const LEN_WITH_SIZE: usize = Self::LEN + 16;
+}
+
+impl TryFrom<&[u8]> for ClosePositionArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let market_id = u64::from_le_bytes(
+            data[0..8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+        let close_size = if data.len() >= Self::LEN_WITH_SIZE {
+            Some(i128::from_le_bytes(
+                data[8..24].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            ))
+        } else {
+            None
+        };
+
+        Ok(Self { market_id, close_size })
+    }
+}
+
+pub fn process_close_position(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+
+    let [
+        user,  // The trader (must sign transaction)
+        market_authority, // Authority the market was created by, its PDA seed
+        collateral_mint, // Token mint for collateral (e.g., USDC)
+        market_account, // Stores market configuration, owns the vault
+        user_account, // User's trading account
+        collateral_vault, // Vault holding all collateral
+        payout_token_account, // Token account credited with the payout, the user's or their delegate's
+        user_position_account, // Account storing position data
+        pyth_price_account, // Pyth oracle for price feeds
+        token_program,
+        clock_sysvar, // Solana clock for timestamps
+        insurance_vault, // Market's insurance fund, covers losses beyond the position's margin
+        optional @ .. // [delegation] when paying a delegate, then [oracle_snapshot] to record the settlement oracle state
+        ] = accounts else {
+        return Err(not_enough_accounts(12, accounts.len()));
+    };
+
+    // ---- Basic checks ----
+    if !user.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    // The user is credited the position account's rent on a full close
+    check_writable(&[user, market_account, user_account, collateral_vault, payout_token_account, user_position_account, insurance_vault])?;
+    if *token_program.key() != pinocchio_token::ID {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // ---- Parse instruction ----
+    let ClosePositionArgs { market_id, close_size } = ClosePositionArgs::try_from(instruction_data)?;
+
+    // ---- Derive & check PDAs ----
+    let (market_account_pda, market_bump) = pubkey::find_program_address(
+        &[b"market_account", market_authority.key().as_ref(), market_id.to_le_bytes().as_ref()],
+        &crate::ID
+    );
+    if *market_account.key() != market_account_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let (user_account_pda, _user_bump) = pubkey::find_program_address(
+        &[b"user_account", user.key().as_ref()],
+        &crate::ID
+    );
+    if *user_account.key() != user_account_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let (user_position_account_pda, _position_bump) = pubkey::find_program_address(
+        &[b"position", user.key().as_ref(), market_id.to_le_bytes().as_ref()],
+        &crate::ID
+    );
+    if *user_position_account.key() != user_position_account_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let (collateral_vault_pda, _collateral_bump) = pubkey::find_program_address(
+        &[b"collateral_vault", market_account.key().as_ref()],
+        &crate::ID
+    );
+
+    // ---- Validate market ----
+    // Market status and the global kill switch are deliberately not checked:
+    // both only block new opens, users must always be able to exit.
+    let (oracle, collateral_decimals, fee_rate, close_fee_discount, open_interest_long, open_interest_short) = {
+        let market = Market::load_initialized_mut(market_account)?;
+        check_collateral_vault(&market, &collateral_vault_pda, collateral_vault.key())?;
+        check_market_accounts(&market, market_authority.key(), collateral_vault.key(), collateral_mint.key())?;
+        (
+            market.oracle_config(),
+            market.collateral_decimals,
+            market.fee_rate,
+            market.close_fee_discount,
+            market.open_interest_long,
+            market.open_interest_short,
+        )
+    };
+
+    // ---- Token account validations ----
+    // Paying anyone but the user takes their delegation PDA as the first optional account
+    let pays_user = TokenAccount::from_account_info(payout_token_account)?.owner() == user.key();
+    let (delegation, optional) = match optional {
+        [delegation, rest @ ..] if !pays_user => (Some(delegation), rest),
+        _ => (None, optional),
+    };
+    if let Some(delegation) = delegation {
+        let (delegation_pda, _delegation_bump) = pubkey::find_program_address(
+            &[b"delegation", user.key().as_ref()],
+            &crate::ID
+        );
+        if *delegation.key() != delegation_pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+    }
+
+    let vault_balance = {
+        let payout_ta = TokenAccount::from_account_info(payout_token_account)?;
+        check_payout_destination(&payout_ta, collateral_mint.key(), user.key(), delegation)?;
+
+        let vault_ta = TokenAccount::from_account_info(collateral_vault)?;
+        if *vault_ta.mint() != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        vault_ta.amount()
+    };
+    let decimals = Mint::from_account_info(collateral_mint)?.decimals();
+
+    // ---- Load position ----
+    let (size, entry_price, margin, funding_payment) = {
+        let position = Position::from_account_info_mut(user_position_account)?;
+        if position.user != *user.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if !position.is_open() || position.size == 0 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        (position.size, position.entry_price, position.margin, position.funding_payment)
+    };
+    let closed_size = closed_size(size, close_size)?;
+    let full_close = closed_size == size;
+
+    // ---- Sysvars / Oracle ----
+    let clock = Clock::from_account_info(clock_sysvar)?;
+    let current_price = get_price_for_feed(pyth_price_account, &clock, &oracle)?.price;
+
+    // Funding accrued since the position's last settlement is paid or received on close
+    let (pending_funding, funding_index) = {
+        let mut market = Market::from_account_info_mut(market_account)?;
+        market.accrue_funding(current_price, clock.unix_timestamp)?;
+        let position = Position::from_account_info(user_position_account)?;
+        (position.pending_funding(market.cumulative_funding_index, collateral_decimals)?, market.cumulative_funding_index)
+    };
+
+    // A partial close settles pending funding into margin at the old size, then
+    // takes the closed contracts' share of margin and accrued funding with it
+    let (closed_margin, closed_funding, margin_delta) = if full_close {
+        (margin, funding_payment as i128 + pending_funding as i128, 0)
+    } else {
+        let mut position = Position::from_account_info_mut(user_position_account)?;
+        let margin_delta = position.settle_funding_index(funding_index, collateral_decimals)?;
+        let funding_share = position.settle_funding_share(closed_size.unsigned_abs())?;
+        (margin_share(position.margin, closed_size, size)?, funding_share as i128, margin_delta)
+    };
+
+    // ---- Settle PnL ----
+    // Converted to collateral units, rounded down so losses round away from zero
+    let realized_pnl = math::quote_to_collateral(
+        calculate_realized_pnl(closed_size, entry_price, current_price)?,
+        collateral_decimals,
+        RoundingMode::Down
+    )?;
+
+    let reduces_risk = is_risk_reducing(closed_size, open_interest_long, open_interest_short);
+    let close_fee = calculate_close_fee(
+        notional_in_collateral(closed_size, current_price, collateral_decimals)?,
+        fee_rate,
+        close_fee_discount,
+        reduces_risk
+    )?;
+
+    // Funding received but not compounded into margin, and funding still pending, is settled with the PnL,
+    // the close fee stays in the vault
+    let settlement = realized_pnl + closed_funding - close_fee as i128;
+    let settled = settle_pnl(closed_margin, settlement);
+    check_vault_covers(settled.equity, vault_balance)?;
+    let payout = settled.equity;
+
+    // ---- Transfer payout from vault -> user (signed by market PDA) ----
+    if payout > 0 {
+        let market_id_bytes = market_id.to_le_bytes();
+        let bump_ref = &[market_bump];
+        let seeds = seeds!(
+            b"market_account",
+            market_authority.key().as_ref(),
+            &market_id_bytes,
+            bump_ref
+        );
+        let signer = Signer::from(&seeds);
+
+        TransferChecked {
+            from: collateral_vault,
+            to: payout_token_account,
+            authority: market_account,
+            mint: collateral_mint,
+            amount: payout,
+            decimals,
+        }.invoke_signed(&[signer])?;
+    }
+
+    // ---- Update accounting ----
+    let mut user_account_data = UserAccount::from_account_info_mut(user_account)?;
+    if user_account_data.owner != *user.key() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    user_account_data.apply_margin_delta(margin_delta)?;
+    user_account_data.margin_balance = user_account_data.margin_balance.saturating_sub(closed_margin);
+
+    if full_close {
+        // The slot is freed before the position account is closed below
+        remove_position_from_user(&mut user_account_data, user_position_account.key())?;
+    } else {
+        // The remainder keeps its entry price
+        let mut position = Position::from_account_info_mut(user_position_account)?;
+        position.size -= closed_size;
+        position.margin -= closed_margin;
+        position.last_funding_settlement = clock.unix_timestamp;
+    }
+
+    {
+        let mut market = Market::from_account_info_mut(market_account)?;
+        remove_open_interest(&mut market, closed_size);
+        market.debit_collateral(payout);
+        // A partial close settled funding into the position's margin first
+        market.apply_margin_delta(margin_delta)?;
+        market.release_margin(closed_margin);
+        // Whatever part of the fee the closed margin couldn't pay is never collected
+        market.collect_fees(close_fee.saturating_sub(settled.deficit))?;
+    }
+    // Losses and fee beyond the closed margin are covered like a liquidation's
+    let cover = cover_deficit(market_account, collateral_mint, collateral_vault, insurance_vault, settled.deficit)?;
+    assert_vault_solvent(&*Market::from_account_info(market_account)?, &*TokenAccount::from_account_info(collateral_vault)?)?;
+
+    // ---- Record oracle snapshot ----
+    if let Some(oracle_snapshot) = optional.first() {
+        record_oracle_snapshot(oracle_snapshot, user_position_account.key(), pyth_price_account, oracle.oracle_kind, clock.unix_timestamp)?;
+    }
+
+    // ---- Reclaim position rent ----
+    // Only a flat position goes away, a partial close keeps the account
+    if full_close {
+        close_program_account(user_position_account, user)?;
+    }
+
+    println!("Position closed successfully");
+    println!("Closed Size: {}", closed_size);
+    println!("Exit Price: {}", current_price);
+    println!("Realized PnL: {}", realized_pnl);
+    println!("Close Fee: {}", close_fee);
+    println!("Payout: {}", payout);
+    println!("Covered by Insurance: {}", cover.insurance_draw);
+    println!("Bad Debt: {}", cover.bad_debt);
+
+    Ok(())
+}
+
+/// The payout goes to a token account of the collateral mint owned by the
+/// user, or by the user's current delegate when their delegation is passed.
+fn check_payout_destination(
+    payout_ta: &TokenAccount,
+    collateral_mint: &Pubkey,
+    user: &Pubkey,
+    delegation: Option<&AccountInfo>
+) -> ProgramResult {
+    if payout_ta.mint() != collateral_mint {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if payout_ta.owner() == user {
+        return Ok(());
+    }
+
+    match delegation {
+        Some(delegation) => check_delegation(delegation, user, payout_ta.owner()),
+        None => Err(ProgramError::InvalidAccountData),
+    }
+}
+
+/// Contracts a close takes off a position of `position_size`, in the
+/// position's direction. `close_size` must oppose the position; anything at
+/// or beyond its size closes it in full, as does no size at all.
+fn closed_size(position_size: i128, close_size: Option<i128>) -> Result<i128, ProgramError> {
+    let Some(close_size) = close_size else {
+        return Ok(position_size);
+    };
+    if close_size == 0 || close_size.signum() == position_size.signum() {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    if close_size.unsigned_abs() >= position_size.unsigned_abs() {
+        return Ok(position_size);
+    }
+    Ok(-close_size)
+}
+
+/// Margin returned with `closed_size` of `position_size` contracts, rounded
+/// down so the remainder is never left short.
+pub(crate) fn margin_share(margin: u64, closed_size: i128, position_size: i128) -> Result<u64, ProgramError> {
+    let share = math::mul_div(
+        margin as i128,
+        closed_size.unsigned_abs() as i128,
+        position_size.unsigned_abs() as i128,
+        RoundingMode::Down
+    )?;
+    u64::try_from(share).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+pub(crate) fn calculate_realized_pnl(size: i128, entry_price: u64, exit_price: u64) -> Result<i128, ProgramError> {
+    let price_delta = (exit_price as i128)
+        .checked_sub(entry_price as i128)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    size.checked_mul(price_delta)
+        .ok_or(ProgramError::ArithmeticOverflow)
+}
+
+/// Whether closing `size` shrinks the side carrying more open interest.
+pub(crate) fn is_risk_reducing(size: i128, open_interest_long: u64, open_interest_short: u64) -> bool {
+    (size > 0 && open_interest_long > open_interest_short)
+        || (size < 0 && open_interest_short > open_interest_long)
+}
+
+/// Trading fee on the closed notional, with `discount_bps` of it waived for
+/// risk-reducing closes. The discount rounds down so the fee stays against the trader.
+pub(crate) fn calculate_close_fee(
+    position_value: u64,
+    fee_rate_bps: u64,
+    discount_bps: u64,
+    reduces_risk: bool
+) -> Result<u64, ProgramError> {
+    let fee = calculate_trading_fee(position_value, fee_rate_bps)?;
+    if !reduces_risk {
+        return Ok(fee);
+    }
+
+    let discount = math::mul_div_u64(fee, discount_bps.min(10_000), 10_000, RoundingMode::Down)?;
+    Ok(fee - discount)
+}
+
+/// A position's margin settled against its PnL net of fees and funding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PnlSettlement {
+    pub equity: u64, // What's left of margin plus PnL, owed to the position
+    pub deficit: u64, // Loss beyond margin, charged to the insurance fund
+}
+
+/// Settles `pnl` against `margin`. The trader never loses more than their
+/// margin: any excess loss is a deficit routed to the insurance fund, so close
+/// and liquidation both use this rather than subtracting from a u64 margin.
+pub(crate) fn settle_pnl(margin: u64, pnl: i128) -> PnlSettlement {
+    let equity = (margin as i128).saturating_add(pnl);
+    if equity < 0 {
+        return PnlSettlement {
+            equity: 0,
+            deficit: u64::try_from(equity.unsigned_abs()).unwrap_or(u64::MAX),
+        };
+    }
+
+    PnlSettlement {
+        equity: u64::try_from(equity).unwrap_or(u64::MAX),
+        deficit: 0,
+    }
+}
+
+/// A vault short of `amount` is insolvent, the payout fails rather than being
+/// cut to whatever the vault still holds.
+pub(crate) fn check_vault_covers(amount: u64, vault_balance: u64) -> ProgramResult {
+    if amount > vault_balance {
+        return Err(PerpError::VaultInsolvent.into());
+    }
+    Ok(())
+}
+
+// =========================== TESTING process_close_position ===========================
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::{calculate_close_fee, calculate_realized_pnl, check_vault_covers, closed_size, is_risk_reducing, margin_share, settle_pnl, PnlSettlement};
+    use crate::{errors::PerpError, instructions::{apply_deficit_cover, split_deficit}, states::{with_account_infos, Market, Position, TestAccount}};
+
+    #[test]
+    fn test_risk_reducing_close_discounted() {
+        // Closing a long while longs dominate
+        let reduces_risk = is_risk_reducing(10, 1_000, 400);
+        assert!(reduces_risk);
+        // 100_000 notional at 10 bps is 100, half of it waived
+        assert_eq!(calculate_close_fee(100_000, 10, 5_000, reduces_risk).unwrap(), 50);
+        assert_eq!(calculate_close_fee(100_000, 10, 10_000, reduces_risk).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_close_fee_rounding_against_trader() {
+        // 999 notional at 10 bps is a 0.999 fee, charged as 1
+        assert_eq!(calculate_close_fee(999, 10, 0, false).unwrap(), 1);
+        // 15 of fee with 33.33% waived: the 4.9995 discount rounds down to 4
+        assert_eq!(calculate_close_fee(15_000, 10, 3_333, true).unwrap(), 11);
+    }
+
+    #[test]
+    fn test_risk_neutral_close_full_fee() {
+        // Balanced book, and closing the minority side
+        assert!(!is_risk_reducing(10, 500, 500));
+        assert!(!is_risk_reducing(-10, 1_000, 400));
+        assert_eq!(calculate_close_fee(100_000, 10, 5_000, false).unwrap(), 100);
+    }
+
+    #[test]
+    fn test_realized_pnl_long_and_short() {
+        assert_eq!(calculate_realized_pnl(10, 100, 110).unwrap(), 100);
+        assert_eq!(calculate_realized_pnl(10, 100, 90).unwrap(), -100);
+        assert_eq!(calculate_realized_pnl(-10, 100, 90).unwrap(), 100);
+        assert_eq!(calculate_realized_pnl(-10, 100, 110).unwrap(), -100);
+    }
+
+    #[test]
+    fn test_settle_pnl_within_margin() {
+        assert_eq!(settle_pnl(1_000, 250), PnlSettlement { equity: 1_250, deficit: 0 });
+        assert_eq!(settle_pnl(1_000, -250), PnlSettlement { equity: 750, deficit: 0 });
+        assert_eq!(settle_pnl(1_000, -1_000), PnlSettlement { equity: 0, deficit: 0 });
+    }
+
+    #[test]
+    fn test_loss_plus_fee_beyond_margin_covered_like_a_liquidation() {
+        // 10x long, 1_000 margin on 10_000 notional, price falls 11%
+        let margin = 1_000;
+        let realized_pnl = calculate_realized_pnl(100, 100, 89).unwrap();
+        let close_fee = calculate_close_fee(8_900, 10, 0, false).unwrap();
+        // 8.9 of fee rounds up
+        assert_eq!((realized_pnl, close_fee), (-1_100, 9));
+
+        let settled = settle_pnl(margin, realized_pnl - close_fee as i128);
+        assert_eq!(settled, PnlSettlement { equity: 0, deficit: 109 });
+
+        // Covered the same way as a liquidation: insurance first, then bad debt
+        let cover = split_deficit(settled.deficit, 100);
+        let mut market = Market { total_collateral: 5_000, ..Market::default() };
+        apply_deficit_cover(&mut market, cover).unwrap();
+        assert_eq!((market.total_collateral, market.bad_debt), (5_100, 9));
+    }
+
+    #[test]
+    fn test_payout_beyond_vault_fails() {
+        assert!(check_vault_covers(0, 0).is_ok());
+        assert!(check_vault_covers(1_000, 1_000).is_ok());
+        assert_eq!(
+            check_vault_covers(1_001, 1_000),
+            Err(ProgramError::Custom(PerpError::VaultInsolvent as u32))
+        );
+    }
+
+    #[test]
+    fn test_payout_destination_owner_and_mint() {
+        use pinocchio::program_error::ProgramError;
+        use pinocchio_token::state::TokenAccount;
+
+        use super::check_payout_destination;
+        use crate::{errors::PerpError, states::{with_account_infos, DelegateAuthority, TestAccount}};
+
+        const USER: [u8; 32] = [1u8; 32];
+        const DELEGATE: [u8; 32] = [2u8; 32];
+        const MINT: [u8; 32] = [3u8; 32];
+
+        let token_account = |owner: [u8; 32], mint: [u8; 32]| {
+            let mut data = vec![0u8; TokenAccount::LEN];
+            data[..32].copy_from_slice(&mint);
+            data[32..64].copy_from_slice(&owner);
+            data[108] = 1; // Initialized
+            data
+        };
+        let mut delegation = vec![0u8; DelegateAuthority::SIZE];
+        let offset = core::mem::offset_of!(DelegateAuthority, user);
+        delegation[offset..offset + 32].copy_from_slice(&USER);
+        let offset = core::mem::offset_of!(DelegateAuthority, delegate);
+        delegation[offset..offset + 32].copy_from_slice(&DELEGATE);
+
+        let (custody, delegate_owned, foreign, wrong_mint) = (
+            token_account(USER, MINT),
+            token_account(DELEGATE, MINT),
+            token_account([9u8; 32], MINT),
+            token_account(USER, [8u8; 32]),
+        );
+        let account = |key: u8, owner, data| TestAccount { key: [key; 32], owner, is_signer: false, is_writable: true, lamports: 0, data };
+        let accounts = [
+            account(10, crate::ID, &delegation),
+            account(11, pinocchio_token::ID, &custody),
+            account(12, pinocchio_token::ID, &delegate_owned),
+            account(13, pinocchio_token::ID, &foreign),
+            account(14, pinocchio_token::ID, &wrong_mint),
+        ];
+
+        with_account_infos(&accounts, |accounts| {
+            let check = |index: usize, delegation| {
+                let payout_ta = TokenAccount::from_account_info(&accounts[index]).unwrap();
+                check_payout_destination(&payout_ta, &MINT, &USER, delegation)
+            };
+
+            // Any user-owned account of the collateral mint, not just the default one
+            assert!(check(1, None).is_ok());
+            // The user's approved delegate
+            assert!(check(2, Some(&accounts[0])).is_ok());
+            assert_eq!(check(2, None), Err(ProgramError::InvalidAccountData));
+            // Someone else entirely, with or without a delegation
+            assert_eq!(check(3, None), Err(ProgramError::InvalidAccountData));
+            assert_eq!(check(3, Some(&accounts[0])), Err(PerpError::UnauthorizedDelegate.into()));
+            assert_eq!(check(4, None), Err(ProgramError::InvalidAccountData));
+        });
+    }
+
+    #[test]
+    fn test_long_closed_forty_percent() {
+        // 10 long at 100 with 200 margin, closing 4 at 110
+        let mut position = Position { size: 10, entry_price: 100, margin: 200, is_active: 1, ..Position::default() };
+        let closed = closed_size(position.size, Some(-4)).unwrap();
+        assert_eq!(closed, 4);
+
+        let closed_margin = margin_share(position.margin, closed, position.size).unwrap();
+        let realized_pnl = calculate_realized_pnl(closed, position.entry_price, 110).unwrap();
+        assert_eq!((closed_margin, realized_pnl), (80, 40));
+        assert_eq!(settle_pnl(closed_margin, realized_pnl), PnlSettlement { equity: 120, deficit: 0 });
+
+        // The remaining 6 keep their entry and the rest of the margin
+        position.size -= closed;
+        position.margin -= closed_margin;
+        assert_eq!((position.size, position.entry_price, position.margin), (6, 100, 120));
+        assert!(position.is_open());
+    }
+
+    #[test]
+    fn test_short_fully_closed() {
+        // Closing more than the position holds flattens it
+        assert_eq!(closed_size(-5, Some(8)), Ok(-5));
+        assert_eq!(closed_size(-5, Some(5)), Ok(-5));
+        assert_eq!(closed_size(-5, None), Ok(-5));
+
+        // The whole margin comes back with the PnL, price fell from 100 to 90
+        assert_eq!(margin_share(100, -5, -5), Ok(100));
+        let realized_pnl = calculate_realized_pnl(-5, 100, 90).unwrap();
+        assert_eq!(settle_pnl(100, realized_pnl), PnlSettlement { equity: 150, deficit: 0 });
+    }
+
+    #[test]
+    fn test_close_size_must_oppose_the_position() {
+        assert_eq!(closed_size(10, Some(4)), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(closed_size(-10, Some(-4)), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(closed_size(10, Some(0)), Err(ProgramError::InvalidInstructionData));
+
+        // Margin shares round down, leaving the remainder whole
+        assert_eq!(margin_share(100, 1, 3), Ok(33));
+    }
+
+    #[test]
+    fn test_closed_position_rent_returns_to_user() {
+        let position_data = [0xabu8; Position::SIZE];
+        let accounts = [
+            TestAccount { key: [1u8; 32], owner: crate::ID, is_signer: false, is_writable: true, lamports: 2_000_000, data: &position_data },
+            TestAccount { key: [2u8; 32], owner: pinocchio_system::ID, is_signer: true, is_writable: true, lamports: 5_000, data: &[] },
+        ];
+
+        with_account_infos(&accounts, |accounts| {
+            let [position, user] = accounts else { unreachable!() };
+            crate::instructions::close_program_account(position, user).unwrap();
+
+            assert_eq!(user.lamports(), 2_005_000);
+            assert_eq!(position.lamports(), 0);
+            assert!(position.try_borrow_data().unwrap().iter().all(|byte| *byte == 0));
+        });
+    }
+
+    #[test]
+    fn test_paused_market_still_closes() {
+        use mollusk_svm::result::Check;
+        use mollusk_svm_programs_token::token;
+        use solana_sdk::{account::Account, instruction::{AccountMeta, Instruction}, pubkey::Pubkey};
+        use crate::{instructions::{fixtures::{self, read, token_balance, AUTHORITY, COLLATERAL_MINT, MARKET_ID, PROGRAM_ID, USER}, PerpetualInstructions}, states::MarketStatus};
+
+        let mollusk = fixtures::mollusk();
+        let keys = fixtures::keys();
+        let payout_token_account = Pubkey::new_unique();
+        let price_update = Pubkey::new_unique();
+
+        // 10 long at $150 on $200 of margin, opened before the pause with its $1.50 fee in the vault
+        let market = Market {
+            status: MarketStatus::Paused as u8,
+            open_interest_long: 10,
+            locked_margin: 200_000_000,
+            total_collateral: 201_500_000,
+            fees_collected: 1_500_000,
+            ..fixtures::market(&keys)
+        };
+        let position = fixtures::position(&keys, 10, 150_00000000, 200_000_000);
+        let user_account = fixtures::user_account(&keys.user_position_account, 200_000_000);
+
+        let mut data = vec![PerpetualInstructions::ClosePosition as u8];
+        data.extend_from_slice(&MARKET_ID.to_le_bytes());
+        let (clock_id, clock_account) = mollusk.sysvars.keyed_account_for_clock_sysvar();
+        let instruction = Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(USER, true),                            // user
+                AccountMeta::new_readonly(AUTHORITY, false),             // market_authority
+                AccountMeta::new_readonly(COLLATERAL_MINT, false),       // collateral_mint
+                AccountMeta::new(keys.market_account, false),            // market_account
+                AccountMeta::new(keys.user_account, false),              // user_account
+                AccountMeta::new(keys.collateral_vault, false),          // collateral_vault
+                AccountMeta::new(payout_token_account, false),           // payout_token_account
+                AccountMeta::new(keys.user_position_account, false),     // user_position_account
+                AccountMeta::new_readonly(price_update, false),          // pyth_price_account
+                AccountMeta::new_readonly(token::ID, false),             // token_program
+                AccountMeta::new_readonly(clock_id, false),              // clock_sysvar
+                AccountMeta::new(keys.insurance_vault, false),           // insurance_vault
+            ],
+            data,
+        };
+        let accounts: Vec<(Pubkey, Account)> = vec![
+            (USER, fixtures::wallet()),
+            (AUTHORITY, fixtures::wallet()),
+            (COLLATERAL_MINT, fixtures::mint(201_500_000)),
+            (keys.market_account, fixtures::program_account(bytemuck::bytes_of(&market))),
+            (keys.user_account, fixtures::program_account(bytemuck::bytes_of(&user_account))),
+            (keys.collateral_vault, fixtures::token_account(&keys.market_account, 201_500_000)),
+            (payout_token_account, fixtures::token_account(&USER, 0)),
+            (keys.user_position_account, fixtures::program_account(bytemuck::bytes_of(&position))),
+            (price_update, fixtures::price_update(150_00000000)),
+            token::keyed_account(),
+            (clock_id, clock_account),
+            (keys.insurance_vault, fixtures::token_account(&keys.market_account, 0)),
+        ];
+
+        let result = mollusk.process_and_validate_instruction(&instruction, &accounts, &[Check::success()]);
+
+        // The whole margin comes back less the $1.50 close fee, pause or not
+        assert_eq!(token_balance(result.get_account(&payout_token_account).unwrap()), 198_500_000);
+        assert_eq!(token_balance(result.get_account(&keys.collateral_vault).unwrap()), 3_000_000);
+        let market: Market = read(result.get_account(&keys.market_account).unwrap());
+        assert_eq!((market.open_interest_long, market.locked_margin), (0, 0));
+        assert_eq!((market.total_collateral, market.fees_collected), (3_000_000, 3_000_000));
+    }
+}
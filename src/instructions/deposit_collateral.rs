@@ -0,0 +1,250 @@
+use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, pubkey::Pubkey, sysvars::clock::Clock, *};
+use pinocchio_token::instructions::TransferChecked;
+use pinocchio_token::state::{Mint, TokenAccount};
+
+use crate::{instructions::{assert_vault_solvent, check_collateral_vault, check_existing_user_account, check_market_accounts, check_writable, create_program_account, not_enough_accounts}, states::{Market, UserAccount}};
+
+/// Instruction data: [market_id: u64][amount: u64]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepositCollateralArgs {
+    pub market_id: u64,
+    pub amount: u64,
+}
+
+impl DepositCollateralArgs {
+    pub const LEN: usize = 8 + 8;
+}
+
+impl TryFrom<&[u8]> for DepositCollateralArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let market_id = u64::from_le_bytes(
+            data[0..8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+        let amount = u64::from_le_bytes(
+            data[8..16].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+
+        Ok(Self { market_id, amount })
+    }
+}
+
+/// Moves collateral into the market vault and credits the user's margin
+/// balance. A user without an account gets one here, so a first deposit
+/// doesn't need a separate init_user.
+pub fn process_deposit_collateral(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+
+    let [
+        user,  // The trader (must sign transaction), pays for a new user account
+        market_authority, // Authority the market was created by, its PDA seed
+        collateral_mint, // Token mint for collateral (e.g., USDC)
+        market_account, // Stores market configuration, owns the vault
+        user_account, // User's trading account, created if empty
+        collateral_vault, // Vault holding all collateral
+        user_token_account, // User's token account to debit
+        _system_program,
+        token_program,
+        clock_sysvar, // Solana clock for timestamps
+        ] = accounts else {
+        return Err(not_enough_accounts(10, accounts.len()));
+    };
+
+    // ---- Basic checks ----
+    if !user.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[user, market_account, user_account, collateral_vault, user_token_account])?;
+    if *token_program.key() != pinocchio_token::ID {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // ---- Parse instruction ----
+    let DepositCollateralArgs { market_id, amount } = DepositCollateralArgs::try_from(instruction_data)?;
+    if amount == 0 {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    // ---- Derive & check PDAs ----
+    let (market_account_pda, _market_bump) = pubkey::find_program_address(
+        &[b"market_account", market_authority.key().as_ref(), market_id.to_le_bytes().as_ref()],
+        &crate::ID
+    );
+    if *market_account.key() != market_account_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let (user_account_pda, user_bump) = pubkey::find_program_address(
+        &[b"user_account", user.key().as_ref()],
+        &crate::ID
+    );
+    if *user_account.key() != user_account_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let (collateral_vault_pda, _collateral_bump) = pubkey::find_program_address(
+        &[b"collateral_vault", market_account.key().as_ref()],
+        &crate::ID
+    );
+
+    // ---- Validate market ----
+    {
+        let market = Market::load_initialized_mut(market_account)?;
+        check_collateral_vault(&market, &collateral_vault_pda, collateral_vault.key())?;
+        check_market_accounts(&market, market_authority.key(), collateral_vault.key(), collateral_mint.key())?;
+    }
+
+    // ---- Token account validations ----
+    {
+        let user_ta = TokenAccount::from_account_info(user_token_account)?;
+        if *user_ta.owner() != *user.key() || *user_ta.mint() != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let vault_ta = TokenAccount::from_account_info(collateral_vault)?;
+        if *vault_ta.mint() != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+    }
+    let decimals = Mint::from_account_info(collateral_mint)?.decimals();
+
+    let clock = Clock::from_account_info(clock_sysvar)?;
+
+    // ---- Create the user account on first deposit ----
+    let create_user_account = user_account.data_is_empty();
+    if create_user_account {
+        println!("Creating user account");
+
+        let bump_ref = &[user_bump];
+        let seeds = seeds!(
+            b"user_account",
+            user.key().as_ref(),
+            bump_ref
+        );
+        create_program_account(user, user_account, UserAccount::SIZE, Signer::from(&seeds))?;
+    } else {
+        check_existing_user_account(user_account, user.key())?;
+    }
+
+    // ---- Transfer user -> vault ----
+    TransferChecked {
+        from: user_token_account,
+        to: collateral_vault,
+        authority: user,
+        mint: collateral_mint,
+        amount,
+        decimals,
+    }.invoke()?;
+
+    // ---- Update accounting ----
+    let mut user_account_data = if create_user_account {
+        UserAccount::init_from_account_info_mut(user_account)?
+    } else {
+        UserAccount::from_account_info_mut(user_account)?
+    };
+    credit_deposit(&mut user_account_data, user.key(), create_user_account, collateral_mint.key(), amount, clock.unix_timestamp)?;
+
+    let mut market = Market::from_account_info_mut(market_account)?;
+    market.credit_collateral(amount)?;
+    assert_vault_solvent(&market, &*TokenAccount::from_account_info(collateral_vault)?)?;
+
+    println!("Collateral deposited: {}", amount);
+
+    Ok(())
+}
+
+/// Credits `amount` of `mint` to the user's margin balance, setting the
+/// account up first when it was just created.
+fn credit_deposit(
+    user_account: &mut UserAccount,
+    user: &Pubkey,
+    created: bool,
+    mint: &Pubkey,
+    amount: u64,
+    now: i64
+) -> ProgramResult {
+    if created {
+        user_account.initialize(*user);
+    }
+    user_account.bind_collateral_mint(mint)?;
+
+    user_account.margin_balance = user_account.margin_balance
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    user_account.deposit_time = now;
+    Ok(())
+}
+
+// =========================== TESTING process_deposit_collateral ===========================
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::{program_error::ProgramError, pubkey::Pubkey};
+
+    use super::{credit_deposit, DepositCollateralArgs};
+    use crate::{errors::PerpError, states::{with_account_info, UserAccount}};
+
+    const USER: Pubkey = [2u8; 32];
+    const USDC: Pubkey = [5u8; 32];
+
+    #[test]
+    fn test_first_deposit_creates_user_account() {
+        // A freshly created account is all zeroes
+        with_account_info(&[0u8; UserAccount::SIZE], |account| {
+            let mut user_account = UserAccount::init_from_account_info_mut(account).unwrap();
+            credit_deposit(&mut user_account, &USER, true, &USDC, 5_000, 1_700_000_000).unwrap();
+
+            assert_eq!(user_account.owner, USER);
+            assert_eq!(user_account.margin_balance, 5_000);
+            assert_eq!(user_account.deposit_time, 1_700_000_000);
+            assert!(user_account.open_positions.iter().all(|key| *key == Pubkey::default()));
+        });
+    }
+
+    #[test]
+    fn test_deposit_adds_to_existing_balance() {
+        with_account_info(&[0u8; UserAccount::SIZE], |account| {
+            let mut user_account = UserAccount::init_from_account_info_mut(account).unwrap();
+            credit_deposit(&mut user_account, &USER, true, &USDC, 5_000, 1).unwrap();
+            credit_deposit(&mut user_account, &USER, false, &USDC, 2_500, 2).unwrap();
+            assert_eq!(user_account.margin_balance, 7_500);
+            assert_eq!(user_account.deposit_time, 2);
+
+            assert_eq!(
+                credit_deposit(&mut user_account, &USER, false, &USDC, u64::MAX, 3),
+                Err(ProgramError::ArithmeticOverflow)
+            );
+        });
+    }
+
+    #[test]
+    fn test_deposit_in_another_mint_rejected() {
+        with_account_info(&[0u8; UserAccount::SIZE], |account| {
+            let mut user_account = UserAccount::init_from_account_info_mut(account).unwrap();
+            credit_deposit(&mut user_account, &USER, true, &USDC, 5_000, 1).unwrap();
+            assert_eq!(user_account.collateral_mint, USDC);
+
+            // Depositing into a market with a different mint would mix units in one balance
+            assert_eq!(
+                credit_deposit(&mut user_account, &USER, false, &[6u8; 32], 2_500, 2),
+                Err(PerpError::CollateralMintMismatch.into())
+            );
+            assert_eq!(user_account.margin_balance, 5_000);
+        });
+    }
+
+    #[test]
+    fn test_deposit_args_round_trip() {
+        let mut data = 7u64.to_le_bytes().to_vec();
+        data.extend_from_slice(&1_000u64.to_le_bytes());
+        assert_eq!(
+            DepositCollateralArgs::try_from(data.as_slice()),
+            Ok(DepositCollateralArgs { market_id: 7, amount: 1_000 })
+        );
+        assert_eq!(DepositCollateralArgs::try_from(&data[..15]), Err(ProgramError::InvalidInstructionData));
+    }
+}
@@ -0,0 +1,139 @@
+use pinocchio::{account_info::AccountInfo, program_error::ProgramError, *};
+use pinocchio_token::instructions::TransferChecked;
+use pinocchio_token::state::{Mint, TokenAccount};
+
+use crate::{errors::PerpError, instructions::{check_market_pda, check_writable, not_enough_accounts}, states::Market};
+
+/// Instruction data: [market_id: u64][amount: u64]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepositInsuranceArgs {
+    pub market_id: u64,
+    pub amount: u64,
+}
+
+impl DepositInsuranceArgs {
+    pub const LEN: usize = 8 + 8;
+}
+
+impl TryFrom<&[u8]> for DepositInsuranceArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let market_id = u64::from_le_bytes(
+            data[0..8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+        let amount = u64::from_le_bytes(
+            data[8..16].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+
+        Ok(Self { market_id, amount })
+    }
+}
+
+/// Tops up the market's insurance fund. Only the market authority can deposit,
+/// the funds cover liquidation shortfalls and are never credited to a user.
+pub fn process_deposit_insurance(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+
+    let [
+        authority, // Market authority (must sign transaction)
+        collateral_mint, // Token mint for collateral (e.g., USDC)
+        market_account, // Market the fund belongs to, owns the insurance vault
+        insurance_vault, // Vault holding the market's insurance fund
+        authority_token_account, // Authority's token account to debit
+        token_program,
+        ] = accounts else {
+        return Err(not_enough_accounts(6, accounts.len()));
+    };
+
+    // ---- Basic checks ----
+    if !authority.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[insurance_vault, authority_token_account])?;
+    if *token_program.key() != pinocchio_token::ID {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // ---- Parse instruction ----
+    let DepositInsuranceArgs { market_id, amount } = DepositInsuranceArgs::try_from(instruction_data)?;
+    if amount == 0 {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    // ---- Validate market ----
+    {
+        let market = Market::load_initialized_mut(market_account)?;
+        check_market_pda(&market, market_account, market_id)?;
+        if market.authority != *authority.key() || market.collateral_mint != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if market.insurance_vault != *insurance_vault.key() {
+            return Err(PerpError::VaultMismatch.into());
+        }
+    }
+
+    // ---- Token account validations ----
+    {
+        let authority_ta = TokenAccount::from_account_info(authority_token_account)?;
+        if *authority_ta.owner() != *authority.key() || *authority_ta.mint() != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+    }
+    let decimals = Mint::from_account_info(collateral_mint)?.decimals();
+
+    // ---- Transfer authority -> insurance vault ----
+    TransferChecked {
+        from: authority_token_account,
+        to: insurance_vault,
+        authority,
+        mint: collateral_mint,
+        amount,
+        decimals,
+    }.invoke()?;
+
+    println!("Insurance deposited: {}", amount);
+
+    Ok(())
+}
+
+// =========================== TESTING process_deposit_insurance ===========================
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::{process_deposit_insurance, DepositInsuranceArgs};
+    use crate::states::{with_account_infos, TestAccount};
+
+    #[test]
+    fn test_deposit_insurance_args_round_trip() {
+        let mut data = 3u64.to_le_bytes().to_vec();
+        data.extend_from_slice(&25_000u64.to_le_bytes());
+        assert_eq!(
+            DepositInsuranceArgs::try_from(data.as_slice()),
+            Ok(DepositInsuranceArgs { market_id: 3, amount: 25_000 })
+        );
+        assert_eq!(DepositInsuranceArgs::try_from(&data[..15]), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_non_signer_authority_rejected() {
+        let account = |key: u8| TestAccount {
+            key: [key; 32],
+            owner: pinocchio_system::ID,
+            is_signer: false,
+            is_writable: true,
+            lamports: 0,
+            data: &[],
+        };
+        let accounts = [account(1), account(2), account(3), account(4), account(5), TestAccount { key: pinocchio_token::ID, ..account(0) }];
+
+        with_account_infos(&accounts, |accounts| {
+            assert_eq!(process_deposit_insurance(accounts, &[0u8; 9]), Err(ProgramError::MissingRequiredSignature));
+        });
+    }
+}
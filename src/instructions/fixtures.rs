@@ -0,0 +1,184 @@
+//! Accounts for running handlers under Mollusk: a fully initialized SOL-PERP
+//! market, its vaults, a Pyth price update and packed SPL Token accounts,
+//! with the SPL Token program loaded.
+//!
+//! Mollusk runs `target/deploy/pinocchio_perp.so`, so these tests need the
+//! program rebuilt with `cargo build-sbf` first.
+
+use mollusk_svm::Mollusk;
+use mollusk_svm_programs_token::token;
+use solana_sdk::{account::Account, program_pack::Pack, pubkey::Pubkey};
+use spl_token::{solana_program::program_option::COption, state::{Account as TokenAccount, AccountState, Mint}};
+
+use crate::{
+    instructions::{PriceFeedMessage, PriceUpdateV2, VerificationLevel, PYTH_RECEIVER_ID, SOL_USD_FEED_ID},
+    states::{Market, MarketStatus, OracleKind, Position, UserAccount},
+};
+
+pub(crate) const PROGRAM_ID: Pubkey = Pubkey::new_from_array(crate::ID);
+pub(crate) const AUTHORITY: Pubkey = Pubkey::new_from_array([1u8; 32]);
+pub(crate) const USER: Pubkey = Pubkey::new_from_array([2u8; 32]);
+pub(crate) const COLLATERAL_MINT: Pubkey = Pubkey::new_from_array([3u8; 32]);
+pub(crate) const MARKET_ID: u64 = 66;
+/// Unix time the clock and price updates are set to.
+pub(crate) const NOW: i64 = 1_700_000_000;
+/// Slot the clock and price updates are set to.
+pub(crate) const SLOT: u64 = 100;
+
+/// Mollusk with SPL Token loaded and the clock at NOW.
+pub(crate) fn mollusk() -> Mollusk {
+    let mut mollusk = Mollusk::new(&PROGRAM_ID, "target/deploy/pinocchio_perp");
+    token::add_program(&mut mollusk);
+    mollusk.sysvars.clock.slot = SLOT;
+    mollusk.sysvars.clock.unix_timestamp = NOW;
+    mollusk
+}
+
+/// Every PDA of USER's trading on the MARKET_ID market.
+pub(crate) struct Keys {
+    pub market_account: Pubkey,
+    pub market_bump: u8,
+    pub collateral_vault: Pubkey,
+    pub collateral_bump: u8,
+    pub insurance_vault: Pubkey,
+    pub insurance_bump: u8,
+    pub user_account: Pubkey,
+    pub user_position_account: Pubkey,
+}
+
+pub(crate) fn keys() -> Keys {
+    let pda = |seeds: &[&[u8]]| Pubkey::find_program_address(seeds, &PROGRAM_ID);
+    let (market_account, market_bump) = pda(&[b"market_account", AUTHORITY.as_ref(), &MARKET_ID.to_le_bytes()]);
+    let (collateral_vault, collateral_bump) = pda(&[b"collateral_vault", market_account.as_ref()]);
+    let (insurance_vault, insurance_bump) = pda(&[b"insurance_vault", market_account.as_ref()]);
+    let (user_account, _) = pda(&[b"user_account", USER.as_ref()]);
+    let (user_position_account, _) = pda(&[b"position", USER.as_ref(), &MARKET_ID.to_le_bytes()]);
+    Keys { market_account, market_bump, collateral_vault, collateral_bump, insurance_vault, insurance_bump, user_account, user_position_account }
+}
+
+/// An active SOL-PERP market on a 6 decimal collateral: 10% initial and 5%
+/// maintenance margin, a 10 bps fee and a 25% liquidator cut.
+pub(crate) fn market(keys: &Keys) -> Market {
+    Market {
+        discriminator: Market::DISCRIMINATOR,
+        is_initialized: 1,
+        market_id: MARKET_ID,
+        market_symbol: *b"SOL-PERP\0\0\0\0\0\0\0\0",
+        collateral_mint: COLLATERAL_MINT.to_bytes(),
+        collateral_vault: keys.collateral_vault.to_bytes(),
+        insurance_vault: keys.insurance_vault.to_bytes(),
+        authority: AUTHORITY.to_bytes(),
+        seed_authority: AUTHORITY.to_bytes(),
+        bump: keys.market_bump,
+        collateral_bump: keys.collateral_bump,
+        insurance_bump: keys.insurance_bump,
+        initial_margin: 1_000,
+        maintenance_margin: 500,
+        max_leverage: 20,
+        fee_rate: 10,
+        liquidation_fee: 2_500,
+        funding_interval: 28_800,
+        status: MarketStatus::Active as u8,
+        feed_id: PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap(),
+        collateral_decimals: 6,
+        oracle_kind: OracleKind::Pyth as u8,
+        ..Market::default()
+    }
+}
+
+/// USER's open position on the market, `size` contracts at `entry_price`.
+pub(crate) fn position(keys: &Keys, size: i128, entry_price: u64, margin: u64) -> Position {
+    Position {
+        discriminator: Position::DISCRIMINATOR,
+        user: USER.to_bytes(),
+        market: keys.market_account.to_bytes(),
+        size,
+        entry_price,
+        margin,
+        last_funding_settlement: NOW,
+        is_active: 1,
+        ..Position::default()
+    }
+}
+
+/// USER's trading account holding `margin_balance`, with `position` open.
+pub(crate) fn user_account(position: &Pubkey, margin_balance: u64) -> UserAccount {
+    let mut open_positions = [[0u8; 32]; 10];
+    open_positions[0] = position.to_bytes();
+    UserAccount {
+        discriminator: UserAccount::DISCRIMINATOR,
+        owner: USER.to_bytes(),
+        margin_balance,
+        open_positions,
+        deposit_time: 0,
+        collateral_mint: COLLATERAL_MINT.to_bytes(),
+    }
+}
+
+/// `data` in an account this program owns.
+pub(crate) fn program_account(data: &[u8]) -> Account {
+    Account { lamports: 1_000_000_000, data: data.to_vec(), owner: PROGRAM_ID, ..Account::default() }
+}
+
+/// A system account holding one SOL, for signers and payers.
+pub(crate) fn wallet() -> Account {
+    Account { lamports: solana_sdk::native_token::LAMPORTS_PER_SOL, ..Account::default() }
+}
+
+/// The collateral mint, 6 decimals.
+pub(crate) fn mint(supply: u64) -> Account {
+    token::create_account_for_mint(Mint {
+        mint_authority: COption::None,
+        supply,
+        decimals: 6,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    })
+}
+
+/// A collateral token account of `owner` holding `amount`.
+pub(crate) fn token_account(owner: &Pubkey, amount: u64) -> Account {
+    token::create_account_for_token_account(TokenAccount {
+        mint: COLLATERAL_MINT,
+        owner: *owner,
+        amount,
+        delegate: COption::None,
+        state: AccountState::Initialized,
+        is_native: COption::None,
+        delegated_amount: 0,
+        close_authority: COption::None,
+    })
+}
+
+/// Token balance of `account`.
+pub(crate) fn token_balance(account: &Account) -> u64 {
+    TokenAccount::unpack(&account.data).unwrap().amount
+}
+
+/// A fully verified SOL/USD update at `price` (1e8 scale), published at NOW
+/// and posted at SLOT, in an account the Pyth receiver owns.
+pub(crate) fn price_update(price: i64) -> Account {
+    let update = PriceUpdateV2 {
+        write_authority: [0u8; 32],
+        verification_level: VerificationLevel::Full,
+        price_message: PriceFeedMessage {
+            feed_id: PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap(),
+            price,
+            conf: 1_000_000,
+            exponent: -8,
+            publish_time: NOW,
+            prev_publish_time: NOW - 1,
+            ema_price: price,
+            ema_conf: 1_000_000,
+        },
+        posted_slot: SLOT,
+    };
+    let mut data = vec![0u8; core::mem::size_of::<PriceUpdateV2>()];
+    unsafe { core::ptr::write_unaligned(data.as_mut_ptr() as *mut PriceUpdateV2, update) };
+    Account { lamports: 1_000_000_000, data, owner: Pubkey::new_from_array(PYTH_RECEIVER_ID), ..Account::default() }
+}
+
+/// Reads a Pod state back out of a resulting account.
+pub(crate) fn read<T: bytemuck::Pod>(account: &Account) -> T {
+    bytemuck::pod_read_unaligned(&account.data[..core::mem::size_of::<T>()])
+}
@@ -0,0 +1,46 @@
+use pinocchio::{account_info::AccountInfo, cpi::set_return_data, program_error::ProgramError, sysvars::clock::Clock, *};
+
+use crate::{instructions::{get_price_for_feed, not_enough_accounts}, states::{Market, Position}};
+
+/// Read-only view: sets the position's withdrawable margin (u64, little endian)
+/// as the transaction's return data. Nothing is written.
+pub fn process_get_withdrawable_margin(accounts: &[AccountInfo]) -> ProgramResult {
+
+    let [
+        market_account, // Market the position trades on
+        user_position_account, // Position to query
+        pyth_price_account, // Pyth oracle for price feeds
+        clock_sysvar // Solana clock for timestamps
+        ] = accounts else {
+        return Err(not_enough_accounts(4, accounts.len()));
+    };
+
+    if !market_account.is_owned_by(&crate::ID) || !user_position_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    let market = Market::from_account_info(market_account)?;
+    if !market.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    let position = Position::from_account_info(user_position_account)?;
+    if position.market != *market_account.key() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let clock = Clock::from_account_info(clock_sysvar)?;
+    let current_price = get_price_for_feed(pyth_price_account, &clock, &market.oracle_config())?.price;
+
+    let withdrawable = if position.is_open() {
+        position.withdrawable_margin(current_price, market.maintenance_margin, market.collateral_decimals)?
+    } else {
+        0
+    };
+
+    set_return_data(&withdrawable.to_le_bytes());
+
+    println!("Withdrawable Margin: {}", withdrawable);
+
+    Ok(())
+}
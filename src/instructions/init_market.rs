@@ -6,34 +6,273 @@ use pinocchio::{
     sysvars::{rent::Rent, Sysvar}, 
     *
 };
-use crate::states::Market;
+use pythnet_sdk::messages::FeedId;
+
+use crate::{instructions::{check_collateral_priced, check_symbol_matches_feed, check_writable, not_enough_accounts, PriceUpdateV2, SOL_USD_FEED_ID}, states::{FundingSample, Market, MarketStatus, OracleKind, FUNDING_HISTORY_LEN}};
 use pinocchio_system::instructions::CreateAccount;
-use pinocchio_token::instructions::InitializeAccount3;
+use pinocchio_token::{instructions::InitializeAccount3, state::{Mint, TokenAccount}};
+
+/// Instruction data: [market_id: u64][market_symbol: [u8; 16]][max_leverage: u64]
+/// optionally followed by [max_publish_gap: u64], then [withdraw_delay: u64],
+/// then [close_fee_discount: u64], then [min_margin: u64], then [max_open_interest: u64],
+/// then [tick_size: u64], then [allow_ema_fallback: u8], then [max_slot_lag: u64],
+/// then [liquidation_fee: u64], then [max_funding_rate: u64], then
+/// [feed_id: 64 hex chars] (the SOL/USD feed when omitted), then [max_conf_bps: u64],
+/// then [use_ema: u8], then [max_price_age: u64] (Market::DEFAULT_MAX_PRICE_AGE when zero),
+/// then [min_verification_level: u8] (0 = any update, Market::FULL_VERIFICATION = full only),
+/// then [oracle_kind: u8] (see OracleKind, Pyth when omitted)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InitializeMarketArgs {
+    pub market_id: u64,
+    pub market_symbol: [u8; 16],
+    pub max_leverage: u64,
+    pub max_publish_gap: u64,
+    pub withdraw_delay: u64,
+    pub close_fee_discount: u64,
+    pub min_margin: u64,
+    pub max_open_interest: u64,
+    pub tick_size: u64,
+    pub allow_ema_fallback: bool,
+    pub max_slot_lag: u64,
+    pub liquidation_fee: u64,
+    pub max_funding_rate: u64,
+    pub feed_id: FeedId,
+    pub max_conf_bps: u64,
+    pub use_ema: bool,
+    pub max_price_age: u64,
+    pub min_verification_level: u8,
+    pub oracle_kind: u8,
+}
+
+impl InitializeMarketArgs {
+    pub const LEN: usize = 8 + 16 + 8;
+    pub const LEN_WITH_ORACLE_CONFIG: usize = Self::LEN + 8;
+    pub const LEN_WITH_WITHDRAW_DELAY: usize = Self::LEN_WITH_ORACLE_CONFIG + 8;
+    pub const LEN_WITH_CLOSE_FEE_DISCOUNT: usize = Self::LEN_WITH_WITHDRAW_DELAY + 8;
+    pub const LEN_WITH_MIN_MARGIN: usize = Self::LEN_WITH_CLOSE_FEE_DISCOUNT + 8;
+    pub const LEN_WITH_OI_CAP: usize = Self::LEN_WITH_MIN_MARGIN + 8;
+    pub const LEN_WITH_TICK_SIZE: usize = Self::LEN_WITH_OI_CAP + 8;
+    pub const LEN_WITH_EMA_FALLBACK: usize = Self::LEN_WITH_TICK_SIZE + 1;
+    pub const LEN_WITH_SLOT_LAG: usize = Self::LEN_WITH_EMA_FALLBACK + 8;
+    pub const LEN_WITH_LIQUIDATION_FEE: usize = Self::LEN_WITH_SLOT_LAG + 8;
+    pub const LEN_WITH_MAX_FUNDING_RATE: usize = Self::LEN_WITH_LIQUIDATION_FEE + 8;
+    pub const LEN_WITH_FEED_ID: usize = Self::LEN_WITH_MAX_FUNDING_RATE + 64;
+    pub const LEN_WITH_MAX_CONF: usize = Self::LEN_WITH_FEED_ID + 8;
+    pub const LEN_WITH_USE_EMA: usize = Self::LEN_WITH_MAX_CONF + 1;
+    pub const LEN_WITH_PRICE_AGE: usize = Self::LEN_WITH_USE_EMA + 8;
+    pub const LEN_WITH_VERIFICATION_LEVEL: usize = Self::LEN_WITH_PRICE_AGE + 1;
+    pub const LEN_WITH_ORACLE_KIND: usize = Self::LEN_WITH_VERIFICATION_LEVEL + 1;
+
+    /// Highest max_leverage a market can be created with, as a multiple of margin.
+    pub const MAX_LEVERAGE: u64 = 1_000;
+}
+
+impl TryFrom<&[u8]> for InitializeMarketArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let market_id = u64::from_le_bytes(
+            data[0..8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+
+        let mut market_symbol = [0u8; 16];
+        market_symbol.copy_from_slice(&data[8..24]);
+
+        let max_leverage = u64::from_le_bytes(
+            data[24..32].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+        // Zero would block every open, anything huge disables the cap
+        if max_leverage == 0 || max_leverage > Self::MAX_LEVERAGE {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let max_publish_gap = if data.len() >= Self::LEN_WITH_ORACLE_CONFIG {
+            u64::from_le_bytes(
+                data[32..40].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+
+        let withdraw_delay = if data.len() >= Self::LEN_WITH_WITHDRAW_DELAY {
+            u64::from_le_bytes(
+                data[40..48].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+
+        let close_fee_discount = if data.len() >= Self::LEN_WITH_CLOSE_FEE_DISCOUNT {
+            u64::from_le_bytes(
+                data[48..56].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+        if close_fee_discount > 10_000 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let min_margin = if data.len() >= Self::LEN_WITH_MIN_MARGIN {
+            u64::from_le_bytes(
+                data[56..64].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+
+        let max_open_interest = if data.len() >= Self::LEN_WITH_OI_CAP {
+            u64::from_le_bytes(
+                data[64..72].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+
+        let tick_size = if data.len() >= Self::LEN_WITH_TICK_SIZE {
+            u64::from_le_bytes(
+                data[72..80].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+
+        let allow_ema_fallback = data.len() >= Self::LEN_WITH_EMA_FALLBACK && data[80] != 0;
+
+        let max_slot_lag = if data.len() >= Self::LEN_WITH_SLOT_LAG {
+            u64::from_le_bytes(
+                data[81..89].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+
+        let liquidation_fee = if data.len() >= Self::LEN_WITH_LIQUIDATION_FEE {
+            u64::from_le_bytes(
+                data[89..97].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+        if liquidation_fee > 10_000 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let max_funding_rate = if data.len() >= Self::LEN_WITH_MAX_FUNDING_RATE {
+            u64::from_le_bytes(
+                data[97..105].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+        if max_funding_rate > 10_000 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let feed_id = if data.len() >= Self::LEN_WITH_FEED_ID {
+            let feed_hex = core::str::from_utf8(&data[105..169]).map_err(|_| ProgramError::InvalidInstructionData)?;
+            PriceUpdateV2::get_feed_id_from_hex(feed_hex)?
+        } else {
+            PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID)?
+        };
+
+        let max_conf_bps = if data.len() >= Self::LEN_WITH_MAX_CONF {
+            u64::from_le_bytes(
+                data[169..177].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+
+        let use_ema = data.len() >= Self::LEN_WITH_USE_EMA && data[177] != 0;
+
+        let max_price_age = if data.len() >= Self::LEN_WITH_PRICE_AGE {
+            u64::from_le_bytes(
+                data[178..186].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+
+        let min_verification_level = if data.len() >= Self::LEN_WITH_VERIFICATION_LEVEL { data[186] } else { 0 };
+
+        let oracle_kind = if data.len() >= Self::LEN_WITH_ORACLE_KIND { data[187] } else { OracleKind::Pyth as u8 };
+        if oracle_kind > OracleKind::Switchboard as u8 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Self {
+            market_id,
+            market_symbol,
+            max_leverage,
+            max_publish_gap,
+            withdraw_delay,
+            close_fee_discount,
+            min_margin,
+            max_open_interest,
+            tick_size,
+            allow_ema_fallback,
+            max_slot_lag,
+            liquidation_fee,
+            max_funding_rate,
+            feed_id,
+            max_conf_bps,
+            use_ema,
+            max_price_age,
+            min_verification_level,
+            oracle_kind,
+        })
+    }
+}
 
 pub fn initialize_market(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
 
-    let [authority, collateral_mint, market_account, collateral_vault, system_program, token_program] = accounts else {
-        return Err(ProgramError::NotEnoughAccountKeys);
+    let [authority, collateral_mint, market_account, collateral_vault, insurance_vault, _system_program, token_program] = accounts else {
+        return Err(not_enough_accounts(7, accounts.len()));
     };
 
+    // The authority pays for the market and both vault accounts, so it must
+    // sign before anything is derived or created
     if !authority.is_signer() {
         return Err(ProgramError::MissingRequiredSignature);
     }
+    check_writable(&[authority, market_account, collateral_vault, insurance_vault])?;
+    if *token_program.key() != pinocchio_token::ID {
+        return Err(ProgramError::IncorrectProgramId);
+    }
 
-    // if instruction_data.len() < 32 {
-    //     return Err(ProgramError::InvalidInstructionData);
-    // }
-
-    let market_id = u64::from_le_bytes(
-        instruction_data[0..8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
-    );
-
-    let mut market_symbol = [0u8; 16];
-    market_symbol.copy_from_slice(&instruction_data[8..24]);
-
-    let max_leverage = u64::from_le_bytes(
-        instruction_data[24..32].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
-    );
+    // Checked before anything is created, a bad mint would only surface in InitializeAccount3
+    check_collateral_mint(collateral_mint)?;
+
+    let InitializeMarketArgs {
+        market_id,
+        market_symbol,
+        max_leverage,
+        max_publish_gap,
+        withdraw_delay,
+        close_fee_discount,
+        min_margin,
+        max_open_interest,
+        tick_size,
+        allow_ema_fallback,
+        max_slot_lag,
+        liquidation_fee,
+        max_funding_rate,
+        feed_id,
+        max_conf_bps,
+        use_ema,
+        max_price_age,
+        min_verification_level,
+        oracle_kind,
+    } = InitializeMarketArgs::try_from(instruction_data)?;
+    // A Switchboard market has no Pyth feed to match
+    if oracle_kind == OracleKind::Pyth as u8 {
+        check_symbol_matches_feed(&market_symbol, &feed_id)?;
+    }
 
     let (market_account_pda, market_bump) = pubkey::find_program_address(
         &[b"market_account", authority.key().as_ref(), market_id.to_le_bytes().as_ref()],
@@ -41,17 +280,30 @@ pub fn initialize_market(accounts: &[AccountInfo], instruction_data: &[u8]) -> P
     );
 
     let (collateral_vault_pda, collateral_bump) = pubkey::find_program_address(
-        &[b"collateral_vault", collateral_mint.key().as_ref(), market_id.to_le_bytes().as_ref()],
+        &[b"collateral_vault", market_account.key().as_ref()],
+        &crate::ID
+    );
+
+    // Keyed by the market rather than the bare market_id, which is only
+    // unique per authority
+    let (insurance_vault_pda, insurance_bump) = pubkey::find_program_address(
+        &[b"insurance_vault", market_account.key().as_ref()],
         &crate::ID
     );
 
-    if *collateral_vault.key() != collateral_vault_pda {
+    if *collateral_vault.key() != collateral_vault_pda || *insurance_vault.key() != insurance_vault_pda {
         return Err(ProgramError::InvalidSeeds);
     }
 
     if *market_account.key() != market_account_pda {
         return Err(ProgramError::InvalidSeeds);
     }
+
+    let collateral_state = vault_state(collateral_vault)?;
+    let insurance_state = vault_state(insurance_vault)?;
+    if collateral_state == VaultState::Initialized && insurance_state == VaultState::Initialized {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
     
     if market_account.data_is_empty() {
         println!("Initializing Market Account!");
@@ -68,18 +320,12 @@ pub fn initialize_market(accounts: &[AccountInfo], instruction_data: &[u8]) -> P
         );
         let signer = Signer::from(&seeds);
 
-        CreateAccount {
-            from: authority,
-            to: market_account,
-            lamports,
-            space: Market::SIZE as u64,
-            owner: &crate::ID
-        }.invoke_signed(&[signer])?;
+        create_market_account(authority, market_account, lamports).invoke_signed(&[signer])?;
 
         // Initialize market data
-        let mut market_data = Market::from_account_info_mut(market_account)?;
-        market_data.is_initialized = true;
-        market_data.market_id = market_id as u8;
+        let mut market_data = Market::init_from_account_info_mut(market_account)?;
+        market_data.is_initialized = 1;
+        market_data.market_id = market_id;
         market_data.market_symbol = market_symbol;
         market_data.oracle = Pubkey::default();
         market_data.collateral_mint = *collateral_mint.key(); // FIXED: Set actual mint
@@ -97,55 +343,361 @@ pub fn initialize_market(accounts: &[AccountInfo], instruction_data: &[u8]) -> P
         market_data.total_collateral = 0;
         market_data.unrealized_pnl = 0;
         market_data.authority = *authority.key();
+        market_data.seed_authority = *authority.key();
         market_data.bump = market_bump;
         market_data.collateral_bump = collateral_bump;
+        market_data.status = MarketStatus::Active as u8;
+        market_data.max_publish_gap = max_publish_gap;
+        market_data.feed_id = feed_id;
+        market_data.withdraw_delay = withdraw_delay;
+        market_data.close_fee_discount = close_fee_discount;
+        market_data.min_margin = min_margin;
+        market_data.cumulative_funding_index = 0;
+        market_data.funding_history = [FundingSample::default(); FUNDING_HISTORY_LEN];
+        market_data.funding_history_len = 0;
+        market_data.funding_history_next = 0;
+        market_data.max_open_interest = max_open_interest;
+        market_data.tick_size = tick_size;
+        market_data.allow_ema_fallback = u8::from(allow_ema_fallback);
+        market_data.collateral_decimals = Mint::from_account_info(collateral_mint)?.decimals();
+        market_data.locked_margin = 0;
+        market_data.max_slot_lag = max_slot_lag;
+        market_data.liquidation_fee = liquidation_fee;
+        market_data.bad_debt = 0;
+        market_data.insurance_vault = *insurance_vault.key();
+        market_data.insurance_bump = insurance_bump;
+        market_data.max_funding_rate = max_funding_rate;
+        market_data.funding_rate_updated_at = 0;
+        market_data.max_conf_bps = max_conf_bps;
+        market_data.use_ema = u8::from(use_ema);
+        market_data.max_price_age = max_price_age;
+        market_data.min_verification_level = min_verification_level;
+        market_data.oracle_kind = oracle_kind;
 
         println!("Market Account Initialized!");
     } else {
-        return Err(ProgramError::AccountAlreadyInitialized);
+        // The market exists but a vault never finished initializing: retry
+        // the vaults only. The market keeps the parameters it was created with.
+        let market_data = Market::load_initialized_mut(market_account)?;
+        if market_data.seed_authority != *authority.key()
+            || market_data.collateral_mint != *collateral_mint.key()
+            || market_data.collateral_vault != *collateral_vault.key()
+            || market_data.insurance_vault != *insurance_vault.key()
+        {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        println!("Retrying vault initialization");
     }
-    
-    if collateral_vault.data_is_empty() {
-        println!("Initializing Collateral Vault!");
 
-        // Step 1: Create the account with system program
-        let token_account_lamports = Rent::get()?.minimum_balance(165); // Token account size
+    if collateral_state != VaultState::Initialized {
+        println!("Creating Collateral Vault!");
 
-        let collateral_id_bytes = market_id.to_le_bytes();
         let collateral_bump_ref = &[collateral_bump];
         let vault_seeds = seeds!(
             b"collateral_vault",
-            collateral_mint.key().as_ref(),
-            &collateral_id_bytes,
+            market_account.key().as_ref(),
             collateral_bump_ref
         );
-        let vault_signer = Signer::from(&vault_seeds);
-
-        CreateAccount {
-            from: authority,
-            to: collateral_vault,
-            lamports: token_account_lamports,
-            space: 165, // Token account size
-            owner: token_program.key(), // Owned by token program!
-        }.invoke_signed(&[vault_signer])?;
-
-        // Step 2: Initialize as token account owned by market PDA
-        InitializeAccount3 {
-            account: collateral_vault,
-            mint: collateral_mint,
-            owner: &market_account_pda, // Market PDA owns the vault!
-        }.invoke()?;
+        initialize_vault(
+            authority,
+            collateral_vault,
+            collateral_mint,
+            token_program,
+            &market_account_pda,
+            collateral_state,
+            Signer::from(&vault_seeds)
+        )?;
 
         println!("Collateral Vault Initialized!");
+    }
+
+    if insurance_state != VaultState::Initialized {
+        println!("Creating Insurance Vault!");
+
+        let insurance_bump_ref = &[insurance_bump];
+        let insurance_seeds = seeds!(
+            b"insurance_vault",
+            market_account.key().as_ref(),
+            insurance_bump_ref
+        );
+        initialize_vault(
+            authority,
+            insurance_vault,
+            collateral_mint,
+            token_program,
+            &market_account_pda,
+            insurance_state,
+            Signer::from(&insurance_seeds)
+        )?;
+
+        println!("Insurance Vault Initialized!");
+    }
+
+    Ok(())
+}
+
+/// Creates a vault PDA as an SPL token account for the collateral mint, owned
+/// by the market PDA. The vault PDA only signs its own creation, the market PDA
+/// is what signs transfers out.
+fn initialize_vault(
+    authority: &AccountInfo,
+    vault: &AccountInfo,
+    collateral_mint: &AccountInfo,
+    token_program: &AccountInfo,
+    market_account_pda: &Pubkey,
+    state: VaultState,
+    vault_signer: Signer
+) -> ProgramResult {
+    if state == VaultState::Missing {
+        // Step 1: Create the account with system program
+        let token_account_lamports = Rent::get()?.minimum_balance(TokenAccount::LEN);
+
+        create_vault_account(authority, vault, token_program, token_account_lamports).invoke_signed(&[vault_signer])?;
+    }
+
+    // Step 2: Initialize as token account owned by market PDA. Kept separate
+    // from step 1 so a vault created without it can be retried.
+    initialize_vault_account(vault, collateral_mint, market_account_pda).invoke()
+}
+
+/// The market account, paid for by the authority and owned by this program.
+fn create_market_account<'a>(authority: &'a AccountInfo, market_account: &'a AccountInfo, lamports: u64) -> CreateAccount<'a> {
+    CreateAccount {
+        from: authority,
+        to: market_account,
+        lamports,
+        space: Market::SIZE as u64,
+        owner: &crate::ID
+    }
+}
+
+/// A vault account, paid for by the authority and owned by the token program.
+fn create_vault_account<'a>(
+    authority: &'a AccountInfo,
+    vault: &'a AccountInfo,
+    token_program: &'a AccountInfo,
+    lamports: u64
+) -> CreateAccount<'a> {
+    CreateAccount {
+        from: authority,
+        to: vault,
+        lamports,
+        space: TokenAccount::LEN as u64,
+        owner: token_program.key(), // Owned by token program!
+    }
+}
+
+/// Initializes a vault for the collateral mint with the market PDA as its owner.
+fn initialize_vault_account<'a>(
+    vault: &'a AccountInfo,
+    collateral_mint: &'a AccountInfo,
+    market_account_pda: &'a Pubkey
+) -> InitializeAccount3<'a> {
+    InitializeAccount3 {
+        account: vault,
+        mint: collateral_mint,
+        owner: market_account_pda, // Market PDA owns the vault!
+    }
+}
+
+/// How far a vault got through creation and initialization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VaultState {
+    Missing, // Not created yet
+    Uninitialized, // Created for the token program, InitializeAccount3 never landed
+    Initialized,
+}
+
+fn vault_state(vault: &AccountInfo) -> Result<VaultState, ProgramError> {
+    if vault.data_is_empty() {
+        return Ok(VaultState::Missing);
+    }
+
+    let vault_ta = TokenAccount::from_account_info(vault)?;
+    if vault_ta.is_initialized() {
+        Ok(VaultState::Initialized)
     } else {
-        return Err(ProgramError::AccountAlreadyInitialized);
+        Ok(VaultState::Uninitialized)
     }
+}
 
+/// The collateral mint must be an initialized SPL token mint priced in dollars.
+fn check_collateral_mint(collateral_mint: &AccountInfo) -> ProgramResult {
+    check_collateral_priced(collateral_mint.key())?;
+    if !Mint::from_account_info(collateral_mint)?.is_initialized() {
+        return Err(ProgramError::UninitializedAccount);
+    }
     Ok(())
 }
 
 // =========================== TESTING initialize_market ===========================
 
+#[cfg(test)]
+mod tests {
+    use pinocchio::{program_error::ProgramError, pubkey::Pubkey};
+
+    use super::initialize_market;
+    use crate::states::{with_account_infos, TestAccount};
+
+    const AUTHORITY_LAMPORTS: u64 = 1_000_000_000;
+
+    fn accounts(authority_signs: bool, token_program: Pubkey) -> [TestAccount<'static>; 7] {
+        let system_account = |key: u8, lamports: u64, is_signer: bool| TestAccount {
+            key: [key; 32],
+            owner: pinocchio_system::ID,
+            is_signer,
+            is_writable: true,
+            lamports,
+            data: &[],
+        };
+
+        [
+            system_account(1, AUTHORITY_LAMPORTS, authority_signs), // authority
+            system_account(2, 0, false), // collateral_mint
+            system_account(3, 0, false), // market_account
+            system_account(4, 0, false), // collateral_vault
+            system_account(5, 0, false), // insurance_vault
+            system_account(0, 0, false), // system_program
+            TestAccount { key: token_program, ..system_account(0, 0, false) },
+        ]
+    }
+
+    #[test]
+    fn test_authority_pays_for_accounts_the_market_pda_owns() {
+        use super::{create_market_account, create_vault_account, initialize_vault_account};
+        use crate::states::Market;
+        use pinocchio_token::state::TokenAccount;
+
+        const MARKET_PDA: Pubkey = [9u8; 32];
+
+        with_account_infos(&accounts(true, pinocchio_token::ID), |accounts| {
+            let [authority, collateral_mint, market_account, collateral_vault, _, _, token_program] = accounts else { unreachable!() };
+
+            let market = create_market_account(authority, market_account, 1_000);
+            assert_eq!(*market.from.key(), *authority.key());
+            assert_eq!(*market.to.key(), *market_account.key());
+            assert_eq!((market.lamports, market.space), (1_000, Market::SIZE as u64));
+            assert_eq!(*market.owner, crate::ID);
+
+            let vault = create_vault_account(authority, collateral_vault, token_program, 2_000);
+            assert_eq!(*vault.from.key(), *authority.key());
+            assert_eq!(*vault.to.key(), *collateral_vault.key());
+            assert_eq!((vault.lamports, vault.space), (2_000, TokenAccount::LEN as u64));
+            assert_eq!(*vault.owner, pinocchio_token::ID);
+
+            // The vault PDA only signs its creation, the market PDA owns the tokens
+            let init = initialize_vault_account(collateral_vault, collateral_mint, &MARKET_PDA);
+            assert_eq!(*init.account.key(), *collateral_vault.key());
+            assert_eq!(*init.mint.key(), *collateral_mint.key());
+            assert_eq!(*init.owner, MARKET_PDA);
+        });
+    }
+
+    #[test]
+    fn test_non_signer_authority_rejected_before_creation() {
+        with_account_infos(&accounts(false, pinocchio_token::ID), |accounts| {
+            assert_eq!(initialize_market(accounts, &[]), Err(ProgramError::MissingRequiredSignature));
+
+            // Nothing was created or paid for
+            assert!(accounts[2].data_is_empty());
+            assert!(accounts[3].data_is_empty());
+            assert!(accounts[4].data_is_empty());
+            assert_eq!(accounts[0].lamports(), AUTHORITY_LAMPORTS);
+        });
+    }
+
+    #[test]
+    fn test_max_leverage_bounds() {
+        use super::InitializeMarketArgs;
+
+        let args = |max_leverage: u64| {
+            let mut data = vec![0u8; InitializeMarketArgs::LEN];
+            data[24..32].copy_from_slice(&max_leverage.to_le_bytes());
+            InitializeMarketArgs::try_from(data.as_slice())
+        };
+
+        assert_eq!(args(20).unwrap().max_leverage, 20);
+        assert_eq!(args(InitializeMarketArgs::MAX_LEVERAGE).unwrap().max_leverage, InitializeMarketArgs::MAX_LEVERAGE);
+        assert_eq!(args(u64::MAX), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(args(InitializeMarketArgs::MAX_LEVERAGE + 1), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(args(0), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_feed_id_read_from_hex_and_defaults_to_sol() {
+        use super::InitializeMarketArgs;
+        use crate::instructions::{PriceUpdateV2, BTC_USD_FEED_ID, SOL_USD_FEED_ID};
+
+        let mut data = vec![0u8; InitializeMarketArgs::LEN_WITH_MAX_FUNDING_RATE];
+        data[24..32].copy_from_slice(&20u64.to_le_bytes());
+        let sol = PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap();
+        assert_eq!(InitializeMarketArgs::try_from(data.as_slice()).unwrap().feed_id, sol);
+
+        data.extend_from_slice(BTC_USD_FEED_ID.as_bytes());
+        let btc = PriceUpdateV2::get_feed_id_from_hex(BTC_USD_FEED_ID).unwrap();
+        assert_eq!(InitializeMarketArgs::try_from(data.as_slice()).unwrap().feed_id, btc);
+
+        // Not hex
+        let len = data.len();
+        data[len - 1] = b'z';
+        assert_eq!(InitializeMarketArgs::try_from(data.as_slice()), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_wrong_token_program_rejected() {
+        with_account_infos(&accounts(true, [9u8; 32]), |accounts| {
+            assert_eq!(initialize_market(accounts, &[]), Err(ProgramError::IncorrectProgramId));
+        });
+    }
+
+    fn token_account(key: u8, data: &[u8]) -> TestAccount<'_> {
+        TestAccount { key: [key; 32], owner: pinocchio_token::ID, is_signer: false, is_writable: true, lamports: 0, data }
+    }
+
+    #[test]
+    fn test_vault_left_uninitialized_can_be_retried() {
+        use super::{vault_state, VaultState};
+        use pinocchio_token::state::TokenAccount;
+
+        // CreateAccount landed but InitializeAccount3 didn't
+        let created = [0u8; TokenAccount::LEN];
+        // What a successful retry leaves behind: the state byte set to Initialized
+        let mut initialized = [0u8; TokenAccount::LEN];
+        initialized[108] = 1;
+
+        let missing = TestAccount { data: &[], owner: pinocchio_system::ID, ..token_account(4, &[]) };
+        with_account_infos(&[missing, token_account(5, &created), token_account(6, &initialized)], |accounts| {
+            assert_eq!(vault_state(&accounts[0]), Ok(VaultState::Missing));
+            assert_eq!(vault_state(&accounts[1]), Ok(VaultState::Uninitialized));
+            assert_eq!(vault_state(&accounts[2]), Ok(VaultState::Initialized));
+        });
+    }
+
+    #[test]
+    fn test_collateral_mint_validated() {
+        use super::check_collateral_mint;
+        use pinocchio_token::state::Mint;
+
+        let uninitialized = [0u8; Mint::LEN];
+        let mut initialized = [0u8; Mint::LEN];
+        initialized[44] = 6; // decimals
+        initialized[45] = 1; // is_initialized
+
+        let foreign = TestAccount { owner: [9u8; 32], ..token_account(7, &initialized) };
+        with_account_infos(&[token_account(3, &initialized), token_account(5, &uninitialized), foreign], |accounts| {
+            assert!(check_collateral_mint(&accounts[0]).is_ok());
+            assert_eq!(check_collateral_mint(&accounts[1]), Err(ProgramError::UninitializedAccount));
+            assert_eq!(check_collateral_mint(&accounts[2]), Err(ProgramError::InvalidAccountOwner));
+        });
+
+        // wSOL isn't priced in dollars, see check_collateral_priced
+        let native = TestAccount { key: crate::instructions::NATIVE_MINT, ..token_account(0, &initialized) };
+        with_account_infos(&[native], |accounts| {
+            assert_eq!(check_collateral_mint(&accounts[0]), Err(crate::errors::PerpError::UnpricedCollateral.into()));
+        });
+    }
+}
+
 // #[cfg(test)]
 // mod testing {
 //     use mollusk_svm::{Mollusk, result::Check, program};
@@ -173,7 +725,7 @@ pub fn initialize_market(accounts: &[AccountInfo], instruction_data: &[u8]) -> P
 //         );
 
 //         let (collateral_vault_pda, collateral_bump) = Pubkey::find_program_address(
-//             &[b"collateral_vault", COLLATERAL_MINT.as_ref(), MARKET_ID.to_le_bytes().as_ref()],
+//             &[b"collateral_vault", market_account_pda.as_ref()],
 //             &PROGRAM_ID
 //         );
 
@@ -1,16 +1,17 @@
-use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, pubkey::Pubkey, sysvars::{rent::Rent, Sysvar}, *};
+use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, sysvars::{rent::Rent, Sysvar}, *};
 use pinocchio_system::instructions::CreateAccount;
-use crate::states::UserAccount;
+use crate::{instructions::{check_writable, not_enough_accounts}, states::UserAccount};
 
 pub fn initialize_user_account(accounts: &[AccountInfo]) -> ProgramResult {
 
-    let [user, user_account, system_program] = accounts else {
-        return Err(ProgramError::InvalidAccountData);
+    let [user, user_account, _system_program] = accounts else {
+        return Err(not_enough_accounts(3, accounts.len()));
     };
 
     if !user.is_signer() {
         return Err(ProgramError::InvalidAccountData);
     };
+    check_writable(&[user, user_account])?;
 
     let (user_account_pda, bump) = pubkey::find_program_address(
         &[b"user_account", user.key().as_ref()],
@@ -43,11 +44,9 @@ pub fn initialize_user_account(accounts: &[AccountInfo]) -> ProgramResult {
             owner: &crate::ID
         }.invoke_signed(&[signer_seeds])?;
 
-        let mut user_account_info_mut = UserAccount::from_account_info_mut(user_account)?;
+        let mut user_account_info_mut = UserAccount::init_from_account_info_mut(user_account)?;
 
-        user_account_info_mut.owner = *user.key();
-        user_account_info_mut.margin_balance = 0;
-        user_account_info_mut.open_positions = [Pubkey::default(); 10];
+        user_account_info_mut.initialize(*user.key());
 
         msg!("User account initialized");
     } else {
@@ -0,0 +1,564 @@
+use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, sysvars::clock::Clock, *};
+use pinocchio_token::instructions::TransferChecked;
+use pinocchio_token::state::{Mint, TokenAccount};
+
+use crate::{
+    errors::PerpError,
+    math::{self, RoundingMode},
+    instructions::{assert_vault_solvent, check_collateral_vault, check_vault_covers, check_market_accounts, check_liquidation_grace, check_writable, close_liquidated_position, get_price_for_feed, not_enough_accounts, record_oracle_snapshot, settle_liquidation, settle_pnl, OraclePrice},
+    states::{Market, Position, UserAccount},
+};
+
+/// Instruction data: [market_id: u64]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LiquidateArgs {
+    pub market_id: u64,
+}
+
+impl LiquidateArgs {
+    pub const LEN: usize = 8;
+}
+
+impl TryFrom<&[u8]> for LiquidateArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let market_id = u64::from_le_bytes(
+            data[0..8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+
+        Ok(Self { market_id })
+    }
+}
+
+/// Liquidates an undercollateralized position directly, without going through
+/// the queue. The signer is the liquidator, not the position owner. The grace
+/// rule is the queue's, see check_direct_liquidation. The liquidator is paid
+/// the market's liquidation_fee (bps) of the margin left after the position's
+/// losses, the rest stays in the collateral vault. Losses beyond the margin
+/// are covered from the insurance vault while it has funds, only what it
+/// can't cover is recorded as bad debt.
+pub fn process_liquidate(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+
+    let [
+        liquidator, // Keeper liquidating the position (must sign transaction)
+        market_authority, // Authority the market was created by, its PDA seed
+        collateral_mint, // Token mint for collateral (e.g., USDC)
+        market_account, // Market the position trades on, owns the vault
+        user_account, // Position owner's trading account
+        collateral_vault, // Vault holding all collateral
+        insurance_vault, // Market's insurance fund, covers losses beyond the position's margin
+        liquidator_token_account, // Liquidator's token account credited with the reward
+        user_position_account, // Position to liquidate
+        pyth_price_account, // Pyth oracle for price feeds
+        token_program,
+        clock_sysvar, // Solana clock for timestamps
+        optional @ .. // [oracle_snapshot]: records the settlement oracle state if passed
+        ] = accounts else {
+        return Err(not_enough_accounts(12, accounts.len()));
+    };
+
+    // ---- Basic checks ----
+    if !liquidator.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[market_account, user_account, collateral_vault, insurance_vault, liquidator_token_account, user_position_account])?;
+    if *token_program.key() != pinocchio_token::ID {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if !user_position_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    // ---- Parse instruction ----
+    let LiquidateArgs { market_id } = LiquidateArgs::try_from(instruction_data)?;
+
+    // ---- Derive & check PDAs ----
+    let (market_account_pda, market_bump) = pubkey::find_program_address(
+        &[b"market_account", market_authority.key().as_ref(), market_id.to_le_bytes().as_ref()],
+        &crate::ID
+    );
+    if *market_account.key() != market_account_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let (collateral_vault_pda, _collateral_bump) = pubkey::find_program_address(
+        &[b"collateral_vault", market_account.key().as_ref()],
+        &crate::ID
+    );
+
+    // ---- Validate market ----
+    let (oracle, maintenance_margin, collateral_decimals, liquidation_fee) = {
+        let market = Market::load_initialized_mut(market_account)?;
+        check_collateral_vault(&market, &collateral_vault_pda, collateral_vault.key())?;
+        check_market_accounts(&market, market_authority.key(), collateral_vault.key(), collateral_mint.key())?;
+        if market.insurance_vault != *insurance_vault.key() {
+            return Err(PerpError::VaultMismatch.into());
+        }
+        (
+            market.oracle_config(),
+            market.maintenance_margin,
+            market.collateral_decimals,
+            market.liquidation_fee,
+        )
+    };
+
+    // ---- Token account validations ----
+    let vault_balance = {
+        let liquidator_ta = TokenAccount::from_account_info(liquidator_token_account)?;
+        if *liquidator_ta.mint() != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let vault_ta = TokenAccount::from_account_info(collateral_vault)?;
+        if *vault_ta.mint() != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        vault_ta.amount()
+    };
+    let decimals = Mint::from_account_info(collateral_mint)?.decimals();
+
+    // ---- Sysvars / Oracle ----
+    let clock = Clock::from_account_info(clock_sysvar)?;
+    let oracle_price = get_price_for_feed(pyth_price_account, &clock, &oracle)?;
+    let current_price = oracle_price.price;
+
+    // Funding accrued since the position's last settlement counts towards its equity, as on close
+    let funding_index = {
+        let mut market = Market::from_account_info_mut(market_account)?;
+        market.accrue_funding(current_price, clock.unix_timestamp)?;
+        market.cumulative_funding_index
+    };
+
+    // ---- Check the position is underwater ----
+    let (size, settled) = {
+        let position = Position::from_account_info(user_position_account)?;
+        if position.market != *market_account.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        check_direct_liquidation(&position, &oracle_price, funding_index, maintenance_margin, collateral_decimals)?;
+
+        let (user_account_pda, _user_bump) = pubkey::find_program_address(
+            &[b"user_account", position.user.as_ref()],
+            &crate::ID
+        );
+        if *user_account.key() != user_account_pda {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // Funding still owed either way is settled with the PnL
+        let settlement = math::checked_add(
+            position.unrealized_pnl_at(current_price, collateral_decimals)?,
+            position.unsettled_funding(funding_index, collateral_decimals)?
+        )?;
+        (position.size, settle_pnl(position.margin, settlement))
+    };
+
+    let reward = liquidator_reward(settled.equity, liquidation_fee)?;
+    check_vault_covers(reward, vault_balance)?;
+    let event = settle_liquidation(
+        *user_position_account.key(),
+        *liquidator.key(),
+        size,
+        current_price,
+        settled.equity,
+        reward
+    );
+
+    let market_id_bytes = market_id.to_le_bytes();
+    let bump_ref = &[market_bump];
+    let seeds = seeds!(
+        b"market_account",
+        market_authority.key().as_ref(),
+        &market_id_bytes,
+        bump_ref
+    );
+
+    // ---- Transfer reward from vault -> liquidator (signed by market PDA) ----
+    if let Some(transfer) = reward_transfer(collateral_vault, liquidator_token_account, market_account, collateral_mint, reward, decimals) {
+        transfer.invoke_signed(&[Signer::from(&seeds)])?;
+    }
+
+    // ---- Update accounting ----
+    {
+        let mut position = Position::from_account_info_mut(user_position_account)?;
+        let mut user_account_data = UserAccount::from_account_info_mut(user_account)?;
+        let mut market = Market::from_account_info_mut(market_account)?;
+        close_liquidated_position(&mut position, &mut user_account_data, &mut market, clock.unix_timestamp)?;
+        market.debit_collateral(reward);
+        // The rest of the remaining equity is kept as the liquidation penalty
+        market.collect_fees(settled.equity.saturating_sub(reward))?;
+    }
+    let cover = cover_deficit(market_account, collateral_mint, collateral_vault, insurance_vault, settled.deficit)?;
+    assert_vault_solvent(&*Market::from_account_info(market_account)?, &*TokenAccount::from_account_info(collateral_vault)?)?;
+
+    if let Some(oracle_snapshot) = optional.first() {
+        record_oracle_snapshot(oracle_snapshot, user_position_account.key(), pyth_price_account, oracle.oracle_kind, clock.unix_timestamp)?;
+    }
+
+    event.emit();
+
+    println!("Position liquidated");
+    println!("Liquidation Price: {}", current_price);
+    println!("Liquidator Reward: {}", reward);
+    println!("Covered by Insurance: {}", cover.insurance_draw);
+
+    Ok(())
+}
+
+/// How a loss beyond a closed position's margin is absorbed, see cover_deficit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DeficitCover {
+    pub insurance_draw: u64, // Moved from the insurance vault into the collateral vault
+    pub bad_debt: u64, // What the insurance fund couldn't cover
+}
+
+/// Takes the deficit from the insurance fund as far as its balance goes, the rest is bad debt.
+pub(crate) fn split_deficit(deficit: u64, insurance_balance: u64) -> DeficitCover {
+    let insurance_draw = deficit.min(insurance_balance);
+    DeficitCover { insurance_draw, bad_debt: deficit - insurance_draw }
+}
+
+/// Books a deficit split by split_deficit: the draw lands in the collateral
+/// vault, the rest is left for ADL to socialize.
+pub(crate) fn apply_deficit_cover(market: &mut Market, cover: DeficitCover) -> ProgramResult {
+    market.credit_collateral(cover.insurance_draw)?;
+    market.record_bad_debt(cover.bad_debt)
+}
+
+/// Covers the loss of a position that closed below zero equity the same way
+/// whichever instruction closed it: the insurance vault pays what it can into
+/// the collateral vault and the rest is recorded as bad debt. The market
+/// account must not be borrowed by the caller, it signs the transfer.
+pub(crate) fn cover_deficit(
+    market_account: &AccountInfo,
+    collateral_mint: &AccountInfo,
+    collateral_vault: &AccountInfo,
+    insurance_vault: &AccountInfo,
+    deficit: u64
+) -> Result<DeficitCover, ProgramError> {
+    let (cover, seed_authority, market_id, market_bump, decimals) = {
+        let market = Market::from_account_info(market_account)?;
+        if market.insurance_vault != *insurance_vault.key() {
+            return Err(PerpError::VaultMismatch.into());
+        }
+        let insurance_ta = TokenAccount::from_account_info(insurance_vault)?;
+        if *insurance_ta.mint() != market.collateral_mint {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        (
+            split_deficit(deficit, insurance_ta.amount()),
+            market.seed_authority,
+            market.market_id,
+            market.bump,
+            market.collateral_decimals,
+        )
+    };
+
+    // ---- Cover the shortfall insurance -> vault (signed by market PDA) ----
+    if cover.insurance_draw > 0 {
+        let market_id_bytes = market_id.to_le_bytes();
+        let bump_ref = &[market_bump];
+        let seeds = seeds!(
+            b"market_account",
+            seed_authority.as_ref(),
+            &market_id_bytes,
+            bump_ref
+        );
+
+        TransferChecked {
+            from: insurance_vault,
+            to: collateral_vault,
+            authority: market_account,
+            mint: collateral_mint,
+            amount: cover.insurance_draw,
+            decimals,
+        }.invoke_signed(&[Signer::from(&seeds)])?;
+    }
+
+    apply_deficit_cover(&mut *Market::from_account_info_mut(market_account)?, cover)?;
+    Ok(cover)
+}
+
+/// A position can be liquidated directly once it is underwater at `oracle_price`
+/// and was already marked underwater on an earlier oracle update, the same
+/// grace LiquidateFromQueue gives it.
+fn check_direct_liquidation(
+    position: &Position,
+    oracle_price: &OraclePrice,
+    funding_index: i128,
+    maintenance_margin: u64,
+    decimals: u8
+) -> ProgramResult {
+    if !position.is_open() || !position.is_liquidatable(oracle_price.price, funding_index, maintenance_margin, decimals)? {
+        return Err(PerpError::PositionNotLiquidatable.into());
+    }
+    check_liquidation_grace(position, oracle_price.publish_time)
+}
+
+/// The liquidator's cut of the margin left after losses, rounded down. A
+/// bankrupt position has nothing left and pays nothing.
+fn liquidator_reward(remaining_margin: u64, liquidation_fee_bps: u64) -> Result<u64, ProgramError> {
+    math::mul_div_u64(remaining_margin, liquidation_fee_bps.min(10_000), 10_000, RoundingMode::Down)
+}
+
+/// The vault -> liquidator transfer of `reward`, signed by the market PDA.
+/// A bankrupt position pays nothing, so no transfer is made.
+fn reward_transfer<'a>(
+    collateral_vault: &'a AccountInfo,
+    liquidator_token_account: &'a AccountInfo,
+    market_account: &'a AccountInfo,
+    collateral_mint: &'a AccountInfo,
+    reward: u64,
+    decimals: u8
+) -> Option<TransferChecked<'a>> {
+    (reward > 0).then_some(TransferChecked {
+        from: collateral_vault,
+        to: liquidator_token_account,
+        authority: market_account,
+        mint: collateral_mint,
+        amount: reward,
+        decimals,
+    })
+}
+
+// =========================== TESTING process_liquidate ===========================
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_deficit_cover, check_direct_liquidation, liquidator_reward, reward_transfer, split_deficit, DeficitCover};
+    use crate::{errors::PerpError, instructions::{check_vault_covers, close_liquidated_position, settle_liquidation, settle_pnl, OraclePrice}, math::PRICE_DECIMALS, states::{Market, Position, UserAccount}};
+
+    #[test]
+    fn test_liquidator_paid_from_remaining_margin() {
+        // 10 long at 100 with 100 margin, 500 bps maintenance: at 94, equity 40 under 47
+        let mut position = Position { size: 10, entry_price: 100, margin: 100, is_active: 1, ..Position::default() };
+        assert!(position.is_liquidatable(94, 0, 500, PRICE_DECIMALS).unwrap());
+
+        let settled = settle_pnl(position.margin, position.unrealized_pnl_at(94, PRICE_DECIMALS).unwrap());
+        let reward = liquidator_reward(settled.equity, 2_500).unwrap();
+        assert_eq!((settled.equity, reward), (40, 10));
+
+        // The liquidator's cut comes out of the penalty, the rest stays in the vault
+        let event = settle_liquidation([1u8; 32], [2u8; 32], position.size, 94, settled.equity, reward);
+        assert_eq!(event.penalty - event.liquidator_reward, 30);
+
+        let mut market = Market { open_interest_long: 10, total_collateral: 100, locked_margin: 100, ..Market::default() };
+        let mut user_account = UserAccount { discriminator: UserAccount::DISCRIMINATOR, owner: [3u8; 32], margin_balance: 100, open_positions: [[0u8; 32]; 10], deposit_time: 0, collateral_mint: [0u8; 32] };
+        close_liquidated_position(&mut position, &mut user_account, &mut market, 1_000).unwrap();
+        market.debit_collateral(reward);
+
+        assert!(!position.is_open());
+        assert_eq!((position.size, position.margin), (0, 0));
+        assert_eq!(user_account.margin_balance, 0);
+        assert_eq!((market.open_interest_long, market.locked_margin, market.total_collateral), (0, 0, 90));
+    }
+
+    #[test]
+    fn test_liquidation_settles_unsettled_funding() {
+        // 10 long at 100 with 100 margin owing 20 of funding: at 94 its 40 of equity is 20
+        let mut position = Position { size: 10, entry_price: 100, margin: 100, is_active: 1, ..Position::default() };
+        let funding_index = 2 * Market::FUNDING_INDEX_PRECISION;
+        position.track_underwater(true, 1_000);
+        let oracle_price = OraclePrice { price: 94, publish_time: 1_001, conf_bps: 0 };
+        assert_eq!(check_direct_liquidation(&position, &oracle_price, funding_index, 500, PRICE_DECIMALS), Ok(()));
+
+        let settlement = position.unrealized_pnl_at(94, PRICE_DECIMALS).unwrap()
+            + position.unsettled_funding(funding_index, PRICE_DECIMALS).unwrap();
+        let settled = settle_pnl(position.margin, settlement);
+        let reward = liquidator_reward(settled.equity, 2_500).unwrap();
+        assert_eq!((settled.equity, reward), (20, 5));
+
+        // A vault short of the reward fails the liquidation rather than paying less
+        assert_eq!(check_vault_covers(reward, 5), Ok(()));
+        assert_eq!(check_vault_covers(reward, 4), Err(PerpError::VaultInsolvent.into()));
+    }
+
+    #[test]
+    fn test_reward_transfer_credits_the_liquidator() {
+        use crate::states::{with_account_infos, TestAccount};
+
+        let account = |key: [u8; 32]| TestAccount { key, owner: pinocchio_token::ID, is_signer: false, is_writable: true, lamports: 0, data: &[] };
+        let accounts = [account([1u8; 32]), account([2u8; 32]), account([3u8; 32]), account([4u8; 32])];
+        with_account_infos(&accounts, |accounts| {
+            let [vault, liquidator_token_account, market_account, mint] = accounts else { unreachable!() };
+
+            // 40 of equity left at 2_500 bps: exactly 10 reaches the liquidator
+            let reward = liquidator_reward(40, 2_500).unwrap();
+            let transfer = reward_transfer(vault, liquidator_token_account, market_account, mint, reward, 6).unwrap();
+            assert_eq!(transfer.amount, 10);
+            assert_eq!(transfer.decimals, 6);
+            assert_eq!(*transfer.from.key(), [1u8; 32]);
+            assert_eq!(*transfer.to.key(), [2u8; 32]);
+            assert_eq!(*transfer.authority.key(), [3u8; 32]);
+
+            // Bankrupt: nothing is sent
+            assert!(reward_transfer(vault, liquidator_token_account, market_account, mint, liquidator_reward(0, 2_500).unwrap(), 6).is_none());
+        });
+    }
+
+    #[test]
+    fn test_bankrupt_position_pays_no_reward() {
+        // 10 long at 100 with 100 margin at 80: 200 of loss, nothing left to seize
+        let settled = settle_pnl(100, -200);
+        assert_eq!((settled.equity, settled.deficit), (0, 100));
+        assert_eq!(liquidator_reward(settled.equity, 500), Ok(0));
+
+        let event = settle_liquidation([1u8; 32], [2u8; 32], 10, 80, settled.equity, 0);
+        assert_eq!((event.penalty, event.liquidator_reward), (0, 0));
+    }
+
+    #[test]
+    fn test_ten_x_long_with_negative_equity_is_closed_as_bad_debt() {
+        // 10x long: 10 contracts at 100 on 100 margin. At 85 the loss is 150, equity -50
+        let mut position = Position { size: 10, entry_price: 100, margin: 100, is_active: 1, ..Position::default() };
+        assert_eq!(position.equity_at(85, PRICE_DECIMALS).unwrap(), -50);
+        assert!(position.is_liquidatable(85, 0, 500, PRICE_DECIMALS).unwrap());
+
+        let settled = settle_pnl(position.margin, position.unrealized_pnl_at(85, PRICE_DECIMALS).unwrap());
+        assert_eq!((settled.equity, settled.deficit), (0, 50));
+        assert_eq!(liquidator_reward(settled.equity, 500), Ok(0));
+
+        let mut market = Market { open_interest_long: 25, total_collateral: 300, locked_margin: 100, ..Market::default() };
+        let mut user_account = UserAccount { discriminator: UserAccount::DISCRIMINATOR, owner: [3u8; 32], margin_balance: 100, open_positions: [[0u8; 32]; 10], deposit_time: 0, collateral_mint: [0u8; 32] };
+        close_liquidated_position(&mut position, &mut user_account, &mut market, 1_000).unwrap();
+        // An empty insurance fund leaves all of it as bad debt
+        apply_deficit_cover(&mut market, split_deficit(settled.deficit, 0)).unwrap();
+
+        assert!(!position.is_open());
+        assert_eq!(market.open_interest_long, 15);
+        assert_eq!(market.bad_debt, 50);
+        // Nothing left the vault
+        assert_eq!(market.total_collateral, 300);
+    }
+
+    #[test]
+    fn test_insurance_fund_covers_shortfall_before_bad_debt() {
+        // Same 10x long at 85: 50 short, but the insurance vault only holds 30
+        let mut position = Position { size: 10, entry_price: 100, margin: 100, is_active: 1, ..Position::default() };
+        let settled = settle_pnl(position.margin, position.unrealized_pnl_at(85, PRICE_DECIMALS).unwrap());
+        let cover = split_deficit(settled.deficit, 30);
+        assert_eq!(cover, DeficitCover { insurance_draw: 30, bad_debt: 20 });
+
+        let mut market = Market { open_interest_long: 10, total_collateral: 300, locked_margin: 100, ..Market::default() };
+        let mut user_account = UserAccount { discriminator: UserAccount::DISCRIMINATOR, owner: [3u8; 32], margin_balance: 100, open_positions: [[0u8; 32]; 10], deposit_time: 0, collateral_mint: [0u8; 32] };
+        close_liquidated_position(&mut position, &mut user_account, &mut market, 1_000).unwrap();
+        apply_deficit_cover(&mut market, cover).unwrap();
+
+        assert_eq!(market.bad_debt, 20);
+        assert_eq!(market.total_collateral, 330);
+    }
+
+    #[test]
+    fn test_funded_insurance_absorbs_the_whole_deficit() {
+        assert_eq!(split_deficit(50, 80), DeficitCover { insurance_draw: 50, bad_debt: 0 });
+        assert_eq!(split_deficit(0, 80), DeficitCover { insurance_draw: 0, bad_debt: 0 });
+    }
+
+    #[test]
+    fn test_just_underwater_position_not_liquidated_directly() {
+        // 10 long at 100 with 10 margin is underwater at 94 against a 5% requirement
+        let mut position = Position { size: 10, entry_price: 100, margin: 10, is_active: 1, ..Position::default() };
+        let oracle_price = OraclePrice { price: 94, publish_time: 1_000, conf_bps: 0 };
+        let grace = Err(PerpError::LiquidationGracePeriod.into());
+
+        // Never marked, then marked on the very update the liquidation would use
+        assert_eq!(check_direct_liquidation(&position, &oracle_price, 0, 500, PRICE_DECIMALS), grace);
+        position.track_underwater(true, 1_000);
+        assert_eq!(check_direct_liquidation(&position, &oracle_price, 0, 500, PRICE_DECIMALS), grace);
+
+        // Still underwater on the next update
+        let next = OraclePrice { publish_time: 1_001, ..oracle_price };
+        assert_eq!(check_direct_liquidation(&position, &next, 0, 500, PRICE_DECIMALS), Ok(()));
+    }
+
+    #[test]
+    fn test_healthy_position_is_not_liquidatable() {
+        let position = Position { size: -10, entry_price: 100, margin: 100, is_active: 1, ..Position::default() };
+        assert!(!position.is_liquidatable(104, 0, 500, PRICE_DECIMALS).unwrap());
+    }
+
+    #[test]
+    fn test_liquidator_reward_bounds() {
+        assert_eq!(liquidator_reward(0, 1_000), Ok(0));
+        assert_eq!(liquidator_reward(999, 0), Ok(0));
+        // A reward over 100% is capped at the whole equity
+        assert_eq!(liquidator_reward(999, 20_000), Ok(999));
+        assert_eq!(liquidator_reward(999, 1_000), Ok(99));
+    }
+
+    #[test]
+    fn test_paused_market_still_liquidates() {
+        use mollusk_svm::result::Check;
+        use mollusk_svm_programs_token::token;
+        use solana_sdk::{account::Account, instruction::{AccountMeta, Instruction}, pubkey::Pubkey};
+        use crate::{instructions::{fixtures::{self, read, token_balance, AUTHORITY, COLLATERAL_MINT, MARKET_ID, NOW, PROGRAM_ID}, PerpetualInstructions}, states::MarketStatus};
+
+        let mollusk = fixtures::mollusk();
+        let keys = fixtures::keys();
+        let liquidator = Pubkey::new_unique();
+        let liquidator_token_account = Pubkey::new_unique();
+        let price_update = Pubkey::new_unique();
+
+        // 10 long at $150 on $100 of margin. At $142 the $80 loss leaves $20
+        // of equity against $71 of maintenance, and the mark scan has seen it underwater
+        let market = Market {
+            status: MarketStatus::Paused as u8,
+            open_interest_long: 10,
+            locked_margin: 100_000_000,
+            total_collateral: 101_500_000,
+            fees_collected: 1_500_000,
+            ..fixtures::market(&keys)
+        };
+        let position = Position { underwater_since: NOW - 10, ..fixtures::position(&keys, 10, 150_00000000, 100_000_000) };
+        let user_account = fixtures::user_account(&keys.user_position_account, 100_000_000);
+
+        let mut data = vec![PerpetualInstructions::Liquidate as u8];
+        data.extend_from_slice(&MARKET_ID.to_le_bytes());
+        let (clock_id, clock_account) = mollusk.sysvars.keyed_account_for_clock_sysvar();
+        let instruction = Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new(liquidator, true),                      // liquidator
+                AccountMeta::new_readonly(AUTHORITY, false),             // market_authority
+                AccountMeta::new_readonly(COLLATERAL_MINT, false),       // collateral_mint
+                AccountMeta::new(keys.market_account, false),            // market_account
+                AccountMeta::new(keys.user_account, false),              // user_account
+                AccountMeta::new(keys.collateral_vault, false),          // collateral_vault
+                AccountMeta::new(keys.insurance_vault, false),           // insurance_vault
+                AccountMeta::new(liquidator_token_account, false),       // liquidator_token_account
+                AccountMeta::new(keys.user_position_account, false),     // user_position_account
+                AccountMeta::new_readonly(price_update, false),          // pyth_price_account
+                AccountMeta::new_readonly(token::ID, false),             // token_program
+                AccountMeta::new_readonly(clock_id, false),              // clock_sysvar
+            ],
+            data,
+        };
+        let accounts: Vec<(Pubkey, Account)> = vec![
+            (liquidator, fixtures::wallet()),
+            (AUTHORITY, fixtures::wallet()),
+            (COLLATERAL_MINT, fixtures::mint(101_500_000)),
+            (keys.market_account, fixtures::program_account(bytemuck::bytes_of(&market))),
+            (keys.user_account, fixtures::program_account(bytemuck::bytes_of(&user_account))),
+            (keys.collateral_vault, fixtures::token_account(&keys.market_account, 101_500_000)),
+            (keys.insurance_vault, fixtures::token_account(&keys.market_account, 0)),
+            (liquidator_token_account, fixtures::token_account(&liquidator, 0)),
+            (keys.user_position_account, fixtures::program_account(bytemuck::bytes_of(&position))),
+            (price_update, fixtures::price_update(142_00000000)),
+            token::keyed_account(),
+            (clock_id, clock_account),
+        ];
+
+        let result = mollusk.process_and_validate_instruction(&instruction, &accounts, &[Check::success()]);
+
+        // A 25% cut of the $20 left, pause or not
+        assert_eq!(token_balance(result.get_account(&liquidator_token_account).unwrap()), 5_000_000);
+        let market: Market = read(result.get_account(&keys.market_account).unwrap());
+        assert_eq!((market.open_interest_long, market.locked_margin), (0, 0));
+        assert_eq!(market.total_collateral, 96_500_000);
+    }
+}
@@ -0,0 +1,263 @@
+use pinocchio::{account_info::AccountInfo, program_error::ProgramError, sysvars::clock::Clock, *};
+use pinocchio_token::state::TokenAccount;
+
+use crate::{
+    errors::PerpError,
+    events::PositionLiquidated,
+    math,
+    instructions::{assert_vault_solvent, check_writable, cover_deficit, get_price_for_feed, not_enough_accounts, record_oracle_snapshot, remove_open_interest, settle_pnl},
+    states::{LiquidationQueue, Market, Position, UserAccount},
+};
+
+/// Liquidates the most underwater position in the market's queue. The signer
+/// is the liquidator, not the position owner. The position's remaining
+/// equity stays in the collateral vault, losses beyond its margin are covered
+/// like any other close's, see cover_deficit. A position is only liquidated
+/// once an oracle update newer than the one it was marked at still finds it
+/// underwater.
+pub fn process_liquidate_from_queue(accounts: &[AccountInfo]) -> ProgramResult {
+
+    let [
+        liquidator, // Keeper cranking the queue (must sign transaction)
+        market_account, // Market the position trades on, owns the vaults
+        liquidation_queue, // Queue PDA for the market
+        user_account, // Position owner's trading account
+        user_position_account, // Position at the head of the queue
+        pyth_price_account, // Pyth oracle for price feeds
+        clock_sysvar, // Solana clock for timestamps
+        collateral_mint, // Token mint for collateral (e.g., USDC)
+        collateral_vault, // Vault holding all collateral
+        insurance_vault, // Market's insurance fund, covers losses beyond the position's margin
+        token_program,
+        optional @ .. // [oracle_snapshot]: records the settlement oracle state if passed
+        ] = accounts else {
+        return Err(not_enough_accounts(11, accounts.len()));
+    };
+
+    if !liquidator.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[market_account, liquidation_queue, user_account, user_position_account, collateral_vault, insurance_vault])?;
+    if !market_account.is_owned_by(&crate::ID)
+        || !liquidation_queue.is_owned_by(&crate::ID)
+        || !user_position_account.is_owned_by(&crate::ID)
+    {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+    if *token_program.key() != pinocchio_token::ID {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut queue = LiquidationQueue::from_account_info_mut(liquidation_queue)?;
+    if queue.market != *market_account.key() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // Entries must be liquidated in health order
+    let Some(head) = queue.pop_front() else {
+        return Err(ProgramError::InvalidAccountData);
+    };
+    if head.position != *user_position_account.key() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // Scoped so the market is released before cover_deficit signs with it
+    let (oracle, clock, settled, event) = {
+        let mut market = Market::load_initialized_mut(market_account)?;
+        if market.collateral_vault != *collateral_vault.key() {
+            return Err(PerpError::VaultMismatch.into());
+        }
+        if market.collateral_mint != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let clock = Clock::from_account_info(clock_sysvar)?;
+        let oracle = market.oracle_config();
+        let oracle_price = get_price_for_feed(pyth_price_account, &clock, &oracle)?;
+        let current_price = oracle_price.price;
+        // Funding accrued since the position's last settlement counts towards its equity, as on close
+        market.accrue_funding(current_price, clock.unix_timestamp)?;
+        let funding_index = market.cumulative_funding_index;
+
+        let mut position = Position::from_account_info_mut(user_position_account)?;
+        if position.market != *market_account.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // The position may have recovered since it was marked: drop it and stop
+        if !position.is_open() || !position.is_liquidatable(current_price, funding_index, market.maintenance_margin, market.collateral_decimals)? {
+            println!("Position no longer liquidatable, removed from queue");
+            return Ok(());
+        }
+        check_liquidation_grace(&position, oracle_price.publish_time)?;
+
+        let (user_account_pda, _user_bump) = pubkey::find_program_address(
+            &[b"user_account", position.user.as_ref()],
+            &crate::ID
+        );
+        if *user_account.key() != user_account_pda {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        // ---- Close the position ----
+        // Funding still owed either way is settled with the PnL
+        let settlement = math::checked_add(
+            position.unrealized_pnl_at(current_price, market.collateral_decimals)?,
+            position.unsettled_funding(funding_index, market.collateral_decimals)?
+        )?;
+        let settled = settle_pnl(position.margin, settlement);
+        let event = settle_liquidation(
+            *user_position_account.key(),
+            *liquidator.key(),
+            position.size,
+            current_price,
+            settled.equity,
+            0
+        );
+
+        let mut user_account_data = UserAccount::from_account_info_mut(user_account)?;
+        close_liquidated_position(&mut position, &mut user_account_data, &mut market, clock.unix_timestamp)?;
+        // No reward is paid from the queue, the whole remaining equity is kept
+        market.collect_fees(settled.equity)?;
+        (oracle, clock, settled, event)
+    };
+
+    let cover = cover_deficit(market_account, collateral_mint, collateral_vault, insurance_vault, settled.deficit)?;
+    assert_vault_solvent(&*Market::from_account_info(market_account)?, &*TokenAccount::from_account_info(collateral_vault)?)?;
+
+    if let Some(oracle_snapshot) = optional.first() {
+        record_oracle_snapshot(oracle_snapshot, user_position_account.key(), pyth_price_account, oracle.oracle_kind, clock.unix_timestamp)?;
+    }
+
+    event.emit();
+
+    println!("Position liquidated");
+    println!("Liquidation Price: {}", event.settlement_price);
+    println!("Penalty: {}", event.penalty);
+    println!("Covered by Insurance: {}", cover.insurance_draw);
+
+    Ok(())
+}
+
+/// Rejects liquidating a position the mark scan hasn't seen underwater on an
+/// earlier oracle update than `publish_time`.
+pub(crate) fn check_liquidation_grace(position: &Position, publish_time: i64) -> ProgramResult {
+    if !position.liquidation_grace_elapsed(publish_time) {
+        return Err(PerpError::LiquidationGracePeriod.into());
+    }
+    Ok(())
+}
+
+/// Splits the liquidated position's equity, see settle_pnl. All of it is
+/// taken as the liquidation penalty: `liquidator_reward` of it goes to the
+/// liquidator and the rest stays in the vault.
+pub(crate) fn settle_liquidation(
+    position: pinocchio::pubkey::Pubkey,
+    liquidator: pinocchio::pubkey::Pubkey,
+    size_closed: i128,
+    settlement_price: u64,
+    equity: u64,
+    liquidator_reward: u64
+) -> PositionLiquidated {
+    PositionLiquidated {
+        position,
+        liquidator,
+        size_closed,
+        settlement_price,
+        penalty: equity,
+        insurance_contribution: 0,
+        owner_remainder: 0,
+        liquidator_reward,
+    }
+}
+
+/// Flattens a liquidated position and takes it out of the user's and the
+/// market's books. Losses beyond its margin are left to cover_deficit.
+pub(crate) fn close_liquidated_position(
+    position: &mut Position,
+    user_account: &mut UserAccount,
+    market: &mut Market,
+    now: i64
+) -> ProgramResult {
+    let size = position.size;
+    let margin = position.margin;
+
+    position.size = 0;
+    position.margin = 0;
+    position.unrealized_pnl = 0;
+    position.funding_payment = 0;
+    position.is_active = 0;
+    position.underwater_since = 0;
+    position.last_funding_settlement = now;
+
+    user_account.margin_balance = user_account.margin_balance.saturating_sub(margin);
+
+    remove_open_interest(market, size);
+    market.release_margin(margin);
+    Ok(())
+}
+
+// =========================== TESTING process_liquidate_from_queue ===========================
+
+#[cfg(test)]
+mod tests {
+    use super::{check_liquidation_grace, settle_liquidation};
+    use crate::{errors::PerpError, events::PositionLiquidated, instructions::{apply_deficit_cover, settle_pnl, split_deficit}, math::PRICE_DECIMALS, states::{Market, Position}};
+
+    fn underwater_position() -> Position {
+        // 10 long at 100 with 10 margin: -50 equity at 94 against a 5% maintenance requirement of 47
+        Position { size: 10, entry_price: 100, margin: 10, is_active: 1, ..Default::default() }
+    }
+
+    #[test]
+    fn test_just_underwater_position_not_liquidated() {
+        let mut position = underwater_position();
+        assert!(position.is_liquidatable(94, 0, 500, PRICE_DECIMALS).unwrap());
+
+        // Never marked
+        assert_eq!(check_liquidation_grace(&position, 1_000), Err(PerpError::LiquidationGracePeriod.into()));
+
+        // Marked on the same oracle update the liquidation would use
+        position.track_underwater(true, 1_000);
+        assert_eq!(check_liquidation_grace(&position, 1_000), Err(PerpError::LiquidationGracePeriod.into()));
+    }
+
+    #[test]
+    fn test_position_underwater_past_grace_liquidated() {
+        let mut position = underwater_position();
+        position.track_underwater(true, 1_000);
+        assert_eq!(check_liquidation_grace(&position, 1_001), Ok(()));
+
+        let settled = settle_pnl(position.margin, position.unrealized_pnl_at(94, PRICE_DECIMALS).unwrap());
+        let event = settle_liquidation([1u8; 32], [2u8; 32], position.size, 94, settled.equity, 0);
+        assert_eq!(event.size_closed, 10);
+    }
+
+    #[test]
+    fn test_liquidation_event_reconciles_with_margin_and_pnl() {
+        let (margin, realized_pnl) = (100u64, -60i128);
+        let settled = settle_pnl(margin, realized_pnl);
+        let event = settle_liquidation([1u8; 32], [2u8; 32], 10, 94, settled.equity, 0);
+
+        let decoded = PositionLiquidated::from_bytes(&event.to_bytes()).unwrap();
+        assert_eq!(decoded.size_closed, 10);
+        assert_eq!(decoded.settlement_price, 94);
+        assert_eq!(
+            (decoded.penalty + decoded.insurance_contribution + decoded.owner_remainder) as i128,
+            margin as i128 + realized_pnl
+        );
+    }
+
+    #[test]
+    fn test_bankrupt_liquidation_records_bad_debt() {
+        let settled = settle_pnl(100, -200);
+        let event = settle_liquidation([1u8; 32], [2u8; 32], 10, 80, settled.equity, 0);
+        assert_eq!(event.penalty + event.insurance_contribution + event.owner_remainder, 0);
+
+        // With no insurance left every shortfall lands in bad debt, which ADL reads
+        let mut market = Market::default();
+        apply_deficit_cover(&mut market, split_deficit(settled.deficit, 0)).unwrap();
+        apply_deficit_cover(&mut market, split_deficit(settled.deficit, 0)).unwrap();
+        assert_eq!(market.bad_debt, 200);
+        assert_eq!(market.total_collateral, 0);
+    }
+}
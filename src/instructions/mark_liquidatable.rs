@@ -0,0 +1,85 @@
+use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, sysvars::clock::Clock, *};
+
+use crate::{instructions::{check_writable, create_program_account, get_price_for_feed, not_enough_accounts}, states::{LiquidationQueue, Market, Position}};
+
+/// Scans the given positions and queues the underwater ones by health, dropping
+/// any that recovered. Each position remembers the oracle update it was first
+/// seen underwater at, which starts its liquidation grace period. Permissionless: the keeper only pays for the queue account.
+pub fn process_mark_liquidatable(accounts: &[AccountInfo]) -> ProgramResult {
+
+    let [
+        keeper, // Pays for the queue account on first use
+        market_account, // Market the positions trade on
+        liquidation_queue, // Queue PDA for the market
+        pyth_price_account, // Pyth oracle for price feeds
+        _system_program,
+        clock_sysvar, // Solana clock for timestamps
+        positions @ .. // Positions to scan (writable)
+        ] = accounts else {
+        return Err(not_enough_accounts(6, accounts.len()));
+    };
+
+    if !keeper.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[keeper, market_account, liquidation_queue])?;
+    if !market_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    let (queue_pda, queue_bump) = pubkey::find_program_address(
+        &[b"liquidation_queue", market_account.key().as_ref()],
+        &crate::ID
+    );
+    if *liquidation_queue.key() != queue_pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let clock = Clock::from_account_info(clock_sysvar)?;
+    let (oracle_price, funding_index, maintenance_margin, collateral_decimals) = {
+        let mut market = Market::load_initialized_mut(market_account)?;
+        let oracle_price = get_price_for_feed(pyth_price_account, &clock, &market.oracle_config())?;
+        // Unsettled funding counts towards equity, as it does when the position is liquidated
+        market.accrue_funding(oracle_price.price, clock.unix_timestamp)?;
+        (oracle_price, market.cumulative_funding_index, market.maintenance_margin, market.collateral_decimals)
+    };
+    let current_price = oracle_price.price;
+
+    if liquidation_queue.data_is_empty() {
+        let bump_ref = &[queue_bump];
+        let seeds = seeds!(
+            b"liquidation_queue",
+            market_account.key().as_ref(),
+            bump_ref
+        );
+        create_program_account(keeper, liquidation_queue, LiquidationQueue::SIZE, Signer::from(&seeds))?;
+    }
+
+    let mut queue = LiquidationQueue::from_account_info_mut(liquidation_queue)?;
+    queue.market = *market_account.key();
+
+    for position_account in positions {
+        if !position_account.is_owned_by(&crate::ID) {
+            return Err(ProgramError::InvalidAccountOwner);
+        }
+
+        check_writable(&[position_account])?;
+
+        let mut position = Position::from_account_info_mut(position_account)?;
+        if position.market != *market_account.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let underwater = position.is_open() && position.is_liquidatable(current_price, funding_index, maintenance_margin, collateral_decimals)?;
+        position.track_underwater(underwater, oracle_price.publish_time);
+        if underwater {
+            queue.upsert(*position_account.key(), position.health_bps(current_price, funding_index, maintenance_margin, collateral_decimals)?);
+        } else {
+            queue.remove(position_account.key());
+        }
+    }
+
+    println!("Liquidation queue length: {}", queue.len);
+
+    Ok(())
+}
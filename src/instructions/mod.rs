@@ -1,4 +1,6 @@
-use pinocchio::{program_error::ProgramError, *};
+use pinocchio::{account_info::{AccountInfo, RefMut}, log::sol_log, program_error::ProgramError, ProgramResult};
+
+use crate::states::Market;
 
 pub mod init_market;
 pub use init_market::*;
@@ -9,14 +11,93 @@ pub use init_user::*;
 pub mod pyth_price;
 pub use pyth_price::*;
 
+pub mod switchboard_price;
+pub use switchboard_price::*;
+
 pub mod open_position;
 pub use open_position::*;
 
+pub mod open_interest;
+pub(crate) use open_interest::*;
+
+pub mod close_position;
+pub use close_position::*;
+
+pub mod set_market_params;
+pub use set_market_params::*;
+
+pub mod withdraw_collateral;
+pub use withdraw_collateral::*;
+
+pub mod settle_funding;
+pub use settle_funding::*;
+
+pub mod mark_liquidatable;
+pub use mark_liquidatable::*;
+
+pub mod liquidate_from_queue;
+pub use liquidate_from_queue::*;
+
+pub mod get_withdrawable_margin;
+pub use get_withdrawable_margin::*;
+
+pub mod set_trading_halted;
+pub use set_trading_halted::*;
+
+pub mod set_delegate;
+pub use set_delegate::*;
+
+pub mod deposit_collateral;
+pub use deposit_collateral::*;
+
+pub mod liquidate;
+pub use liquidate::*;
+
+pub mod deposit_insurance;
+pub use deposit_insurance::*;
+
+pub mod adl;
+pub use adl::*;
+
+pub mod pause_market;
+pub use pause_market::*;
+
+pub mod update_max_leverage;
+pub use update_max_leverage::*;
+
+pub mod transfer_authority;
+pub use transfer_authority::*;
+
+pub mod close_market;
+pub use close_market::*;
+
+#[cfg(test)]
+pub(crate) mod fixtures;
+
 #[repr(u8)]
 pub enum PerpetualInstructions {
     InitializeMarket,
     InitializeUser,
-    OpenPosition
+    OpenPosition,
+    ClosePosition,
+    SetMarketParams,
+    WithdrawCollateral,
+    SettleFunding,
+    MarkLiquidatable,
+    LiquidateFromQueue,
+    GetWithdrawableMargin,
+    SetTradingHalted,
+    SetDelegate,
+    DepositCollateral,
+    Liquidate,
+    DepositInsurance,
+    Adl,
+    PauseMarket,
+    ResumeMarket,
+    UpdateMaxLeverage,
+    TransferMarketAuthority,
+    AcceptMarketAuthority,
+    CloseMarket
 }
 
 impl TryFrom<&u8> for PerpetualInstructions {
@@ -27,8 +108,127 @@ impl TryFrom<&u8> for PerpetualInstructions {
             0 => Ok(PerpetualInstructions::InitializeMarket),
             1 => Ok(PerpetualInstructions::InitializeUser),
             2 => Ok(PerpetualInstructions::OpenPosition),
+            3 => Ok(PerpetualInstructions::ClosePosition),
+            4 => Ok(PerpetualInstructions::SetMarketParams),
+            5 => Ok(PerpetualInstructions::WithdrawCollateral),
+            6 => Ok(PerpetualInstructions::SettleFunding),
+            7 => Ok(PerpetualInstructions::MarkLiquidatable),
+            8 => Ok(PerpetualInstructions::LiquidateFromQueue),
+            9 => Ok(PerpetualInstructions::GetWithdrawableMargin),
+            10 => Ok(PerpetualInstructions::SetTradingHalted),
+            11 => Ok(PerpetualInstructions::SetDelegate),
+            12 => Ok(PerpetualInstructions::DepositCollateral),
+            13 => Ok(PerpetualInstructions::Liquidate),
+            14 => Ok(PerpetualInstructions::DepositInsurance),
+            15 => Ok(PerpetualInstructions::Adl),
+            16 => Ok(PerpetualInstructions::PauseMarket),
+            17 => Ok(PerpetualInstructions::ResumeMarket),
+            18 => Ok(PerpetualInstructions::UpdateMaxLeverage),
+            19 => Ok(PerpetualInstructions::TransferMarketAuthority),
+            20 => Ok(PerpetualInstructions::AcceptMarketAuthority),
+            21 => Ok(PerpetualInstructions::CloseMarket),
             _ => Err(ProgramError::InvalidInstructionData)
 
         }
     }
-}
\ No newline at end of file
+}
+
+/// Rejects read-only accounts a handler is about to write, up front, rather
+/// than failing deep inside a write or CPI.
+pub(crate) fn check_writable(accounts: &[&AccountInfo]) -> ProgramResult {
+    if accounts.iter().any(|account| !account.is_writable()) {
+        return Err(ProgramError::Immutable);
+    }
+    Ok(())
+}
+
+/// Logs the expected and provided account counts for a handler whose account
+/// list didn't match, so a wrong client account list is obvious.
+pub(crate) fn not_enough_accounts(expected: usize, provided: usize) -> ProgramError {
+    sol_log(&format!("Expected {} accounts, got {}", expected, provided));
+    ProgramError::NotEnoughAccountKeys
+}
+
+/// Loads the market in `[authority, market_account]` for writing, once the
+/// authority has signed and matches the market's.
+pub(crate) fn load_market_as_authority<'a>(accounts: &'a [AccountInfo]) -> Result<RefMut<'a, Market>, ProgramError> {
+
+    let [authority, market_account] = accounts else {
+        return Err(not_enough_accounts(2, accounts.len()));
+    };
+
+    if !authority.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[market_account])?;
+    if !market_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    let market = Market::load_initialized_mut(market_account)?;
+    if market.authority != *authority.key() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(market)
+}
+
+/// Checks `market_account` is the market PDA for `market_id`. The seeds use
+/// the authority the market was created by, which may no longer be the
+/// current one.
+pub(crate) fn check_market_pda(market: &Market, market_account: &AccountInfo, market_id: u64) -> ProgramResult {
+    if !market_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+    let (market_account_pda, _market_bump) = pinocchio::pubkey::find_program_address(
+        &[b"market_account", market.seed_authority.as_ref(), market_id.to_le_bytes().as_ref()],
+        &crate::ID
+    );
+    if *market_account.key() != market_account_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::{account_info::AccountInfo, program_error::ProgramError, ProgramResult};
+
+    use crate::states::{with_account_infos, TestAccount};
+
+    #[test]
+    fn test_too_few_accounts_rejected_by_every_handler() {
+        type Handler = fn(&[AccountInfo], &[u8]) -> ProgramResult;
+        let handlers: [(&str, Handler); 22] = [
+            ("initialize_market", super::initialize_market),
+            ("initialize_user_account", |accounts, _| super::initialize_user_account(accounts)),
+            ("open_position", super::process_open_position),
+            ("close_position", super::process_close_position),
+            ("set_market_params", super::process_set_market_params),
+            ("withdraw_collateral", super::process_withdraw_collateral),
+            ("settle_funding", |accounts, _| super::process_settle_funding(accounts)),
+            ("mark_liquidatable", |accounts, _| super::process_mark_liquidatable(accounts)),
+            ("liquidate_from_queue", |accounts, _| super::process_liquidate_from_queue(accounts)),
+            ("get_withdrawable_margin", |accounts, _| super::process_get_withdrawable_margin(accounts)),
+            ("set_trading_halted", super::process_set_trading_halted),
+            ("set_delegate", super::process_set_delegate),
+            ("deposit_collateral", super::process_deposit_collateral),
+            ("liquidate", super::process_liquidate),
+            ("deposit_insurance", super::process_deposit_insurance),
+            ("adl", super::process_adl),
+            ("pause_market", super::process_pause_market),
+            ("resume_market", |accounts, _| super::process_resume_market(accounts)),
+            ("update_max_leverage", super::process_update_max_leverage),
+            ("transfer_authority", |accounts, _| super::process_transfer_authority(accounts)),
+            ("accept_authority", |accounts, _| super::process_accept_authority(accounts)),
+            ("close_market", super::process_close_market),
+        ];
+
+        let data = [0u8; 64];
+        let signer = TestAccount { key: [1u8; 32], owner: [0u8; 32], is_signer: true, is_writable: true, lamports: 0, data: &[] };
+        with_account_infos(&[signer], |accounts| {
+            for (name, handler) in handlers {
+                assert_eq!(handler(accounts, &data), Err(ProgramError::NotEnoughAccountKeys), "{}", name);
+            }
+        });
+    }
+}
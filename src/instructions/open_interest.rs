@@ -0,0 +1,61 @@
+use pinocchio::program_error::ProgramError;
+
+use crate::states::Market;
+
+/// Adds an opened `size` to its side's open interest. Longs are positive sizes,
+/// shorts negative.
+pub(crate) fn add_open_interest(market: &mut Market, size: i128) -> Result<(), ProgramError> {
+    let abs_size = u64::try_from(size.unsigned_abs()).map_err(|_| ProgramError::ArithmeticOverflow)?;
+
+    if size > 0 {
+        market.open_interest_long = market.open_interest_long
+            .checked_add(abs_size)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    } else {
+        market.open_interest_short = market.open_interest_short
+            .checked_add(abs_size)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+
+    Ok(())
+}
+
+/// Removes a closed, liquidated or deleveraged `size` from its side's open interest.
+pub(crate) fn remove_open_interest(market: &mut Market, size: i128) {
+    let abs_size = size.unsigned_abs() as u64;
+
+    if size > 0 {
+        market.open_interest_long = market.open_interest_long.saturating_sub(abs_size);
+    } else {
+        market.open_interest_short = market.open_interest_short.saturating_sub(abs_size);
+    }
+}
+
+// =========================== TESTING open interest ===========================
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::{add_open_interest, remove_open_interest};
+    use crate::states::Market;
+
+    #[test]
+    fn test_open_interest_tracks_each_side() {
+        let mut market = Market::default();
+        add_open_interest(&mut market, 10).unwrap();
+        add_open_interest(&mut market, -4).unwrap();
+        assert_eq!((market.open_interest_long, market.open_interest_short), (10, 4));
+
+        remove_open_interest(&mut market, 3);
+        remove_open_interest(&mut market, -10);
+        assert_eq!((market.open_interest_long, market.open_interest_short), (7, 0));
+    }
+
+    #[test]
+    fn test_open_interest_overflow_rejected() {
+        let mut market = Market { open_interest_long: u64::MAX, ..Market::default() };
+        assert_eq!(add_open_interest(&mut market, 1), Err(ProgramError::ArithmeticOverflow));
+        assert_eq!(add_open_interest(&mut market, -(u64::MAX as i128) - 1), Err(ProgramError::ArithmeticOverflow));
+    }
+}
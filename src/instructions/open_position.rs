@@ -1,61 +1,196 @@
-use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, pubkey::Pubkey, sysvars::{clock::Clock, rent::Rent, Sysvar}, *};
+use pinocchio::{account_info::{AccountInfo, Ref}, instruction::Signer, program_error::ProgramError, pubkey::Pubkey, sysvars::{clock::Clock, rent::Rent, Sysvar}, *};
 use pinocchio_system::instructions::CreateAccount;
-use pinocchio_token::instructions::TransferChecked;
-use pinocchio_token::state::TokenAccount;
+use pinocchio_token::instructions::{CloseAccount, InitializeAccount3, TransferChecked};
+use pinocchio_token::state::{Mint, TokenAccount};
+
+use crate::{errors::PerpError, events::{FeeBreakdown, PartialFill, PositionUpdated}, math::{self, RoundingMode}, instructions::{add_open_interest, check_delegation, check_oracle_slot_lag, check_symbol_matches_feed, check_trading_not_halted, check_writable, cover_deficit, get_price_for_feed, not_enough_accounts, remove_open_interest, settle_pnl, OraclePrice, PnlSettlement}, states::{Market, OracleKind, UserAccount, Position}};
+
+/// Instruction data: [market_id: u64][size: i128][margin_amount: u64]
+/// optionally followed by a limit entry [target_price: u64][post_only: u8]
+/// and then [auto_compound_funding: u8], [margin_in_quote: u8] and
+/// [close_size: u128]. A non-zero close_size flips the position: the existing
+/// position, which must be exactly that size, is closed and its PnL realized,
+/// then `size` and `margin_amount` open a fresh position on the other side.
+/// A trailing [allow_partial: u8] fills what fits under the market's open
+/// interest cap instead of rejecting the whole order. A final [wrap_sol: u8]
+/// funds a wSOL market from the signer's native SOL, see wrap_sol_and_deposit,
+/// and is refused until collateral is priced, see check_collateral_priced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpenPositionArgs {
+    pub market_id: u64,
+    pub size: i128,
+    pub margin_amount: u64,
+    pub target_price: u64,
+    pub post_only: bool,
+    pub auto_compound_funding: bool,
+    pub margin_in_quote: bool, // margin_amount is USD at 1e8 scale, converted to collateral one to one
+    pub close_size: u128, // Size of the existing position to close before opening, 0 when not flipping
+    pub allow_partial: bool, // Fill up to the open interest cap, margin scaled to the filled size
+    pub wrap_sol: bool, // Fund the transfer from native SOL through a temporary wSOL account
+}
+
+impl OpenPositionArgs {
+    pub const LEN: usize = 8 + 16 + 8;
+    pub const LEN_WITH_LIMIT: usize = Self::LEN + 8 + 1;
+    pub const LEN_WITH_FUNDING_OPTION: usize = Self::LEN_WITH_LIMIT + 1;
+    pub const LEN_WITH_QUOTE_MARGIN: usize = Self::LEN_WITH_FUNDING_OPTION + 1;
+    pub const LEN_WITH_FLIP: usize = Self::LEN_WITH_QUOTE_MARGIN + 16;
+    pub const LEN_WITH_PARTIAL: usize = Self::LEN_WITH_FLIP + 1;
+    pub const LEN_WITH_WRAP_SOL: usize = Self::LEN_WITH_PARTIAL + 1;
+}
+
+impl TryFrom<&[u8]> for OpenPositionArgs {
+    type Error = ProgramError;
 
-use crate::{instructions::get_sol_price_for_trading, states::{Market, UserAccount, Position}};
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let market_id = u64::from_le_bytes(
+            data[0..8].try_into()
+                .map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+        let size = i128::from_le_bytes(
+            data[8..24].try_into()
+                .map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+        let margin_amount = u64::from_le_bytes(
+            data[24..32].try_into()
+                .map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+
+        let (target_price, post_only) = if data.len() >= Self::LEN_WITH_LIMIT {
+            let target_price = u64::from_le_bytes(
+                data[32..40].try_into()
+                    .map_err(|_| ProgramError::InvalidInstructionData)?
+            );
+            (target_price, data[40] != 0)
+        } else {
+            (0, false)
+        };
+
+        let auto_compound_funding = data.len() >= Self::LEN_WITH_FUNDING_OPTION && data[41] != 0;
+        let margin_in_quote = data.len() >= Self::LEN_WITH_QUOTE_MARGIN && data[42] != 0;
+
+        let close_size = if data.len() >= Self::LEN_WITH_FLIP {
+            u128::from_le_bytes(
+                data[43..59].try_into()
+                    .map_err(|_| ProgramError::InvalidInstructionData)?
+            )
+        } else {
+            0
+        };
+
+        let allow_partial = data.len() >= Self::LEN_WITH_PARTIAL && data[59] != 0;
+        let wrap_sol = data.len() >= Self::LEN_WITH_WRAP_SOL && data[60] != 0;
+
+        Ok(Self {
+            market_id,
+            size,
+            margin_amount,
+            target_price,
+            post_only,
+            auto_compound_funding,
+            margin_in_quote,
+            close_size,
+            allow_partial,
+            wrap_sol,
+        })
+    }
+}
+
+/// The wrapped SOL mint.
+pub const NATIVE_MINT: Pubkey = pinocchio_pubkey::pubkey!("So11111111111111111111111111111111111111112");
 
 pub fn process_open_position(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
 
     let [
-        user,  // The trader (must sign transaction)
-        market_authority, // Authority that controls the market
+        user,  // The trader the position belongs to
+        market_authority, // Authority the market was created by, its PDA seed
         collateral_mint, // Token mint for collateral (e.g., USDC)
         user_mint, // User's token mint (must match collateral mint)
         market_account, // Stores market configuration
         user_account, // User's trading account
         collateral_vault, // Vault holding all collateral
-        user_token_account, // User's token account to debit
+        user_token_account, // User's token account to debit, or the signer's wSOL PDA when wrapping SOL
         user_position_account, // Account storing position data
         pyth_price_account, // Pyth oracle for price feeds
         system_program, 
         token_program,
-        clock_sysvar // Solana clock for timestamps
+        clock_sysvar, // Solana clock for timestamps
+        global_config, // Global config PDA, for the kill switch
+        authority, // Signs and pays: the user, or their delegate
+        delegation, // The user's delegation PDA, only read when a delegate signs
+        insurance_vault, // Market's insurance fund, covers a flipped-out position's losses beyond its margin
+        other_positions @ .. // The user's positions in other markets, to compute free margin
         ] = accounts else {
-        return Err(ProgramError::InvalidAccountData);
+        return Err(not_enough_accounts(17, accounts.len()));
     };
 
     // ---- Basic checks ----
-    if !user.is_signer() {
+    if !authority.is_signer() {
         return Err(ProgramError::MissingRequiredSignature);
     }
+    if authority.key() != user.key() {
+        let (delegation_pda, _delegation_bump) = pubkey::find_program_address(
+            &[b"delegation", user.key().as_ref()],
+            &crate::ID
+        );
+        if *delegation.key() != delegation_pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        check_delegation(delegation, user.key(), authority.key())?;
+    }
+    check_writable(&[authority, market_account, user_account, collateral_vault, user_token_account, user_position_account, insurance_vault])?;
     if *system_program.key() != pinocchio_system::ID {
         return Err(ProgramError::InvalidAccountData);
     }
     if *token_program.key() != pinocchio_token::ID {
         return Err(ProgramError::InvalidAccountData);
     }
-    if instruction_data.len() < 25 {
-        return Err(ProgramError::InvalidInstructionData);
-    }
     if user_mint.key() != collateral_mint.key() {
         return Err(ProgramError::InvalidAccountData);
     }
+    check_collateral_priced(collateral_mint.key())?;
 
     // ---- Parse instruction ----
-    let market_id = instruction_data[0];
-    let size = i128::from_le_bytes(
-        instruction_data[1..17].try_into()
-            .map_err(|_| ProgramError::InvalidInstructionData)?
-    );
-    let margin_amount = u64::from_le_bytes(
-        instruction_data[17..25].try_into()
-            .map_err(|_| ProgramError::InvalidInstructionData)?
-    );
+    let OpenPositionArgs {
+        market_id,
+        size,
+        margin_amount,
+        target_price,
+        post_only,
+        auto_compound_funding,
+        margin_in_quote,
+        close_size,
+        allow_partial,
+        wrap_sol,
+    } = OpenPositionArgs::try_from(instruction_data)?;
     if size == 0 {
         return Err(ProgramError::InvalidInstructionData);
     };
 
+    // The temporary wSOL account is created by this instruction, so it must not exist yet
+    let wsol_bump = if wrap_sol {
+        if *collateral_mint.key() != NATIVE_MINT {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (wsol_pda, wsol_bump) = pubkey::find_program_address(
+            &[b"wsol", authority.key().as_ref()],
+            &crate::ID
+        );
+        if *user_token_account.key() != wsol_pda {
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if !user_token_account.data_is_empty() {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        wsol_bump
+    } else {
+        0
+    };
+
     // ---- Derive & check PDAs ----
     let (market_account_pda, _market_bump) = pubkey::find_program_address(
         &[b"market_account", market_authority.key().as_ref(), market_id.to_le_bytes().as_ref()],
@@ -78,66 +213,90 @@ pub fn process_open_position(accounts: &[AccountInfo], instruction_data: &[u8])
         &crate::ID
     );
     if *user_position_account.key() != user_position_account_pda {
-        return Err(ProgramError::InvalidAccountData);
+        return Err(PerpError::WrongPositionForMarket.into());
     }
 
     let (collateral_vault_pda, _collateral_bump) = pubkey::find_program_address(
-        &[b"collateral_vault", collateral_mint.key().as_ref(), market_id.to_le_bytes().as_ref()],
+        &[b"collateral_vault", market_account.key().as_ref()],
         &crate::ID
     );
-    if *collateral_vault.key() != collateral_vault_pda {
-        return Err(ProgramError::InvalidSeeds);
-    }
 
     // ---- Load market ----
-    let mut market = Market::from_account_info_mut(market_account)?;
-    if !market.is_initialized {
-        return Err(ProgramError::UninitializedAccount);
-    }
-    if market.authority != *market_authority.key() {
-        return Err(ProgramError::InvalidAccountData);
-    }
-    if market.collateral_vault != *collateral_vault.key() {
-        return Err(ProgramError::InvalidAccountData);
+    let mut market = Market::load_initialized_mut(market_account)?;
+    check_collateral_vault(&market, &collateral_vault_pda, collateral_vault.key())?;
+    check_market_accounts(&market, market_authority.key(), collateral_vault.key(), collateral_mint.key())?;
+
+    let (global_config_pda, _config_bump) = pubkey::find_program_address(&[b"global_config"], &crate::ID);
+    if *global_config.key() != global_config_pda {
+        return Err(ProgramError::InvalidSeeds);
     }
-    if market.collateral_mint != *collateral_mint.key() {
-        return Err(ProgramError::InvalidAccountData);
+    check_trading_not_halted(global_config)?;
+    if market.oracle_kind == OracleKind::Pyth as u8 {
+        check_symbol_matches_feed(&market.market_symbol, &market.feed_id)?;
     }
 
     // ---- Token account validations ----
-    let user_ta = TokenAccount::from_account_info(user_token_account)?;
-    if *user_ta.owner() != *user.key() || *user_ta.mint() != *collateral_mint.key() {
-        return Err(ProgramError::InvalidAccountData);
-    }
+    // Scoped so the borrows are released before the transfer CPI
+    {
+        if !wrap_sol {
+            let user_ta = TokenAccount::from_account_info(user_token_account)?;
+            if *user_ta.owner() != *user.key() || *user_ta.mint() != *collateral_mint.key() {
+                return Err(ProgramError::InvalidAccountData);
+            }
+        }
 
-    let vault_ta = TokenAccount::from_account_info(collateral_vault)?;
-    if *vault_ta.mint() != *collateral_mint.key() {
-        return Err(ProgramError::InvalidAccountData);
+        let vault_ta = TokenAccount::from_account_info(collateral_vault)?;
+        if *vault_ta.mint() != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
     }
+    let decimals = Mint::from_account_info(collateral_mint)?.decimals();
 
     // ---- Sysvars / Oracle ----
     let clock = Clock::from_account_info(clock_sysvar)?;
     let current_time = clock.unix_timestamp;
 
-    let current_price = get_sol_price_for_trading(
-        pyth_price_account,
-        &clock,
-        60
+    let oracle = market.oracle_config();
+    let oracle_price = get_price_for_feed(pyth_price_account, &clock, &oracle)?;
+    check_oracle_slot_lag(pyth_price_account, &clock, oracle.oracle_kind, market.max_slot_lag)?;
+    // Only entries are gated on confidence, a wide band must never trap a position
+    oracle_price.check_confidence(market.max_conf_bps)?;
+    // Fills happen on the market's tick grid, see snap_to_tick
+    let oracle_price = OraclePrice { price: market.snap_to_tick(oracle_price.price, size)?, ..oracle_price };
+    let current_price = oracle_price.price;
+
+    let margin_amount = margin_in_collateral(margin_amount, margin_in_quote, decimals)?;
+
+    // ---- Open interest cap ----
+    let requested_size = size;
+    let (size, margin_amount) = fit_open_interest_cap(
+        size,
+        margin_amount,
+        market.open_interest_capacity(size),
+        allow_partial
     )?;
 
+    // Bring the funding index up to date so the position starts from it. A
+    // market nobody has cranked for a while is caught up here rather than
+    // rejected: the stale window is charged to the positions that were open
+    // during it, and the new position only pays funding from now on.
+    market.accrue_funding(current_price, current_time)?;
+
+    // Post-only orders must not have any side effects when the target isn't met
+    if post_only && !is_price_fillable(size, current_price, target_price) {
+        return Err(PerpError::OrderNotFillable.into());
+    }
+
     // ---- Notional & margin checks (u128) ----
-    let position_value = calculate_position_value(size, current_price)?;
+    // In collateral units, like the margin and fee it is checked against
+    let position_value = notional_in_collateral(size, current_price, market.collateral_decimals)?;
     let required_margin = calculate_required_margin(position_value, market.initial_margin)?;
 
     if margin_amount < required_margin {
         return Err(ProgramError::InsufficientFunds);
     }
 
-    let leverage = calculate_leverage(position_value, margin_amount)?;
-
-    if leverage > market.max_leverage {
-        return Err(ProgramError::InvalidInstructionData);
-    }
+    check_leverage(position_value, margin_amount, market.max_leverage)?;
 
     // ---- Fee calculation (u128) ----
     let trading_fee = calculate_trading_fee(position_value, market.fee_rate)?;
@@ -145,83 +304,133 @@ pub fn process_open_position(accounts: &[AccountInfo], instruction_data: &[u8])
         .checked_add(trading_fee)
         .ok_or(ProgramError::ArithmeticOverflow)?;
 
-    // ---- Ensure user account exists ----
-    let mut user_account_data = if user_account.data_is_empty() {
-        let lamports = Rent::get()?.minimum_balance(UserAccount::SIZE);
+    // ---- Validate existing accounts before creating anything ----
+    let AccountsToCreate { user_account: create_user_account, position: create_position_account } = preflight_accounts(
+        user,
+        user_account,
+        user_position_account,
+        market_account,
+        &market,
+        size,
+        margin_amount,
+        close_size
+    )?;
 
+    // ---- Create missing accounts back to back, before any funds move ----
+    if create_user_account {
+        let bump_ref = &[user_bump];
         let seeds = seeds!(
             b"user_account",
-            user.key().as_ref()
+            user.key().as_ref(),
+            bump_ref
         );
-
-        let signer_seeds = Signer::from(&seeds);
-
-        CreateAccount {
-            from: user,
-            to: user_account,
-            lamports,
-            space: UserAccount::SIZE as u64,
-            owner: &crate::ID
-        }.invoke_signed(&[signer_seeds])?;
-
-        let mut user_data = UserAccount::from_account_info_mut(user_account)?;
-        user_data.owner = *user.key();
-        user_data.margin_balance = 0;
-        user_data.open_positions = [Pubkey::default(); 10];
-        
-        user_data
-    } else {
-        UserAccount::from_account_info_mut(user_account)?
-    };
-
-    if user_account_data.owner != *user.key() {
-        return Err(ProgramError::InvalidAccountData);
+        create_program_account(authority, user_account, UserAccount::SIZE, Signer::from(&seeds))?;
     }
 
-    // ---- Transfer margin from user -> vault ----
-    TransferChecked {
-        from: user_token_account,
-        to: collateral_vault,
-        authority: user,
-        mint: collateral_mint,
-        amount: margin_amount,
-        decimals: 6, 
-    }.invoke()?;
-
-    user_account_data.margin_balance = user_account_data.margin_balance.checked_add(margin_amount)
-        .ok_or(ProgramError::ArithmeticOverflow)?;
-
-    if user_account_data.margin_balance < trading_fee as u64 {
-        return Err(ProgramError::InsufficientFunds);
-    };
-
-    user_account_data.margin_balance = user_account_data.margin_balance.checked_sub(trading_fee as u64)
-        .ok_or(ProgramError::InsufficientFunds)?;
-
-    // ---- Create or update position ----
-    let position_data = if user_position_account.data_is_empty() {
+    if create_position_account {
         println!("Creating new position account");
 
-        let lamports = Rent::get()?.minimum_balance(Position::SIZE);
-
         let market_id_bytes = market_id.to_le_bytes();
+        let bump_ref = &[position_bump];
         let seeds = seeds!(
             b"position",
             user.key().as_ref(),
-            market_id_bytes.as_ref()
+            market_id_bytes.as_ref(),
+            bump_ref
         );
+        create_program_account(authority, user_position_account, Position::SIZE, Signer::from(&seeds))?;
+    }
 
-        let signer_seeds = Signer::from(&seeds);
-
-        CreateAccount {
-            from: user,
-            to: user_position_account,
-            lamports,
-            space: Position::SIZE as u64,
-            owner: &crate::ID
-        }.invoke_signed(&[signer_seeds])?;
+    let mut user_account_data = if create_user_account {
+        let mut user_account_data = UserAccount::init_from_account_info_mut(user_account)?;
+        user_account_data.initialize(*user.key());
+        user_account_data
+    } else {
+        UserAccount::from_account_info_mut(user_account)?
+    };
+    // Only a balance held in this market's mint can fund or receive anything here
+    user_account_data.bind_collateral_mint(collateral_mint.key())?;
 
+    // ---- Flip: close the existing position before opening the other side ----
+    let flip_deficit = if close_size > 0 {
         let mut position = Position::from_account_info_mut(user_position_account)?;
+        let closed_size = position.size;
+        let close_fee = calculate_trading_fee(
+            notional_in_collateral(closed_size, current_price, market.collateral_decimals)?,
+            market.fee_rate
+        )?;
+        let closed = close_for_flip(
+            &mut position,
+            &mut user_account_data,
+            market.cumulative_funding_index,
+            current_price,
+            close_fee,
+            market.collateral_decimals
+        )?;
+        // Funding settled into margin before the close is released with it
+        market.apply_margin_delta(closed.funding_delta)?;
+        market.release_margin(closed.margin);
+        market.collect_fees(close_fee.saturating_sub(closed.settled.deficit))?;
+
+        remove_open_interest(&mut market, closed_size);
+
+        println!("Flip closed size: {}", closed_size);
+        println!("Flip Realized PnL: {}", closed.realized_pnl);
+        println!("Flip Close Fee: {}", close_fee);
+        closed.settled.deficit
+    } else {
+        0
+    };
+
+    // ---- Draw from free margin_balance first, transfer only the shortfall ----
+    let free_balance = free_margin_balance(&user_account_data, Some(user_position_account), other_positions)?;
+    let (from_balance, from_transfer) = split_margin_sources(total_required, free_balance);
+
+    let wrap = if wrap_sol {
+        sol_wrap_plan(from_transfer, Rent::get()?.minimum_balance(TokenAccount::LEN))?
+    } else {
+        None
+    };
+    if let Some(wrap) = wrap {
+        let bump_ref = &[wsol_bump];
+        let seeds = seeds!(
+            b"wsol",
+            authority.key().as_ref(),
+            bump_ref
+        );
+        wrap_sol_and_deposit(authority, user_token_account, collateral_mint, collateral_vault, wrap, decimals, Signer::from(&seeds))?;
+
+        println!("Wrapped lamports: {}", wrap.lamports);
+        println!("Unwrapped lamports: {}", wrap.refund);
+        user_account_data.deposit_time = current_time;
+    } else if from_transfer > 0 && !wrap_sol {
+        TransferChecked {
+            from: user_token_account,
+            to: collateral_vault,
+            // A delegate needs an SPL token approval from the user to move funds
+            authority,
+            mint: collateral_mint,
+            amount: from_transfer,
+            decimals,
+        }.invoke()?;
+
+        user_account_data.deposit_time = current_time;
+    }
+
+    // margin_balance includes margin locked in positions, so only the newly
+    // transferred collateral is credited and the fee is debited
+    user_account_data.margin_balance = user_account_data.margin_balance.checked_add(from_transfer)
+        .ok_or(ProgramError::ArithmeticOverflow)?
+        .checked_sub(trading_fee)
+        .ok_or(ProgramError::InsufficientFunds)?;
+
+    // ---- Initialize or update position ----
+    let mut position = if create_position_account {
+        Position::init_from_account_info_mut(user_position_account)?
+    } else {
+        Position::from_account_info_mut(user_position_account)?
+    };
+    if create_position_account {
         position.user = *user.key();
         position.market = *market_account.key();
         position.size = size;
@@ -230,32 +439,54 @@ pub fn process_open_position(accounts: &[AccountInfo], instruction_data: &[u8])
         position.unrealized_pnl = 0;
         position.funding_payment = 0;
         position.last_funding_settlement = current_time;
-        position.is_active = true;
+        position.is_active = 1;
+        position.auto_compound_funding = u8::from(auto_compound_funding);
+        position.entry_oracle_time = oracle_price.publish_time;
+        position.last_funding_index = market.cumulative_funding_index;
+        position.underwater_since = 0;
 
         add_position_to_user(&mut user_account_data, user_position_account.key())?;
-        
-        position
     } else {
         println!("Updating existing position");
-        let mut position = Position::from_account_info_mut(user_position_account)?;
-        
-        if position.user != *user.key() {
-            return Err(ProgramError::InvalidAccountData);
+        if !position.is_open() {
+            // Reopening a closed position starts its funding fresh
+            position.funding_payment = 0;
+            position.auto_compound_funding = u8::from(auto_compound_funding);
+            position.underwater_since = 0;
         }
+        let funding_settled = update_existing_position(
+            &mut position,
+            size,
+            oracle_price,
+            margin_amount,
+            market.cumulative_funding_index,
+            market.collateral_decimals,
+            current_time
+        )?;
+        user_account_data.apply_margin_delta(funding_settled)?;
+        market.apply_margin_delta(funding_settled)?;
+    }
 
-        update_existing_position(&mut position, size, current_price, margin_amount, current_time)?;
-        
-        position
-    };
-
-    // ---- Update market accounting (new collateral only) ----
-    market.total_collateral = market
-        .total_collateral
-        .checked_add(margin_amount)
-        .ok_or(ProgramError::ArithmeticOverflow)?;
+    // ---- Update market accounting (transferred collateral only) ----
+    market.credit_collateral(from_transfer)?;
+    market.collect_fees(trading_fee)?;
+    market.lock_margin(margin_amount)?;
 
     // Update market open interest
-    update_market_open_interest(&mut market, size, margin_amount)?;
+    add_open_interest(&mut market, size)?;
+
+    // A flip's losses beyond the closed margin are covered like any close's,
+    // once the market is released for the insurance transfer
+    let (maintenance_margin, collateral_decimals) = (market.maintenance_margin, market.collateral_decimals);
+    drop(market);
+    cover_deficit(market_account, collateral_mint, collateral_vault, insurance_vault, flip_deficit)?;
+    assert_vault_solvent(&*Market::from_account_info(market_account)?, &*TokenAccount::from_account_info(collateral_vault)?)?;
+
+    fee_breakdown(*user_position_account.key(), margin_amount, trading_fee).emit();
+    position_updated(*user_position_account.key(), &position, maintenance_margin, collateral_decimals)?.emit();
+    if size != requested_size {
+        PartialFill { position: *user_position_account.key(), requested_size, filled_size: size }.emit();
+    }
 
     println!("Position opened successfully");
     println!("Size: {}", size);
@@ -263,20 +494,397 @@ pub fn process_open_position(accounts: &[AccountInfo], instruction_data: &[u8])
     println!("Margin: {}", margin_amount);
     println!("Trading Fee: {}", trading_fee);
     println!("Total Deducted: {}", total_required);
+    println!("From Margin Balance: {}", from_balance);
+    println!("From Token Transfer: {}", from_transfer);
 
     Ok(())
 }
 
-fn calculate_position_value(size: i128, price: u64) -> Result<u64, ProgramError> {
-    let abs_size = size.abs() as u64;
-    abs_size.checked_mul(price)
-        .ok_or(ProgramError::ArithmeticOverflow)
+/// Shrinks an order to `capacity` when partial fills are allowed, scaling its
+/// margin to the filled size (rounded up, against the trader). Otherwise an
+/// order over the cap is rejected, as is one with nothing left to fill.
+fn fit_open_interest_cap(
+    size: i128,
+    margin: u64,
+    capacity: u64,
+    allow_partial: bool
+) -> Result<(i128, u64), ProgramError> {
+    let requested = size.unsigned_abs();
+    if requested <= capacity as u128 {
+        return Ok((size, margin));
+    }
+    if !allow_partial || capacity == 0 {
+        return Err(PerpError::OpenInterestCapExceeded.into());
+    }
+
+    let filled = capacity as i128 * size.signum();
+    let margin = math::mul_div(margin as i128, capacity as i128, requested as i128, RoundingMode::Up)?;
+    Ok((filled, margin as u64))
 }
 
+/// The whole trading fee currently stays in the vault as treasury, nothing
+/// goes to insurance and there are no maker rebates.
+fn fee_breakdown(position: Pubkey, margin: u64, trading_fee: u64) -> FeeBreakdown {
+    FeeBreakdown {
+        position,
+        margin,
+        trading_fee,
+        insurance_contribution: 0,
+        treasury_contribution: trading_fee,
+        maker_rebate: 0,
+    }
+}
+
+/// The position's state after this open, with its liquidation price at the
+/// market's maintenance margin.
+fn position_updated(
+    key: Pubkey,
+    position: &Position,
+    maintenance_margin_bps: u64,
+    decimals: u8
+) -> Result<PositionUpdated, ProgramError> {
+    Ok(PositionUpdated {
+        position: key,
+        size: position.size,
+        entry_price: position.entry_price,
+        margin: position.margin,
+        liquidation_price: position.liquidation_price(maintenance_margin_bps, decimals)?,
+    })
+}
+
+/// Margin, fees, PnL and funding are all converted one collateral token per
+/// dollar. wSOL would need its own oracle for that, so it is refused as
+/// collateral until the conversion prices the mint.
+pub(crate) fn check_collateral_priced(collateral_mint: &Pubkey) -> ProgramResult {
+    if *collateral_mint == NATIVE_MINT {
+        return Err(PerpError::UnpricedCollateral.into());
+    }
+    Ok(())
+}
+
+/// The margin the open asks for, in collateral units. A quote (USD, 1e8
+/// scale) margin converts one to one, the collateral being a $1 stablecoin
+/// whatever the market's base asset trades at. Rounds down.
+fn margin_in_collateral(margin_amount: u64, margin_in_quote: bool, decimals: u8) -> Result<u64, ProgramError> {
+    if !margin_in_quote {
+        return Ok(margin_amount);
+    }
+    math::quote_to_collateral_u64(margin_amount, decimals, RoundingMode::Down)
+}
+
+/// Lamports moved through the temporary wSOL account of a SOL-funded open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SolWrap {
+    lamports: u64, // Taken from the signer: the transferred part of the open plus the account's rent
+    to_vault: u64, // Transferred to the collateral vault
+    refund: u64, // The rent, returned to the signer when the account is closed
+}
+
+/// Wraps only `from_transfer`, the part of the open free margin doesn't
+/// cover, plus the account's rent. Nothing is wrapped when free margin covers
+/// the whole open.
+fn sol_wrap_plan(from_transfer: u64, rent_exempt: u64) -> Result<Option<SolWrap>, ProgramError> {
+    if from_transfer == 0 {
+        return Ok(None);
+    }
+
+    let lamports = from_transfer.checked_add(rent_exempt).ok_or(ProgramError::ArithmeticOverflow)?;
+    Ok(Some(SolWrap { lamports, to_vault: from_transfer, refund: rent_exempt }))
+}
+
+/// Creates the signer's wSOL account funded with `wrap.lamports`, moves
+/// `wrap.to_vault` into the vault and closes the account, unwrapping the rest
+/// back to the signer.
+fn wrap_sol_and_deposit(
+    authority: &AccountInfo,
+    wsol_account: &AccountInfo,
+    native_mint: &AccountInfo,
+    collateral_vault: &AccountInfo,
+    wrap: SolWrap,
+    decimals: u8,
+    signer: Signer
+) -> ProgramResult {
+    CreateAccount {
+        from: authority,
+        to: wsol_account,
+        lamports: wrap.lamports,
+        space: TokenAccount::LEN as u64,
+        owner: &pinocchio_token::ID
+    }.invoke_signed(&[signer])?;
+
+    // A native account's balance is its lamports above rent, no sync needed
+    InitializeAccount3 {
+        account: wsol_account,
+        mint: native_mint,
+        owner: authority.key(),
+    }.invoke()?;
+
+    TransferChecked {
+        from: wsol_account,
+        to: collateral_vault,
+        authority,
+        mint: native_mint,
+        amount: wrap.to_vault,
+        decimals,
+    }.invoke()?;
+
+    CloseAccount {
+        account: wsol_account,
+        destination: authority,
+        authority,
+    }.invoke()
+}
+
+pub(crate) fn create_program_account(
+    payer: &AccountInfo,
+    account: &AccountInfo,
+    space: usize,
+    signer: Signer
+) -> ProgramResult {
+    let lamports = Rent::get()?.minimum_balance(space);
+
+    CreateAccount {
+        from: payer,
+        to: account,
+        lamports,
+        space: space as u64,
+        owner: &crate::ID
+    }.invoke_signed(&[signer])
+}
+
+/// Closes a program-owned account, returning its rent to `destination`. The
+/// data is zeroed so nothing stale survives, and the address can be created
+/// again with create_program_account. Nothing may hold a borrow of `account`.
+pub(crate) fn close_program_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    account.try_borrow_mut_data()?.fill(0);
+
+    let lamports = account.lamports();
+    *destination.try_borrow_mut_lamports()? = destination.lamports()
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    *account.try_borrow_mut_lamports()? = 0;
+
+    account.close()
+}
+
+/// The user's margin_balance not locked in an active position, see
+/// UserAccount::free_margin. Every position tracked in `open_positions`, other
+/// than `current_position`, must be passed in `other_positions`.
+pub(crate) fn free_margin_balance(
+    user_account: &UserAccount,
+    current_position: Option<&AccountInfo>,
+    other_positions: &[AccountInfo]
+) -> Result<u64, ProgramError> {
+    let positions = load_open_positions(user_account, current_position, other_positions)?;
+    let positions: Vec<&Position> = positions.iter().map(|position| &**position).collect();
+    Ok(user_account.free_margin(&positions))
+}
+
+/// Loads every position tracked in the user's `open_positions`, checking each
+/// belongs to this program and to the user.
+fn load_open_positions<'a>(
+    user_account: &UserAccount,
+    current_position: Option<&'a AccountInfo>,
+    other_positions: &'a [AccountInfo]
+) -> Result<Vec<Ref<'a, Position>>, ProgramError> {
+    let mut positions = Vec::new();
+
+    for position_key in user_account.open_positions.iter().filter(|key| **key != Pubkey::default()) {
+        let position_account = match current_position {
+            Some(current) if current.key() == position_key => current,
+            _ => other_positions
+                .iter()
+                .find(|account| account.key() == position_key)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?,
+        };
+
+        if !position_account.is_owned_by(&crate::ID) {
+            return Err(ProgramError::InvalidAccountOwner);
+        }
+
+        let position = Position::from_account_info(position_account)?;
+        check_position_owner(&position, &user_account.owner)?;
+        positions.push(position);
+    }
+
+    Ok(positions)
+}
+
+/// Splits `required` into the part covered by the free balance and the
+/// shortfall that has to come from a token transfer.
+fn split_margin_sources(required: u64, free_balance: u64) -> (u64, u64) {
+    let from_balance = required.min(free_balance);
+    (from_balance, required - from_balance)
+}
+
+/// An existing user account must be this program's and belong to `user`.
+pub(crate) fn check_existing_user_account(user_account: &AccountInfo, user: &Pubkey) -> ProgramResult {
+    if !user_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    let user_account_data = UserAccount::from_account_info(user_account)?;
+    if user_account_data.owner != *user {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+/// The vault passed in must be both the market's vault PDA and the vault the
+/// market recorded at init. Either mismatch is the same VaultMismatch, checked
+/// before the vault is loaded as a token account.
+pub(crate) fn check_collateral_vault(
+    market: &Market,
+    collateral_vault_pda: &Pubkey,
+    collateral_vault: &Pubkey
+) -> ProgramResult {
+    if *collateral_vault != *collateral_vault_pda || market.collateral_vault != *collateral_vault {
+        return Err(PerpError::VaultMismatch.into());
+    }
+    Ok(())
+}
+
+/// The collateral vault must hold at least what the market's accounting says
+/// it does. Run after the transfers of every instruction that moves collateral,
+/// so an accounting bug fails the transaction instead of drifting silently.
+pub(crate) fn assert_vault_solvent(market: &Market, vault: &TokenAccount) -> ProgramResult {
+    if vault.amount() < market.total_collateral {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+/// The authority, vault and mint passed in must be the ones the market was created with.
+pub(crate) fn check_market_accounts(
+    market: &Market,
+    market_authority: &Pubkey,
+    collateral_vault: &Pubkey,
+    collateral_mint: &Pubkey
+) -> ProgramResult {
+    if market.seed_authority != *market_authority
+        || market.collateral_vault != *collateral_vault
+        || market.collateral_mint != *collateral_mint
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+/// Which of the user and position accounts an open has to create.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AccountsToCreate {
+    user_account: bool,
+    position: bool,
+}
+
+/// Every check on the user and position accounts that can reject the open,
+/// run before either is created so a brand-new user is never left with an
+/// orphaned user account.
+#[allow(clippy::too_many_arguments)]
+fn preflight_accounts(
+    user: &AccountInfo,
+    user_account: &AccountInfo,
+    user_position_account: &AccountInfo,
+    market_account: &AccountInfo,
+    market: &Market,
+    size: i128,
+    margin_amount: u64,
+    close_size: u128
+) -> Result<AccountsToCreate, ProgramError> {
+    let create = AccountsToCreate {
+        user_account: user_account.data_is_empty(),
+        position: user_position_account.data_is_empty(),
+    };
+
+    if !create.user_account {
+        check_existing_user_account(user_account, user.key())?;
+    }
+    check_market_status(market)?;
+    let existing_margin = if create.position {
+        if close_size > 0 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        0
+    } else {
+        let position = Position::from_account_info(user_position_account)?;
+        check_position_owner(&position, user.key())?;
+        check_position_market(&position, market_account.key())?;
+        if close_size > 0 {
+            // The flipped-to position starts from its own margin alone
+            check_flip(&position, close_size, size)?;
+            0
+        } else if position.is_open() {
+            check_same_side(&position, size)?;
+            position.margin
+        } else {
+            0
+        }
+    };
+
+    // Rounding in the bps check lets dust positions through, the absolute floor doesn't
+    check_min_margin(
+        existing_margin.checked_add(margin_amount).ok_or(ProgramError::ArithmeticOverflow)?,
+        market.min_margin
+    )?;
+
+    Ok(create)
+}
+
+fn check_position_owner(position: &Position, user: &Pubkey) -> ProgramResult {
+    if position.user != *user {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+/// The position PDA is per (user, market_id), but the stored market is what
+/// the position was actually opened against.
+fn check_position_market(position: &Position, market: &Pubkey) -> ProgramResult {
+    if position.market != *market {
+        return Err(PerpError::WrongPositionForMarket.into());
+    }
+    Ok(())
+}
+
+/// A long fills at or below its target price, a short at or above it.
+fn is_price_fillable(size: i128, current_price: u64, target_price: u64) -> bool {
+    if size > 0 {
+        current_price <= target_price
+    } else {
+        current_price >= target_price
+    }
+}
+
+fn check_min_margin(position_margin: u64, min_margin: u64) -> ProgramResult {
+    if position_margin < min_margin {
+        return Err(PerpError::MarginBelowMinimum.into());
+    }
+    Ok(())
+}
+
+/// Notional of `size` contracts at a normalized `price`, in quote units
+/// (PRICE_DECIMALS). The u64 form of math::quote_value the margin and fee
+/// math is written against.
+pub(crate) fn calculate_position_value(size: i128, price: u64) -> Result<u64, ProgramError> {
+    let value = math::quote_value(size, price as i128, -(math::PRICE_DECIMALS as i32))?;
+    u64::try_from(value).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+/// Notional of `size` contracts at `price` in collateral units of a mint with
+/// `decimals`, the unit margin requirements and fees are charged in. Rounds up.
+pub(crate) fn notional_in_collateral(size: i128, price: u64, decimals: u8) -> Result<u64, ProgramError> {
+    math::quote_to_collateral_u64(calculate_position_value(size, price)?, decimals, RoundingMode::Up)
+}
+
+/// A market without an initial margin would allow free leverage, so it is
+/// rejected outright, and dust notionals still require at least one unit.
+/// Rounds up, against the trader.
 fn calculate_required_margin(position_value: u64, initial_margin_bps: u64) -> Result<u64, ProgramError> {
-    position_value.checked_mul(initial_margin_bps)
-        .and_then(|v| v.checked_div(10000)) 
-        .ok_or(ProgramError::ArithmeticOverflow)
+    if initial_margin_bps == 0 {
+        return Err(PerpError::ZeroInitialMargin.into());
+    }
+    math::mul_div_u64(position_value, initial_margin_bps, 10_000, RoundingMode::Up)
+        .map(|v| v.max(1))
 }
 
 fn calculate_leverage(position_value: u64, margin: u64) -> Result<u64, ProgramError> {
@@ -287,74 +895,156 @@ fn calculate_leverage(position_value: u64, margin: u64) -> Result<u64, ProgramEr
         .ok_or(ProgramError::ArithmeticOverflow)
 }
 
-fn calculate_trading_fee(position_value: u64, fee_rate_bps: u64) -> Result<u64, ProgramError> {
-    position_value.checked_mul(fee_rate_bps)
-        .and_then(|v| v.checked_div(10000))
-        .ok_or(ProgramError::ArithmeticOverflow)
+pub(crate) fn check_leverage(position_value: u64, margin: u64, max_leverage: u64) -> ProgramResult {
+    if calculate_leverage(position_value, margin)? > max_leverage {
+        return Err(PerpError::LeverageTooHigh.into());
+    }
+    Ok(())
+}
+
+/// Fee on `position_value`, rounded up against the trader.
+pub(crate) fn calculate_trading_fee(position_value: u64, fee_rate_bps: u64) -> Result<u64, ProgramError> {
+    math::mul_div_u64(position_value, fee_rate_bps, 10_000, RoundingMode::Up)
 }
 
+/// Applies a fill on the position's side to an existing position, settling
+/// its pending funding up to `funding_index` first. Returns the resulting
+/// change in margin, which the caller applies to the margin balance. A closed
+/// position being reopened settles nothing.
 fn update_existing_position(
     position: &mut Position,
     additional_size: i128,
-    current_price: u64,
+    fill: OraclePrice,
     additional_margin: u64,
+    funding_index: i128,
+    decimals: u8,
     current_time: i64
-) -> Result<(), ProgramError> {
-    if !position.is_active {
+) -> Result<i64, ProgramError> {
+    let current_price = fill.price;
+    position.entry_oracle_time = fill.publish_time;
+    position.last_funding_settlement = current_time;
 
+    if !position.is_open() {
+        // Nothing accrued on a closed position, its funding starts from here
         position.size = additional_size;
         position.entry_price = current_price;
         position.margin = additional_margin;
-        position.is_active = true;
-        position.last_funding_settlement = current_time;
-        return Ok(());
+        position.is_active = 1;
+        position.last_funding_index = funding_index;
+        return Ok(0);
     }
 
+    check_same_side(position, additional_size)?;
+
+    // Settle funding accrued at the old size first, so the averaged entry is
+    // computed on a funding-clean position
+    let funding_settled = position.settle_funding_index(funding_index, decimals)?;
+
     let current_size = position.size;
     let new_total_size = current_size
         .checked_add(additional_size)
         .ok_or(ProgramError::ArithmeticOverflow)?;
 
-    if (current_size > 0 && additional_size > 0) || (current_size < 0 && additional_size < 0) {
-
-        let current_notional = (current_size.abs() as u64)
-            .checked_mul(position.entry_price)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
-        
-        let additional_notional = (additional_size.abs() as u64)
-            .checked_mul(current_price)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
-        
-        let total_notional = current_notional
-            .checked_add(additional_notional)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
-        
-        if new_total_size != 0 {
-            position.entry_price = total_notional
-                .checked_div(new_total_size.abs() as u64)
-                .ok_or(ProgramError::ArithmeticOverflow)?;
-        }
-        
-        position.size = new_total_size;
-
-    } else if (current_size > 0 && additional_size < 0) || (current_size < 0 && additional_size > 0) {
+    // Signed notionals, summed in i128 like the margin math so a position past
+    // u64 notional still averages
+    let total_notional = math::checked_add(
+        math::checked_mul(current_size, position.entry_price as i128)?,
+        math::checked_mul(additional_size, current_price as i128)?
+    )?;
 
-        position.size = new_total_size;
-        
-        if new_total_size == 0 {
-            position.is_active = false;
-        } else if (current_size > 0 && new_total_size < 0) || (current_size < 0 && new_total_size > 0) {
-            position.entry_price = current_price;
-        }
-    }
+    // Both sides agree, so the new size can't be zero and the average is
+    // positive. It rounds against the trader: up for longs, down for shorts
+    let rounding = if new_total_size > 0 { RoundingMode::Up } else { RoundingMode::Down };
+    let entry_price = math::mul_div(total_notional, 1, new_total_size, rounding)?;
+    position.entry_price = u64::try_from(entry_price).map_err(|_| ProgramError::ArithmeticOverflow)?;
+    position.size = new_total_size;
 
     position.margin = position.margin
         .checked_add(additional_margin)
         .ok_or(ProgramError::ArithmeticOverflow)?;
 
+    Ok(funding_settled)
+}
+
+/// An open can only add to an open position's side. Shrinking it goes through
+/// ClosePosition, which realizes the PnL and charges the close fee, and
+/// turning it around takes close_size, see check_flip.
+fn check_same_side(position: &Position, size: i128) -> ProgramResult {
+    if position.is_open() && position.size.signum() != size.signum() {
+        return Err(ProgramError::InvalidInstructionData);
+    }
     Ok(())
 }
 
+/// A flip must close the whole existing position and open the other side.
+fn check_flip(position: &Position, close_size: u128, open_size: i128) -> ProgramResult {
+    let opposite = (position.size > 0 && open_size < 0) || (position.size < 0 && open_size > 0);
+    if !position.is_open() || position.size.unsigned_abs() != close_size || !opposite {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    Ok(())
+}
+
+/// Paused and reduce-only markets take no opens. Reducing a position in a
+/// reduce-only market goes through ClosePosition, which takes the closed
+/// size off open interest and realizes its PnL.
+fn check_market_status(market: &Market) -> ProgramResult {
+    if market.allows_open() {
+        return Ok(());
+    }
+    if market.is_paused() {
+        return Err(PerpError::MarketPaused.into());
+    }
+    Err(PerpError::ReduceOnly.into())
+}
+
+/// The closing half of a flip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FlipClose {
+    realized_pnl: i128, // PnL on the closed size, before fees and funding
+    margin: u64, // Margin closed, funding settled into it included
+    funding_delta: i64, // Change in margin from the funding settled before the close
+    settled: PnlSettlement, // Margin settled against PnL, funding and the close fee
+}
+
+/// Closes `position` in full at `price` for a flip. Funding is settled and the
+/// PnL realized into the user's margin balance the same way a close would,
+/// except the equity stays in the vault as free margin for the new side.
+fn close_for_flip(
+    position: &mut Position,
+    user_account: &mut UserAccount,
+    funding_index: i128,
+    price: u64,
+    close_fee: u64,
+    decimals: u8
+) -> Result<FlipClose, ProgramError> {
+    let index_delta = position.settle_funding_index(funding_index, decimals)?;
+    let funding_share = position.settle_funding_share(position.size.unsigned_abs())?;
+    position.margin = position.margin
+        .checked_add_signed(funding_share)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let funding_delta = index_delta
+        .checked_add(funding_share)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    user_account.apply_margin_delta(funding_delta)?;
+
+    let realized_pnl = position.unrealized_pnl_at(price, decimals)?;
+    let settled = settle_pnl(position.margin, realized_pnl - close_fee as i128);
+    user_account.margin_balance = user_account.margin_balance
+        .saturating_sub(position.margin)
+        .checked_add(settled.equity)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    let margin = position.margin;
+    position.size = 0;
+    position.margin = 0;
+    position.unrealized_pnl = 0;
+    position.funding_payment = 0;
+    position.is_active = 0;
+
+    Ok(FlipClose { realized_pnl, margin, funding_delta, settled })
+}
+
 fn add_position_to_user(
     user_account: &mut UserAccount,
     position_key: &Pubkey
@@ -375,27 +1065,16 @@ fn add_position_to_user(
     Err(ProgramError::AccountAlreadyInitialized)
 }
 
-fn update_market_open_interest(
-    market: &mut Market,
-    size: i128,
-    margin: u64
+/// Frees the user's slot holding `position_key`, the inverse of add_position_to_user.
+pub(crate) fn remove_position_from_user(
+    user_account: &mut UserAccount,
+    position_key: &Pubkey
 ) -> Result<(), ProgramError> {
-    let abs_size = size.abs() as u64;
-    
-    if size > 0 {
-        market.open_interest_long = market.open_interest_long
-            .checked_add(abs_size)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
-    } else {
-        market.open_interest_short = market.open_interest_short
-            .checked_add(abs_size)
-            .ok_or(ProgramError::ArithmeticOverflow)?;
-    }
-
-    market.total_collateral = market.total_collateral
-        .checked_add(margin)
-        .ok_or(ProgramError::ArithmeticOverflow)?;
-
+    let slot = user_account.open_positions
+        .iter_mut()
+        .find(|slot| *slot == position_key)
+        .ok_or(ProgramError::InvalidAccountData)?;
+    *slot = Pubkey::default();
     Ok(())
 }
 
@@ -405,199 +1084,1054 @@ fn update_market_open_interest(
 mod tests {
     use mollusk_svm::{Mollusk, result::Check, program};
     use solana_sdk::{
-        account::Account, instruction::{AccountMeta, Instruction}, pubkey::Pubkey, pubkey
+        account::Account, instruction::{AccountMeta, Instruction}, pubkey::Pubkey
     };
 
-    const PROGRAM_ID: Pubkey = solana_sdk::pubkey!("BXacY2xWwx7ogSa1CnvrdXxAigBMwwszoZf4Q98E2YoV");
-    const AUTHORITY: Pubkey = Pubkey::new_from_array([1u8; 32]);
-    const USER: Pubkey = Pubkey::new_from_array([2u8; 32]);
-    const MARKET_ID: u64 = 66;
-    const COLLATERAL_MINT: Pubkey = Pubkey::new_from_array([3u8; 32]);
-    const USER_MINT: Pubkey = Pubkey::new_from_array([3u8; 32]);
+    use crate::math::PRICE_DECIMALS;
 
     #[test]
-    fn test_process_open_position() {
-        let mollusk = Mollusk::new(&PROGRAM_ID, "target/deploy/pinocchio_perp");
-        let price_update_pubkey = Pubkey::new_unique();
-        let user_token_account_pubkey = Pubkey::new_unique();
-        
-        let (market_account_pda, _market_bump) = Pubkey::find_program_address(
-            &[b"market_account", AUTHORITY.as_ref(), MARKET_ID.to_le_bytes().as_ref()],
-            &PROGRAM_ID
-        );
+    fn test_post_only_fillable_target() {
+        // Long with oracle below target, short with oracle above target, and both at the target
+        assert!(super::is_price_fillable(10, 99_00000000, 100_00000000));
+        assert!(super::is_price_fillable(-10, 101_00000000, 100_00000000));
+        assert!(super::is_price_fillable(10, 100_00000000, 100_00000000));
+        assert!(super::is_price_fillable(-10, 100_00000000, 100_00000000));
+    }
 
-        let (user_account_pda, _user_bump) = Pubkey::find_program_address(
-            &[b"user_account", USER.as_ref()],
-            &PROGRAM_ID
+    #[test]
+    fn test_post_only_non_fillable_target() {
+        assert!(!super::is_price_fillable(10, 101_00000000, 100_00000000));
+        assert!(!super::is_price_fillable(-10, 99_00000000, 100_00000000));
+    }
+
+    #[test]
+    fn test_zero_initial_margin_market_rejected() {
+        assert_eq!(
+            super::calculate_required_margin(1_000_000, 0),
+            Err(super::PerpError::ZeroInitialMargin.into())
         );
+    }
+
+    #[test]
+    fn test_required_margin_floor() {
+        // 10 * 1000 bps / 10000 rounds down to 0 without the floor
+        assert_eq!(super::calculate_required_margin(10, 1000), Ok(1));
+        assert_eq!(super::calculate_required_margin(1_000_000, 1000), Ok(100_000));
+    }
+
+    #[test]
+    fn test_margin_and_fee_round_up() {
+        // 999 notional at 1000 bps needs 99.9 margin, at 10 bps pays a 0.999 fee
+        assert_eq!(super::calculate_required_margin(999, 1000), Ok(100));
+        assert_eq!(super::calculate_trading_fee(999, 10), Ok(1));
+        assert_eq!(super::calculate_trading_fee(10_000, 10), Ok(10));
+    }
 
-        let (user_position_account_pda, _position_bump) = Pubkey::find_program_address(
-            &[b"position", USER.as_ref(), MARKET_ID.to_le_bytes().as_ref()],
-            &PROGRAM_ID
+    #[test]
+    fn test_averaged_entry_rounds_against_trader() {
+        let fill = super::OraclePrice { price: 101, publish_time: 0, conf_bps: 0 };
+
+        // 1 at 100 plus 2 at 101 averages 100.67: longs round up, shorts down
+        let mut long = crate::states::Position { size: 1, entry_price: 100, is_active: 1, ..crate::states::Position::default() };
+        super::update_existing_position(&mut long, 2, fill, 0, 0, PRICE_DECIMALS, 0).unwrap();
+        assert_eq!(long.entry_price, 101);
+
+        let mut short = crate::states::Position { size: -1, entry_price: 100, is_active: 1, ..crate::states::Position::default() };
+        super::update_existing_position(&mut short, -2, fill, 0, 0, PRICE_DECIMALS, 0).unwrap();
+        assert_eq!(short.entry_price, 100);
+    }
+
+    #[test]
+    fn test_averaged_entry_beyond_u64_notional() {
+        // 1e11 contracts at $1_500 is already 1.5e22 of notional, past u64,
+        // see test_requirement_beyond_u64_notional. Doubling it at $1_600 averages $1_550
+        let fill = super::OraclePrice { price: 1600_00000000, publish_time: 0, conf_bps: 0 };
+        let size = 100_000_000_000;
+
+        let mut long = crate::states::Position { size, entry_price: 1500_00000000, is_active: 1, ..crate::states::Position::default() };
+        super::update_existing_position(&mut long, size, fill, 0, 0, PRICE_DECIMALS, 0).unwrap();
+        assert_eq!((long.size, long.entry_price), (2 * size, 1550_00000000));
+
+        let mut short = crate::states::Position { size: -size, entry_price: 1500_00000000, is_active: 1, ..crate::states::Position::default() };
+        super::update_existing_position(&mut short, -size, fill, 0, 0, PRICE_DECIMALS, 0).unwrap();
+        assert_eq!((short.size, short.entry_price), (-2 * size, 1550_00000000));
+    }
+
+    #[test]
+    fn test_program_foreign_user_account_rejected() {
+        use crate::states::{with_account_infos, TestAccount, UserAccount};
+
+        let mut data = [0u8; UserAccount::SIZE];
+        data[..8].copy_from_slice(&UserAccount::DISCRIMINATOR);
+        let owner_offset = core::mem::offset_of!(UserAccount, owner);
+        data[owner_offset..owner_offset + 32].copy_from_slice(&[2u8; 32]);
+
+        let account = |owner| TestAccount { key: [5u8; 32], owner, is_signer: false, is_writable: true, lamports: 0, data: &data };
+        with_account_infos(&[account([9u8; 32]), account(crate::ID)], |accounts| {
+            assert_eq!(
+                super::check_existing_user_account(&accounts[0], &[2u8; 32]),
+                Err(pinocchio::program_error::ProgramError::InvalidAccountOwner)
+            );
+            assert!(super::check_existing_user_account(&accounts[1], &[2u8; 32]).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_leverage_above_cap_rejected() {
+        // 10_000 notional on 1_000 margin is 10x against a 5x cap
+        assert_eq!(
+            super::check_leverage(10_000, 1_000, 5),
+            Err(super::PerpError::LeverageTooHigh.into())
         );
+    }
+
+    #[test]
+    fn test_leverage_at_cap_accepted() {
+        assert!(super::check_leverage(5_000, 1_000, 5).is_ok());
+    }
 
-        let (collateral_vault_pda, _collateral_bump) = Pubkey::find_program_address(
-            &[b"collateral_vault", COLLATERAL_MINT.as_ref(), MARKET_ID.to_le_bytes().as_ref()],
-            &PROGRAM_ID
+    #[test]
+    fn test_dust_position_fails_min_margin_floor() {
+        // 10 notional at 1000 bps only needs 1 unit of margin
+        let required = super::calculate_required_margin(10, 1000).unwrap();
+        assert_eq!(required, 1);
+        assert_eq!(
+            super::check_min_margin(required, 100),
+            Err(super::PerpError::MarginBelowMinimum.into())
         );
+        assert!(super::check_min_margin(100, 100).is_ok());
+        // No floor configured
+        assert!(super::check_min_margin(required, 0).is_ok());
+    }
 
-        let (system_program_id, system_account) = program::keyed_account_for_system_program();
-        let token_program = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
-        
-        // Create clock sysvar account
-        let clock_id = solana_sdk::sysvar::clock::id();
-        let clock_account = Account {
-            lamports: 0,
-            data: vec![0; 40],
-            owner: solana_sdk::sysvar::id(),
-            executable: false,
-            rent_epoch: 0,
+    #[test]
+    fn test_foreign_position_rejected_before_account_creation() {
+        use crate::states::{with_account_infos, Market, Position, TestAccount};
+        use super::AccountsToCreate;
+
+        const USER: [u8; 32] = [2u8; 32];
+        const MARKET: [u8; 32] = [7u8; 32];
+
+        let market = Market { min_margin: 100, ..Market::default() };
+        // Runs the pre-flight checks for a brand-new user with an existing
+        // position owned by `owner`, then again with no position account yet
+        let preflight = |owner, margin| {
+            let position = Position { discriminator: Position::DISCRIMINATOR, user: owner, market: MARKET, ..Default::default() };
+            let account = |key: [u8; 32], data| TestAccount { key, owner: crate::ID, is_signer: false, is_writable: true, lamports: 0, data };
+            let accounts = [
+                account([1u8; 32], bytemuck::bytes_of(&position)), // existing position, first for alignment
+                account(USER, &[]),
+                account([4u8; 32], &[]), // brand-new user account
+                account([5u8; 32], &[]), // brand-new position account
+                account(MARKET, &[]),
+            ];
+            with_account_infos(&accounts, |accounts| {
+                let [position, user, user_account, new_position, market_account] = accounts else { unreachable!() };
+                let run = |position| super::preflight_accounts(user, user_account, position, market_account, &market, 5, margin, 0);
+                let results = (run(position), run(new_position));
+                // Nothing is created whatever the outcome
+                assert!(user_account.data_is_empty() && new_position.data_is_empty());
+                results
+            })
         };
 
-        // Create instruction data with proper size (25 bytes total)
-        let mut instruction_data = vec![0u8; 25];
-        instruction_data[0] = MARKET_ID as u8;
-        instruction_data[1..17].copy_from_slice(&i128::from(10).to_le_bytes());
-        instruction_data[17..25].copy_from_slice(&1000u64.to_le_bytes());
+        // A brand-new user opening on someone else's position is rejected
+        // before the user account CreateAccount runs
+        let (existing, _) = preflight([9u8; 32], 100);
+        assert_eq!(existing, Err(pinocchio::program_error::ProgramError::InvalidAccountData));
+        // So is one whose margin misses the floor, with both accounts still to create
+        let (_, fresh) = preflight(USER, 99);
+        assert_eq!(fresh, Err(super::PerpError::MarginBelowMinimum.into()));
+
+        let (existing, fresh) = preflight(USER, 100);
+        assert_eq!(existing, Ok(AccountsToCreate { user_account: true, position: false }));
+        assert_eq!(fresh, Ok(AccountsToCreate { user_account: true, position: true }));
+    }
 
-        let instruction = Instruction {
-            program_id: PROGRAM_ID,
-            accounts: vec![
-                AccountMeta::new(USER, true),                              // 1. user
-                AccountMeta::new(AUTHORITY, false),                        // 2. market_authority
-                AccountMeta::new(COLLATERAL_MINT, false),                 // 3. collateral_mint
-                AccountMeta::new(USER_MINT, false),                       // 4. user_mint
-                AccountMeta::new(market_account_pda, false),              // 5. market_account
-                AccountMeta::new(user_account_pda, false),                // 6. user_account
-                AccountMeta::new(collateral_vault_pda, false),            // 7. collateral_vault
-                AccountMeta::new(user_token_account_pubkey, false),       // 8. user_token_account
-                AccountMeta::new(user_position_account_pda, false),       // 9. user_position_account
-                AccountMeta::new_readonly(price_update_pubkey, false),    // 10. pyth_price_account
-                AccountMeta::new_readonly(system_program_id, false),      // 11. system_program
-                AccountMeta::new_readonly(token_program, false),          // 12. token_program
-                AccountMeta::new_readonly(clock_id, false)                // 13. clock_sysvar
-            ],
-            data: instruction_data,
+    #[test]
+    fn test_position_from_another_market_rejected() {
+        let position = crate::states::Position {
+            user: [2u8; 32],
+            market: [7u8; 32],
+            ..Default::default()
         };
+        assert_eq!(
+            super::check_position_market(&position, &[8u8; 32]),
+            Err(crate::errors::PerpError::WrongPositionForMarket.into())
+        );
+        assert!(super::check_position_market(&position, &[7u8; 32]).is_ok());
+    }
 
-        // Create all the accounts
-        let user = Account {
-            lamports: solana_sdk::native_token::LAMPORTS_PER_SOL,
-            data: vec![],
-            owner: solana_sdk::system_program::id(),
-            executable: false,
-            rent_epoch: 0,
-        };
+    #[test]
+    fn test_realistic_market_accepted_and_each_mismatch_rejected() {
+        use crate::states::{with_account_info, Market};
+
+        const MARKET_AUTHORITY: [u8; 32] = [1u8; 32];
+        const VAULT: [u8; 32] = [4u8; 32];
+        const MINT: [u8; 32] = [3u8; 32];
+
+        // A fully initialized market, not just the is_initialized byte
+        let mut data = vec![0u8; Market::SIZE];
+        data[..8].copy_from_slice(&Market::DISCRIMINATOR);
+        data[core::mem::offset_of!(Market, is_initialized)] = 1;
+        for (offset, key) in [
+            (core::mem::offset_of!(Market, seed_authority), MARKET_AUTHORITY),
+            (core::mem::offset_of!(Market, collateral_vault), VAULT),
+            (core::mem::offset_of!(Market, collateral_mint), MINT),
+        ] {
+            data[offset..offset + 32].copy_from_slice(&key);
+        }
 
-        let user_account = Account {
-            lamports: solana_sdk::native_token::LAMPORTS_PER_SOL,
-            data: vec![],
-            owner: solana_sdk::system_program::id(),
-            executable: false,
-            rent_epoch: 0,
-        };
+        with_account_info(&data, |account| {
+            let market = Market::load_initialized_mut(account).unwrap();
+            assert!(super::check_market_accounts(&market, &MARKET_AUTHORITY, &VAULT, &MINT).is_ok());
+
+            // Defaults no longer slip through, and each field is checked on its own
+            let invalid = Err(pinocchio::program_error::ProgramError::InvalidAccountData);
+            assert_eq!(super::check_market_accounts(&market, &[0u8; 32], &[0u8; 32], &[0u8; 32]), invalid);
+            assert_eq!(super::check_market_accounts(&market, &[9u8; 32], &VAULT, &MINT), invalid);
+            assert_eq!(super::check_market_accounts(&market, &MARKET_AUTHORITY, &[9u8; 32], &MINT), invalid);
+            assert_eq!(super::check_market_accounts(&market, &MARKET_AUTHORITY, &VAULT, &[9u8; 32]), invalid);
+        });
+    }
 
-        let authority_account = Account {
-            lamports: solana_sdk::native_token::LAMPORTS_PER_SOL,
-            data: vec![],
-            owner: solana_sdk::system_program::id(),
-            executable: false,
-            rent_epoch: 0,
-        };
+    #[test]
+    fn test_vault_pda_not_recorded_by_market_rejected() {
+        const VAULT_PDA: [u8; 32] = [4u8; 32];
+        let vault_mismatch = Err(crate::errors::PerpError::VaultMismatch.into());
+
+        // Valid as the market's vault PDA, but the market recorded another vault
+        let market = crate::states::Market { collateral_vault: [6u8; 32], ..Default::default() };
+        assert_eq!(super::check_collateral_vault(&market, &VAULT_PDA, &VAULT_PDA), vault_mismatch);
 
-        let collateral_mint_account = Account {
-            lamports: 0,
-            data: vec![0; 82],
-            owner: token_program,
-            executable: false,
-            rent_epoch: 0,
+        // Recorded by the market but not at the PDA: same error
+        let market = crate::states::Market { collateral_vault: [6u8; 32], ..Default::default() };
+        assert_eq!(super::check_collateral_vault(&market, &VAULT_PDA, &[6u8; 32]), vault_mismatch);
+
+        let market = crate::states::Market { collateral_vault: VAULT_PDA, ..Default::default() };
+        assert!(super::check_collateral_vault(&market, &VAULT_PDA, &VAULT_PDA).is_ok());
+    }
+
+    #[test]
+    fn test_vault_must_cover_total_collateral() {
+        use crate::states::{with_account_infos, Market, TestAccount};
+        use pinocchio_token::state::TokenAccount;
+
+        // Vault token account holding 1_000 (amount lives at bytes 64..72)
+        let mut data = [0u8; TokenAccount::LEN];
+        data[64..72].copy_from_slice(&1_000u64.to_le_bytes());
+        data[108] = 1;
+        let vault = TestAccount { key: [4u8; 32], owner: pinocchio_token::ID, is_signer: false, is_writable: true, lamports: 0, data: &data };
+
+        with_account_infos(&[vault], |accounts| {
+            let vault = TokenAccount::from_account_info(&accounts[0]).unwrap();
+            assert!(super::assert_vault_solvent(&Market { total_collateral: 1_000, ..Default::default() }, &vault).is_ok());
+            // Fees left in the vault can put it above the accounting
+            assert!(super::assert_vault_solvent(&Market { total_collateral: 900, ..Default::default() }, &vault).is_ok());
+            assert_eq!(
+                super::assert_vault_solvent(&Market { total_collateral: 1_001, ..Default::default() }, &vault),
+                Err(pinocchio::program_error::ProgramError::InvalidAccountData)
+            );
+        });
+    }
+
+    #[test]
+    fn test_adding_to_a_position_re_emits_its_liquidation_price() {
+        use crate::events::PositionUpdated;
+        use crate::states::Position;
+
+        // 10 long at $150 with $150 of 6-decimal margin, 500 bps maintenance
+        let mut position = Position {
+            size: 10,
+            entry_price: 150_00000000,
+            margin: 150_000_000,
+            is_active: 1,
+            ..Position::default()
         };
+        let opened = super::position_updated([5u8; 32], &position, 500, 6).unwrap();
+        let opened = PositionUpdated::from_bytes(&opened.to_bytes()).unwrap();
+        // (1_500 - 150) / (10 * 0.95) = 142.105..., rounded up
+        assert_eq!(opened.liquidation_price, 142_10526316);
+
+        // Adding 10 at $160 with another $150 averages the entry to $155
+        let fill = super::OraclePrice { price: 160_00000000, publish_time: 0, conf_bps: 0 };
+        super::update_existing_position(&mut position, 10, fill, 150_000_000, 0, PRICE_DECIMALS, 0).unwrap();
+        let added = super::position_updated([5u8; 32], &position, 500, 6).unwrap();
+        assert_eq!(added.entry_price, 155_00000000);
+        assert_eq!(added.size, 20);
+        // (3_100 - 300) / (20 * 0.95) = 147.368...
+        assert_eq!(added.liquidation_price, 147_36842106);
+        assert!(added.liquidation_price > opened.liquidation_price);
+    }
+
+    #[test]
+    fn test_fee_breakdown_reconciles_with_deduction() {
+        let position_value = super::calculate_position_value(-7, 150_00000000).unwrap();
+        let margin = position_value / 10;
+        let trading_fee = super::calculate_trading_fee(position_value, 7).unwrap();
+        let total_deducted = margin + trading_fee;
+
+        let event = super::fee_breakdown([5u8; 32], margin, trading_fee);
+        let decoded = crate::events::FeeBreakdown::from_bytes(&event.to_bytes()).unwrap();
+        assert_eq!(
+            decoded.insurance_contribution + decoded.treasury_contribution + decoded.maker_rebate,
+            decoded.trading_fee
+        );
+        assert_eq!(decoded.margin + decoded.trading_fee, total_deducted);
+    }
+
+    #[test]
+    fn test_open_funded_from_native_sol_reconciles() {
+        const RENT: u64 = 2_039_280;
+
+        // 1 SOL of margin and a 0.005 SOL fee, 0.3 SOL of it covered by free margin
+        let total_required = 1_005_000_000;
+        let free_balance = 300_000_000;
+        let (from_balance, from_transfer) = super::split_margin_sources(total_required, free_balance);
+        let wrap = super::sol_wrap_plan(from_transfer, RENT).unwrap().unwrap();
+
+        // Only the shortfall is wrapped, on top of the account's rent
+        assert_eq!(wrap.lamports, 705_000_000 + RENT);
+        assert_eq!(wrap.to_vault, 705_000_000);
+        // Everything wrapped is either deposited or unwrapped, and only the rent comes back
+        assert_eq!(wrap.to_vault + wrap.refund, wrap.lamports);
+        assert_eq!(wrap.refund, RENT);
+        // The signer ends down exactly what the vault received
+        assert_eq!(wrap.lamports - wrap.refund, from_transfer);
+        assert_eq!(from_balance + wrap.to_vault, total_required);
+    }
+
+    #[test]
+    fn test_open_covered_by_free_margin_wraps_nothing() {
+        let (_, from_transfer) = super::split_margin_sources(1_005_000_000, 2_000_000_000);
+        assert_eq!(from_transfer, 0);
+        assert_eq!(super::sol_wrap_plan(from_transfer, 2_039_280), Ok(None));
+    }
+
+    #[test]
+    fn test_margin_requirement_in_collateral_units() {
+        // 10 contracts at $150 is $1_500 of notional, 1_500 USDC at 6 decimals
+        let position_value = super::notional_in_collateral(10, 150_00000000, 6).unwrap();
+        assert_eq!(position_value, 1_500_000_000);
+
+        // 10% initial margin is 150 USDC, a 10 bps fee 1.5 USDC
+        assert_eq!(super::calculate_required_margin(position_value, 1_000), Ok(150_000_000));
+        assert_eq!(super::calculate_trading_fee(position_value, 10), Ok(1_500_000));
+        assert!(super::check_leverage(position_value, 150_000_000, 10).is_ok());
+
+        // A 9-decimal mint needs a thousand times more units for the same dollars
+        assert_eq!(super::notional_in_collateral(10, 150_00000000, 9), Ok(1_500_000_000_000));
+    }
+
+    #[test]
+    fn test_entry_price_snapped_to_tick() {
+        let market = crate::states::Market { tick_size: 1_000_000, ..Default::default() };
+        let raw = 155_12345678;
+
+        let long_fill = super::OraclePrice { price: market.snap_to_tick(raw, 10).unwrap(), publish_time: 0, conf_bps: 0 };
+        let short_fill = super::OraclePrice { price: market.snap_to_tick(raw, -10).unwrap(), publish_time: 0, conf_bps: 0 };
+        assert_eq!(long_fill.price, 155_13000000);
+        assert_eq!(short_fill.price, 155_12000000);
+
+        let mut long = crate::states::Position::default();
+        super::update_existing_position(&mut long, 10, long_fill, 100, 0, PRICE_DECIMALS, 0).unwrap();
+        assert_eq!(long.entry_price, 155_13000000);
+
+        let mut short = crate::states::Position::default();
+        super::update_existing_position(&mut short, -10, short_fill, 100, 0, PRICE_DECIMALS, 0).unwrap();
+        assert_eq!(short.entry_price, 155_12000000);
+
+        // On-grid prices and markets without a grid are left alone
+        assert_eq!(market.snap_to_tick(155_00000000, 10), Ok(155_00000000));
+        assert_eq!(crate::states::Market::default().snap_to_tick(raw, -10), Ok(raw));
+    }
+
+    #[test]
+    fn test_partial_fill_at_open_interest_cap() {
+        let mut market = crate::states::Market { max_open_interest: 1_000, open_interest_long: 940, ..Default::default() };
+
+        // 100 long only has room for 60, margin shrinks with it
+        let capacity = market.open_interest_capacity(100);
+        assert_eq!(super::fit_open_interest_cap(100, 1_000, capacity, true), Ok((60, 600)));
+        // 7 of 9 fit: 77.78 of margin rounds up
+        assert_eq!(super::fit_open_interest_cap(9, 100, 7, true), Ok((7, 78)));
+        // Shorts have their own side
+        assert_eq!(super::fit_open_interest_cap(-100, 1_000, market.open_interest_capacity(-100), true), Ok((-100, 1_000)));
+
+        // Nothing left to fill
+        market.open_interest_long = 1_000;
+        assert_eq!(
+            super::fit_open_interest_cap(100, 1_000, market.open_interest_capacity(100), true),
+            Err(crate::errors::PerpError::OpenInterestCapExceeded.into())
+        );
+    }
 
-        let user_mint_account = Account {
-            lamports: 0,
-            data: vec![0; 82],
-            owner: token_program,
-            executable: false,
-            rent_epoch: 0,
+    #[test]
+    fn test_order_over_cap_rejected_without_partial() {
+        let market = crate::states::Market { max_open_interest: 1_000, open_interest_long: 940, ..Default::default() };
+        assert_eq!(
+            super::fit_open_interest_cap(100, 1_000, market.open_interest_capacity(100), false),
+            Err(crate::errors::PerpError::OpenInterestCapExceeded.into())
+        );
+        assert_eq!(super::fit_open_interest_cap(60, 1_000, market.open_interest_capacity(60), false), Ok((60, 1_000)));
+
+        // Uncapped markets take any size
+        let uncapped = crate::states::Market::default();
+        assert_eq!(super::fit_open_interest_cap(i64::MAX as i128, 1, uncapped.open_interest_capacity(1), false), Ok((i64::MAX as i128, 1)));
+    }
+
+    #[test]
+    fn test_margin_balance_covers_half_transfer_covers_rest() {
+        assert_eq!(super::split_margin_sources(1_000, 500), (500, 500));
+    }
+
+    #[test]
+    fn test_margin_sources_edges() {
+        // Balance alone covers it, no transfer needed
+        assert_eq!(super::split_margin_sources(1_000, 5_000), (1_000, 0));
+        // No free balance, everything is transferred
+        assert_eq!(super::split_margin_sources(1_000, 0), (0, 1_000));
+    }
+
+    #[test]
+    fn test_usd_margin_converts_to_six_decimal_tokens() {
+        // $150 of margin is 150 whole tokens of a $1 collateral, whatever the base asset costs
+        assert_eq!(super::margin_in_collateral(150_00000000, true, 6), Ok(150_000_000));
+        // Sub-unit dust rounds down
+        assert_eq!(super::margin_in_collateral(1_00000099, true, 6), Ok(1_000_000));
+        // Collateral amounts pass through untouched
+        assert_eq!(super::margin_in_collateral(150_000_000, false, 6), Ok(150_000_000));
+    }
+
+    #[test]
+    fn test_wsol_collateral_rejected_until_priced() {
+        use crate::math::{quote_to_collateral, RoundingMode};
+
+        // On a 9 decimal wSOL market with SOL at $150, $150 of margin converts
+        // to 150 SOL and a $10 profit on 1 contract from 150 to 160 to 10 SOL
+        const SOL: u64 = 1_000_000_000;
+        let margin = super::margin_in_collateral(150_00000000, true, 9).unwrap();
+        let pnl = quote_to_collateral(
+            crate::instructions::calculate_realized_pnl(1, 150_00000000, 160_00000000).unwrap(),
+            9,
+            RoundingMode::Down
+        ).unwrap();
+        assert_eq!((margin, pnl), (150 * SOL, 10 * SOL as i128));
+        // Worth $22_500 and $1_500 in dollars, not $150 and $10
+        assert_eq!((margin / SOL * 150, pnl / SOL as i128 * 150), (22_500, 1_500));
+
+        // So a wSOL market can't be opened on, wrapped or not
+        assert_eq!(super::check_collateral_priced(&super::NATIVE_MINT), Err(crate::errors::PerpError::UnpricedCollateral.into()));
+        assert!(super::check_collateral_priced(&[3u8; 32]).is_ok());
+    }
+
+    fn clock(slot: u64, unix_timestamp: i64) -> pinocchio::sysvars::clock::Clock {
+        pinocchio::sysvars::clock::Clock { slot, epoch_start_timestamp: 0, epoch: 0, leader_schedule_epoch: 0, unix_timestamp }
+    }
+
+    /// The price update account the open handler reads, published `age`
+    /// seconds before `now` and posted at `posted_slot`.
+    fn sol_price_update_data(now: i64, age: i64, posted_slot: u64) -> Vec<u8> {
+        use crate::instructions::{PriceFeedMessage, PriceUpdateV2, VerificationLevel, SOL_USD_FEED_ID};
+
+        let update = PriceUpdateV2 {
+            write_authority: [0u8; 32],
+            verification_level: VerificationLevel::Full,
+            price_message: PriceFeedMessage {
+                feed_id: PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap(),
+                price: 150_00000000,
+                conf: 1_000_000,
+                exponent: -8,
+                publish_time: now - age,
+                prev_publish_time: now - age - 1,
+                ema_price: 150_00000000,
+                ema_conf: 1_000_000,
+            },
+            posted_slot,
         };
+        let mut data = vec![0u8; core::mem::size_of::<PriceUpdateV2>()];
+        unsafe { core::ptr::write_unaligned(data.as_mut_ptr() as *mut PriceUpdateV2, update) };
+        data
+    }
+
+    /// `data` in an account owned by the Pyth receiver, as the oracle checks expect.
+    fn with_price_update<R>(data: &[u8], f: impl FnOnce(&pinocchio::account_info::AccountInfo) -> R) -> R {
+        use crate::{instructions::PYTH_RECEIVER_ID, states::{with_account_infos, TestAccount}};
+
+        let account = TestAccount { key: [0u8; 32], owner: PYTH_RECEIVER_ID, is_signer: false, is_writable: false, lamports: 0, data };
+        with_account_infos(&[account], |accounts| f(&accounts[0]))
+    }
+
+    #[test]
+    fn test_spoofed_price_update_rejected() {
+        use crate::instructions::{get_price_for_feed, check_oracle_slot_lag};
+
+        const NOW: i64 = 1_700_000_000;
+        let clock = clock(0, NOW);
+        let market = crate::states::Market::default();
+
+        // Identical bytes in an account the Pyth receiver doesn't own
+        crate::states::with_account_info(&sol_price_update_data(NOW, 0, 0), |price_update| {
+            assert_eq!(get_price_for_feed(price_update, &clock, &market.oracle_config()), Err(pinocchio::program_error::ProgramError::IncorrectProgramId));
+            assert_eq!(check_oracle_slot_lag(price_update, &clock, crate::states::OracleKind::Pyth, 0), Err(pinocchio::program_error::ProgramError::IncorrectProgramId));
+        });
+    }
+
+    // Mollusk needs the deployed program; these run the oracle read the open
+    // handler does, on the same account bytes and the same 60 second max age
+    #[test]
+    fn test_open_rejects_price_older_than_max_age() {
+        use crate::instructions::{get_price_for_feed, PriceUpdateV2, SOL_USD_FEED_ID};
+
+        const NOW: i64 = 1_700_000_000;
+        let clock = clock(0, NOW);
+        let feed_id = PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap();
+
+        with_price_update(&sol_price_update_data(NOW, 120, 0), |price_update| {
+            assert_eq!(
+                get_price_for_feed(price_update, &clock, &crate::states::Market { feed_id, ..crate::states::Market::default() }.oracle_config()),
+                Err(crate::errors::PerpError::OracleStale.into())
+            );
+        });
+    }
 
-        // Market account needs to be initialized with proper Market struct data
-        let market_data_size = 200; // Adjust based on your Market struct size
-        let mut market_data = vec![0u8; market_data_size];
-        
-        // Set basic market data - adjust offsets based on your Market struct
-        market_data[0] = 1; // is_initialized = true
-        // You may need to set other fields like:
-        // - collateral_vault pubkey
-        // - collateral_mint pubkey  
-        // - initial_margin, max_leverage, fee_rate values
-        
-        let market_account = Account {
-            lamports: 1000000,
-            data: market_data,
-            owner: PROGRAM_ID, // Market account should be owned by your program
-            executable: false,
-            rent_epoch: 0,
+    #[test]
+    fn test_open_accepts_fresh_price() {
+        use crate::instructions::{get_price_for_feed, PriceUpdateV2, SOL_USD_FEED_ID};
+
+        const NOW: i64 = 1_700_000_000;
+        let clock = clock(0, NOW);
+        let feed_id = PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap();
+
+        // At exactly the max age the price is still usable
+        for age in [0, 5, 60] {
+            with_price_update(&sol_price_update_data(NOW, age, 0), |price_update| {
+                let price = get_price_for_feed(price_update, &clock, &crate::states::Market { feed_id, ..crate::states::Market::default() }.oracle_config()).unwrap();
+                assert_eq!(price.price, 150_00000000);
+                assert_eq!(price.publish_time, NOW - age);
+            });
+        }
+    }
+
+    #[test]
+    fn test_open_accepts_oracle_within_slot_lag() {
+        use crate::instructions::check_oracle_slot_lag;
+
+        const SLOT: u64 = 300_000_000;
+        let clock = clock(SLOT, 1_700_000_000);
+        let market = crate::states::Market { max_slot_lag: 25, ..crate::states::Market::default() };
+
+        // Posted this slot, a few slots back, and exactly at the limit
+        for lag in [0, 3, 25] {
+            with_price_update(&sol_price_update_data(clock.unix_timestamp, 0, SLOT - lag), |price_update| {
+                assert_eq!(check_oracle_slot_lag(price_update, &clock, crate::states::OracleKind::Pyth, market.max_slot_lag), Ok(()));
+            });
+        }
+    }
+
+    #[test]
+    fn test_open_rejects_oracle_lagging_slots() {
+        use crate::instructions::check_oracle_slot_lag;
+
+        const SLOT: u64 = 300_000_000;
+        let clock = clock(SLOT, 1_700_000_000);
+        let market = crate::states::Market { max_slot_lag: 25, ..crate::states::Market::default() };
+
+        // A fresh publish_time doesn't save an update posted 26 slots ago
+        with_price_update(&sol_price_update_data(clock.unix_timestamp, 0, SLOT - 26), |price_update| {
+            assert_eq!(
+                check_oracle_slot_lag(price_update, &clock, crate::states::OracleKind::Pyth, market.max_slot_lag),
+                Err(crate::errors::PerpError::OracleStale.into())
+            );
+            // Markets without a slot lag don't check it
+            assert_eq!(check_oracle_slot_lag(price_update, &clock, crate::states::OracleKind::Pyth, 0), Ok(()));
+        });
+    }
+
+    #[test]
+    fn test_btc_market_against_sol_feed_rejected() {
+        use crate::instructions::{PriceFeedMessage, PriceUpdateV2, VerificationLevel, BTC_USD_FEED_ID, SOL_USD_FEED_ID};
+
+        let market = crate::states::Market {
+            is_initialized: 1,
+            market_symbol: *b"BTC-PERP\0\0\0\0\0\0\0\0",
+            feed_id: PriceUpdateV2::get_feed_id_from_hex(BTC_USD_FEED_ID).unwrap(),
+            ..Default::default()
+        };
+        let sol_update = PriceUpdateV2 {
+            write_authority: [0u8; 32],
+            verification_level: VerificationLevel::Full,
+            price_message: PriceFeedMessage {
+                feed_id: PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap(),
+                price: 150_00000000,
+                conf: 1_000_000,
+                exponent: -8,
+                publish_time: 1_700_000_000,
+                prev_publish_time: 1_700_000_000 - 1,
+                ema_price: 150_00000000,
+                ema_conf: 1_000_000,
+            },
+            posted_slot: 0,
         };
+        let clock = clock(0, 1_700_000_000);
+
+        assert_eq!(
+            sol_update.get_price_for_trading(&clock, &market.oracle_config()),
+            Err(super::PerpError::OracleFeedMismatch.into())
+        );
+    }
 
-        let collateral_vault_account = Account {
-            lamports: 0,
-            data: vec![0; 165], // SPL token account size
-            owner: token_program,
-            executable: false,
-            rent_epoch: 0,
+    #[test]
+    fn test_fill_records_oracle_publish_time() {
+        use crate::instructions::{PriceFeedMessage, PriceUpdateV2, VerificationLevel};
+
+        const PUBLISH_TIME: i64 = 1_700_000_000;
+
+        let update = PriceUpdateV2 {
+            write_authority: [0u8; 32],
+            verification_level: VerificationLevel::Full,
+            price_message: PriceFeedMessage {
+                feed_id: [7u8; 32],
+                price: 150_00000000,
+                conf: 1_000_000,
+                exponent: -8,
+                publish_time: PUBLISH_TIME,
+                prev_publish_time: PUBLISH_TIME - 1,
+                ema_price: 150_00000000,
+                ema_conf: 1_000_000,
+            },
+            posted_slot: 0,
         };
+        let clock = clock(0, PUBLISH_TIME + 5);
+        let fill = update.get_price_for_trading(&clock, &crate::states::Market { feed_id: [7u8; 32], ..crate::states::Market::default() }.oracle_config()).unwrap();
+        assert_eq!(fill.publish_time, PUBLISH_TIME);
+
+        // Reopening and adding to a position both record the fill's publish time
+        let mut position = crate::states::Position::default();
+        super::update_existing_position(&mut position, 10, fill, 1_000, 0, PRICE_DECIMALS, clock.unix_timestamp).unwrap();
+        assert_eq!(position.entry_oracle_time, PUBLISH_TIME);
+
+        let later_fill = super::OraclePrice { price: 160_00000000, publish_time: PUBLISH_TIME + 30, conf_bps: 0 };
+        super::update_existing_position(&mut position, 10, later_fill, 1_000, 0, PRICE_DECIMALS, clock.unix_timestamp).unwrap();
+        assert_eq!(position.entry_oracle_time, PUBLISH_TIME + 30);
+        assert_eq!(position.entry_price, 155_00000000);
+    }
+
+    #[test]
+    fn test_opposite_side_open_rejected_without_flip() {
+        // A losing 10 long at 100 with the price at 80
+        let mut position = crate::states::Position { size: 10, entry_price: 100, margin: 1_000, is_active: 1, ..crate::states::Position::default() };
+        let fill = super::OraclePrice { price: 80, publish_time: 0, conf_bps: 0 };
+
+        // Neither shrinking nor turning it into a short at 80 may drop the 200 loss
+        for size in [-4, -10, -15] {
+            assert_eq!(super::check_same_side(&position, size), Err(pinocchio::program_error::ProgramError::InvalidInstructionData));
+            assert_eq!(
+                super::update_existing_position(&mut position, size, fill, 0, 0, PRICE_DECIMALS, 0),
+                Err(pinocchio::program_error::ProgramError::InvalidInstructionData)
+            );
+        }
+        assert_eq!((position.size, position.entry_price, position.margin), (10, 100, 1_000));
 
-        let user_token_account = Account {
-            lamports: 0,
-            data: vec![0; 165], // SPL token account size
-            owner: token_program,
-            executable: false,
-            rent_epoch: 0,
+        // Adding to it, or reopening once closed, is fine
+        assert_eq!(super::check_same_side(&position, 5), Ok(()));
+        let closed = crate::states::Position { is_active: 0, ..position };
+        assert_eq!(super::check_same_side(&closed, -5), Ok(()));
+    }
+
+    #[test]
+    fn test_pending_funding_settled_before_adding_to_a_position() {
+        use crate::states::{Market, Position};
+
+        // 10 long at 100 owes 3 of funding since its last settlement
+        let mut position = Position { size: 10, entry_price: 100, margin: 1_000, is_active: 1, ..Position::default() };
+        let funding_index = 3 * Market::FUNDING_INDEX_PRECISION / 10;
+        let fill = super::OraclePrice { price: 120, publish_time: 0, conf_bps: 0 };
+
+        let settled = super::update_existing_position(&mut position, 10, fill, 500, funding_index, PRICE_DECIMALS, 7_200).unwrap();
+        assert_eq!(settled, -3);
+        assert_eq!(position.margin, 1_497);
+        assert_eq!((position.size, position.entry_price), (20, 110));
+        assert_eq!((position.last_funding_index, position.last_funding_settlement), (funding_index, 7_200));
+
+        // Reopening a closed position applies nothing, it only starts the clock
+        let mut closed = Position { last_funding_index: 0, ..Position::default() };
+        let reopened = super::update_existing_position(&mut closed, -5, fill, 500, funding_index, PRICE_DECIMALS, 7_200).unwrap();
+        assert_eq!(reopened, 0);
+        assert_eq!(closed.margin, 500);
+        assert_eq!((closed.last_funding_index, closed.last_funding_settlement), (funding_index, 7_200));
+    }
+
+    #[test]
+    fn test_repeated_open_close_does_not_drift() {
+        use crate::instructions::{add_open_interest, calculate_close_fee, calculate_realized_pnl, is_risk_reducing, margin_share, remove_open_interest, settle_pnl};
+        use crate::math::{quote_to_collateral, RoundingMode};
+
+        // Mirrors the open and close accounting over 100 round trips at awkward
+        // prices against a 6 decimal mint, each closed in three partial chunks.
+        // total_collateral must match the vault exactly after every step.
+        const DECIMALS: u8 = 6;
+        let mut market = crate::states::Market {
+            fee_rate: 7,
+            initial_margin: 1_000,
+            close_fee_discount: 2_500,
+            collateral_decimals: DECIMALS,
+            ..crate::states::Market::default()
         };
+        // A resting short, so closes are risk reducing on some cycles and not on others
+        add_open_interest(&mut market, -50).unwrap();
+        let mut vault: u64 = 0;
+
+        for i in 0..100u64 {
+            let size = if i % 2 == 0 { 3 + 7 * i as i128 } else { -(3 + 7 * i as i128) };
+            let entry_price = 9_999_999_997 + i * 13;
+            let (vault_before, fees_before) = (vault, market.fees_collected);
+
+            // ---- Open ----
+            let open_value = super::notional_in_collateral(size, entry_price, DECIMALS).unwrap();
+            let margin = super::calculate_required_margin(open_value, market.initial_margin).unwrap();
+            let open_fee = super::calculate_trading_fee(open_value, market.fee_rate).unwrap();
+            let (_, from_transfer) = super::split_margin_sources(margin + open_fee, 0);
+            vault += from_transfer;
+            market.credit_collateral(from_transfer).unwrap();
+            market.collect_fees(open_fee).unwrap();
+            market.lock_margin(margin).unwrap();
+            add_open_interest(&mut market, size).unwrap();
+
+            // ---- Close a third, half of what's left, then the rest ----
+            let (mut remaining, mut remaining_margin) = (size, margin);
+            for step in 1..=3u64 {
+                let closed = if step == 3 { remaining } else { remaining / (4 - step as i128) };
+                // Flat round trips every third cycle, otherwise sub-cent moves either way
+                let exit_price = match i % 3 {
+                    0 => entry_price,
+                    1 => entry_price + 1_111 * step,
+                    _ => entry_price - 777 * step,
+                };
+
+                let closed_margin = if closed == remaining {
+                    remaining_margin
+                } else {
+                    margin_share(remaining_margin, closed, remaining).unwrap()
+                };
+                let pnl = quote_to_collateral(
+                    calculate_realized_pnl(closed, entry_price, exit_price).unwrap(),
+                    DECIMALS,
+                    RoundingMode::Down
+                ).unwrap();
+                let close_fee = calculate_close_fee(
+                    super::notional_in_collateral(closed, exit_price, DECIMALS).unwrap(),
+                    market.fee_rate,
+                    market.close_fee_discount,
+                    is_risk_reducing(closed, market.open_interest_long, market.open_interest_short)
+                ).unwrap();
+                let settled = settle_pnl(closed_margin, pnl - close_fee as i128);
+                assert_eq!(settled.deficit, 0);
+
+                let payout = settled.equity.min(vault);
+                vault -= payout;
+                market.debit_collateral(payout);
+                market.release_margin(closed_margin);
+                market.collect_fees(close_fee).unwrap();
+                remove_open_interest(&mut market, closed);
+                remaining -= closed;
+                remaining_margin -= closed_margin;
+
+                assert_eq!(market.total_collateral, vault, "drift after round trip {} step {}", i, step);
+            }
+
+            // Partial closes hand back every unit of margin, none is stranded
+            assert_eq!((remaining, remaining_margin, market.locked_margin), (0, 0, 0));
+            // A flat round trip leaves exactly the booked fees behind
+            if i % 3 == 0 {
+                assert_eq!(vault - vault_before, market.fees_collected - fees_before);
+            }
+        }
+        assert_eq!((market.open_interest_long, market.open_interest_short), (0, 50));
+    }
+
+    #[test]
+    fn test_fresh_funding_market_opens_at_current_index() {
+        use crate::states::{Market, Position};
+
+        let mut market = Market { funding_rate: 10, funding_interval: 3600, ..Market::default() };
+        market.accrue_funding(100, 1).unwrap();
+        market.accrue_funding(100, 1 + 3600).unwrap();
+
+        // Opening a minute after the last crank only accrues that minute
+        let index_before = market.cumulative_funding_index;
+        market.accrue_funding(100, 1 + 3660).unwrap();
+        assert_eq!(market.cumulative_funding_index - index_before, Market::FUNDING_INDEX_PRECISION / 600);
+
+        let opened = Position { size: 10, is_active: 1, last_funding_index: market.cumulative_funding_index, ..Position::default() };
+        assert_eq!(opened.pending_funding(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), 0);
+    }
 
-        let user_position_account = Account {
-            lamports: 0,
-            data: vec![],
-            owner: solana_sdk::system_program::id(), // Will be created by the program
-            executable: false,
-            rent_epoch: 0,
+    #[test]
+    fn test_stale_funding_market_is_cranked_on_open() {
+        use crate::states::{Market, Position};
+
+        let mut market = Market { funding_rate: 10, funding_interval: 3600, ..Market::default() };
+        market.accrue_funding(100, 1).unwrap();
+        let existing = Position { size: 10, is_active: 1, last_funding_index: market.cumulative_funding_index, ..Position::default() };
+
+        // Nobody cranked for 30 intervals, the open catches the market up
+        let now = 1 + 30 * 3600;
+        market.accrue_funding(100, now).unwrap();
+        assert_eq!(market.last_funding_time, now);
+        let opened = Position { size: 10, is_active: 1, last_funding_index: market.cumulative_funding_index, ..Position::default() };
+
+        // The whole stale window lands on the position that was open during it
+        assert_eq!(existing.pending_funding(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), -30);
+        assert_eq!(opened.pending_funding(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_flip_long_to_short_with_separate_margins() {
+        use crate::states::{Position, UserAccount};
+
+        let mut position = Position { size: 10, entry_price: 100, margin: 200, is_active: 1, ..Position::default() };
+        let mut user_account = UserAccount {
+            discriminator: UserAccount::DISCRIMINATOR,
+            owner: [2u8; 32],
+            margin_balance: 200,
+            open_positions: [[0u8; 32]; 10],
+            deposit_time: 0,
+            collateral_mint: [0u8; 32],
         };
+        super::check_flip(&position, 10, -5).unwrap();
+
+        // Close the 10 long at 110 with a 3 unit close fee
+        let closed = super::close_for_flip(&mut position, &mut user_account, 0, 110, 3, PRICE_DECIMALS).unwrap();
+        assert_eq!(closed.realized_pnl, 100);
+        assert_eq!(closed.settled.equity, 200 + 100 - 3);
+        assert_eq!(user_account.margin_balance, 297);
+        assert_eq!((closed.margin, closed.funding_delta), (200, 0));
+        assert!(!position.is_open());
+        assert_eq!(position.margin, 0);
+
+        // Open 5 short with its own margin, nothing carried over from the long
+        let fill = super::OraclePrice { price: 110, publish_time: 0, conf_bps: 0 };
+        super::update_existing_position(&mut position, -5, fill, 50, 0, PRICE_DECIMALS, 0).unwrap();
+        assert_eq!(position.size, -5);
+        assert_eq!(position.margin, 50);
+        assert_eq!(position.entry_price, 110);
+    }
 
-        let price_update_account = Account {
-            lamports: 0,
-            data: vec![0; 200], // Pyth price account size
-            owner: PROGRAM_ID, // Or actual Pyth program ID
-            executable: false,
-            rent_epoch: 0,
+    #[test]
+    fn test_flip_releases_margin_with_its_settled_funding() {
+        use crate::states::{Market, Position, UserAccount};
+
+        // 10 long on 200 margin holding 6 of received funding, then owing 4 more
+        let mut position = Position { size: 10, entry_price: 100, margin: 200, funding_payment: 6, is_active: 1, ..Position::default() };
+        let mut user_account = UserAccount {
+            discriminator: UserAccount::DISCRIMINATOR,
+            owner: [2u8; 32],
+            margin_balance: 200,
+            open_positions: [[0u8; 32]; 10],
+            deposit_time: 0,
+            collateral_mint: [0u8; 32],
         };
+        let mut market = Market { locked_margin: 200, ..Market::default() };
+        let funding_index = Market::FUNDING_INDEX_PRECISION * 2 / 5;
+
+        let closed = super::close_for_flip(&mut position, &mut user_account, funding_index, 100, 0, PRICE_DECIMALS).unwrap();
+        assert_eq!((closed.margin, closed.funding_delta), (202, 2));
+        assert_eq!(user_account.margin_balance, 202);
 
-        let token_program_account = Account {
-            lamports: 0,
-            data: vec![],
-            owner: Pubkey::default(),
-            executable: true,
-            rent_epoch: 0,
+        // Locked margin moves with the funding, so the close releases all of it
+        market.apply_margin_delta(closed.funding_delta).unwrap();
+        market.release_margin(closed.margin);
+        assert_eq!(market.locked_margin, 0);
+    }
+
+    #[test]
+    fn test_removed_position_slot_is_reused() {
+        use crate::states::UserAccount;
+
+        let mut user_account = UserAccount {
+            discriminator: UserAccount::DISCRIMINATOR,
+            owner: [2u8; 32],
+            margin_balance: 0,
+            open_positions: [[0u8; 32]; 10],
+            deposit_time: 0,
+            collateral_mint: [0u8; 32],
         };
+        for key in [[7u8; 32], [8u8; 32], [9u8; 32]] {
+            super::add_position_to_user(&mut user_account, &key).unwrap();
+        }
+
+        super::remove_position_from_user(&mut user_account, &[8u8; 32]).unwrap();
+        assert_eq!(user_account.open_positions[..3], [[7u8; 32], [0u8; 32], [9u8; 32]]);
+        // Removing it again finds nothing
+        assert_eq!(
+            super::remove_position_from_user(&mut user_account, &[8u8; 32]),
+            Err(pinocchio::program_error::ProgramError::InvalidAccountData)
+        );
+
+        // The next position takes the freed slot
+        super::add_position_to_user(&mut user_account, &[10u8; 32]).unwrap();
+        assert_eq!(user_account.open_positions[1], [10u8; 32]);
+    }
+
+    #[test]
+    fn test_flip_requires_whole_position_and_opposite_side() {
+        let position = crate::states::Position { size: 10, is_active: 1, ..crate::states::Position::default() };
+        let invalid = Err(pinocchio::program_error::ProgramError::InvalidInstructionData);
+        assert_eq!(super::check_flip(&position, 4, -5), invalid);
+        assert_eq!(super::check_flip(&position, 10, 5), invalid);
+
+        let closed = crate::states::Position { is_active: 0, ..position };
+        assert_eq!(super::check_flip(&closed, 10, -5), invalid);
+    }
+
+    #[test]
+    fn test_open_under_each_market_status() {
+        use crate::{errors::PerpError, states::{Market, MarketStatus}};
+
+        let market = |status: MarketStatus| Market { status: status as u8, ..Market::default() };
 
-        mollusk.process_and_validate_instruction(
-            &instruction,
-            &vec![
-                (USER, user),
-                (AUTHORITY, authority_account),
-                (COLLATERAL_MINT, collateral_mint_account),
-                (USER_MINT, user_mint_account),
-                (market_account_pda, market_account),
-                (user_account_pda, user_account),
-                (collateral_vault_pda, collateral_vault_account),
-                (user_token_account_pubkey, user_token_account), // This was missing!
-                (user_position_account_pda, user_position_account),
-                (price_update_pubkey, price_update_account),
-                (system_program_id, system_account),
-                (token_program, token_program_account),
-                (clock_id, clock_account),
+        assert!(super::check_market_status(&market(MarketStatus::Active)).is_ok());
+        assert_eq!(super::check_market_status(&market(MarketStatus::Paused)), Err(PerpError::MarketPaused.into()));
+        // Even an order that would shrink a position is refused, it has to go through ClosePosition
+        assert_eq!(super::check_market_status(&market(MarketStatus::ReduceOnly)), Err(PerpError::ReduceOnly.into()));
+    }
+
+    /// A brand-new user's open on a fully initialized SOL-PERP market, with
+    /// SPL Token loaded into Mollusk and real token accounts on both sides.
+    struct OpenFixture {
+        mollusk: Mollusk,
+        instruction: Instruction,
+        accounts: Vec<(Pubkey, Account)>,
+        market_account: Pubkey,
+        collateral_vault: Pubkey,
+        user_token_account: Pubkey,
+    }
+
+    fn open_fixture(market: impl FnOnce(&mut crate::states::Market), user_tokens: u64, size: i128, margin: u64) -> OpenFixture {
+        use crate::instructions::fixtures::{self, AUTHORITY, COLLATERAL_MINT, MARKET_ID, PROGRAM_ID, USER};
+        use mollusk_svm_programs_token::token;
+
+        let mollusk = fixtures::mollusk();
+        let keys = fixtures::keys();
+        let (global_config, _) = Pubkey::find_program_address(&[b"global_config"], &PROGRAM_ID);
+        let (delegation, _) = Pubkey::find_program_address(&[b"delegation", USER.as_ref()], &PROGRAM_ID);
+        let user_token_account = Pubkey::new_unique();
+        let price_update = Pubkey::new_unique();
+
+        let mut market_data = fixtures::market(&keys);
+        market(&mut market_data);
+
+        // Instruction discriminator followed by the 32 bytes of OpenPositionArgs
+        let mut data = vec![crate::instructions::PerpetualInstructions::OpenPosition as u8];
+        data.extend_from_slice(&MARKET_ID.to_le_bytes());
+        data.extend_from_slice(&size.to_le_bytes());
+        data.extend_from_slice(&margin.to_le_bytes());
+
+        let (system_program, system_account) = program::keyed_account_for_system_program();
+        let (clock_id, clock_account) = mollusk.sysvars.keyed_account_for_clock_sysvar();
+        let instruction = Instruction {
+            program_id: PROGRAM_ID,
+            accounts: vec![
+                AccountMeta::new_readonly(USER, false),                     // user
+                AccountMeta::new_readonly(AUTHORITY, false),                // market_authority
+                AccountMeta::new_readonly(COLLATERAL_MINT, false),          // collateral_mint
+                AccountMeta::new_readonly(COLLATERAL_MINT, false),          // user_mint
+                AccountMeta::new(keys.market_account, false),               // market_account
+                AccountMeta::new(keys.user_account, false),                 // user_account, created by the open
+                AccountMeta::new(keys.collateral_vault, false),             // collateral_vault
+                AccountMeta::new(user_token_account, false),                // user_token_account
+                AccountMeta::new(keys.user_position_account, false),        // user_position_account, created by the open
+                AccountMeta::new_readonly(price_update, false),             // pyth_price_account
+                AccountMeta::new_readonly(system_program, false),           // system_program
+                AccountMeta::new_readonly(token::ID, false),                // token_program
+                AccountMeta::new_readonly(clock_id, false),                 // clock_sysvar
+                AccountMeta::new_readonly(global_config, false),            // global_config, never created
+                AccountMeta::new(USER, true),                               // authority, the user itself
+                AccountMeta::new_readonly(delegation, false),               // delegation, unread when the user signs
+                AccountMeta::new(keys.insurance_vault, false),              // insurance_vault, only drawn on a flip
             ],
-            &[Check::success()],
+            data,
+        };
+
+        let accounts = vec![
+            (USER, fixtures::wallet()),
+            (AUTHORITY, fixtures::wallet()),
+            (COLLATERAL_MINT, fixtures::mint(user_tokens)),
+            (keys.market_account, fixtures::program_account(bytemuck::bytes_of(&market_data))),
+            (keys.user_account, Account::default()),
+            (keys.collateral_vault, fixtures::token_account(&keys.market_account, 0)),
+            (user_token_account, fixtures::token_account(&USER, user_tokens)),
+            (keys.user_position_account, Account::default()),
+            (price_update, fixtures::price_update(150_00000000)),
+            (system_program, system_account),
+            token::keyed_account(),
+            (clock_id, clock_account),
+            (global_config, Account::default()),
+            (delegation, Account::default()),
+            (keys.insurance_vault, fixtures::token_account(&keys.market_account, 0)),
+        ];
+
+        OpenFixture {
+            mollusk,
+            instruction,
+            accounts,
+            market_account: keys.market_account,
+            collateral_vault: keys.collateral_vault,
+            user_token_account,
+        }
+    }
+
+    #[test]
+    fn test_open_moves_margin_and_fee_into_vault() {
+        use crate::instructions::fixtures::{read, token_balance};
+        use crate::states::Market;
+
+        // 10 SOL at $150 is $1_500 of notional, so $200 of margin and a 10 bps fee of $1.50
+        let fixture = open_fixture(|_| {}, 1_000_000_000, 10, 200_000_000);
+        let result = fixture.mollusk.process_and_validate_instruction(&fixture.instruction, &fixture.accounts, &[Check::success()]);
+
+        // The fee stays in the vault alongside the margin
+        assert_eq!(token_balance(result.get_account(&fixture.collateral_vault).unwrap()), 201_500_000);
+        assert_eq!(token_balance(result.get_account(&fixture.user_token_account).unwrap()), 1_000_000_000 - 201_500_000);
+
+        let market: Market = read(result.get_account(&fixture.market_account).unwrap());
+        assert_eq!(market.total_collateral, 201_500_000);
+        assert_eq!((market.fees_collected, market.locked_margin), (1_500_000, 200_000_000));
+    }
+
+    #[test]
+    fn test_paused_market_refuses_opens() {
+        use crate::{errors::PerpError, instructions::fixtures::token_balance, states::MarketStatus};
+
+        let fixture = open_fixture(|market| market.status = MarketStatus::Paused as u8, 1_000_000_000, 10, 200_000_000);
+        let result = fixture.mollusk.process_and_validate_instruction(
+            &fixture.instruction,
+            &fixture.accounts,
+            &[Check::err(solana_sdk::program_error::ProgramError::Custom(PerpError::MarketPaused as u32))]
         );
+        assert_eq!(token_balance(result.get_account(&fixture.user_token_account).unwrap()), 1_000_000_000);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_process_open_position() {
+        use crate::instructions::fixtures::{self, read, USER};
+        use crate::states::{Position, UserAccount};
+
+        // A brand-new user opens 10 long at $150 on $200 of margin
+        let fixture = open_fixture(|_| {}, 1_000_000_000, 10, 200_000_000);
+        let result = fixture.mollusk.process_and_validate_instruction(&fixture.instruction, &fixture.accounts, &[Check::success()]);
+
+        let keys = fixtures::keys();
+        let position: Position = read(result.get_account(&keys.user_position_account).unwrap());
+        assert_eq!(position.discriminator, Position::DISCRIMINATOR);
+        assert_eq!((position.user, position.market), (USER.to_bytes(), fixture.market_account.to_bytes()));
+        assert_eq!((position.size, position.entry_price, position.margin), (10, 150_00000000, 200_000_000));
+        assert!(position.is_open());
+
+        let user_account: UserAccount = read(result.get_account(&keys.user_account).unwrap());
+        assert_eq!(user_account.owner, USER.to_bytes());
+        assert_eq!(user_account.margin_balance, 200_000_000);
+        assert_eq!(user_account.open_positions[0], keys.user_position_account.to_bytes());
+    }
+}
@@ -0,0 +1,120 @@
+use pinocchio::{account_info::AccountInfo, program_error::ProgramError, ProgramResult};
+
+use crate::{
+    instructions::load_market_as_authority,
+    states::MarketStatus,
+};
+
+/// Instruction data: [status: u8]
+///
+/// Either Paused (no opens) or ReduceOnly (no opens, positions can only be
+/// shrunk through ClosePosition). Closes go through in both.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PauseMarketArgs {
+    pub status: MarketStatus,
+}
+
+impl PauseMarketArgs {
+    pub const LEN: usize = 1;
+}
+
+impl TryFrom<&[u8]> for PauseMarketArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let status = match data[0] {
+            s if s == MarketStatus::Paused as u8 => MarketStatus::Paused,
+            s if s == MarketStatus::ReduceOnly as u8 => MarketStatus::ReduceOnly,
+            _ => return Err(ProgramError::InvalidInstructionData),
+        };
+
+        Ok(Self { status })
+    }
+}
+
+/// Pauses the market or puts it in reduce-only mode. Market authority only.
+pub fn process_pause_market(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+    let mut market = load_market_as_authority(accounts)?;
+    let PauseMarketArgs { status } = PauseMarketArgs::try_from(instruction_data)?;
+    market.status = status as u8;
+
+    println!("Market status: {:?}", status);
+
+    Ok(())
+}
+
+/// Reopens the market to all orders. Market authority only.
+pub fn process_resume_market(accounts: &[AccountInfo]) -> ProgramResult {
+    let mut market = load_market_as_authority(accounts)?;
+    market.status = MarketStatus::Active as u8;
+
+    println!("Market status: {:?}", MarketStatus::Active);
+
+    Ok(())
+}
+
+// =========================== TESTING process_pause_market ===========================
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::{process_pause_market, process_resume_market, PauseMarketArgs};
+    use crate::states::{with_account_infos, Market, MarketStatus, TestAccount};
+
+    const AUTHORITY: [u8; 32] = [1u8; 32];
+
+    fn market_bytes() -> Vec<u8> {
+        let mut data = vec![0u8; Market::SIZE];
+        data[..8].copy_from_slice(&Market::DISCRIMINATOR);
+        data[core::mem::offset_of!(Market, is_initialized)] = 1;
+        let authority = core::mem::offset_of!(Market, authority);
+        data[authority..authority + 32].copy_from_slice(&AUTHORITY);
+        data
+    }
+
+    fn accounts(signer: [u8; 32], market: &[u8]) -> [TestAccount<'_>; 2] {
+        [
+            TestAccount { key: signer, owner: [0u8; 32], is_signer: true, is_writable: false, lamports: 0, data: &[] },
+            TestAccount { key: [2u8; 32], owner: crate::ID, is_signer: false, is_writable: true, lamports: 0, data: market },
+        ]
+    }
+
+    #[test]
+    fn test_pause_args_accept_paused_and_reduce_only() {
+        assert_eq!(PauseMarketArgs::try_from(&[1u8][..]).unwrap().status, MarketStatus::Paused);
+        assert_eq!(PauseMarketArgs::try_from(&[2u8][..]).unwrap().status, MarketStatus::ReduceOnly);
+        // Active goes through ResumeMarket
+        assert_eq!(PauseMarketArgs::try_from(&[0u8][..]), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(PauseMarketArgs::try_from(&[3u8][..]), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(PauseMarketArgs::try_from(&[][..]), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_authority_pauses_and_resumes() {
+        let data = market_bytes();
+        with_account_infos(&accounts(AUTHORITY, &data), |accounts| {
+            process_pause_market(accounts, &[MarketStatus::ReduceOnly as u8]).unwrap();
+            assert_eq!(Market::from_account_info(&accounts[1]).unwrap().status, MarketStatus::ReduceOnly as u8);
+
+            process_resume_market(accounts).unwrap();
+            assert!(Market::from_account_info(&accounts[1]).unwrap().allows_open());
+        });
+    }
+
+    #[test]
+    fn test_non_authority_cannot_pause() {
+        let data = market_bytes();
+        with_account_infos(&accounts([9u8; 32], &data), |accounts| {
+            assert_eq!(
+                process_pause_market(accounts, &[MarketStatus::Paused as u8]),
+                Err(ProgramError::InvalidAccountData)
+            );
+            assert_eq!(process_resume_market(accounts), Err(ProgramError::InvalidAccountData));
+        });
+    }
+}
@@ -1,8 +1,21 @@
-use pinocchio::{account_info::AccountInfo, program_error::ProgramError, pubkey::Pubkey, sysvars::clock::Clock, *};
-use pinocchio_pubkey::*;
+use pinocchio::{account_info::{AccountInfo, Ref}, program_error::ProgramError, pubkey::Pubkey, sysvars::clock::Clock, *};
 use pythnet_sdk::messages::FeedId;
 
-const SOL_USD_FEED_ID: &str = "ef0d8b6fda2ceba41da15d4095d1da392a0d2f8ed0c6c7bc0f4cfac8c280b56d";
+use crate::{errors::PerpError, instructions::{not_enough_accounts, AggregatorRound}, math::PRICE_DECIMALS, states::{Market, OracleKind, OracleSnapshot}};
+
+/// Pyth receiver program, the owner of every genuine PriceUpdateV2 account
+pub const PYTH_RECEIVER_ID: Pubkey = pinocchio_pubkey::pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+
+pub const SOL_USD_FEED_ID: &str = "ef0d8b6fda2ceba41da15d4095d1da392a0d2f8ed0c6c7bc0f4cfac8c280b56d";
+pub const BTC_USD_FEED_ID: &str = "e62df6c8b4a85fe1a67db44dc12de5db330f7ac66b72dc658afedf0f4a415b43";
+pub const ETH_USD_FEED_ID: &str = "ff61491a931112ddf1bd8147cd1b641375f79f5825126d665480874634fd0ace";
+
+/// Feeds whose base asset is known, checked against market symbols
+const KNOWN_FEEDS: [(&str, &[u8]); 3] = [
+    (SOL_USD_FEED_ID, b"SOL"),
+    (BTC_USD_FEED_ID, b"BTC"),
+    (ETH_USD_FEED_ID, b"ETH"),
+];
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum VerificationLevel {
@@ -46,6 +59,21 @@ pub struct PriceUpdateV2 {
 
 impl PriceUpdateV2 {
     pub const LEN: usize = 8 + 32 + 2 + 32 + 8 + 8 + 4 + 8 + 8 + 8 + 8 + 8;
+
+    /// Loads a price update, rejecting accounts not written by the Pyth
+    /// receiver. Anyone can lay out an account with the same bytes.
+    pub fn from_account_info(account: &AccountInfo) -> Result<Ref<'_, Self>, ProgramError> {
+        if !account.is_owned_by(&PYTH_RECEIVER_ID) {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if account.data_len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(Ref::map(account.try_borrow_data()?, |data| unsafe {
+            &*(data.as_ptr() as *const Self)
+        }))
+    }
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -56,6 +84,37 @@ pub struct Price {
     pub publish_time: i64,
 }
 
+/// A market's oracle settings, see Market::oracle_config.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct OracleConfig {
+    pub oracle_kind: OracleKind,
+    pub feed_id: FeedId,
+    pub max_age_seconds: u64,
+    pub max_publish_gap: u64,
+    pub allow_ema_fallback: bool,
+    pub use_ema: bool,
+    pub min_verification_level: VerificationLevel,
+}
+
+/// Normalized price (8 decimals) and the oracle publish time it came from.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct OraclePrice {
+    pub price: u64,
+    pub publish_time: i64,
+    pub conf_bps: u64, // Confidence interval as bps of the price, rounded up
+}
+
+impl OraclePrice {
+    /// Rejects a price whose confidence interval is wider than `max_conf_bps`
+    /// of the price. A `max_conf_bps` of zero disables the check.
+    pub fn check_confidence(&self, max_conf_bps: u64) -> Result<(), ProgramError> {
+        if max_conf_bps != 0 && self.conf_bps > max_conf_bps {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(())
+    }
+}
+
 fn decode_hex_char(c: u8) -> Result<u8, ProgramError> {
     match c {
         b'0'..=b'9' => Ok(c - b'0'),
@@ -68,7 +127,7 @@ fn decode_hex_char(c: u8) -> Result<u8, ProgramError> {
 fn decode_hex(input: &str) -> Result<Vec<u8>, ProgramError> {
     let input_bytes = input.as_bytes();
     
-    if input_bytes.len() % 2 != 0 {
+    if input_bytes.len() % 2 == 1 {
         return Err(ProgramError::InvalidInstructionData);
     }
     
@@ -89,8 +148,15 @@ impl PriceUpdateV2 {
         feed_id: &FeedId
     ) -> Result<Price, ProgramError> {
         if self.price_message.feed_id != *feed_id {
-            return Err(ProgramError::InvalidAccountData);
+            return Err(PerpError::OracleFeedMismatch.into());
         };
+        // Checked before any age math, which would report an empty account as merely stale
+        if self.price_message.publish_time <= 0 {
+            return Err(PerpError::OracleNeverPublished.into());
+        }
+        if self.price_message.price <= 0 {
+            return Err(PerpError::OracleInvalidPrice.into());
+        }
 
         Ok(Price {
             price: self.price_message.price,
@@ -111,12 +177,136 @@ impl PriceUpdateV2 {
 
         let age = clock.unix_timestamp.saturating_sub(price.publish_time);
         if age > max_age as i64 {
+            return Err(PerpError::OracleStale.into());
+        };
+
+        Ok(price)
+    }
+
+    /// EMA price of `feed_id`, no older than `max_age` seconds. The EMA shares
+    /// the update's publish_time with the spot price.
+    pub fn get_ema_price_no_older_than(
+        &self,
+        clock: &Clock,
+        max_age: u64,
+        feed_id: &FeedId
+    ) -> Result<Price, ProgramError> {
+        let spot = self.get_price_unchecked(feed_id)?;
+
+        let age = clock.unix_timestamp.saturating_sub(spot.publish_time);
+        if age > max_age as i64 {
+            return Err(PerpError::OracleStale.into());
+        };
+
+        Ok(Price {
+            price: self.price_message.ema_price,
+            conf: self.price_message.ema_conf,
+            exponent: spot.exponent,
+            publish_time: spot.publish_time,
+        })
+    }
+
+    /// Rejects intermittent feeds whose previous update is too far behind the
+    /// current one. A `max_gap` of zero disables the check.
+    pub fn check_publish_gap(&self, max_gap: u64) -> Result<(), ProgramError> {
+        if max_gap == 0 {
+            return Ok(());
+        }
+
+        let gap = self.price_message.publish_time
+            .saturating_sub(self.price_message.prev_publish_time);
+        if gap > max_gap as i64 {
+            return Err(PerpError::OracleFeedGap.into());
+        }
+
+        Ok(())
+    }
+
+    /// Rejects updates posted more than `max_slot_lag` slots before the current
+    /// slot. Slots come from the runtime rather than the publisher, so this
+    /// catches old updates a doctored publish_time would hide. A `max_slot_lag`
+    /// of zero disables the check.
+    pub fn check_slot_lag(&self, clock: &Clock, max_slot_lag: u64) -> Result<(), ProgramError> {
+        if max_slot_lag == 0 {
+            return Ok(());
+        }
+
+        let lag = clock.slot.saturating_sub(self.posted_slot);
+        if lag > max_slot_lag {
+            return Err(PerpError::OracleStale.into());
+        }
+
+        Ok(())
+    }
+
+    /// Normalized price of the configured feed, see checked_price. Digits past
+    /// PRICE_DECIMALS are lost, get_price_for_trading_raw keeps them.
+    pub fn get_price_for_trading(&self, clock: &Clock, config: &OracleConfig) -> Result<OraclePrice, ProgramError> {
+        let price = self.checked_price(clock, config)?;
+
+        Ok(OraclePrice {
+            price: normalize_pyth_price(price.price, price.exponent)?,
+            publish_time: price.publish_time,
+            conf_bps: confidence_bps(price),
+        })
+    }
+
+    /// The price get_price_for_trading normalizes, as the `(mantissa, exponent)`
+    /// pair Pyth publishes. Sub-cent and very large prices survive intact, see
+    /// math::quote_value for notional math on it.
+    pub fn get_price_for_trading_raw(&self, clock: &Clock, config: &OracleConfig) -> Result<(i128, i32), ProgramError> {
+        let price = self.checked_price(clock, config)?;
+        if price.price <= 0 {
+            return Err(PerpError::OracleInvalidPrice.into());
+        }
+        Ok((price.price as i128, price.exponent))
+    }
+
+    /// Price of the configured feed after the verification, staleness and
+    /// publish gap checks. With `use_ema` the EMA is always used, so a single
+    /// update can't move it. Otherwise with `allow_ema_fallback`, a spot price
+    /// too stale to trade on falls back to the EMA, which is smoothed enough
+    /// to be trusted for EMA_FALLBACK_AGE_MULTIPLIER times as long.
+    fn checked_price(&self, clock: &Clock, config: &OracleConfig) -> Result<Price, ProgramError> {
+        // An update signed by too few guardians is as good as unverified
+        if !self.verification_level.gte(config.min_verification_level) {
             return Err(ProgramError::InvalidAccountData);
+        }
+
+        let spot = if config.use_ema {
+            self.get_ema_price_no_older_than(clock, config.max_age_seconds, &config.feed_id)
+        } else {
+            self.get_price_no_older_than(clock, config.max_age_seconds, &config.feed_id)
         };
+        let price = match spot {
+            Ok(price) => price,
+            Err(stale) if config.allow_ema_fallback && !config.use_ema => {
+                let ema_max_age = config.max_age_seconds.saturating_mul(EMA_FALLBACK_AGE_MULTIPLIER);
+                // A failed fallback reports why the spot price was rejected
+                let ema = self.get_ema_price_no_older_than(clock, ema_max_age, &config.feed_id).map_err(|_| stale)?;
+                println!("Spot price stale, using EMA");
+                ema
+            }
+            Err(stale) => return Err(stale),
+        };
+        self.check_publish_gap(config.max_publish_gap)?;
 
         Ok(price)
     }
 
+    /// Raw oracle state to record for a settlement of `position` at `recorded_at`.
+    pub fn snapshot(&self, position: &Pubkey, recorded_at: i64) -> OracleSnapshot {
+        OracleSnapshot {
+            position: *position,
+            recorded_at,
+            price: self.price_message.price,
+            conf: self.price_message.conf,
+            exponent: self.price_message.exponent,
+            publish_time: self.price_message.publish_time,
+            slot: self.posted_slot,
+        }
+    }
+
     pub fn get_feed_id_from_hex(input: &str) -> Result<FeedId, ProgramError> {
         let mut feed_id: FeedId = [0; 32];
         
@@ -148,7 +338,7 @@ impl PriceUpdateV2 {
 pub fn fetch_sol_price(accounts: &[AccountInfo]) -> ProgramResult {
 
     let [signer, price_update_account, clock_sysvar] = accounts else {
-        return Err(ProgramError::InvalidAccountData);
+        return Err(not_enough_accounts(3, accounts.len()));
     };
 
     if !signer.is_signer() {
@@ -157,19 +347,11 @@ pub fn fetch_sol_price(accounts: &[AccountInfo]) -> ProgramResult {
 
     let clock = Clock::from_account_info(clock_sysvar)?;
 
-    let price_update_data = unsafe { price_update_account.borrow_data_unchecked() };
-
-    if price_update_data.len() < PriceUpdateV2::LEN {
-        return Err(ProgramError::InvalidAccountData);
-    }
-
-    let price_update = unsafe { 
-        &*(price_update_data.as_ptr() as *const PriceUpdateV2) 
-    };
+    let price_update = PriceUpdateV2::from_account_info(price_update_account)?;
 
     let sol_feed_id = PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID)?;
 
-    let max_age = 60;
+    let max_age = Market::DEFAULT_MAX_PRICE_AGE;
 
     let sol_price = price_update.get_price_no_older_than(&clock, max_age, &sol_feed_id)?;
 
@@ -189,52 +371,440 @@ pub fn fetch_sol_price(accounts: &[AccountInfo]) -> ProgramResult {
     Ok(())
 }
 
-pub fn get_sol_price_for_trading(
+pub fn get_price_for_feed(
     price_update_account: &AccountInfo,
     clock: &Clock,
-    max_age_seconds: u64,
-) -> Result<u64, ProgramError> {
-    
-    let price_update_data = price_update_account.try_borrow_data()?;
-    if price_update_data.len() < PriceUpdateV2::LEN {
-        return Err(ProgramError::InvalidAccountData);
+    config: &OracleConfig,
+) -> Result<OraclePrice, ProgramError> {
+    // Only Pyth publishes an EMA or verification level, Switchboard markets get the age check
+    if config.oracle_kind == OracleKind::Switchboard {
+        return AggregatorRound::from_account_info(price_update_account)?.price_no_older_than(clock, config.max_age_seconds);
     }
 
-    let price_update = unsafe { 
-        &*(price_update_data.as_ptr() as *const PriceUpdateV2) 
-    };
+    let price_update = PriceUpdateV2::from_account_info(price_update_account)?;
 
-    let sol_feed_id = PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID)?;
+    price_update.get_price_for_trading(clock, config)
+}
 
-    let sol_price = price_update.get_price_no_older_than(clock, max_age_seconds, &sol_feed_id)?;
+/// Slot freshness check on the price update account, see PriceUpdateV2::check_slot_lag.
+pub fn check_oracle_slot_lag(
+    price_update_account: &AccountInfo,
+    clock: &Clock,
+    oracle_kind: OracleKind,
+    max_slot_lag: u64,
+) -> Result<(), ProgramError> {
+    if oracle_kind == OracleKind::Switchboard {
+        return AggregatorRound::from_account_info(price_update_account)?.check_slot_lag(clock, max_slot_lag);
+    }
 
-    let price_normalized = normalize_pyth_price(sol_price)?;
-    
-    Ok(price_normalized)
+    let price_update = PriceUpdateV2::from_account_info(price_update_account)?;
+
+    price_update.check_slot_lag(clock, max_slot_lag)
 }
 
-fn normalize_pyth_price(price: Price) -> Result<u64, ProgramError> {
-    if price.price <= 0 {
+/// Writes the oracle state a close or liquidation of `position` settled at
+/// into the caller-allocated snapshot account. A snapshot already holding a
+/// record is never overwritten.
+pub fn record_oracle_snapshot(
+    snapshot_account: &AccountInfo,
+    position: &Pubkey,
+    price_update_account: &AccountInfo,
+    oracle_kind: OracleKind,
+    recorded_at: i64,
+) -> ProgramResult {
+    if !snapshot_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+    if !snapshot_account.is_writable() {
+        return Err(ProgramError::Immutable);
+    }
+    // Exact size so no other program account can be passed in and overwritten
+    if snapshot_account.data_len() != OracleSnapshot::SIZE {
         return Err(ProgramError::InvalidAccountData);
     }
 
-    let normalized_price = if price.exponent < 0 {
-        let scale_factor = 10_i64.pow((-price.exponent) as u32);
-        let target_scale = 100_000_000i64; 
-        
-        if scale_factor == target_scale {
-            price.price as u64
-        } else if scale_factor > target_scale {
-            (price.price / (scale_factor / target_scale)) as u64
-        } else {
-            (price.price * (target_scale / scale_factor)) as u64
+    let recorded = match oracle_kind {
+        OracleKind::Pyth => PriceUpdateV2::from_account_info(price_update_account)?.snapshot(position, recorded_at),
+        OracleKind::Switchboard => AggregatorRound::from_account_info(price_update_account)?.snapshot(position, recorded_at)?,
+    };
+
+    let mut snapshot = OracleSnapshot::from_account_info_mut(snapshot_account)?;
+    if snapshot.is_recorded() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    *snapshot = recorded;
+
+    Ok(())
+}
+
+/// Rejects a market whose symbol names a different asset than its feed, e.g.
+/// "BTC-PERP" priced off the SOL feed. Feeds missing from KNOWN_FEEDS can't be
+/// checked and pass.
+pub fn check_symbol_matches_feed(market_symbol: &[u8; 16], feed_id: &FeedId) -> ProgramResult {
+    for (feed_hex, asset) in KNOWN_FEEDS {
+        if PriceUpdateV2::get_feed_id_from_hex(feed_hex)? != *feed_id {
+            continue;
         }
+
+        // The asset must be the whole first token of the symbol, "SOLX" is not SOL
+        let matches = market_symbol.starts_with(asset)
+            && matches!(market_symbol.get(asset.len()), Some(b'-' | b'/' | 0) | None);
+        if !matches {
+            return Err(PerpError::SymbolFeedMismatch.into());
+        }
+        return Ok(());
+    }
+
+    println!("Market feed has no known asset, symbol not checked");
+    Ok(())
+}
+
+/// How many times the spot max age an EMA fallback may be, see get_price_for_trading.
+pub const EMA_FALLBACK_AGE_MULTIPLIER: u64 = 3;
+
+/// Largest exponent magnitude whose power of ten still fits in an i64.
+const MAX_PRICE_EXPONENT: u32 = 18;
+
+/// `conf` as bps of `price`, rounded up. Both share the exponent, so it cancels.
+/// Only called once normalize_pyth_price has accepted the price as positive.
+fn confidence_bps(price: Price) -> u64 {
+    let bps = (price.conf as u128 * 10_000).div_ceil(price.price.max(1) as u128);
+    u64::try_from(bps).unwrap_or(u64::MAX)
+}
+
+/// Scales a spot or EMA `(price, exponent)` pair to PRICE_DECIMALS. Scaling
+/// happens in u128, so only a result that doesn't fit a u64 is rejected.
+/// Digits finer than PRICE_DECIMALS are truncated.
+fn normalize_pyth_price(price: i64, exponent: i32) -> Result<u64, ProgramError> {
+    if price <= 0 || exponent.unsigned_abs() > MAX_PRICE_EXPONENT {
+        return Err(PerpError::OracleInvalidPrice.into());
+    }
+
+    // Net power of ten taking the price from `exponent` to PRICE_DECIMALS decimals
+    let shift = exponent + PRICE_DECIMALS as i32;
+    let scale = 10_u128.pow(shift.unsigned_abs());
+    let normalized = if shift >= 0 {
+        (price as u128).checked_mul(scale).ok_or(ProgramError::ArithmeticOverflow)?
     } else {
-        let multiplier = 10_i64.pow(price.exponent as u32);
-        (price.price * multiplier * 100_000_000) as u64
+        price as u128 / scale
     };
 
-    Ok(normalized_price)
+    u64::try_from(normalized).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price_update(publish_time: i64, prev_publish_time: i64) -> PriceUpdateV2 {
+        PriceUpdateV2 {
+            write_authority: [0u8; 32],
+            verification_level: VerificationLevel::Full,
+            price_message: PriceFeedMessage {
+                feed_id: [0u8; 32],
+                price: 150_00000000,
+                conf: 1_000_000,
+                exponent: -8,
+                publish_time,
+                prev_publish_time,
+                ema_price: 150_00000000,
+                ema_conf: 1_000_000,
+            },
+            posted_slot: 0,
+        }
+    }
+
+    #[test]
+    fn test_continuous_feed_accepted() {
+        let update = price_update(1_700_000_000, 1_700_000_000 - 1);
+        assert!(update.check_publish_gap(10).is_ok());
+    }
+
+    #[test]
+    fn test_gappy_feed_rejected() {
+        let update = price_update(1_700_000_000, 1_700_000_000 - 600);
+        assert_eq!(update.check_publish_gap(10), Err(PerpError::OracleFeedGap.into()));
+        // Disabled when no threshold is configured
+        assert!(update.check_publish_gap(0).is_ok());
+    }
+
+    fn clock_at(unix_timestamp: i64) -> Clock {
+        Clock { slot: 0, epoch_start_timestamp: 0, epoch: 0, leader_schedule_epoch: 0, unix_timestamp }
+    }
+
+    fn config(max_age_seconds: u64, allow_ema_fallback: bool, use_ema: bool) -> OracleConfig {
+        OracleConfig {
+            feed_id: [0u8; 32],
+            max_age_seconds,
+            max_publish_gap: 0,
+            allow_ema_fallback,
+            use_ema,
+            min_verification_level: VerificationLevel::Partial { num_signatures: 0 },
+            oracle_kind: OracleKind::Pyth,
+        }
+    }
+
+    #[test]
+    fn test_stale_spot_falls_back_to_fresh_ema() {
+        let mut update = price_update(1_700_000_000, 1_700_000_000 - 1);
+        update.price_message.ema_price = 149_00000000;
+        // 90s old: stale for a 60s spot window, within the 180s EMA window
+        let clock = clock_at(1_700_000_090);
+
+        let price = update.get_price_for_trading(&clock, &config(60, true, false)).unwrap();
+        assert_eq!((price.price, price.publish_time), (149_00000000, 1_700_000_000));
+
+        // Fresh spot is used as is
+        let fresh = update.get_price_for_trading(&clock_at(1_700_000_030), &config(60, true, false)).unwrap();
+        assert_eq!(fresh.price, 150_00000000);
+    }
+
+    #[test]
+    fn test_max_price_age_is_per_market() {
+        let update = price_update(1_700_000_000, 1_700_000_000 - 1);
+        let clock = clock_at(1_700_000_090);
+
+        let tight = Market { max_price_age: 60, ..Market::default() };
+        assert_eq!(
+            update.get_price_for_trading(&clock, &tight.oracle_config()),
+            Err(PerpError::OracleStale.into())
+        );
+
+        let loose = Market { max_price_age: 120, ..Market::default() };
+        let price = update.get_price_for_trading(&clock, &loose.oracle_config()).unwrap();
+        assert_eq!(price.price, 150_00000000);
+    }
+
+    #[test]
+    fn test_under_signed_update_rejected() {
+        let mut update = price_update(1_700_000_000, 1_700_000_000 - 1);
+        let clock = clock_at(1_700_000_010);
+        let market = Market { min_verification_level: 5, ..Market::default() };
+
+        assert!(update.get_price_for_trading(&clock, &market.oracle_config()).is_ok());
+
+        update.verification_level = VerificationLevel::Partial { num_signatures: 1 };
+        assert_eq!(update.get_price_for_trading(&clock, &market.oracle_config()), Err(ProgramError::InvalidAccountData));
+        // Any update is accepted when no minimum is configured
+        assert!(update.get_price_for_trading(&clock, &config(60, false, false)).is_ok());
+
+        // Full verification rejects even a well signed partial update
+        update.verification_level = VerificationLevel::Partial { num_signatures: 13 };
+        let market = Market { min_verification_level: Market::FULL_VERIFICATION, ..market };
+        assert_eq!(update.get_price_for_trading(&clock, &market.oracle_config()), Err(ProgramError::InvalidAccountData));
+    }
+
+    #[test]
+    fn test_raw_price_keeps_sub_cent_digits() {
+        let mut update = price_update(1_700_000_000, 1_700_000_000 - 1);
+        // $0.000000123, finer than PRICE_DECIMALS
+        update.price_message.price = 123;
+        update.price_message.exponent = -9;
+        let clock = clock_at(1_700_000_010);
+
+        assert_eq!(update.get_price_for_trading_raw(&clock, &config(60, false, false)).unwrap(), (123, -9));
+        // The normalized price keeps only the 12
+        assert_eq!(update.get_price_for_trading(&clock, &config(60, false, false)).unwrap().price, 12);
+
+        update.price_message.price = 0;
+        assert_eq!(
+            update.get_price_for_trading_raw(&clock, &config(60, false, false)),
+            Err(PerpError::OracleInvalidPrice.into())
+        );
+    }
+
+    #[test]
+    fn test_unpublished_and_non_positive_prices_have_distinct_errors() {
+        let clock = clock_at(1_700_000_000);
+
+        let never_published = price_update(0, 0);
+        assert_eq!(
+            never_published.get_price_for_trading(&clock, &config(60, true, false)),
+            Err(PerpError::OracleNeverPublished.into())
+        );
+
+        let mut negative = price_update(1_700_000_000, 1_700_000_000 - 1);
+        negative.price_message.price = -1;
+        assert_eq!(
+            negative.get_price_for_trading(&clock, &config(60, false, false)),
+            Err(PerpError::OracleInvalidPrice.into())
+        );
+
+        // Neither is confused with pointing at the wrong feed
+        assert_eq!(
+            negative.get_price_for_trading(&clock, &OracleConfig { feed_id: [1u8; 32], ..config(60, false, false) }),
+            Err(PerpError::OracleFeedMismatch.into())
+        );
+    }
+
+    #[test]
+    fn test_use_ema_prices_off_ema() {
+        let mut update = price_update(1_700_000_000, 1_700_000_000 - 1);
+        update.price_message.price = 190_00000000; // One manipulated update
+        update.price_message.ema_price = 150_12000000;
+        let clock = clock_at(1_700_000_010);
+
+        let ema = update.get_price_for_trading(&clock, &config(60, false, true)).unwrap();
+        assert_eq!((ema.price, ema.publish_time), (150_12000000, 1_700_000_000));
+        let spot = update.get_price_for_trading(&clock, &config(60, false, false)).unwrap();
+        assert_eq!(spot.price, 190_00000000);
+
+        // The EMA is held to the spot max age, the fallback doesn't stretch it
+        assert_eq!(
+            update.get_price_for_trading(&clock_at(1_700_000_090), &config(60, true, true)),
+            Err(PerpError::OracleStale.into())
+        );
+    }
+
+    #[test]
+    fn test_stale_spot_rejected_without_fallback() {
+        let update = price_update(1_700_000_000, 1_700_000_000 - 1);
+        let clock = clock_at(1_700_000_090);
+        assert_eq!(
+            update.get_price_for_trading(&clock, &config(60, false, false)),
+            Err(PerpError::OracleStale.into())
+        );
+
+        // Too old even for the EMA
+        assert_eq!(
+            update.get_price_for_trading(&clock_at(1_700_000_181), &config(60, true, false)),
+            Err(PerpError::OracleStale.into())
+        );
+    }
+
+    #[test]
+    fn test_exponent_at_supported_bound() {
+        // $1.50 expressed with 18 decimals, normalized to 8
+        let normalized = normalize_pyth_price(150 * 10_i64.pow(16), -18).unwrap();
+        assert_eq!(normalized, 1_50000000);
+        assert_eq!(normalize_pyth_price(150_00000000, -8).unwrap(), 150_00000000);
+    }
+
+    #[test]
+    fn test_normalize_exponent_matches_target_scale() {
+        assert_eq!(normalize_pyth_price(150_12345678, -8).unwrap(), 150_12345678);
+    }
+
+    #[test]
+    fn test_normalize_finer_exponent_divides() {
+        // $150.1234567891 with 10 decimals, truncated to 8
+        assert_eq!(normalize_pyth_price(150_1234567891, -10).unwrap(), 150_12345678);
+    }
+
+    #[test]
+    fn test_normalize_coarser_exponent_multiplies() {
+        // $150.123456 with 6 decimals
+        assert_eq!(normalize_pyth_price(150_123456, -6).unwrap(), 150_12345600);
+    }
+
+    #[test]
+    fn test_normalize_positive_exponent() {
+        // 15 * 10^1 = $150
+        assert_eq!(normalize_pyth_price(15, 1).unwrap(), 150_00000000);
+        assert_eq!(normalize_pyth_price(150, 0).unwrap(), 150_00000000);
+        // 3 * 10^2 = $300
+        assert_eq!(normalize_pyth_price(3, 2).unwrap(), 300_00000000);
+    }
+
+    #[test]
+    fn test_normalize_overflow_rejected() {
+        // Fits an i64 at its own exponent, not a u64 once scaled to 8 decimals
+        assert_eq!(normalize_pyth_price(i64::MAX, 0), Err(ProgramError::ArithmeticOverflow));
+        assert_eq!(normalize_pyth_price(1, 18), Err(ProgramError::ArithmeticOverflow));
+        // Largest whole-dollar price that still fits
+        let max_dollars = (u64::MAX / 100_000_000) as i64;
+        assert_eq!(normalize_pyth_price(max_dollars, 0).unwrap(), max_dollars as u64 * 100_000_000);
+    }
+
+    #[test]
+    fn test_exponent_out_of_range_rejected() {
+        assert_eq!(normalize_pyth_price(150, -30), Err(PerpError::OracleInvalidPrice.into()));
+        assert_eq!(normalize_pyth_price(150, 30), Err(PerpError::OracleInvalidPrice.into()));
+    }
+
+    fn symbol(name: &[u8]) -> [u8; 16] {
+        let mut symbol = [0u8; 16];
+        symbol[..name.len()].copy_from_slice(name);
+        symbol
+    }
+
+    #[test]
+    fn test_symbol_wired_to_wrong_feed_rejected() {
+        let sol_feed = PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap();
+        assert_eq!(
+            check_symbol_matches_feed(&symbol(b"BTC-PERP"), &sol_feed),
+            Err(PerpError::SymbolFeedMismatch.into())
+        );
+        assert_eq!(
+            check_symbol_matches_feed(&symbol(b"SOLX-PERP"), &sol_feed),
+            Err(PerpError::SymbolFeedMismatch.into())
+        );
+    }
+
+    #[test]
+    fn test_symbol_matching_feed_accepted() {
+        let sol_feed = PriceUpdateV2::get_feed_id_from_hex(SOL_USD_FEED_ID).unwrap();
+        let btc_feed = PriceUpdateV2::get_feed_id_from_hex(BTC_USD_FEED_ID).unwrap();
+        assert!(check_symbol_matches_feed(&symbol(b"SOL-PERP"), &sol_feed).is_ok());
+        assert!(check_symbol_matches_feed(&symbol(b"BTC/USD"), &btc_feed).is_ok());
+        // Unknown feeds can't be checked
+        assert!(check_symbol_matches_feed(&symbol(b"BTC-PERP"), &[7u8; 32]).is_ok());
+    }
+
+    #[test]
+    fn test_wide_confidence_band_rejected() {
+        let clock = clock_at(1_700_000_000);
+
+        // 75 either side of 150 is a 50% band
+        let mut update = price_update(1_700_000_000, 1_700_000_000 - 1);
+        update.price_message.conf = 75_00000000;
+        let price = update.get_price_for_trading(&clock, &config(60, false, false)).unwrap();
+        assert_eq!(price.conf_bps, 5_000);
+        assert_eq!(price.check_confidence(200), Err(ProgramError::InvalidAccountData));
+        // Unchecked when no threshold is configured
+        assert!(price.check_confidence(0).is_ok());
+
+        // 0.15 either side of 150 is a 0.1% band
+        update.price_message.conf = 15_000_000;
+        let price = update.get_price_for_trading(&clock, &config(60, false, false)).unwrap();
+        assert_eq!(price.conf_bps, 10);
+        assert!(price.check_confidence(200).is_ok());
+    }
+
+    #[test]
+    fn test_oracle_snapshot_matches_price_update_at_close() {
+        use crate::states::{with_account_infos, TestAccount};
+
+        let mut update = price_update(1_700_000_000, 1_700_000_000 - 1);
+        update.posted_slot = 250_000_000;
+        let mut update_data = vec![0u8; core::mem::size_of::<PriceUpdateV2>()];
+        unsafe { core::ptr::write_unaligned(update_data.as_mut_ptr() as *mut PriceUpdateV2, update) };
+
+        let snapshot_data = [0u8; OracleSnapshot::SIZE];
+        let accounts = [
+            TestAccount { key: [3u8; 32], owner: PYTH_RECEIVER_ID, is_signer: false, is_writable: false, lamports: 0, data: &update_data },
+            TestAccount { key: [5u8; 32], owner: crate::ID, is_signer: false, is_writable: true, lamports: 0, data: &snapshot_data },
+        ];
+        with_account_infos(&accounts, |accounts| {
+            let closed_at = 1_700_000_007;
+            record_oracle_snapshot(&accounts[1], &[1u8; 32], &accounts[0], OracleKind::Pyth, closed_at).unwrap();
+
+            let snapshot = *OracleSnapshot::from_account_info(&accounts[1]).unwrap();
+            assert_eq!(snapshot.position, [1u8; 32]);
+            assert_eq!(snapshot.recorded_at, closed_at);
+            assert_eq!(snapshot.price, update.price_message.price);
+            assert_eq!(snapshot.conf, update.price_message.conf);
+            assert_eq!(snapshot.exponent, update.price_message.exponent);
+            assert_eq!(snapshot.publish_time, update.price_message.publish_time);
+            assert_eq!(snapshot.slot, update.posted_slot);
+
+            // A recorded snapshot is never overwritten
+            assert_eq!(
+                record_oracle_snapshot(&accounts[1], &[2u8; 32], &accounts[0], OracleKind::Pyth, closed_at + 1),
+                Err(ProgramError::AccountAlreadyInitialized)
+            );
+        });
+    }
 }
 
 // =============== TESTING fetch_sol_price ===============
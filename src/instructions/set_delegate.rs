@@ -0,0 +1,133 @@
+use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, pubkey::Pubkey, *};
+
+use crate::{
+    errors::PerpError,
+    instructions::{check_writable, create_program_account, not_enough_accounts},
+    states::DelegateAuthority,
+};
+
+/// Instruction data: [delegate: Pubkey], the default key revokes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetDelegateArgs {
+    pub delegate: Pubkey,
+}
+
+impl SetDelegateArgs {
+    pub const LEN: usize = 32;
+}
+
+impl TryFrom<&[u8]> for SetDelegateArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let delegate = data[0..32].try_into().map_err(|_| ProgramError::InvalidInstructionData)?;
+        Ok(Self { delegate })
+    }
+}
+
+/// Sets or revokes the key allowed to open positions on the user's behalf.
+pub fn process_set_delegate(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+
+    let [
+        user, // The delegating trader (must sign transaction)
+        delegation, // Delegation PDA for the user
+        _system_program
+        ] = accounts else {
+        return Err(not_enough_accounts(3, accounts.len()));
+    };
+
+    if !user.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[user, delegation])?;
+
+    let SetDelegateArgs { delegate } = SetDelegateArgs::try_from(instruction_data)?;
+
+    let (delegation_pda, delegation_bump) = pubkey::find_program_address(
+        &[b"delegation", user.key().as_ref()],
+        &crate::ID
+    );
+    if *delegation.key() != delegation_pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    if delegation.data_is_empty() {
+        let bump_ref = &[delegation_bump];
+        let seeds = seeds!(b"delegation", user.key().as_ref(), bump_ref);
+        create_program_account(user, delegation, DelegateAuthority::SIZE, Signer::from(&seeds))?;
+    } else if !delegation.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    let mut delegation_data = DelegateAuthority::from_account_info_mut(delegation)?;
+    delegation_data.user = *user.key();
+    delegation_data.delegate = delegate;
+    delegation_data.bump = delegation_bump;
+
+    println!("Delegate set");
+
+    Ok(())
+}
+
+/// Checks that `delegation` lets `delegate` trade for `user`. The caller
+/// checks the PDA address.
+pub fn check_delegation(delegation: &AccountInfo, user: &Pubkey, delegate: &Pubkey) -> ProgramResult {
+    if delegation.data_is_empty() || !delegation.is_owned_by(&crate::ID) {
+        return Err(PerpError::UnauthorizedDelegate.into());
+    }
+
+    if !DelegateAuthority::from_account_info(delegation)?.allows(user, delegate) {
+        return Err(PerpError::UnauthorizedDelegate.into());
+    }
+    Ok(())
+}
+
+// =========================== TESTING process_set_delegate ===========================
+
+#[cfg(test)]
+mod tests {
+    use super::{check_delegation, PerpError};
+    use crate::states::{with_account_info, DelegateAuthority};
+
+    const USER: [u8; 32] = [1u8; 32];
+    const DELEGATE: [u8; 32] = [2u8; 32];
+
+    fn delegation_bytes(delegate: [u8; 32]) -> Vec<u8> {
+        let mut data = vec![0u8; DelegateAuthority::SIZE];
+        let user = core::mem::offset_of!(DelegateAuthority, user);
+        let delegate_offset = core::mem::offset_of!(DelegateAuthority, delegate);
+        data[user..user + 32].copy_from_slice(&USER);
+        data[delegate_offset..delegate_offset + 32].copy_from_slice(&delegate);
+        data
+    }
+
+    #[test]
+    fn test_valid_delegate_accepted() {
+        with_account_info(&delegation_bytes(DELEGATE), |delegation| {
+            assert!(check_delegation(delegation, &USER, &DELEGATE).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_unauthorized_delegate_rejected() {
+        let unauthorized = Err(PerpError::UnauthorizedDelegate.into());
+
+        with_account_info(&delegation_bytes(DELEGATE), |delegation| {
+            // Someone else, and the right delegate on another user's delegation
+            assert_eq!(check_delegation(delegation, &USER, &[3u8; 32]), unauthorized);
+            assert_eq!(check_delegation(delegation, &[3u8; 32], &DELEGATE), unauthorized);
+        });
+        // Revoked
+        with_account_info(&delegation_bytes([0u8; 32]), |delegation| {
+            assert_eq!(check_delegation(delegation, &USER, &[0u8; 32]), unauthorized);
+        });
+        // Never delegated
+        with_account_info(&[], |delegation| {
+            assert_eq!(check_delegation(delegation, &USER, &DELEGATE), unauthorized);
+        });
+    }
+}
@@ -0,0 +1,162 @@
+use pinocchio::{account_info::AccountInfo, program_error::ProgramError, sysvars::{clock::Clock, Sysvar}, ProgramResult};
+
+use crate::{events::{MarketParams, MarketParamsUpdated}, instructions::{check_writable, not_enough_accounts, InitializeMarketArgs}, states::Market};
+
+/// Instruction data: [initial_margin: u64][maintenance_margin: u64][fee_rate: u64][max_leverage: u64]
+///
+/// Margins and the fee are in bps. The maintenance margin must sit strictly
+/// below the initial margin, or a position would be liquidatable the moment
+/// it opens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetMarketParamsArgs {
+    pub params: MarketParams,
+}
+
+impl SetMarketParamsArgs {
+    pub const LEN: usize = MarketParams::LEN;
+    pub const MAX_FEE_RATE: u64 = 1_000;
+}
+
+fn check_market_params(params: &MarketParams) -> ProgramResult {
+    let MarketParams { initial_margin, maintenance_margin, fee_rate, max_leverage } = *params;
+    if initial_margin == 0 || initial_margin > 10_000 || maintenance_margin >= initial_margin {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if fee_rate > SetMarketParamsArgs::MAX_FEE_RATE {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    if max_leverage == 0 || max_leverage > InitializeMarketArgs::MAX_LEVERAGE {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    Ok(())
+}
+
+impl TryFrom<&[u8]> for SetMarketParamsArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let read = |offset: usize| -> Result<u64, ProgramError> {
+            Ok(u64::from_le_bytes(
+                data[offset..offset + 8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+            ))
+        };
+
+        let params = MarketParams {
+            initial_margin: read(0)?,
+            maintenance_margin: read(8)?,
+            fee_rate: read(16)?,
+            max_leverage: read(24)?,
+        };
+        check_market_params(&params)?;
+
+        Ok(Self { params })
+    }
+}
+
+pub fn process_set_market_params(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+
+    let [authority, market_account] = accounts else {
+        return Err(not_enough_accounts(2, accounts.len()));
+    };
+
+    if !authority.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[market_account])?;
+    if !market_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    let SetMarketParamsArgs { params } = SetMarketParamsArgs::try_from(instruction_data)?;
+
+    let mut market = Market::load_initialized_mut(market_account)?;
+    if market.authority != *authority.key() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let old = MarketParams {
+        initial_margin: market.initial_margin,
+        maintenance_margin: market.maintenance_margin,
+        fee_rate: market.fee_rate,
+        max_leverage: market.max_leverage,
+    };
+
+    market.initial_margin = params.initial_margin;
+    market.maintenance_margin = params.maintenance_margin;
+    market.fee_rate = params.fee_rate;
+    market.max_leverage = params.max_leverage;
+
+    // Only governance actions that actually change something are logged
+    let event = MarketParamsUpdated::new(
+        *market_account.key(),
+        old,
+        params,
+        *authority.key(),
+        Clock::get()?.unix_timestamp,
+    );
+    if event.changed != 0 {
+        event.emit();
+    }
+
+    Ok(())
+}
+
+// =========================== TESTING process_set_market_params ===========================
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::{process_set_market_params, SetMarketParamsArgs};
+    use crate::instructions::InitializeMarketArgs;
+    use crate::states::{with_account_infos, Market, TestAccount};
+
+    fn args(initial_margin: u64, maintenance_margin: u64, fee_rate: u64, max_leverage: u64) -> Result<SetMarketParamsArgs, ProgramError> {
+        let mut data = Vec::with_capacity(SetMarketParamsArgs::LEN);
+        for value in [initial_margin, maintenance_margin, fee_rate, max_leverage] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        SetMarketParamsArgs::try_from(data.as_slice())
+    }
+
+    #[test]
+    fn test_maintenance_above_initial_rejected() {
+        assert!(args(1_000, 500, 10, 10).is_ok());
+        assert_eq!(args(500, 1_000, 10, 10), Err(ProgramError::InvalidInstructionData));
+        // Equal margins would make a fresh position liquidatable
+        assert_eq!(args(500, 500, 10, 10), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_out_of_range_params_rejected() {
+        // No margin at all, or more than the whole notional
+        assert_eq!(args(0, 0, 10, 10), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(args(10_001, 500, 10, 10), Err(ProgramError::InvalidInstructionData));
+
+        assert!(args(1_000, 500, SetMarketParamsArgs::MAX_FEE_RATE, 10).is_ok());
+        assert_eq!(args(1_000, 500, SetMarketParamsArgs::MAX_FEE_RATE + 1, 10), Err(ProgramError::InvalidInstructionData));
+
+        assert_eq!(args(1_000, 500, 10, 0), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(args(1_000, 500, 10, InitializeMarketArgs::MAX_LEVERAGE + 1), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_read_only_market_rejected() {
+        let market = vec![0u8; Market::SIZE];
+        let accounts = [
+            TestAccount { key: [1u8; 32], owner: pinocchio_system::ID, is_signer: true, is_writable: false, lamports: 0, data: &[] },
+            TestAccount { key: [2u8; 32], owner: crate::ID, is_signer: false, is_writable: false, lamports: 0, data: &market },
+        ];
+
+        with_account_infos(&accounts, |accounts| {
+            assert_eq!(
+                process_set_market_params(accounts, &[0u8; SetMarketParamsArgs::LEN]),
+                Err(ProgramError::Immutable)
+            );
+        });
+    }
+}
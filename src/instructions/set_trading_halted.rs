@@ -0,0 +1,125 @@
+use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, *};
+
+use crate::{
+    errors::PerpError,
+    instructions::{check_writable, create_program_account, not_enough_accounts},
+    states::GlobalConfig,
+};
+
+/// Instruction data: [halted: u8]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetTradingHaltedArgs {
+    pub halted: bool,
+}
+
+impl SetTradingHaltedArgs {
+    pub const LEN: usize = 1;
+}
+
+impl TryFrom<&[u8]> for SetTradingHaltedArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Self { halted: data[0] != 0 })
+    }
+}
+
+/// Sets the global kill switch. The first call creates the config and makes
+/// its signer the admin, so it should be sent right after deployment; later
+/// calls must be signed by that admin.
+pub fn process_set_trading_halted(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+
+    let [
+        admin, // Program admin (must sign transaction), pays for the config on creation
+        global_config, // Global config PDA
+        _system_program
+        ] = accounts else {
+        return Err(not_enough_accounts(3, accounts.len()));
+    };
+
+    if !admin.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[admin, global_config])?;
+
+    let SetTradingHaltedArgs { halted } = SetTradingHaltedArgs::try_from(instruction_data)?;
+
+    let (global_config_pda, config_bump) = pubkey::find_program_address(&[b"global_config"], &crate::ID);
+    if *global_config.key() != global_config_pda {
+        return Err(ProgramError::InvalidSeeds);
+    }
+
+    let create_config = global_config.data_is_empty();
+    if create_config {
+        let bump_ref = &[config_bump];
+        let seeds = seeds!(b"global_config", bump_ref);
+        create_program_account(admin, global_config, GlobalConfig::SIZE, Signer::from(&seeds))?;
+    } else if !global_config.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    let mut config = GlobalConfig::from_account_info_mut(global_config)?;
+    if create_config {
+        config.admin = *admin.key();
+        config.bump = config_bump;
+    } else if config.admin != *admin.key() {
+        return Err(ProgramError::IncorrectAuthority);
+    }
+    config.trading_halted = halted;
+
+    println!("Trading halted: {}", halted);
+
+    Ok(())
+}
+
+/// Rejects opens while the global kill switch is set. A config that was never
+/// created means trading was never halted. The caller checks the PDA address.
+pub fn check_trading_not_halted(global_config: &AccountInfo) -> ProgramResult {
+    if global_config.data_is_empty() {
+        return Ok(());
+    }
+    if !global_config.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    if !GlobalConfig::from_account_info(global_config)?.allows_open() {
+        return Err(PerpError::TradingHalted.into());
+    }
+    Ok(())
+}
+
+// =========================== TESTING process_set_trading_halted ===========================
+
+#[cfg(test)]
+mod tests {
+    use super::{check_trading_not_halted, PerpError};
+    use crate::states::{with_account_info, GlobalConfig};
+
+    fn config_bytes(trading_halted: bool) -> Vec<u8> {
+        let mut data = vec![0u8; GlobalConfig::SIZE];
+        data[core::mem::offset_of!(GlobalConfig, trading_halted)] = trading_halted as u8;
+        data
+    }
+
+    #[test]
+    fn test_halted_config_blocks_opens() {
+        with_account_info(&config_bytes(true), |config| {
+            assert_eq!(check_trading_not_halted(config), Err(PerpError::TradingHalted.into()));
+        });
+    }
+
+    #[test]
+    fn test_unhalted_or_missing_config_allows_opens() {
+        with_account_info(&config_bytes(false), |config| {
+            assert!(check_trading_not_halted(config).is_ok());
+        });
+        // Never created
+        with_account_info(&[], |config| {
+            assert!(check_trading_not_halted(config).is_ok());
+        });
+    }
+}
@@ -0,0 +1,228 @@
+use pinocchio::{account_info::AccountInfo, program_error::ProgramError, sysvars::clock::Clock, *};
+
+use crate::{
+    events::FundingSettled,
+    instructions::{check_writable, get_price_for_feed, not_enough_accounts},
+    math::{self, RoundingMode},
+    states::{Market, Position, UserAccount},
+};
+
+/// Advances the market's funding index and settles the position against it.
+/// Permissionless: keepers crank it, the position owner does not sign.
+pub fn process_settle_funding(accounts: &[AccountInfo]) -> ProgramResult {
+
+    let [
+        market_account, // Market the position trades on
+        user_account, // Position owner's trading account
+        user_position_account, // Position to settle
+        pyth_price_account, // Pyth oracle for price feeds
+        clock_sysvar // Solana clock for timestamps
+        ] = accounts else {
+        return Err(not_enough_accounts(5, accounts.len()));
+    };
+
+    check_writable(&[market_account, user_account, user_position_account])?;
+    if !market_account.is_owned_by(&crate::ID) || !user_position_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    let clock = Clock::from_account_info(clock_sysvar)?;
+
+    let (funding_index, collateral_decimals) = {
+        let mut market = Market::load_initialized_mut(market_account)?;
+        let current_price = get_price_for_feed(pyth_price_account, &clock, &market.oracle_config())?.price;
+
+        // Accrue at the rate in effect so far before it's reset
+        market.accrue_funding(current_price, clock.unix_timestamp)?;
+        if !update_funding_rate(&mut market, clock.unix_timestamp)? {
+            println!("funding not due");
+        }
+        let funding_rate = market.funding_rate;
+        market.record_funding_sample(clock.unix_timestamp, funding_rate);
+        (market.cumulative_funding_index, market.collateral_decimals)
+    };
+
+    let mut position = Position::from_account_info_mut(user_position_account)?;
+    if position.market != *market_account.key() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    if !position.is_open() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let (user_account_pda, _user_bump) = pubkey::find_program_address(
+        &[b"user_account", position.user.as_ref()],
+        &crate::ID
+    );
+    if *user_account.key() != user_account_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let payment = position.pending_funding(funding_index, collateral_decimals)?;
+
+    // margin_balance and the market's locked margin both include the
+    // position's margin, keep them in step
+    let margin_delta = position.settle_funding_index(funding_index, collateral_decimals)?;
+    position.last_funding_settlement = clock.unix_timestamp;
+
+    let mut user_account_data = UserAccount::from_account_info_mut(user_account)?;
+    user_account_data.apply_margin_delta(margin_delta)?;
+    Market::from_account_info_mut(market_account)?.apply_margin_delta(margin_delta)?;
+
+    FundingSettled {
+        position: *user_position_account.key(),
+        payment,
+        cumulative_funding_index: funding_index,
+    }.emit();
+
+    println!("Funding settled: {}", payment);
+
+    Ok(())
+}
+
+/// Once a funding interval has passed since the last reset, sets the funding
+/// rate from the open interest imbalance: max_funding_rate when one side holds
+/// everything, scaled down linearly as the sides even out. Positive when longs
+/// dominate, so longs pay shorts. Returns whether the rate was reset; an early
+/// call is a no-op rather than an error, so keepers can crank freely.
+pub fn update_funding_rate(market: &mut Market, current_time: i64) -> Result<bool, ProgramError> {
+    if market.funding_interval <= 0
+        || current_time.saturating_sub(market.funding_rate_updated_at) < market.funding_interval
+    {
+        return Ok(false);
+    }
+
+    let max_rate = market.max_funding_rate.min(10_000) as i128;
+    let rate = math::mul_div(market.open_interest_imbalance_bps() as i128, max_rate, 10_000, RoundingMode::Down)?;
+    market.funding_rate = rate.clamp(-max_rate, max_rate) as i64;
+    market.funding_rate_updated_at = current_time;
+    Ok(true)
+}
+
+// =========================== TESTING process_settle_funding ===========================
+
+#[cfg(test)]
+mod tests {
+    use crate::{math::PRICE_DECIMALS, states::{Market, Position}};
+
+    fn position(size: i128, last_funding_index: i128) -> Position {
+        Position { size, margin: 1_000, is_active: 1, last_funding_index, ..Position::default() }
+    }
+
+    #[test]
+    fn test_positions_settling_at_different_indices() {
+        // 10 bps of 100 per hour, accrued 0.1 per contract each hour
+        let mut market = Market { funding_rate: 10, funding_interval: 3600, last_funding_time: 1, ..Market::default() };
+
+        let mut early = position(10, market.cumulative_funding_index);
+        market.accrue_funding(100, 1 + 3600).unwrap();
+        let mut late = position(10, market.cumulative_funding_index);
+        market.accrue_funding(100, 1 + 3 * 3600).unwrap();
+
+        // Three hours of funding for the early long, two for the late one
+        assert_eq!(early.pending_funding(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), -3);
+        assert_eq!(late.pending_funding(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), -2);
+
+        assert_eq!(early.settle_funding_index(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), -3);
+        assert_eq!(early.margin, 997);
+        // Settled positions owe nothing until the index moves again
+        assert_eq!(early.pending_funding(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), 0);
+        assert_eq!(late.settle_funding_index(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), -2);
+    }
+
+    #[test]
+    fn test_shorts_receive_when_longs_pay() {
+        let mut market = Market { funding_rate: 20, funding_interval: 3600, last_funding_time: 1, ..Market::default() };
+        market.accrue_funding(1_000, 1 + 1800).unwrap();
+
+        // Half an interval at 20 bps of 1_000 is 1 per contract
+        assert_eq!(position(-100, 0).pending_funding(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), 100);
+        assert_eq!(position(100, 0).pending_funding(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), -100);
+    }
+
+    #[test]
+    fn test_longs_receive_when_shorts_pay() {
+        // A negative rate runs the index down: shorts pay, longs receive
+        let mut market = Market { funding_rate: -20, funding_interval: 3600, last_funding_time: 1, ..Market::default() };
+        market.accrue_funding(1_000, 1 + 1800).unwrap();
+
+        let mut short = position(-100, 0);
+        let mut long = position(100, 0);
+        assert_eq!(short.settle_funding_index(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), -100);
+        assert_eq!(short.margin, 900);
+        // Received funding is held in funding_payment until the position closes
+        assert_eq!(long.settle_funding_index(market.cumulative_funding_index, PRICE_DECIMALS).unwrap(), 0);
+        assert_eq!((long.margin, long.funding_payment), (1_000, 100));
+    }
+
+    #[test]
+    fn test_locked_margin_follows_settled_funding() {
+        let mut market = Market { funding_rate: -20, funding_interval: 3600, last_funding_time: 1, ..Market::default() };
+        market.accrue_funding(1_000, 1 + 1800).unwrap();
+
+        // A short paying 100 and a compounding long receiving it, 1_000 margin each
+        let mut short = position(-100, 0);
+        let mut long = Position { auto_compound_funding: 1, ..position(100, 0) };
+        market.lock_margin(short.margin + long.margin).unwrap();
+
+        for position in [&mut short, &mut long] {
+            let margin_delta = position.settle_funding_index(market.cumulative_funding_index, PRICE_DECIMALS).unwrap();
+            market.apply_margin_delta(margin_delta).unwrap();
+        }
+        assert_eq!((short.margin, long.margin), (900, 1_100));
+        assert_eq!(market.locked_margin, short.margin + long.margin);
+
+        // Closing both releases exactly what is locked
+        market.release_margin(short.margin);
+        market.release_margin(long.margin);
+        assert_eq!(market.locked_margin, 0);
+    }
+
+    #[test]
+    fn test_funding_rate_follows_open_interest_imbalance() {
+        use super::update_funding_rate;
+        const NOW: i64 = 1_700_000_000;
+
+        // Longs hold 300 of 400 contracts: half the max rate
+        let mut market = Market {
+            open_interest_long: 300,
+            open_interest_short: 100,
+            funding_interval: 3600,
+            max_funding_rate: 40,
+            ..Market::default()
+        };
+        assert_eq!(update_funding_rate(&mut market, NOW), Ok(true));
+        assert_eq!(market.funding_rate, 20);
+        assert_eq!(market.funding_rate_updated_at, NOW);
+
+        // Unchanged until a full interval has passed, however the book moves
+        market.open_interest_long = 0;
+        assert_eq!(update_funding_rate(&mut market, NOW + 3599), Ok(false));
+        assert_eq!(market.funding_rate, 20);
+
+        // Shorts alone pay the full (clamped) rate
+        assert_eq!(update_funding_rate(&mut market, NOW + 3600), Ok(true));
+        assert_eq!(market.funding_rate, -40);
+
+        // No configured max, no funding
+        let mut unfunded = Market { open_interest_long: 100, funding_interval: 3600, ..Market::default() };
+        update_funding_rate(&mut unfunded, NOW).unwrap();
+        assert_eq!(unfunded.funding_rate, 0);
+    }
+
+    #[test]
+    fn test_second_funding_update_in_the_same_slot_is_a_no_op() {
+        use super::update_funding_rate;
+        const NOW: i64 = 1_700_000_000;
+
+        let mut market = Market { open_interest_long: 100, funding_interval: 3600, max_funding_rate: 40, ..Market::default() };
+        assert_eq!(update_funding_rate(&mut market, NOW), Ok(true));
+        assert_eq!(market.funding_rate, 40);
+
+        // Same timestamp, book flipped: nothing changes and nothing fails
+        market.open_interest_long = 0;
+        market.open_interest_short = 100;
+        assert_eq!(update_funding_rate(&mut market, NOW), Ok(false));
+        assert_eq!((market.funding_rate, market.funding_rate_updated_at), (40, NOW));
+    }
+}
@@ -0,0 +1,249 @@
+use pinocchio::{account_info::AccountInfo, program_error::ProgramError, pubkey::Pubkey, sysvars::clock::Clock};
+
+use crate::{errors::PerpError, instructions::OraclePrice, math, states::OracleSnapshot};
+
+/// Switchboard V2 program, the owner of every genuine aggregator account
+pub const SWITCHBOARD_PROGRAM_ID: Pubkey = pinocchio_pubkey::pubkey!("SW1TCH7qEPTdLsDHRgPuMQjbQxKdH2aBStViMFnt64f");
+
+/// Anchor discriminator of AggregatorAccountData, the first 8 bytes of
+/// sha256("account:AggregatorAccountData")
+const AGGREGATOR_DISCRIMINATOR: [u8; 8] = [217, 230, 65, 101, 201, 162, 27, 125];
+
+// AggregatorAccountData is a packed struct, so fields are read at fixed offsets.
+// latest_confirmed_round follows the discriminator, name, metadata, reserved,
+// queue, four u32 settings, start_after, variance_threshold, four i64/u64
+// timers, is_locked and the crank pubkey.
+const LATEST_ROUND: usize = 8 + 32 + 128 + 32 + 32 + 4 * 4 + 8 + 20 + 4 * 8 + 1 + 32;
+// Within the round: num_success, num_error and is_closed come first
+const ROUND_OPEN_SLOT: usize = LATEST_ROUND + 4 + 4 + 1;
+const ROUND_OPEN_TIMESTAMP: usize = ROUND_OPEN_SLOT + 8;
+const RESULT: usize = ROUND_OPEN_TIMESTAMP + 8;
+const STD_DEVIATION: usize = RESULT + 20;
+const ROUND_END: usize = STD_DEVIATION + 20;
+
+/// The latest confirmed round of a Switchboard aggregator. Values are
+/// SwitchboardDecimals: `mantissa / 10^scale`.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct AggregatorRound {
+    pub mantissa: i128,
+    pub scale: u32,
+    pub std_deviation_mantissa: i128,
+    pub std_deviation_scale: u32,
+    pub round_open_slot: u64,
+    pub round_open_timestamp: i64,
+}
+
+impl AggregatorRound {
+    /// Reads the latest confirmed round, rejecting accounts Switchboard doesn't
+    /// own and Switchboard accounts that aren't aggregators.
+    pub fn from_account_info(account: &AccountInfo) -> Result<Self, ProgramError> {
+        if !account.is_owned_by(&SWITCHBOARD_PROGRAM_ID) {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let data = account.try_borrow_data()?;
+        if data.len() < ROUND_END || data[..8] != AGGREGATOR_DISCRIMINATOR {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let read = |offset: usize, len: usize| &data[offset..offset + len];
+        Ok(Self {
+            mantissa: i128::from_le_bytes(read(RESULT, 16).try_into().unwrap()),
+            scale: u32::from_le_bytes(read(RESULT + 16, 4).try_into().unwrap()),
+            std_deviation_mantissa: i128::from_le_bytes(read(STD_DEVIATION, 16).try_into().unwrap()),
+            std_deviation_scale: u32::from_le_bytes(read(STD_DEVIATION + 16, 4).try_into().unwrap()),
+            round_open_slot: u64::from_le_bytes(read(ROUND_OPEN_SLOT, 8).try_into().unwrap()),
+            round_open_timestamp: i64::from_le_bytes(read(ROUND_OPEN_TIMESTAMP, 8).try_into().unwrap()),
+        })
+    }
+
+    /// The round's result normalized to PRICE_DECIMALS, as get_price_for_trading
+    /// normalizes Pyth prices. The standard deviation stands in for Pyth's
+    /// confidence interval.
+    pub fn price_no_older_than(&self, clock: &Clock, max_age: u64) -> Result<OraclePrice, ProgramError> {
+        if self.round_open_timestamp <= 0 {
+            return Err(PerpError::OracleNeverPublished.into());
+        }
+        let age = clock.unix_timestamp.saturating_sub(self.round_open_timestamp);
+        if age > max_age as i64 {
+            return Err(PerpError::OracleStale.into());
+        }
+
+        let price = normalize_decimal(self.mantissa, self.scale)?;
+        if price == 0 {
+            return Err(PerpError::OracleInvalidPrice.into());
+        }
+        let std_deviation = normalize_decimal(self.std_deviation_mantissa.max(0), self.std_deviation_scale)?;
+        let conf_bps = (std_deviation as u128 * 10_000).div_ceil(price as u128);
+
+        Ok(OraclePrice {
+            price,
+            publish_time: self.round_open_timestamp,
+            conf_bps: u64::try_from(conf_bps).unwrap_or(u64::MAX),
+        })
+    }
+
+    /// Slot lag check matching PriceUpdateV2::check_slot_lag, against the slot
+    /// the round opened at. A `max_slot_lag` of zero disables the check.
+    pub fn check_slot_lag(&self, clock: &Clock, max_slot_lag: u64) -> Result<(), ProgramError> {
+        if max_slot_lag == 0 {
+            return Ok(());
+        }
+
+        if clock.slot.saturating_sub(self.round_open_slot) > max_slot_lag {
+            return Err(PerpError::OracleStale.into());
+        }
+        Ok(())
+    }
+
+    /// Raw oracle state to record for a settlement of `position` at `recorded_at`,
+    /// see PriceUpdateV2::snapshot.
+    pub fn snapshot(&self, position: &Pubkey, recorded_at: i64) -> Result<OracleSnapshot, ProgramError> {
+        let narrow = |mantissa: i128| i64::try_from(mantissa).map_err(|_| ProgramError::ArithmeticOverflow);
+        let exponent = -i32::try_from(self.scale).map_err(|_| ProgramError::ArithmeticOverflow)?;
+        // The snapshot holds one exponent, so the deviation is rescaled to the result's
+        let conf = rescale(self.std_deviation_mantissa.max(0), self.std_deviation_scale, self.scale)?;
+
+        Ok(OracleSnapshot {
+            position: *position,
+            recorded_at,
+            price: narrow(self.mantissa)?,
+            conf: narrow(conf)? as u64,
+            exponent,
+            publish_time: self.round_open_timestamp,
+            slot: self.round_open_slot,
+        })
+    }
+}
+
+/// A SwitchboardDecimal `mantissa / 10^scale` at PRICE_DECIMALS, truncated.
+fn normalize_decimal(mantissa: i128, scale: u32) -> Result<u64, ProgramError> {
+    if mantissa < 0 {
+        return Err(PerpError::OracleInvalidPrice.into());
+    }
+    let normalized = rescale(mantissa, scale, math::PRICE_DECIMALS as u32)?;
+    u64::try_from(normalized).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+/// `mantissa / 10^from_scale` as a mantissa over `10^to_scale`, truncated.
+fn rescale(mantissa: i128, from_scale: u32, to_scale: u32) -> Result<i128, ProgramError> {
+    let pow10 = |exp: u32| 10i128.checked_pow(exp).ok_or(ProgramError::ArithmeticOverflow);
+
+    if to_scale >= from_scale {
+        math::checked_mul(mantissa, pow10(to_scale - from_scale)?)
+    } else {
+        Ok(mantissa / pow10(from_scale - to_scale)?)
+    }
+}
+
+/// Normalized price of a Switchboard aggregator's latest confirmed round, no
+/// older than `max_age` seconds.
+pub fn get_switchboard_price(account: &AccountInfo, clock: &Clock, max_age: u64) -> Result<u64, ProgramError> {
+    Ok(AggregatorRound::from_account_info(account)?.price_no_older_than(clock, max_age)?.price)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::states::{with_account_infos, TestAccount};
+
+    const NOW: i64 = 1_700_000_000;
+
+    /// An aggregator account whose latest round reports `mantissa / 10^scale`.
+    fn aggregator_data(mantissa: i128, scale: u32, opened_at: i64) -> Vec<u8> {
+        let mut data = vec![0u8; ROUND_END + 64];
+        data[..8].copy_from_slice(&AGGREGATOR_DISCRIMINATOR);
+        data[ROUND_OPEN_SLOT..ROUND_OPEN_SLOT + 8].copy_from_slice(&250_000_000u64.to_le_bytes());
+        data[ROUND_OPEN_TIMESTAMP..ROUND_OPEN_TIMESTAMP + 8].copy_from_slice(&opened_at.to_le_bytes());
+        data[RESULT..RESULT + 16].copy_from_slice(&mantissa.to_le_bytes());
+        data[RESULT + 16..RESULT + 20].copy_from_slice(&scale.to_le_bytes());
+        // A deviation of 0.15, 0.1% of a $150 price
+        data[STD_DEVIATION..STD_DEVIATION + 16].copy_from_slice(&15i128.to_le_bytes());
+        data[STD_DEVIATION + 16..STD_DEVIATION + 20].copy_from_slice(&2u32.to_le_bytes());
+        data
+    }
+
+    fn with_aggregator<R>(data: &[u8], owner: Pubkey, f: impl FnOnce(&AccountInfo) -> R) -> R {
+        let account = TestAccount { key: [8u8; 32], owner, is_signer: false, is_writable: false, lamports: 0, data };
+        with_account_infos(&[account], |accounts| f(&accounts[0]))
+    }
+
+    fn clock_at(unix_timestamp: i64) -> Clock {
+        Clock { slot: 250_000_010, epoch_start_timestamp: 0, epoch: 0, leader_schedule_epoch: 0, unix_timestamp }
+    }
+
+    #[test]
+    fn test_aggregator_result_normalized_to_price_decimals() {
+        // $150.123456789 with 9 decimals, truncated to 8
+        with_aggregator(&aggregator_data(150_123456789, 9, NOW), SWITCHBOARD_PROGRAM_ID, |account| {
+            assert_eq!(get_switchboard_price(account, &clock_at(NOW + 5), 60), Ok(150_12345678));
+
+            let price = AggregatorRound::from_account_info(account).unwrap().price_no_older_than(&clock_at(NOW), 60).unwrap();
+            assert_eq!((price.publish_time, price.conf_bps), (NOW, 10));
+        });
+    }
+
+    #[test]
+    fn test_stale_round_rejected() {
+        with_aggregator(&aggregator_data(150_00000000, 8, NOW - 90), SWITCHBOARD_PROGRAM_ID, |account| {
+            assert_eq!(get_switchboard_price(account, &clock_at(NOW), 60), Err(PerpError::OracleStale.into()));
+            assert_eq!(get_switchboard_price(account, &clock_at(NOW), 120), Ok(150_00000000));
+        });
+    }
+
+    #[test]
+    fn test_spoofed_or_foreign_accounts_rejected() {
+        let data = aggregator_data(150_00000000, 8, NOW);
+        with_aggregator(&data, [4u8; 32], |account| {
+            assert_eq!(get_switchboard_price(account, &clock_at(NOW), 60), Err(ProgramError::IncorrectProgramId));
+        });
+
+        // A Switchboard account that isn't an aggregator
+        let mut other = data.clone();
+        other[..8].copy_from_slice(&[0u8; 8]);
+        with_aggregator(&other, SWITCHBOARD_PROGRAM_ID, |account| {
+            assert_eq!(get_switchboard_price(account, &clock_at(NOW), 60), Err(ProgramError::InvalidAccountData));
+        });
+    }
+
+    #[test]
+    fn test_unconfirmed_round_rejected() {
+        with_aggregator(&aggregator_data(0, 0, NOW), SWITCHBOARD_PROGRAM_ID, |account| {
+            assert_eq!(get_switchboard_price(account, &clock_at(NOW), 60), Err(PerpError::OracleInvalidPrice.into()));
+        });
+        // A fresh aggregator has no round at all
+        with_aggregator(&aggregator_data(0, 0, 0), SWITCHBOARD_PROGRAM_ID, |account| {
+            assert_eq!(get_switchboard_price(account, &clock_at(NOW), 60), Err(PerpError::OracleNeverPublished.into()));
+        });
+    }
+
+    #[test]
+    fn test_trading_price_fetch_dispatches_on_oracle_kind() {
+        use crate::{instructions::{check_oracle_slot_lag, get_price_for_feed}, states::{Market, OracleKind}};
+
+        let switchboard = Market { oracle_kind: OracleKind::Switchboard as u8, ..Market::default() };
+        with_aggregator(&aggregator_data(150_00000000, 8, NOW), SWITCHBOARD_PROGRAM_ID, |account| {
+            let price = get_price_for_feed(account, &clock_at(NOW), &switchboard.oracle_config()).unwrap();
+            assert_eq!(price.price, 150_00000000);
+            assert_eq!(check_oracle_slot_lag(account, &clock_at(NOW), OracleKind::Switchboard, 5), Err(PerpError::OracleStale.into()));
+            assert!(check_oracle_slot_lag(account, &clock_at(NOW), OracleKind::Switchboard, 10).is_ok());
+
+            // A Pyth market reads the same account as a (spoofed) price update
+            assert_eq!(
+                get_price_for_feed(account, &clock_at(NOW), &Market::default().oracle_config()),
+                Err(ProgramError::IncorrectProgramId)
+            );
+        });
+    }
+
+    #[test]
+    fn test_snapshot_keeps_result_scale() {
+        with_aggregator(&aggregator_data(150_123456789, 9, NOW), SWITCHBOARD_PROGRAM_ID, |account| {
+            let snapshot = AggregatorRound::from_account_info(account).unwrap().snapshot(&[1u8; 32], NOW + 3).unwrap();
+            assert_eq!((snapshot.price, snapshot.exponent), (150_123456789, -9));
+            // 0.15 at 9 decimals
+            assert_eq!(snapshot.conf, 150_000_000);
+            assert_eq!((snapshot.publish_time, snapshot.slot), (NOW, 250_000_000));
+        });
+    }
+}
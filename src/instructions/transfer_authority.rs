@@ -0,0 +1,142 @@
+use pinocchio::{account_info::AccountInfo, program_error::ProgramError, pubkey::Pubkey, ProgramResult};
+
+use crate::{
+    instructions::{check_writable, load_market_as_authority, not_enough_accounts},
+    states::Market,
+};
+
+/// First half of an authority handover: the current authority proposes the
+/// new one, which only takes over once it signs AcceptMarketAuthority. A
+/// mistyped key never gets control, and proposing again replaces it.
+pub fn process_transfer_authority(accounts: &[AccountInfo]) -> ProgramResult {
+
+    let [
+        _authority, // Current market authority (must sign transaction)
+        _market_account, // Market to hand over
+        new_authority, // Proposed authority, doesn't sign here
+        ] = accounts else {
+        return Err(not_enough_accounts(3, accounts.len()));
+    };
+
+    let mut market = load_market_as_authority(&accounts[..2])?;
+    market.pending_authority = *new_authority.key();
+
+    println!("Proposed market authority: {:?}", new_authority.key());
+
+    Ok(())
+}
+
+/// Second half of an authority handover: the proposed authority signs to take
+/// over the market.
+pub fn process_accept_authority(accounts: &[AccountInfo]) -> ProgramResult {
+
+    let [
+        new_authority, // Proposed market authority (must sign transaction)
+        market_account, // Market being handed over
+        ] = accounts else {
+        return Err(not_enough_accounts(2, accounts.len()));
+    };
+
+    if !new_authority.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[market_account])?;
+    if !market_account.is_owned_by(&crate::ID) {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+
+    let mut market = Market::load_initialized_mut(market_account)?;
+    if market.pending_authority == Pubkey::default() || market.pending_authority != *new_authority.key() {
+        return Err(ProgramError::IncorrectAuthority);
+    }
+    market.authority = market.pending_authority;
+    market.pending_authority = Pubkey::default();
+
+    println!("Market authority: {:?}", new_authority.key());
+
+    Ok(())
+}
+
+// =========================== TESTING process_transfer_authority ===========================
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::{process_accept_authority, process_transfer_authority};
+    use crate::states::{with_account_infos, Market, TestAccount};
+
+    const AUTHORITY: [u8; 32] = [1u8; 32];
+    const NEW_AUTHORITY: [u8; 32] = [5u8; 32];
+    const MARKET: [u8; 32] = [2u8; 32];
+
+    fn signer(key: [u8; 32]) -> TestAccount<'static> {
+        TestAccount { key, owner: [0u8; 32], is_signer: true, is_writable: false, lamports: 0, data: &[] }
+    }
+
+    fn market(data: &[u8]) -> TestAccount<'_> {
+        TestAccount { key: MARKET, owner: crate::ID, is_signer: false, is_writable: true, lamports: 0, data }
+    }
+
+    fn market_bytes() -> Vec<u8> {
+        let mut data = vec![0u8; Market::SIZE];
+        data[..8].copy_from_slice(&Market::DISCRIMINATOR);
+        data[core::mem::offset_of!(Market, is_initialized)] = 1;
+        for field in [core::mem::offset_of!(Market, authority), core::mem::offset_of!(Market, seed_authority)] {
+            data[field..field + 32].copy_from_slice(&AUTHORITY);
+        }
+        data
+    }
+
+    /// Proposes NEW_AUTHORITY and returns the market data afterwards.
+    fn proposed() -> Vec<u8> {
+        let data = market_bytes();
+        let new_authority = TestAccount { is_signer: false, ..signer(NEW_AUTHORITY) };
+        with_account_infos(&[signer(AUTHORITY), market(&data), new_authority], |accounts| {
+            process_transfer_authority(accounts).unwrap();
+            let market = Market::from_account_info(&accounts[1]).unwrap();
+            assert_eq!(market.authority, AUTHORITY);
+            assert_eq!(market.pending_authority, NEW_AUTHORITY);
+            accounts[1].try_borrow_data().unwrap().to_vec()
+        })
+    }
+
+    #[test]
+    fn test_propose_then_accept_hands_over_the_market() {
+        let data = proposed();
+        with_account_infos(&[signer(NEW_AUTHORITY), market(&data)], |accounts| {
+            process_accept_authority(accounts).unwrap();
+            let market = Market::from_account_info(&accounts[1]).unwrap();
+            assert_eq!(market.authority, NEW_AUTHORITY);
+            assert_eq!(market.pending_authority, [0u8; 32]);
+            // The PDA seed doesn't move with the authority
+            assert_eq!(market.seed_authority, AUTHORITY);
+        });
+    }
+
+    #[test]
+    fn test_wrong_key_cannot_accept() {
+        let data = proposed();
+        for key in [[9u8; 32], AUTHORITY] {
+            with_account_infos(&[signer(key), market(&data)], |accounts| {
+                assert_eq!(process_accept_authority(accounts), Err(ProgramError::IncorrectAuthority));
+            });
+        }
+    }
+
+    #[test]
+    fn test_nothing_to_accept_without_a_proposal() {
+        let data = market_bytes();
+        with_account_infos(&[signer([0u8; 32]), market(&data)], |accounts| {
+            assert_eq!(process_accept_authority(accounts), Err(ProgramError::IncorrectAuthority));
+        });
+    }
+
+    #[test]
+    fn test_only_the_current_authority_proposes() {
+        let data = market_bytes();
+        with_account_infos(&[signer(NEW_AUTHORITY), market(&data), signer(NEW_AUTHORITY)], |accounts| {
+            assert_eq!(process_transfer_authority(accounts), Err(ProgramError::InvalidAccountData));
+        });
+    }
+}
@@ -0,0 +1,128 @@
+use pinocchio::{account_info::AccountInfo, program_error::ProgramError, sysvars::{clock::Clock, Sysvar}, ProgramResult};
+
+use crate::{
+    events::{MarketParams, MarketParamsUpdated},
+    instructions::{load_market_as_authority, InitializeMarketArgs},
+};
+
+/// Instruction data: [max_leverage: u64]
+///
+/// Same bounds as at init. Only new opens are checked against it, so
+/// lowering the cap leaves existing positions alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpdateMaxLeverageArgs {
+    pub max_leverage: u64,
+}
+
+impl UpdateMaxLeverageArgs {
+    pub const LEN: usize = 8;
+}
+
+impl TryFrom<&[u8]> for UpdateMaxLeverageArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let max_leverage = u64::from_le_bytes(
+            data[0..8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+        if max_leverage == 0 || max_leverage > InitializeMarketArgs::MAX_LEVERAGE {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        Ok(Self { max_leverage })
+    }
+}
+
+/// Sets the market's max_leverage without touching its other params. Market authority only.
+pub fn process_update_max_leverage(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+    let mut market = load_market_as_authority(accounts)?;
+    let UpdateMaxLeverageArgs { max_leverage } = UpdateMaxLeverageArgs::try_from(instruction_data)?;
+
+    let old = MarketParams {
+        initial_margin: market.initial_margin,
+        maintenance_margin: market.maintenance_margin,
+        fee_rate: market.fee_rate,
+        max_leverage: market.max_leverage,
+    };
+    market.max_leverage = max_leverage;
+
+    let event = MarketParamsUpdated::new(
+        *accounts[1].key(),
+        old,
+        MarketParams { max_leverage, ..old },
+        *accounts[0].key(),
+        Clock::get()?.unix_timestamp,
+    );
+    if event.changed != 0 {
+        event.emit();
+    }
+
+    Ok(())
+}
+
+// =========================== TESTING process_update_max_leverage ===========================
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::{process_update_max_leverage, UpdateMaxLeverageArgs};
+    use crate::{
+        errors::PerpError,
+        instructions::{check_leverage, InitializeMarketArgs},
+        math::PRICE_DECIMALS,
+        states::{with_account_infos, Market, Position, TestAccount},
+    };
+
+    fn args(max_leverage: u64) -> Result<UpdateMaxLeverageArgs, ProgramError> {
+        UpdateMaxLeverageArgs::try_from(&max_leverage.to_le_bytes()[..])
+    }
+
+    #[test]
+    fn test_max_leverage_bounds() {
+        assert_eq!(args(10).unwrap().max_leverage, 10);
+        assert_eq!(args(InitializeMarketArgs::MAX_LEVERAGE).unwrap().max_leverage, InitializeMarketArgs::MAX_LEVERAGE);
+        assert_eq!(args(0), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(args(InitializeMarketArgs::MAX_LEVERAGE + 1), Err(ProgramError::InvalidInstructionData));
+        assert_eq!(UpdateMaxLeverageArgs::try_from(&[10u8][..]), Err(ProgramError::InvalidInstructionData));
+    }
+
+    #[test]
+    fn test_lower_cap_blocks_new_opens_but_keeps_existing_positions() {
+        let mut market = Market { max_leverage: 20, maintenance_margin: 300, ..Market::default() };
+        // 20 contracts at 100 on 100 margin: 20x
+        let position = Position { size: 20, entry_price: 100, margin: 100, is_active: 1, ..Position::default() };
+        let notional = 2_000;
+        assert!(check_leverage(notional, position.margin, market.max_leverage).is_ok());
+
+        market.max_leverage = args(10).unwrap().max_leverage;
+
+        assert!(!position.is_liquidatable(100, 0, market.maintenance_margin, PRICE_DECIMALS).unwrap());
+        assert_eq!(
+            check_leverage(notional, position.margin, market.max_leverage),
+            Err(PerpError::LeverageTooHigh.into())
+        );
+    }
+
+    #[test]
+    fn test_non_authority_cannot_update() {
+        let mut data = vec![0u8; Market::SIZE];
+        data[..8].copy_from_slice(&Market::DISCRIMINATOR);
+        data[core::mem::offset_of!(Market, is_initialized)] = 1;
+        let accounts = [
+            TestAccount { key: [9u8; 32], owner: [0u8; 32], is_signer: true, is_writable: false, lamports: 0, data: &[] },
+            TestAccount { key: [2u8; 32], owner: crate::ID, is_signer: false, is_writable: true, lamports: 0, data: &data },
+        ];
+
+        with_account_infos(&accounts, |accounts| {
+            assert_eq!(
+                process_update_max_leverage(accounts, &10u64.to_le_bytes()),
+                Err(ProgramError::InvalidAccountData)
+            );
+        });
+    }
+}
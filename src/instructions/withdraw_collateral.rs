@@ -0,0 +1,210 @@
+use pinocchio::{account_info::AccountInfo, instruction::Signer, program_error::ProgramError, sysvars::clock::Clock, *};
+use pinocchio_token::instructions::TransferChecked;
+use pinocchio_token::state::{Mint, TokenAccount};
+
+use crate::{errors::PerpError, instructions::{assert_vault_solvent, check_collateral_vault, check_writable, free_margin_balance, not_enough_accounts}, states::{Market, UserAccount}};
+
+/// Instruction data: [market_id: u64][amount: u64]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WithdrawCollateralArgs {
+    pub market_id: u64,
+    pub amount: u64,
+}
+
+impl WithdrawCollateralArgs {
+    pub const LEN: usize = 8 + 8;
+}
+
+impl TryFrom<&[u8]> for WithdrawCollateralArgs {
+    type Error = ProgramError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        if data.len() < Self::LEN {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+
+        let market_id = u64::from_le_bytes(
+            data[0..8].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+        let amount = u64::from_le_bytes(
+            data[8..16].try_into().map_err(|_| ProgramError::InvalidInstructionData)?
+        );
+
+        Ok(Self { market_id, amount })
+    }
+}
+
+pub fn process_withdraw_collateral(accounts: &[AccountInfo], instruction_data: &[u8]) -> ProgramResult {
+
+    let [
+        user,  // The trader (must sign transaction)
+        market_authority, // Authority the market was created by, its PDA seed
+        collateral_mint, // Token mint for collateral (e.g., USDC)
+        market_account, // Stores market configuration, owns the vault
+        user_account, // User's trading account
+        collateral_vault, // Vault holding all collateral
+        user_token_account, // User's token account to credit
+        token_program,
+        clock_sysvar, // Solana clock for timestamps
+        positions @ .. // The user's open position accounts, to compute free margin
+        ] = accounts else {
+        return Err(not_enough_accounts(9, accounts.len()));
+    };
+
+    // ---- Basic checks ----
+    if !user.is_signer() {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_writable(&[market_account, user_account, collateral_vault, user_token_account])?;
+    if *token_program.key() != pinocchio_token::ID {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // ---- Parse instruction ----
+    let WithdrawCollateralArgs { market_id, amount } = WithdrawCollateralArgs::try_from(instruction_data)?;
+    if amount == 0 {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    // ---- Derive & check PDAs ----
+    let (market_account_pda, market_bump) = pubkey::find_program_address(
+        &[b"market_account", market_authority.key().as_ref(), market_id.to_le_bytes().as_ref()],
+        &crate::ID
+    );
+    if *market_account.key() != market_account_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let (user_account_pda, _user_bump) = pubkey::find_program_address(
+        &[b"user_account", user.key().as_ref()],
+        &crate::ID
+    );
+    if *user_account.key() != user_account_pda {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let (collateral_vault_pda, _collateral_bump) = pubkey::find_program_address(
+        &[b"collateral_vault", market_account.key().as_ref()],
+        &crate::ID
+    );
+
+    // ---- Validate market ----
+    let withdraw_delay = {
+        let market = Market::load_initialized_mut(market_account)?;
+        check_collateral_vault(&market, &collateral_vault_pda, collateral_vault.key())?;
+        if market.seed_authority != *market_authority.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if market.collateral_mint != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        market.withdraw_delay
+    };
+
+    // ---- Token account validations ----
+    {
+        let user_ta = TokenAccount::from_account_info(user_token_account)?;
+        if *user_ta.owner() != *user.key() || *user_ta.mint() != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let vault_ta = TokenAccount::from_account_info(collateral_vault)?;
+        if *vault_ta.mint() != *collateral_mint.key() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+    }
+    let decimals = Mint::from_account_info(collateral_mint)?.decimals();
+
+    let clock = Clock::from_account_info(clock_sysvar)?;
+
+    // ---- Free margin & settlement delay ----
+    let mut user_account_data = UserAccount::from_account_info_mut(user_account)?;
+    if user_account_data.owner != *user.key() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    // The balance can only be paid out of a vault of the mint it was deposited in
+    user_account_data.bind_collateral_mint(collateral_mint.key())?;
+
+    check_withdraw_delay(user_account_data.deposit_time, withdraw_delay, clock.unix_timestamp)?;
+
+    let free_margin = free_margin_balance(&user_account_data, None, positions)?;
+    if amount > free_margin {
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    // ---- Transfer vault -> user (signed by market PDA) ----
+    let market_id_bytes = market_id.to_le_bytes();
+    let bump_ref = &[market_bump];
+    let seeds = seeds!(
+        b"market_account",
+        market_authority.key().as_ref(),
+        &market_id_bytes,
+        bump_ref
+    );
+    let signer = Signer::from(&seeds);
+
+    TransferChecked {
+        from: collateral_vault,
+        to: user_token_account,
+        authority: market_account,
+        mint: collateral_mint,
+        amount,
+        decimals,
+    }.invoke_signed(&[signer])?;
+
+    // ---- Update accounting ----
+    user_account_data.margin_balance = user_account_data.margin_balance
+        .checked_sub(amount)
+        .ok_or(ProgramError::InsufficientFunds)?;
+
+    let mut market = Market::from_account_info_mut(market_account)?;
+    market.debit_collateral(amount);
+    assert_vault_solvent(&market, &*TokenAccount::from_account_info(collateral_vault)?)?;
+
+    println!("Collateral withdrawn: {}", amount);
+
+    Ok(())
+}
+
+/// Rejects withdrawals within `withdraw_delay` seconds of the last deposit,
+/// so collateral can't be deposited and pulled out around a single price update.
+fn check_withdraw_delay(deposit_time: i64, withdraw_delay: u64, now: i64) -> ProgramResult {
+    if withdraw_delay == 0 {
+        return Ok(());
+    }
+
+    let elapsed = now.saturating_sub(deposit_time);
+    if elapsed < withdraw_delay as i64 {
+        return Err(PerpError::WithdrawDelayActive.into());
+    }
+
+    Ok(())
+}
+
+// =========================== TESTING process_withdraw_collateral ===========================
+
+#[cfg(test)]
+mod tests {
+    use super::{check_withdraw_delay, PerpError};
+
+    const DEPOSIT_TIME: i64 = 1_700_000_000;
+
+    #[test]
+    fn test_immediate_withdraw_rejected_when_delay_set() {
+        assert_eq!(
+            check_withdraw_delay(DEPOSIT_TIME, 60, DEPOSIT_TIME),
+            Err(PerpError::WithdrawDelayActive.into())
+        );
+        assert_eq!(
+            check_withdraw_delay(DEPOSIT_TIME, 60, DEPOSIT_TIME + 59),
+            Err(PerpError::WithdrawDelayActive.into())
+        );
+    }
+
+    #[test]
+    fn test_withdraw_allowed_after_delay() {
+        assert!(check_withdraw_delay(DEPOSIT_TIME, 60, DEPOSIT_TIME + 60).is_ok());
+        // No delay configured
+        assert!(check_withdraw_delay(DEPOSIT_TIME, 0, DEPOSIT_TIME).is_ok());
+    }
+}
@@ -1,13 +1,27 @@
 use pinocchio::{account_info::AccountInfo, pubkey::Pubkey,program_error::ProgramError, *};
 use pinocchio_pubkey::declare_id;
 
-use crate::instructions::{initialize_market, initialize_user_account, open_position, process_open_position, PerpetualInstructions};
+use crate::instructions::{
+    initialize_market, initialize_user_account, process_open_position, process_close_position,
+    process_set_market_params, process_withdraw_collateral, process_settle_funding,
+    process_mark_liquidatable, process_liquidate_from_queue, process_get_withdrawable_margin,
+    process_set_trading_halted, process_set_delegate, process_deposit_collateral, process_liquidate,
+    process_deposit_insurance, process_adl, process_pause_market, process_resume_market,
+    process_update_max_leverage, process_transfer_authority, process_accept_authority,
+    process_close_market,
+    PerpetualInstructions,
+};
 
 entrypoint!(process_instruction);
 
 declare_id!("BXacY2xWwx7ogSa1CnvrdXxAigBMwwszoZf4Q98E2YoV");
 
+#[cfg(feature = "client")]
+pub mod client;
+pub mod errors;
+pub mod events;
 pub mod instructions;
+pub mod math;
 pub mod states;
 
 pub fn process_instruction(
@@ -22,6 +36,25 @@ pub fn process_instruction(
         PerpetualInstructions::InitializeMarket => initialize_market(accounts, instruction_data)?,
         PerpetualInstructions::InitializeUser => initialize_user_account(accounts)?,
         PerpetualInstructions::OpenPosition => process_open_position(accounts, instruction_data)?,
+        PerpetualInstructions::ClosePosition => process_close_position(accounts, instruction_data)?,
+        PerpetualInstructions::SetMarketParams => process_set_market_params(accounts, instruction_data)?,
+        PerpetualInstructions::WithdrawCollateral => process_withdraw_collateral(accounts, instruction_data)?,
+        PerpetualInstructions::SettleFunding => process_settle_funding(accounts)?,
+        PerpetualInstructions::MarkLiquidatable => process_mark_liquidatable(accounts)?,
+        PerpetualInstructions::LiquidateFromQueue => process_liquidate_from_queue(accounts)?,
+        PerpetualInstructions::GetWithdrawableMargin => process_get_withdrawable_margin(accounts)?,
+        PerpetualInstructions::SetTradingHalted => process_set_trading_halted(accounts, instruction_data)?,
+        PerpetualInstructions::SetDelegate => process_set_delegate(accounts, instruction_data)?,
+        PerpetualInstructions::DepositCollateral => process_deposit_collateral(accounts, instruction_data)?,
+        PerpetualInstructions::Liquidate => process_liquidate(accounts, instruction_data)?,
+        PerpetualInstructions::DepositInsurance => process_deposit_insurance(accounts, instruction_data)?,
+        PerpetualInstructions::Adl => process_adl(accounts, instruction_data)?,
+        PerpetualInstructions::PauseMarket => process_pause_market(accounts, instruction_data)?,
+        PerpetualInstructions::ResumeMarket => process_resume_market(accounts)?,
+        PerpetualInstructions::UpdateMaxLeverage => process_update_max_leverage(accounts, instruction_data)?,
+        PerpetualInstructions::TransferMarketAuthority => process_transfer_authority(accounts)?,
+        PerpetualInstructions::AcceptMarketAuthority => process_accept_authority(accounts)?,
+        PerpetualInstructions::CloseMarket => process_close_market(accounts, instruction_data)?,
     }
     
     Ok(())
@@ -0,0 +1,184 @@
+//! Checked arithmetic shared by the instruction and state code.
+//!
+//! Every helper returns `ProgramError::ArithmeticOverflow` instead of wrapping
+//! or panicking, so callers can `?` straight through.
+//!
+//! Divisions that can leave a remainder go through `mul_div` with an explicit
+//! `RoundingMode`, always chosen against the trader: margin requirements and
+//! fees round up, anything credited to the trader rounds down.
+//!
+//! Notional, PnL and funding come out of `size * price`: quote currency at
+//! `PRICE_DECIMALS`. Margin, fees and balances are collateral token units at
+//! the mint's decimals. `quote_to_collateral` is the one conversion between
+//! the two; collateral is a USD stablecoin, one whole token per dollar.
+
+use pinocchio::program_error::ProgramError;
+
+pub fn checked_add(a: i128, b: i128) -> Result<i128, ProgramError> {
+    a.checked_add(b).ok_or(ProgramError::ArithmeticOverflow)
+}
+
+pub fn checked_sub(a: i128, b: i128) -> Result<i128, ProgramError> {
+    a.checked_sub(b).ok_or(ProgramError::ArithmeticOverflow)
+}
+
+pub fn checked_mul(a: i128, b: i128) -> Result<i128, ProgramError> {
+    a.checked_mul(b).ok_or(ProgramError::ArithmeticOverflow)
+}
+
+/// Direction to round a quotient that has a remainder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundingMode {
+    Down, // Toward negative infinity
+    Up, // Toward positive infinity
+}
+
+/// `a * b / denominator`, rounded in the given direction.
+pub fn mul_div(a: i128, b: i128, denominator: i128, rounding: RoundingMode) -> Result<i128, ProgramError> {
+    let product = checked_mul(a, b)?;
+    let quotient = product.checked_div(denominator).ok_or(ProgramError::ArithmeticOverflow)?;
+    let remainder = product % denominator;
+    if remainder == 0 {
+        return Ok(quotient);
+    }
+
+    // Integer division truncates toward zero, which is already one of the two directions
+    let negative = (remainder < 0) != (denominator < 0);
+    match rounding {
+        RoundingMode::Down if negative => checked_sub(quotient, 1),
+        RoundingMode::Up if !negative => checked_add(quotient, 1),
+        _ => Ok(quotient),
+    }
+}
+
+/// Decimals of normalized oracle prices, and so of every `size * price` amount.
+pub const PRICE_DECIMALS: u8 = 8;
+
+/// Converts a quote amount (`size * price`, PRICE_DECIMALS) to collateral
+/// token units of a mint with `decimals`, rounded in the given direction.
+/// A mint with PRICE_DECIMALS decimals converts one to one.
+pub fn quote_to_collateral(quote: i128, decimals: u8, rounding: RoundingMode) -> Result<i128, ProgramError> {
+    let pow10 = |exp: u8| 10i128.checked_pow(exp as u32).ok_or(ProgramError::ArithmeticOverflow);
+
+    if decimals >= PRICE_DECIMALS {
+        checked_mul(quote, pow10(decimals - PRICE_DECIMALS)?)
+    } else {
+        mul_div(quote, 1, pow10(PRICE_DECIMALS - decimals)?, rounding)
+    }
+}
+
+/// Notional of `size` contracts at a raw oracle price `mantissa * 10^exponent`,
+/// in quote units (PRICE_DECIMALS). Scaling happens once on the full product,
+/// so digits a normalized price would have truncated still count. Rounds down.
+pub fn quote_value(size: i128, mantissa: i128, exponent: i32) -> Result<i128, ProgramError> {
+    let product = checked_mul(size.checked_abs().ok_or(ProgramError::ArithmeticOverflow)?, mantissa)?;
+    let shift = exponent
+        .checked_add(PRICE_DECIMALS as i32)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let scale = 10i128.checked_pow(shift.unsigned_abs()).ok_or(ProgramError::ArithmeticOverflow)?;
+
+    if shift >= 0 {
+        checked_mul(product, scale)
+    } else {
+        mul_div(product, 1, scale, RoundingMode::Down)
+    }
+}
+
+/// `quote_to_collateral` for unsigned amounts.
+pub fn quote_to_collateral_u64(quote: u64, decimals: u8, rounding: RoundingMode) -> Result<u64, ProgramError> {
+    let result = quote_to_collateral(quote as i128, decimals, rounding)?;
+    u64::try_from(result).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+/// Converts collateral token units of a mint with `decimals` back to quote
+/// units, the inverse of `quote_to_collateral`.
+pub fn collateral_to_quote(amount: i128, decimals: u8, rounding: RoundingMode) -> Result<i128, ProgramError> {
+    let pow10 = |exp: u8| 10i128.checked_pow(exp as u32).ok_or(ProgramError::ArithmeticOverflow);
+
+    if decimals <= PRICE_DECIMALS {
+        checked_mul(amount, pow10(PRICE_DECIMALS - decimals)?)
+    } else {
+        mul_div(amount, 1, pow10(decimals - PRICE_DECIMALS)?, rounding)
+    }
+}
+
+/// `mul_div` for unsigned amounts.
+pub fn mul_div_u64(a: u64, b: u64, denominator: u64, rounding: RoundingMode) -> Result<u64, ProgramError> {
+    let result = mul_div(a as i128, b as i128, denominator as i128, rounding)?;
+    u64::try_from(result).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::{checked_add, checked_mul, checked_sub, collateral_to_quote, mul_div, mul_div_u64, quote_to_collateral, quote_to_collateral_u64, quote_value, RoundingMode, PRICE_DECIMALS};
+
+    #[test]
+    fn test_overflow_is_an_error_not_a_wrap() {
+        assert_eq!(checked_add(i128::MAX, 1), Err(ProgramError::ArithmeticOverflow));
+        assert_eq!(checked_sub(i128::MIN, 1), Err(ProgramError::ArithmeticOverflow));
+        assert_eq!(checked_mul(i128::MAX, -2), Err(ProgramError::ArithmeticOverflow));
+        assert_eq!(checked_mul(-4, 5), Ok(-20));
+    }
+
+    #[test]
+    fn test_mul_div_rounds_in_the_requested_direction() {
+        // 7 * 3 / 2 = 10.5
+        assert_eq!(mul_div(7, 3, 2, RoundingMode::Down), Ok(10));
+        assert_eq!(mul_div(7, 3, 2, RoundingMode::Up), Ok(11));
+        // -10.5 rounds toward negative infinity for Down
+        assert_eq!(mul_div(-7, 3, 2, RoundingMode::Down), Ok(-11));
+        assert_eq!(mul_div(-7, 3, 2, RoundingMode::Up), Ok(-10));
+        assert_eq!(mul_div(7, 3, -2, RoundingMode::Down), Ok(-11));
+        // Exact quotients are untouched
+        assert_eq!(mul_div(-8, 3, 2, RoundingMode::Down), Ok(-12));
+        assert_eq!(mul_div_u64(8, 3, 2, RoundingMode::Up), Ok(12));
+    }
+
+    #[test]
+    fn test_mul_div_errors() {
+        assert_eq!(mul_div(1, 1, 0, RoundingMode::Down), Err(ProgramError::ArithmeticOverflow));
+        assert_eq!(mul_div_u64(1, 1, 0, RoundingMode::Up), Err(ProgramError::ArithmeticOverflow));
+        assert_eq!(mul_div_u64(u64::MAX, 2, 1, RoundingMode::Down), Err(ProgramError::ArithmeticOverflow));
+    }
+
+    #[test]
+    fn test_quote_to_collateral_scales_by_mint_decimals() {
+        // $1_500 of notional
+        let notional = 1500_00000000u64;
+        assert_eq!(quote_to_collateral_u64(notional, 6, RoundingMode::Up), Ok(1_500_000_000));
+        assert_eq!(quote_to_collateral_u64(notional, 9, RoundingMode::Up), Ok(1_500_000_000_000));
+        assert_eq!(quote_to_collateral_u64(notional, PRICE_DECIMALS, RoundingMode::Up), Ok(notional));
+
+        // Sub-unit remainders round in the requested direction, losses further from zero
+        assert_eq!(quote_to_collateral(-150, 6, RoundingMode::Down), Ok(-2));
+        assert_eq!(quote_to_collateral(150, 6, RoundingMode::Down), Ok(1));
+        assert_eq!(quote_to_collateral(150, 6, RoundingMode::Up), Ok(2));
+
+        assert_eq!(quote_to_collateral(i128::MAX, 18, RoundingMode::Up), Err(ProgramError::ArithmeticOverflow));
+    }
+
+    #[test]
+    fn test_collateral_to_quote_inverts_quote_to_collateral() {
+        assert_eq!(collateral_to_quote(1_500_000_000, 6, RoundingMode::Down), Ok(1500_00000000));
+        assert_eq!(collateral_to_quote(1_500_000_000_000, 9, RoundingMode::Down), Ok(1500_00000000));
+        assert_eq!(collateral_to_quote(-7, PRICE_DECIMALS, RoundingMode::Down), Ok(-7));
+        // Sub-quote-unit remainders of high decimal mints round as asked
+        assert_eq!(collateral_to_quote(15, 9, RoundingMode::Down), Ok(1));
+        assert_eq!(collateral_to_quote(15, 9, RoundingMode::Up), Ok(2));
+    }
+
+    #[test]
+    fn test_quote_value_scales_raw_prices() {
+        // 10 contracts at $150 published with 8 decimals and with 5
+        assert_eq!(quote_value(10, 150_00000000, -8), Ok(1500_00000000));
+        assert_eq!(quote_value(-10, 150_00000, -5), Ok(1500_00000000));
+        // 1e6 contracts at $0.000000123: a normalized price would drop the 3
+        assert_eq!(quote_value(1_000_000, 123, -9), Ok(12_300_000));
+        // 1e11 contracts at $1_500 is past u64, not i128
+        assert_eq!(quote_value(100_000_000_000, 1500, 0), Ok(15_000_000_000_000_000_000_000));
+
+        assert_eq!(quote_value(i128::MAX, 2, -8), Err(ProgramError::ArithmeticOverflow));
+    }
+}
@@ -0,0 +1,39 @@
+use pinocchio::{account_info::{AccountInfo, Ref, RefMut}, program_error::ProgramError, pubkey::Pubkey};
+
+/// A user's delegation of trading authority (seeds: "delegation", user).
+/// The delegate can open positions for the user; positions stay the user's.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DelegateAuthority {
+    pub user: Pubkey, // User who delegated
+    pub delegate: Pubkey, // Key allowed to trade for the user, default when revoked
+    pub bump: u8, // PDA bump for address derivation
+}
+
+impl DelegateAuthority {
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    pub fn from_account_info(account: &AccountInfo) -> Result<Ref<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(Ref::map(account.try_borrow_data()?, |data| unsafe {
+            &*(data.as_ptr() as *const Self)
+        }))
+    }
+
+    pub fn from_account_info_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(RefMut::map(account.try_borrow_mut_data()?, |data| unsafe {
+            &mut *(data.as_mut_ptr() as *mut Self)
+        }))
+    }
+
+    pub fn allows(&self, user: &Pubkey, delegate: &Pubkey) -> bool {
+        self.user == *user && self.delegate == *delegate && *delegate != Pubkey::default()
+    }
+}
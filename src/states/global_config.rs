@@ -0,0 +1,38 @@
+use pinocchio::{account_info::{AccountInfo, Ref, RefMut}, program_error::ProgramError, pubkey::Pubkey};
+
+/// Program-wide settings, one PDA per deployment (seeds: "global_config").
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlobalConfig {
+    pub admin: Pubkey, // Only key allowed to change the config, set on creation
+    pub trading_halted: bool, // Emergency stop: blocks new opens in every market, never closes
+    pub bump: u8, // PDA bump for address derivation
+}
+
+impl GlobalConfig {
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    pub fn from_account_info(account: &AccountInfo) -> Result<Ref<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(Ref::map(account.try_borrow_data()?, |data| unsafe {
+            &*(data.as_ptr() as *const Self)
+        }))
+    }
+
+    pub fn from_account_info_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(RefMut::map(account.try_borrow_mut_data()?, |data| unsafe {
+            &mut *(data.as_mut_ptr() as *mut Self)
+        }))
+    }
+
+    pub fn allows_open(&self) -> bool {
+        !self.trading_halted
+    }
+}
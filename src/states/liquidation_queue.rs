@@ -0,0 +1,150 @@
+use pinocchio::{account_info::{AccountInfo, RefMut}, program_error::ProgramError, pubkey::Pubkey};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QueueEntry {
+    pub position: Pubkey, // Position account to liquidate
+    pub health_bps: i64, // Equity over maintenance requirement when marked, see Position::health_bps
+}
+
+/// Per-market queue of underwater positions, most underwater first. Keepers
+/// populate it with the mark instruction and liquidate from its head.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LiquidationQueue {
+    pub market: Pubkey, // Market the queued positions trade on
+    pub len: u64, // Number of live entries
+    pub entries: [QueueEntry; LiquidationQueue::CAPACITY], // Sorted by health_bps, ascending
+}
+
+impl LiquidationQueue {
+    pub const CAPACITY: usize = 16;
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    pub fn from_account_info_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(RefMut::map(account.try_borrow_mut_data()?, |data| unsafe {
+            &mut *(data.as_mut_ptr() as *mut Self)
+        }))
+    }
+
+    pub fn entries(&self) -> &[QueueEntry] {
+        &self.entries[..self.len as usize]
+    }
+
+    /// Queues `position` by health, replacing any existing entry for it. When
+    /// full, the healthiest entry is dropped, or the new one if it's healthier still.
+    pub fn upsert(&mut self, position: Pubkey, health_bps: i64) {
+        self.remove(&position);
+
+        let len = self.len as usize;
+        let index = self.entries()
+            .iter()
+            .position(|entry| health_bps < entry.health_bps)
+            .unwrap_or(len);
+        if index == Self::CAPACITY {
+            return;
+        }
+
+        let end = len.min(Self::CAPACITY - 1);
+        self.entries.copy_within(index..end, index + 1);
+        self.entries[index] = QueueEntry { position, health_bps };
+        self.len = (end + 1) as u64;
+    }
+
+    /// Drops `position` from the queue, returning whether it was queued.
+    pub fn remove(&mut self, position: &Pubkey) -> bool {
+        let len = self.len as usize;
+        let Some(index) = self.entries().iter().position(|entry| entry.position == *position) else {
+            return false;
+        };
+
+        self.entries.copy_within(index + 1..len, index);
+        self.entries[len - 1] = QueueEntry::default();
+        self.len -= 1;
+        true
+    }
+
+    /// Removes and returns the most underwater entry.
+    pub fn pop_front(&mut self) -> Option<QueueEntry> {
+        let front = *self.entries().first()?;
+        self.remove(&front.position);
+        Some(front)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LiquidationQueue;
+    use crate::{math::PRICE_DECIMALS, states::Position};
+
+    #[test]
+    fn test_underwater_positions_liquidated_in_health_order() {
+        let position = |margin| Position { size: 10, entry_price: 100, margin, is_active: 1, ..Position::default() };
+        let (price, maintenance_bps) = (94, 500);
+
+        let slightly_under = position(45);
+        let deeply_under = position(5);
+        let healthy = position(500);
+
+        let mut queue = LiquidationQueue::default();
+        for (key, position) in [([1u8; 32], &slightly_under), ([2u8; 32], &deeply_under), ([3u8; 32], &healthy)] {
+            if position.is_liquidatable(price, 0, maintenance_bps, PRICE_DECIMALS).unwrap() {
+                queue.upsert(key, position.health_bps(price, 0, maintenance_bps, PRICE_DECIMALS).unwrap());
+            }
+        }
+
+        assert_eq!(queue.len, 2);
+        assert_eq!(queue.pop_front().unwrap().position, [2u8; 32]);
+        assert_eq!(queue.pop_front().unwrap().position, [1u8; 32]);
+        assert!(queue.pop_front().is_none());
+    }
+
+    #[test]
+    fn test_pops_in_health_order() {
+        let mut queue = LiquidationQueue::default();
+        queue.upsert([1u8; 32], 9_000);
+        queue.upsert([2u8; 32], 4_000);
+        queue.upsert([3u8; 32], 7_000);
+
+        assert_eq!(queue.pop_front().unwrap().position, [2u8; 32]);
+        assert_eq!(queue.pop_front().unwrap().position, [3u8; 32]);
+        assert_eq!(queue.pop_front().unwrap().position, [1u8; 32]);
+        assert!(queue.pop_front().is_none());
+    }
+
+    #[test]
+    fn test_upsert_replaces_existing_entry() {
+        let mut queue = LiquidationQueue::default();
+        queue.upsert([1u8; 32], 9_000);
+        queue.upsert([2u8; 32], 8_000);
+        queue.upsert([1u8; 32], 1_000);
+
+        assert_eq!(queue.len, 2);
+        assert_eq!(queue.entries()[0].position, [1u8; 32]);
+        assert!(queue.remove(&[2u8; 32]));
+        assert!(!queue.remove(&[2u8; 32]));
+    }
+
+    #[test]
+    fn test_full_queue_keeps_most_underwater() {
+        let mut queue = LiquidationQueue::default();
+        for i in 0..LiquidationQueue::CAPACITY {
+            queue.upsert([i as u8 + 1; 32], 1_000 + i as i64);
+        }
+
+        // Healthier than everything queued, dropped
+        queue.upsert([100u8; 32], 9_999);
+        assert_eq!(queue.len as usize, LiquidationQueue::CAPACITY);
+        assert!(queue.entries().iter().all(|entry| entry.position != [100u8; 32]));
+
+        // Worse than everything queued, evicts the healthiest
+        queue.upsert([200u8; 32], 0);
+        assert_eq!(queue.len as usize, LiquidationQueue::CAPACITY);
+        assert_eq!(queue.entries()[0].position, [200u8; 32]);
+        assert_eq!(queue.entries().last().unwrap().health_bps, 1_000 + LiquidationQueue::CAPACITY as i64 - 2);
+    }
+}
@@ -1,9 +1,16 @@
-use pinocchio::{account_info::{AccountInfo, Ref, RefMut}, program_error::ProgramError, pubkey::Pubkey, ProgramResult};
+use bytemuck::{Pod, Zeroable};
+use pinocchio::{account_info::{AccountInfo, Ref, RefMut}, program_error::ProgramError, pubkey::Pubkey};
+use pythnet_sdk::messages::FeedId;
 
-#[derive(Debug, Clone, Copy)]
+use crate::{errors::PerpError, instructions::{OracleConfig, VerificationLevel}, math::{self, RoundingMode}, states::{check_discriminator, write_discriminator}};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
 pub struct Market {
-    pub is_initialized: bool,
-    pub market_id: u8,
+    pub discriminator: [u8; 8], // Market::DISCRIMINATOR once created
+    pub is_initialized: u8,
+    pub _padding0: [u8; 7],
+    pub market_id: u64,
     pub market_symbol: [u8; 16], // Human-readable market name SOL-PERP
     pub oracle: Pubkey, // Price oracle account
     pub collateral_mint: Pubkey, //The SPL Token used for collateral/margin
@@ -35,8 +42,10 @@ pub struct Market {
     pub open_interest_short: u64,// total size of all short positions
 
     // Collateral stats
-    // Total margin locked in the market.
-    pub total_collateral: u64,   // total margin collateral in this market
+    // Collateral transferred into the vault less what was paid out, so it
+    // tracks the vault balance exactly. See credit_collateral/debit_collateral.
+    pub total_collateral: u64,   // total collateral held for this market
+    pub _padding1: [u8; 8],
     // Snapshot of system-wide unrealized profit/loss.
     pub unrealized_pnl: i128,    // system-wide PnL snapshot
 
@@ -48,29 +57,656 @@ pub struct Market {
     pub bump: u8,
 
     pub collateral_bump: u8, // PDA bump for collateral vault
+
+    // Trading status, see MarketStatus. Gates opens, never closes.
+    pub status: u8,
+    pub _padding2: [u8; 5],
+
+    // Max seconds between the oracle's previous and current publish (0 = unchecked)
+    pub max_publish_gap: u64,
+
+    // Pyth feed the market is priced off, the oracle account must publish this feed
+    pub feed_id: FeedId,
+
+    // Seconds after a deposit before collateral can be withdrawn (0 = no delay)
+    pub withdraw_delay: u64,
+
+    // Discount on the close fee (bps of the fee) for closes that shrink the dominant OI side
+    pub close_fee_discount: u64,
+
+    // Absolute floor on a position's margin, in collateral units (0 = none)
+    pub min_margin: u64,
+
+    // Trading and close fees and liquidation penalties left in the collateral
+    // vault. Part of total_collateral but owed to no user, swept by CloseMarket.
+    pub fees_collected: u64,
+
+    // Funding owed per contract since creation, scaled by FUNDING_INDEX_PRECISION.
+    // Grows while the rate is positive (longs pay shorts).
+    pub cumulative_funding_index: i128,
+
+    // Most recent funding rate samples recorded by the funding crank, see funding_history
+    pub funding_history: [FundingSample; FUNDING_HISTORY_LEN],
+    pub funding_history_len: u8, // Samples recorded, up to FUNDING_HISTORY_LEN
+    pub funding_history_next: u8, // Slot the next sample is written to
+    pub _padding3: [u8; 6],
+
+    // Cap on each side's open interest, in contracts (0 = uncapped)
+    pub max_open_interest: u64,
+
+    // Price grid entries are snapped to, in normalized price units (0 = no grid)
+    pub tick_size: u64,
+
+    // Trade on the EMA when the spot price is stale, see get_price_for_trading.
+    // Moot with use_ema.
+    pub allow_ema_fallback: u8,
+
+    // Decimals of the collateral mint, see math::quote_to_collateral
+    pub collateral_decimals: u8,
+    pub _padding4: [u8; 6],
+
+    // Margin backing open positions, in collateral units, see free_collateral
+    pub locked_margin: u64,
+
+    // Max slots the oracle update may trail the current slot when opening (0 = unchecked)
+    pub max_slot_lag: u64,
+
+    // Share of a liquidated position's remaining margin paid to the liquidator, in bps
+    pub liquidation_fee: u64,
+    pub _padding5: [u8; 8],
+
+    // Losses of liquidated positions beyond their margin the insurance fund
+    // couldn't cover, in collateral units
+    pub bad_debt: u128,
+
+    // Token account holding the insurance fund, owned by the market PDA like the collateral vault
+    pub insurance_vault: Pubkey,
+    pub insurance_bump: u8, // PDA bump for insurance vault
+    pub _padding6: [u8; 7],
+
+    // Funding rate (bps per funding_interval) paid when one side holds all the
+    // open interest, see update_funding_rate (0 = no funding)
+    pub max_funding_rate: u64,
+    // When update_funding_rate last reset funding_rate. Separate from
+    // last_funding_time, which every crank advances as it accrues the index.
+    pub funding_rate_updated_at: i64,
+
+    // Widest oracle confidence interval accepted when opening, in bps of the price (0 = unchecked)
+    pub max_conf_bps: u64,
+
+    // Price everything off the Pyth EMA instead of spot, see get_price_for_trading
+    pub use_ema: u8,
+    pub _padding7: [u8; 7],
+
+    // Oldest oracle price accepted, in seconds, see oracle_max_age (0 = DEFAULT_MAX_PRICE_AGE)
+    pub max_price_age: u64,
+
+    // Guardian signatures a partially verified price update needs, FULL_VERIFICATION
+    // accepts only fully verified updates (0 = any update)
+    pub min_verification_level: u8,
+
+    // Oracle program the market is priced off, see OracleKind
+    pub oracle_kind: u8,
+
+    // Authority the market and its vaults were derived from at creation. The
+    // PDA seeds can't change, so this stays put when `authority` is transferred.
+    pub seed_authority: Pubkey,
+    // Proposed next authority, which must sign to accept (default = none)
+    pub pending_authority: Pubkey,
+    pub _padding8: [u8; 14],
+}
+
+/// Fixed so the history can't grow the market account.
+pub const FUNDING_HISTORY_LEN: usize = 24;
+
+/// Funding rate in effect at a crank.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Pod, Zeroable)]
+pub struct FundingSample {
+    pub timestamp: i64,
+    pub funding_rate: i64, // basis points, as Market::funding_rate
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketStatus {
+    Active = 0,
+    Paused = 1,
+    ReduceOnly = 2, // No opens, positions can only be closed or partially closed
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OracleKind {
+    Pyth = 0,
+    Switchboard = 1,
 }
 
 impl Market {
     // pub const SIZE: usize = 1 + 1 + 16 + (3 * 32) + (6 * 8) + (3 * 8) + 16 + 1;
     pub const SIZE: usize = core::mem::size_of::<Self>();
+    pub const FUNDING_INDEX_PRECISION: i128 = 1_000_000;
+    pub const DEFAULT_MAX_PRICE_AGE: u64 = 60;
+    pub const FULL_VERIFICATION: u8 = u8::MAX;
+    pub const DISCRIMINATOR: [u8; 8] = *b"market\0\0";
+
+    // Accounts must be at least SIZE bytes, see UserAccount::SIZE
+
+    pub fn from_account_info(account: &AccountInfo) -> Result<Ref<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
 
-    pub fn from_account_info(account: &AccountInfo) -> Result<Ref<Self>, ProgramError> {
+        let data = account.try_borrow_data()?;
+        check_discriminator(&data, &Self::DISCRIMINATOR)?;
+        Ref::filter_map(data, |data| bytemuck::try_from_bytes(&data[..Self::SIZE]).ok())
+            .map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    pub fn from_account_info_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
         if account.data_len() < Self::SIZE {
             return Err(ProgramError::InvalidAccountData);
         }
 
-        Ok(Ref::map(account.try_borrow_data()?, |data| unsafe {
-            *(data.as_ptr() as *const &Self)
-        }))
+        let data = account.try_borrow_mut_data()?;
+        check_discriminator(&data, &Self::DISCRIMINATOR)?;
+        RefMut::filter_map(data, |data| bytemuck::try_from_bytes_mut(&mut data[..Self::SIZE]).ok())
+            .map_err(|_| ProgramError::InvalidAccountData)
     }
 
-    pub fn from_account_info_mut(account: &AccountInfo) -> Result<RefMut<Self>, ProgramError> {
+    /// Tags a just-created account as a market and loads it, see write_discriminator.
+    pub fn init_from_account_info_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
         if account.data_len() < Self::SIZE {
             return Err(ProgramError::InvalidAccountData);
+        }
+
+        let mut data = account.try_borrow_mut_data()?;
+        write_discriminator(&mut data, &Self::DISCRIMINATOR)?;
+        RefMut::filter_map(data, |data| bytemuck::try_from_bytes_mut(&mut data[..Self::SIZE]).ok())
+            .map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Loads the market mutably and rejects accounts that were never initialized.
+    pub fn load_initialized_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
+        let market = Self::from_account_info_mut(account)?;
+        if !market.is_initialized() {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Ok(market)
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.is_initialized != 0
+    }
+
+    /// Max oracle price age in seconds. Markets created before the age was
+    /// configurable store zero and keep the original 60 seconds.
+    pub fn oracle_max_age(&self) -> u64 {
+        if self.max_price_age == 0 { Self::DEFAULT_MAX_PRICE_AGE } else { self.max_price_age }
+    }
+
+    /// Oracle settings every price fetch for this market is checked against.
+    pub fn oracle_config(&self) -> OracleConfig {
+        let min_verification_level = match self.min_verification_level {
+            Self::FULL_VERIFICATION => VerificationLevel::Full,
+            num_signatures => VerificationLevel::Partial { num_signatures },
+        };
+        let oracle_kind = if self.oracle_kind == OracleKind::Switchboard as u8 { OracleKind::Switchboard } else { OracleKind::Pyth };
+        OracleConfig {
+            oracle_kind,
+            feed_id: self.feed_id,
+            max_age_seconds: self.oracle_max_age(),
+            max_publish_gap: self.max_publish_gap,
+            allow_ema_fallback: self.allow_ema_fallback != 0,
+            use_ema: self.use_ema != 0,
+            min_verification_level,
+        }
+    }
+
+    pub fn allows_open(&self) -> bool {
+        self.status == MarketStatus::Active as u8
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.status == MarketStatus::Paused as u8
+    }
+
+    /// Advances the funding index to `now` at `price`: `funding_rate` bps of the
+    /// price per contract per `funding_interval`. The first call only starts the clock.
+    pub fn accrue_funding(&mut self, price: u64, now: i64) -> Result<(), ProgramError> {
+        if self.last_funding_time == 0 || self.funding_interval <= 0 {
+            self.last_funding_time = now;
+            return Ok(());
+        }
+
+        let elapsed = now.saturating_sub(self.last_funding_time);
+        if elapsed <= 0 {
+            return Ok(());
+        }
+
+        let delta = (price as i128)
+            .checked_mul(self.funding_rate as i128)
+            .and_then(|v| v.checked_mul(elapsed as i128))
+            .and_then(|v| v.checked_mul(Self::FUNDING_INDEX_PRECISION))
+            .ok_or(ProgramError::ArithmeticOverflow)?
+            / (self.funding_interval as i128 * 10_000);
+
+        self.cumulative_funding_index = math::checked_add(self.cumulative_funding_index, delta)?;
+        self.last_funding_time = now;
+        Ok(())
+    }
+
+    /// Appends a funding rate sample, overwriting the oldest once the buffer is
+    /// full. Repeated cranks at the same timestamp record one sample.
+    pub fn record_funding_sample(&mut self, timestamp: i64, funding_rate: i64) {
+        if self.funding_history().last().is_some_and(|sample| sample.timestamp == timestamp) {
+            return;
+        }
+
+        let next = self.funding_history_next as usize % FUNDING_HISTORY_LEN;
+        self.funding_history[next] = FundingSample { timestamp, funding_rate };
+        self.funding_history_next = ((next + 1) % FUNDING_HISTORY_LEN) as u8;
+        if (self.funding_history_len as usize) < FUNDING_HISTORY_LEN {
+            self.funding_history_len += 1;
+        }
+    }
+
+    /// Recorded funding samples, oldest first.
+    pub fn funding_history(&self) -> impl DoubleEndedIterator<Item = FundingSample> + '_ {
+        let len = (self.funding_history_len as usize).min(FUNDING_HISTORY_LEN);
+        let start = (self.funding_history_next as usize + FUNDING_HISTORY_LEN - len) % FUNDING_HISTORY_LEN;
+        (0..len).map(move |i| self.funding_history[(start + i) % FUNDING_HISTORY_LEN])
+    }
+
+    /// Snaps a fill price onto the tick grid against the trader: longs enter at
+    /// the tick above, shorts at the tick below. Prices already on the grid
+    /// are unchanged.
+    pub fn snap_to_tick(&self, price: u64, size: i128) -> Result<u64, ProgramError> {
+        if self.tick_size == 0 {
+            return Ok(price);
+        }
+
+        let rounding = if size > 0 { RoundingMode::Up } else { RoundingMode::Down };
+        let ticks = math::mul_div_u64(price, 1, self.tick_size, rounding)?;
+        let snapped = ticks.checked_mul(self.tick_size).ok_or(ProgramError::ArithmeticOverflow)?;
+        if snapped == 0 {
+            // A short below one tick has no price to enter at
+            return Err(PerpError::OracleInvalidPrice.into());
+        }
+        Ok(snapped)
+    }
+
+    /// How much of a `size` order fits under the open interest cap on its side.
+    pub fn open_interest_capacity(&self, size: i128) -> u64 {
+        if self.max_open_interest == 0 {
+            return u64::MAX;
+        }
+
+        let side = if size > 0 { self.open_interest_long } else { self.open_interest_short };
+        self.max_open_interest.saturating_sub(side)
+    }
+
+    /// Long/short open interest imbalance in bps of total open interest, positive
+    /// when longs dominate. A market with no open interest has no imbalance,
+    /// so funding derived from it is zero rather than a division by zero.
+    pub fn open_interest_imbalance_bps(&self) -> i64 {
+        let total = self.open_interest_long as i128 + self.open_interest_short as i128;
+        if total == 0 {
+            return 0;
+        }
+
+        let skew = self.open_interest_long as i128 - self.open_interest_short as i128;
+        // |skew| <= total, so this is within ±10_000
+        (skew * 10_000 / total) as i64
+    }
+
+    /// Records collateral transferred into the vault.
+    pub fn credit_collateral(&mut self, amount: u64) -> Result<(), ProgramError> {
+        self.total_collateral = self.total_collateral
+            .checked_add(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Records collateral transferred out of the vault.
+    pub fn debit_collateral(&mut self, amount: u64) {
+        self.total_collateral = self.total_collateral.saturating_sub(amount);
+    }
+
+    /// Records fee income left in the vault, see fees_collected.
+    pub fn collect_fees(&mut self, amount: u64) -> Result<(), ProgramError> {
+        self.fees_collected = self.fees_collected
+            .checked_add(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Records margin committed to a position on open.
+    pub fn lock_margin(&mut self, amount: u64) -> Result<(), ProgramError> {
+        self.locked_margin = self.locked_margin
+            .checked_add(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Releases a position's margin when it closes or is liquidated.
+    pub fn release_margin(&mut self, amount: u64) {
+        self.locked_margin = self.locked_margin.saturating_sub(amount);
+    }
+
+    /// Keeps locked margin in step with a position's margin changed by funding,
+    /// the delta settle_funding_index returns.
+    pub fn apply_margin_delta(&mut self, delta: i64) -> Result<(), ProgramError> {
+        if delta < 0 {
+            self.release_margin(delta.unsigned_abs());
+            return Ok(());
+        }
+        self.lock_margin(delta as u64)
+    }
+
+    /// Collateral in the vault not backing an open position, i.e. what the
+    /// market can pay out without touching anyone's margin.
+    pub fn free_collateral(&self) -> u64 {
+        self.total_collateral.saturating_sub(self.locked_margin)
+    }
+
+    /// Writes off up to `amount` of bad debt socialized by ADL, returning what was written off.
+    pub fn clear_bad_debt(&mut self, amount: u64) -> u64 {
+        let cleared = self.bad_debt.min(amount as u128);
+        self.bad_debt -= cleared;
+        cleared as u64
+    }
+
+    /// Records the shortfall of a closed position whose losses exceeded its
+    /// margin and the insurance fund, see cover_deficit.
+    pub fn record_bad_debt(&mut self, shortfall: u64) -> Result<(), ProgramError> {
+        self.bad_debt = self.bad_debt
+            .checked_add(shortfall as u128)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::{FundingSample, Market, MarketStatus, OracleKind, FUNDING_HISTORY_LEN};
+    use crate::states::{with_account_info, Position, UserAccount};
+
+    fn tagged(len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        data[..8].copy_from_slice(&Market::DISCRIMINATOR);
+        data
+    }
+
+    #[test]
+    fn test_exactly_sized_and_oversized_market_load() {
+        for len in [Market::SIZE, Market::SIZE + 64] {
+            with_account_info(&tagged(len), |account| {
+                assert!(Market::from_account_info(account).is_ok());
+                assert!(Market::from_account_info_mut(account).is_ok());
+            });
+        }
+    }
+
+    #[test]
+    fn test_untagged_or_mistagged_account_rejected() {
+        // Never created, or created as a position or user account
+        for tag in [[0u8; 8], Position::DISCRIMINATOR, UserAccount::DISCRIMINATOR] {
+            let mut data = vec![0u8; Market::SIZE];
+            data[..8].copy_from_slice(&tag);
+            with_account_info(&data, |account| {
+                assert!(matches!(Market::from_account_info(account), Err(ProgramError::InvalidAccountData)));
+                assert!(matches!(Market::from_account_info_mut(account), Err(ProgramError::InvalidAccountData)));
+            });
+        }
+    }
+
+    #[test]
+    fn test_init_tags_only_a_zeroed_account() {
+        with_account_info(&vec![0u8; Market::SIZE], |account| {
+            drop(Market::init_from_account_info_mut(account).unwrap());
+            assert!(Market::from_account_info(account).is_ok());
+            assert!(matches!(
+                Market::init_from_account_info_mut(account),
+                Err(ProgramError::AccountAlreadyInitialized)
+            ));
+        });
+
+        let mut position = vec![0u8; Market::SIZE];
+        position[..8].copy_from_slice(&Position::DISCRIMINATOR);
+        with_account_info(&position, |account| {
+            assert!(matches!(
+                Market::init_from_account_info_mut(account),
+                Err(ProgramError::AccountAlreadyInitialized)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_populated_market_round_trips_through_bytes() {
+        let mut market = Market {
+            discriminator: Market::DISCRIMINATOR,
+            is_initialized: 1,
+            market_id: u64::MAX,
+            market_symbol: *b"SOL-PERP\0\0\0\0\0\0\0\0",
+            oracle: [1u8; 32],
+            collateral_mint: [2u8; 32],
+            collateral_vault: [3u8; 32],
+            base_oracle: [4u8; 32],
+            initial_margin: 1_000,
+            maintenance_margin: 500,
+            max_leverage: 20,
+            fee_rate: 10,
+            funding_rate: -7,
+            last_funding_time: 1_700_000_000,
+            funding_interval: 28_800,
+            open_interest_long: 300,
+            open_interest_short: 100,
+            total_collateral: 5_000,
+            unrealized_pnl: i128::MIN,
+            authority: [5u8; 32],
+            bump: 254,
+            collateral_bump: 253,
+            status: MarketStatus::ReduceOnly as u8,
+            max_publish_gap: 30,
+            feed_id: [6u8; 32],
+            withdraw_delay: 3_600,
+            close_fee_discount: 2_500,
+            min_margin: 10,
+            fees_collected: 42,
+            cumulative_funding_index: i128::MAX,
+            max_open_interest: 1_000_000,
+            tick_size: 100,
+            allow_ema_fallback: 1,
+            collateral_decimals: 6,
+            locked_margin: 4_000,
+            max_slot_lag: 25,
+            liquidation_fee: 50,
+            bad_debt: u128::MAX,
+            insurance_vault: [7u8; 32],
+            insurance_bump: 252,
+            max_funding_rate: 100,
+            funding_rate_updated_at: 1_700_000_100,
+            max_conf_bps: 200,
+            use_ema: 1,
+            max_price_age: 120,
+            min_verification_level: Market::FULL_VERIFICATION,
+            oracle_kind: OracleKind::Switchboard as u8,
+            seed_authority: [8u8; 32],
+            pending_authority: [9u8; 32],
+            ..Market::default()
         };
+        for i in 0..FUNDING_HISTORY_LEN as i64 + 3 {
+            market.record_funding_sample(1_000 + i, -i);
+        }
 
-        Ok(RefMut::map(account.try_borrow_mut_data()?, |data| unsafe {
-            &mut *(data.as_mut_ptr() as *mut Self)
-        }))
+        let bytes = bytemuck::bytes_of(&market).to_vec();
+        assert_eq!(bytes.len(), Market::SIZE);
+        with_account_info(&bytes, |account| {
+            let loaded = Market::from_account_info(account).unwrap();
+            assert_eq!(bytemuck::bytes_of(&*loaded), &bytes[..]);
+            assert_eq!(loaded.market_id, u64::MAX);
+            assert_eq!(loaded.cumulative_funding_index, i128::MAX);
+            assert_eq!(loaded.bad_debt, u128::MAX);
+            assert_eq!(loaded.pending_authority, [9u8; 32]);
+            assert!(loaded.funding_history().eq(market.funding_history()));
+        });
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_misaligned_market_rejected() {
+        // One byte in, so the i128 fields can't be referenced in place
+        let backing = vec![0u128; Market::SIZE / 16 + 1];
+        let data: &[u8] = bytemuck::cast_slice(&backing);
+        assert!(bytemuck::try_from_bytes::<Market>(&data[..Market::SIZE]).is_ok());
+        assert!(bytemuck::try_from_bytes::<Market>(&data[1..Market::SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_load_initialized_mut_rejects_zeroed_market() {
+        let zeroed = tagged(Market::SIZE);
+        with_account_info(&zeroed, |account| {
+            assert!(matches!(
+                Market::load_initialized_mut(account),
+                Err(ProgramError::UninitializedAccount)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_load_initialized_mut_accepts_initialized_market() {
+        let mut data = tagged(Market::SIZE);
+        data[core::mem::offset_of!(Market, is_initialized)] = 1;
+        with_account_info(&data, |account| {
+            assert!(Market::load_initialized_mut(account).unwrap().is_initialized());
+        });
+    }
+
+    #[test]
+    fn test_paused_market_blocks_opens() {
+        let mut market = Market {
+            is_initialized: 1,
+            ..Market::default()
+        };
+        assert!(market.allows_open());
+
+        market.status = MarketStatus::Paused as u8;
+        assert!(!market.allows_open());
+
+        market.status = MarketStatus::ReduceOnly as u8;
+        assert!(!market.allows_open());
+        assert!(!market.is_paused());
+
+        market.status = MarketStatus::Active as u8;
+        assert!(market.allows_open());
+    }
+
+    #[test]
+    fn test_oracle_max_age_defaults_when_unset() {
+        assert_eq!(Market::default().oracle_max_age(), Market::DEFAULT_MAX_PRICE_AGE);
+        assert_eq!(Market { max_price_age: 120, ..Market::default() }.oracle_max_age(), 120);
+    }
+
+    #[test]
+    fn test_accrue_funding_index() {
+        let mut market = Market { funding_rate: 10, funding_interval: 3600, ..Market::default() };
+
+        // First accrual only starts the clock
+        market.accrue_funding(100, 1_000).unwrap();
+        assert_eq!(market.cumulative_funding_index, 0);
+
+        // 10 bps of 100 over one interval is 0.1 per contract
+        market.accrue_funding(100, 4_600).unwrap();
+        assert_eq!(market.cumulative_funding_index, Market::FUNDING_INDEX_PRECISION / 10);
+        assert_eq!(market.last_funding_time, 4_600);
+    }
+
+    #[test]
+    fn test_funding_history_wraps_and_keeps_latest() {
+        let mut market = Market::default();
+        market.record_funding_sample(100, 5);
+        market.record_funding_sample(100, 6); // Same crank timestamp, ignored
+        assert_eq!(market.funding_history().collect::<Vec<_>>(), vec![FundingSample { timestamp: 100, funding_rate: 5 }]);
+
+        let total = FUNDING_HISTORY_LEN as i64 + 5;
+        for i in 1..total {
+            market.record_funding_sample(100 + i, i);
+        }
+
+        let history: Vec<_> = market.funding_history().collect();
+        assert_eq!(history.len(), FUNDING_HISTORY_LEN);
+        // The oldest 6 samples were overwritten, the rest are in order
+        assert_eq!(history[0], FundingSample { timestamp: 100 + total - FUNDING_HISTORY_LEN as i64, funding_rate: total - FUNDING_HISTORY_LEN as i64 });
+        assert_eq!(history.last(), Some(&FundingSample { timestamp: 100 + total - 1, funding_rate: total - 1 }));
+        assert!(history.windows(2).all(|pair| pair[0].timestamp + 1 == pair[1].timestamp));
+    }
+
+    #[test]
+    fn test_free_collateral_tracks_opens_and_closes() {
+        let mut market = Market::default();
+
+        // Two opens: 1_000 and 500 margin, each with a 10 fee that stays in the vault
+        market.credit_collateral(1_010).unwrap();
+        market.lock_margin(1_000).unwrap();
+        market.credit_collateral(510).unwrap();
+        market.lock_margin(500).unwrap();
+        assert_eq!(market.locked_margin, 1_500);
+        assert_eq!(market.free_collateral(), 20);
+
+        // A deposit not yet committed to a position is free
+        market.credit_collateral(300).unwrap();
+        assert_eq!(market.free_collateral(), 320);
+
+        // Closing the first at a 200 profit pays out 1_200, the profit comes out of free collateral
+        market.debit_collateral(1_200);
+        market.release_margin(1_000);
+        assert_eq!(market.locked_margin, 500);
+        assert_eq!(market.free_collateral(), 120);
+
+        // Closing the second at a total loss pays nothing and frees its margin
+        market.release_margin(500);
+        assert_eq!(market.locked_margin, 0);
+        assert_eq!(market.free_collateral(), market.total_collateral);
+    }
+
+    #[test]
+    fn test_free_collateral_never_underflows() {
+        let mut market = Market { total_collateral: 100, locked_margin: 100, ..Market::default() };
+        // Paying out more than the free balance leaves the vault short of locked margin
+        market.debit_collateral(50);
+        assert_eq!(market.free_collateral(), 0);
+
+        market.release_margin(u64::MAX);
+        assert_eq!(market.locked_margin, 0);
+
+        market.locked_margin = u64::MAX;
+        assert_eq!(market.lock_margin(1), Err(ProgramError::ArithmeticOverflow));
+    }
+
+    #[test]
+    fn test_empty_market_has_zero_imbalance_funding() {
+        let mut market = Market { funding_interval: 3600, last_funding_time: 1, ..Market::default() };
+        assert_eq!(market.open_interest_imbalance_bps(), 0);
+
+        // Funding driven off the imbalance accrues nothing on an empty market
+        market.funding_rate = market.open_interest_imbalance_bps();
+        market.accrue_funding(150_00000000, 1 + 3600).unwrap();
+        assert_eq!(market.funding_rate, 0);
+        assert_eq!(market.cumulative_funding_index, 0);
+    }
+
+    #[test]
+    fn test_open_interest_imbalance_bps() {
+        let market = Market { open_interest_long: 300, open_interest_short: 100, ..Market::default() };
+        assert_eq!(market.open_interest_imbalance_bps(), 5_000);
+
+        let market = Market { open_interest_long: 0, open_interest_short: 100, ..Market::default() };
+        assert_eq!(market.open_interest_imbalance_bps(), -10_000);
+
+        let market = Market { open_interest_long: u64::MAX, open_interest_short: u64::MAX, ..Market::default() };
+        assert_eq!(market.open_interest_imbalance_bps(), 0);
+    }
+}
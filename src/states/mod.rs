@@ -5,4 +5,107 @@ pub mod user;
 pub use user::*;
 
 pub mod position;
-pub use position::*;
\ No newline at end of file
+pub use position::*;
+
+pub mod liquidation_queue;
+pub use liquidation_queue::*;
+
+pub mod global_config;
+pub use global_config::*;
+
+pub mod delegation;
+pub use delegation::*;
+
+pub mod oracle_snapshot;
+pub use oracle_snapshot::*;
+
+use pinocchio::{program_error::ProgramError, ProgramResult};
+
+/// Market, UserAccount and Position lead with an 8-byte type tag, so an
+/// account of one type can't be loaded as another of the same size.
+/// Callers check the length first.
+pub(crate) fn check_discriminator(data: &[u8], expected: &[u8; 8]) -> ProgramResult {
+    if data[..8] != expected[..] {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+/// Writes the type tag into a just-created account. Only a zeroed account can
+/// be tagged, so a live account can't be re-created as another type.
+pub(crate) fn write_discriminator(data: &mut [u8], discriminator: &[u8; 8]) -> ProgramResult {
+    if data[..8] != [0u8; 8] {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    data[..8].copy_from_slice(discriminator);
+    Ok(())
+}
+/// An account to lay out for `with_account_infos`.
+#[cfg(test)]
+pub(crate) struct TestAccount<'a> {
+    pub key: pinocchio::pubkey::Pubkey,
+    pub owner: pinocchio::pubkey::Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+    pub lamports: u64,
+    pub data: &'a [u8],
+}
+
+/// Lays out accounts the way the runtime serializes them into the program
+/// input, so loaders and early handler checks can be exercised off-chain.
+/// Only the first account's data is guaranteed 16-byte aligned, as i128 fields need on the host.
+#[cfg(test)]
+pub(crate) fn with_account_infos<R>(
+    accounts: &[TestAccount],
+    f: impl FnOnce(&[pinocchio::account_info::AccountInfo]) -> R
+) -> R {
+    use core::mem::MaybeUninit;
+    use pinocchio::{account_info::AccountInfo, entrypoint::deserialize};
+
+    // Per account: [dup, signer, writable, executable, resize_delta][key][owner]
+    // [lamports][data_len][data][realloc padding][rent_epoch], 8-byte aligned
+    const HEADER: usize = 88;
+    const REALLOC_PADDING: usize = 10_240;
+
+    let len = 8 + accounts.iter()
+        .map(|account| HEADER + account.data.len() + REALLOC_PADDING + 8 + 8)
+        .sum::<usize>() + 8 + 32;
+
+    // u128 backing keeps the first account's data 16-byte aligned
+    let mut input = vec![0u128; len / 16 + 2];
+    let bytes = input.as_mut_ptr() as *mut u8;
+
+    let mut infos = [const { MaybeUninit::<AccountInfo>::uninit() }; 8];
+    assert!(accounts.len() <= infos.len());
+
+    unsafe {
+        *(bytes as *mut u64) = accounts.len() as u64;
+        let mut offset = 8;
+        for account in accounts {
+            let raw = bytes.add(offset);
+            *raw = u8::MAX; // not a duplicate
+            *raw.add(1) = account.is_signer as u8;
+            *raw.add(2) = account.is_writable as u8;
+            core::ptr::copy_nonoverlapping(account.key.as_ptr(), raw.add(8), 32);
+            core::ptr::copy_nonoverlapping(account.owner.as_ptr(), raw.add(40), 32);
+            *(raw.add(72) as *mut u64) = account.lamports;
+            *(raw.add(80) as *mut u64) = account.data.len() as u64;
+            core::ptr::copy_nonoverlapping(account.data.as_ptr(), raw.add(HEADER), account.data.len());
+
+            offset += HEADER + account.data.len() + REALLOC_PADDING;
+            offset = (offset + 7) & !7;
+            offset += 8;
+        }
+
+        let (_, count, _) = deserialize(bytes, &mut infos);
+        let infos = core::slice::from_raw_parts(infos.as_ptr() as *const AccountInfo, count);
+        f(infos)
+    }
+}
+
+/// Single program-owned, writable account, see `with_account_infos`.
+#[cfg(test)]
+pub(crate) fn with_account_info<R>(data: &[u8], f: impl FnOnce(&pinocchio::account_info::AccountInfo) -> R) -> R {
+    let account = TestAccount { key: [0u8; 32], owner: crate::ID, is_signer: false, is_writable: true, lamports: 0, data };
+    with_account_infos(&[account], |accounts| f(&accounts[0]))
+}
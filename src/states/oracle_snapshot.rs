@@ -0,0 +1,45 @@
+use pinocchio::{account_info::{AccountInfo, Ref, RefMut}, program_error::ProgramError, pubkey::Pubkey};
+
+/// Oracle state a close or liquidation settled at, kept so a disputed
+/// settlement price can be checked against the raw Pyth update afterwards.
+/// Written once by the handler into an account the caller allocates.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OracleSnapshot {
+    pub position: Pubkey, // Position that was closed or liquidated
+    pub recorded_at: i64, // Clock timestamp of the settlement
+    pub price: i64, // Raw oracle price, scaled by 10^exponent
+    pub conf: u64, // Raw confidence interval, same scale as price
+    pub exponent: i32, // Oracle price exponent
+    pub publish_time: i64, // Oracle publish time of the price
+    pub slot: u64, // Slot the price update was posted at
+}
+
+impl OracleSnapshot {
+    pub const SIZE: usize = core::mem::size_of::<Self>();
+
+    pub fn from_account_info(account: &AccountInfo) -> Result<Ref<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(Ref::map(account.try_borrow_data()?, |data| unsafe {
+            &*(data.as_ptr() as *const Self)
+        }))
+    }
+
+    pub fn from_account_info_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        Ok(RefMut::map(account.try_borrow_mut_data()?, |data| unsafe {
+            &mut *(data.as_mut_ptr() as *mut Self)
+        }))
+    }
+
+    /// A snapshot is never overwritten once it names a position.
+    pub fn is_recorded(&self) -> bool {
+        self.position != Pubkey::default()
+    }
+}
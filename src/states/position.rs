@@ -1,6 +1,13 @@
-use pinocchio::{pubkey::Pubkey, account_info::{AccountInfo, Ref, RefMut}, program_error::ProgramError,*};
+use bytemuck::{Pod, Zeroable};
+use pinocchio::{pubkey::Pubkey, account_info::{AccountInfo, Ref, RefMut}, program_error::ProgramError};
 
+use crate::{math::{self, RoundingMode}, states::{check_discriminator, write_discriminator, Market}};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
 pub struct Position {
+    pub discriminator: [u8; 8], // Position::DISCRIMINATOR once created
+
     /*The wallet public key (on Solana) that owns this position.
     Every position is tied to a specific user.*/
     pub user: Pubkey,
@@ -8,6 +15,7 @@ pub struct Position {
     /*The market (trading pair) where this position belongs.
     Could be the address of a Market account that stores global data for SOL/USDC perp*/
     pub market: Pubkey,
+    pub _padding0: [u8; 8],
 
     /*The position size, i.e. how many contracts this user holds.
     Sign encodes direction:
@@ -44,7 +52,27 @@ pub struct Position {
         True = open position
         False = position closed
      */
-    pub is_active: bool, 
+    pub is_active: u8,
+
+    /*Whether received funding is added straight to margin (set at open).
+    Otherwise it accrues in funding_payment and is paid out on close.*/
+    pub auto_compound_funding: u8,
+    pub _padding1: [u8; 6],
+
+    /*Publish time of the oracle price used for the latest fill.
+    Ties entry_price to a verifiable oracle update.*/
+    pub entry_oracle_time: i64,
+    pub _padding2: [u8; 8],
+
+    /*Market cumulative_funding_index when funding was last settled.
+    Funding owed is the index movement since then times size.*/
+    pub last_funding_index: i128,
+
+    /*Publish time of the oracle update at which the mark scan first saw this
+    position below maintenance margin, 0 while healthy. Liquidation waits for
+    a later oracle update so a single bad tick can't liquidate.*/
+    pub underwater_since: i64,
+    pub _padding3: [u8; 8],
 }
 
 #[repr(u8)]
@@ -56,25 +84,42 @@ pub enum PositionType {
 
 impl Position {
     pub const SIZE: usize = core::mem::size_of::<Self>();
+    pub const DISCRIMINATOR: [u8; 8] = *b"position";
+
+    // Accounts must be at least SIZE bytes, see UserAccount::SIZE
+
+    pub fn from_account_info(account: &AccountInfo) -> Result<Ref<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let data = account.try_borrow_data()?;
+        check_discriminator(&data, &Self::DISCRIMINATOR)?;
+        Ref::filter_map(data, |data| bytemuck::try_from_bytes(&data[..Self::SIZE]).ok())
+            .map_err(|_| ProgramError::InvalidAccountData)
+    }
 
-    pub fn from_account_info(account: &AccountInfo) -> Result<Ref<Self>, ProgramError> {
+    pub fn from_account_info_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
         if account.data_len() < Self::SIZE {
             return Err(ProgramError::InvalidAccountData);
         }
 
-        Ok(Ref::map(account.try_borrow_data()?, |data| unsafe {
-            *(data.as_ptr() as *const &Self)
-        }))
+        let data = account.try_borrow_mut_data()?;
+        check_discriminator(&data, &Self::DISCRIMINATOR)?;
+        RefMut::filter_map(data, |data| bytemuck::try_from_bytes_mut(&mut data[..Self::SIZE]).ok())
+            .map_err(|_| ProgramError::InvalidAccountData)
     }
 
-    pub fn from_account_info_mut(account: &AccountInfo) -> Result<RefMut<Self>, ProgramError> {
+    /// Tags a just-created account as a position and loads it, see write_discriminator.
+    pub fn init_from_account_info_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
         if account.data_len() < Self::SIZE {
             return Err(ProgramError::InvalidAccountData);
         }
 
-        Ok(RefMut::map(account.try_borrow_mut_data()?, |data| unsafe {
-            &mut *(data.as_mut_ptr() as *mut Self)
-        }))
+        let mut data = account.try_borrow_mut_data()?;
+        write_discriminator(&mut data, &Self::DISCRIMINATOR)?;
+        RefMut::filter_map(data, |data| bytemuck::try_from_bytes_mut(&mut data[..Self::SIZE]).ok())
+            .map_err(|_| ProgramError::InvalidAccountData)
     }
 
     pub fn position_type(&self) -> PositionType {
@@ -96,6 +141,444 @@ impl Position {
     }
 
     pub fn is_open(&self) -> bool {
-        self.is_active
+        self.is_active != 0
+    }
+
+    /// Applies a funding payment (positive = received). Paid funding always
+    /// comes out of margin; received funding is compounded into margin or
+    /// accrued in `funding_payment`. Returns the change applied to margin.
+    pub fn apply_funding(&mut self, payment: i64) -> Result<i64, ProgramError> {
+        if payment < 0 {
+            let paid = payment.unsigned_abs().min(self.margin);
+            self.margin -= paid;
+            return Ok(-(paid as i64));
+        }
+
+        if self.auto_compound_funding != 0 {
+            self.margin = self.margin
+                .checked_add(payment as u64)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            Ok(payment)
+        } else {
+            self.funding_payment = self.funding_payment
+                .checked_add(payment)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            Ok(0)
+        }
+    }
+
+    /// Funding received (negative when paid) as the market index moved from
+    /// `last_funding_index` to `current_index`, in collateral units of a mint
+    /// with `decimals`. What the trader owes rounds up.
+    pub fn pending_funding(&self, current_index: i128, decimals: u8) -> Result<i64, ProgramError> {
+        let index_delta = math::checked_sub(current_index, self.last_funding_index)?;
+        let owed = math::mul_div(index_delta, self.size, Market::FUNDING_INDEX_PRECISION, RoundingMode::Up)?;
+        let owed = math::quote_to_collateral(owed, decimals, RoundingMode::Up)?;
+
+        i64::try_from(-owed).map_err(|_| ProgramError::ArithmeticOverflow)
+    }
+
+    /// Funding owed to the position (negative when it owes) that margin doesn't
+    /// reflect yet: the accrued `funding_payment` plus what is pending up to `current_index`.
+    pub fn unsettled_funding(&self, current_index: i128, decimals: u8) -> Result<i128, ProgramError> {
+        math::checked_add(self.funding_payment as i128, self.pending_funding(current_index, decimals)? as i128)
+    }
+
+    /// Settles funding up to `current_index`, returning the change in margin, see apply_funding.
+    pub fn settle_funding_index(&mut self, current_index: i128, decimals: u8) -> Result<i64, ProgramError> {
+        let payment = self.pending_funding(current_index, decimals)?;
+        let margin_delta = self.apply_funding(payment)?;
+        self.last_funding_index = current_index;
+        Ok(margin_delta)
+    }
+
+    /// Settles the share of accrued `funding_payment` belonging to `closed_size`
+    /// contracts, leaving the rest with the still-open size. Returns the amount
+    /// settled, rounded down so the trader is never credited a remainder.
+    pub fn settle_funding_share(&mut self, closed_size: u128) -> Result<i64, ProgramError> {
+        let open_size = self.size.unsigned_abs();
+        if open_size == 0 {
+            return Ok(0);
+        }
+
+        let share = math::mul_div(
+            self.funding_payment as i128,
+            closed_size.min(open_size) as i128,
+            open_size as i128,
+            RoundingMode::Down
+        )?;
+        let share = share as i64; // |share| <= |funding_payment|
+        self.funding_payment -= share;
+        Ok(share)
+    }
+
+    /// PnL if the position were closed at `current_price`, in collateral units
+    /// of a mint with `decimals`. Rounds down, so losses round away from zero.
+    pub fn unrealized_pnl_at(&self, current_price: u64, decimals: u8) -> Result<i128, ProgramError> {
+        let price_delta = (current_price as i128) - (self.entry_price as i128);
+        let pnl = math::checked_mul(self.size, price_delta)?;
+        math::quote_to_collateral(pnl, decimals, RoundingMode::Down)
+    }
+
+    /// Margin plus unrealized PnL at `current_price`, negative once the loss exceeds margin.
+    pub fn equity_at(&self, current_price: u64, decimals: u8) -> Result<i128, ProgramError> {
+        (self.margin as i128)
+            .checked_add(self.unrealized_pnl_at(current_price, decimals)?)
+            .ok_or(ProgramError::ArithmeticOverflow)
+    }
+
+    /// Equity at `current_price` with the funding unsettled up to `funding_index`
+    /// counted, what closing or liquidating the position would actually settle.
+    pub fn equity_with_funding(&self, current_price: u64, funding_index: i128, decimals: u8) -> Result<i128, ProgramError> {
+        math::checked_add(self.equity_at(current_price, decimals)?, self.unsettled_funding(funding_index, decimals)?)
+    }
+
+    /// Margin the position must keep at `price` to avoid liquidation, in
+    /// collateral units of a mint with `decimals`, rounded up.
+    pub fn maintenance_margin_required(&self, price: u64, maintenance_margin_bps: u64, decimals: u8) -> Result<u64, ProgramError> {
+        let notional = math::checked_mul(self.size, price as i128)?.unsigned_abs();
+        let notional = i128::try_from(notional).map_err(|_| ProgramError::ArithmeticOverflow)?;
+
+        let required = math::mul_div(notional, maintenance_margin_bps as i128, 10_000, RoundingMode::Up)?;
+        let required = math::quote_to_collateral(required, decimals, RoundingMode::Up)?;
+
+        u64::try_from(required).map_err(|_| ProgramError::ArithmeticOverflow)
+    }
+
+    /// Whether equity, funding up to the market's `funding_index` included, has
+    /// fallen below the maintenance requirement. Equity exactly at the
+    /// requirement is still healthy. Both the mark scan and liquidation decide
+    /// through this, so they can't disagree.
+    pub fn is_liquidatable(&self, current_price: u64, funding_index: i128, maintenance_margin_bps: u64, decimals: u8) -> Result<bool, ProgramError> {
+        let required = self.maintenance_margin_required(current_price, maintenance_margin_bps, decimals)?;
+        Ok(self.equity_with_funding(current_price, funding_index, decimals)? < required as i128)
+    }
+
+    /// Price at which equity meets the maintenance requirement, ignoring
+    /// unsettled funding. Solves `margin + size * (price - entry) =
+    /// |size| * price * maintenance_margin_bps / 10_000` for price, rounded
+    /// towards the entry so the reported price is never past the real one.
+    /// 0 when no positive price liquidates the position.
+    pub fn liquidation_price(&self, maintenance_margin_bps: u64, decimals: u8) -> Result<u64, ProgramError> {
+        if self.size == 0 {
+            return Ok(0);
+        }
+
+        let margin = math::collateral_to_quote(self.margin as i128, decimals, RoundingMode::Down)?;
+        let numerator = math::checked_mul(
+            math::checked_sub(math::checked_mul(self.size, self.entry_price as i128)?, margin)?,
+            10_000
+        )?;
+        let denominator = math::checked_sub(
+            math::checked_mul(self.size, 10_000)?,
+            math::checked_mul(self.size.abs(), maintenance_margin_bps as i128)?
+        )?;
+        if denominator == 0 {
+            return Ok(0);
+        }
+
+        // Longs are liquidated below the price, shorts above it
+        let rounding = if self.size > 0 { RoundingMode::Up } else { RoundingMode::Down };
+        let price = math::mul_div(numerator, 1, denominator, rounding)?;
+        Ok(price.clamp(0, u64::MAX as i128) as u64)
+    }
+
+    /// Price at which equity is exactly zero, i.e. the liquidation price with
+    /// no maintenance requirement. 0 when no positive price bankrupts the position.
+    pub fn bankruptcy_price(&self, decimals: u8) -> Result<u64, ProgramError> {
+        self.liquidation_price(0, decimals)
+    }
+
+    /// Records the mark scan's verdict at the oracle update `publish_time`. The
+    /// first underwater sighting is kept until the position recovers.
+    pub fn track_underwater(&mut self, underwater: bool, publish_time: i64) {
+        if !underwater {
+            self.underwater_since = 0;
+        } else if self.underwater_since == 0 {
+            self.underwater_since = publish_time;
+        }
+    }
+
+    /// Whether the position has stayed underwater across at least two oracle
+    /// updates: the one the mark scan saw and a later one at `publish_time`.
+    pub fn liquidation_grace_elapsed(&self, publish_time: i64) -> bool {
+        self.underwater_since != 0 && publish_time > self.underwater_since
+    }
+
+    /// Equity over the maintenance requirement in basis points, funding counted
+    /// as in is_liquidatable; below 10_000 the position is liquidatable. Lower is less healthy.
+    pub fn health_bps(&self, price: u64, funding_index: i128, maintenance_margin_bps: u64, decimals: u8) -> Result<i64, ProgramError> {
+        let equity = self.equity_with_funding(price, funding_index, decimals)?;
+        let required = self.maintenance_margin_required(price, maintenance_margin_bps, decimals)?;
+        if required == 0 {
+            return Ok(if equity < 0 { i64::MIN } else { i64::MAX });
+        }
+
+        let health = equity.saturating_mul(10_000) / required as i128;
+        Ok(health.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
+    }
+
+    /// Margin that can be taken out while staying at or above the maintenance
+    /// requirement. Unrealized profit is not withdrawable, so this never
+    /// exceeds the posted margin.
+    pub fn withdrawable_margin(&self, current_price: u64, maintenance_margin_bps: u64, decimals: u8) -> Result<u64, ProgramError> {
+        let equity = self.equity_at(current_price, decimals)?;
+        let required = self.maintenance_margin_required(current_price, maintenance_margin_bps, decimals)?;
+
+        let surplus = equity.saturating_sub(required as i128);
+        Ok(surplus.clamp(0, self.margin as i128) as u64)
+    }
+
+    /// Unrealized PnL at `current_price` as a share of margin (ROE), in basis points.
+    /// A position without margin reports 0 rather than dividing by zero.
+    pub fn roe_bps(&self, current_price: u64, decimals: u8) -> Result<i64, ProgramError> {
+        if self.margin == 0 {
+            return Ok(0);
+        }
+
+        let unrealized_pnl = self.unrealized_pnl_at(current_price, decimals)?;
+
+        let roe_bps = unrealized_pnl
+            .checked_mul(10_000)
+            .ok_or(ProgramError::ArithmeticOverflow)?
+            / self.margin as i128;
+
+        i64::try_from(roe_bps).map_err(|_| ProgramError::ArithmeticOverflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::Position;
+    use crate::states::Market;
+    use crate::states::with_account_info;
+
+    // Collateral with the price decimals, so token amounts read as quote amounts
+    const DECIMALS: u8 = crate::math::PRICE_DECIMALS;
+
+    #[test]
+    fn test_exactly_sized_and_oversized_position_load() {
+        for len in [Position::SIZE, Position::SIZE + 64] {
+            let mut data = vec![0u8; len];
+            data[..8].copy_from_slice(&Position::DISCRIMINATOR);
+            with_account_info(&data, |account| {
+                assert!(Position::from_account_info(account).is_ok());
+                assert!(Position::from_account_info_mut(account).is_ok());
+            });
+        }
+    }
+
+    fn position(size: i128, entry_price: u64, margin: u64) -> Position {
+        Position { size, entry_price, margin, is_active: 1, ..Position::default() }
+    }
+
+    #[test]
+    fn test_roe_price_doubled_at_1x() {
+        // 10 contracts at 100 fully collateralized
+        let long = position(10, 100, 1_000);
+        assert_eq!(long.roe_bps(200, DECIMALS).unwrap(), 10_000);
+    }
+
+    #[test]
+    fn test_roe_negative_fifty_percent() {
+        let long = position(10, 100, 1_000);
+        assert_eq!(long.roe_bps(50, DECIMALS).unwrap(), -5_000);
+    }
+
+    #[test]
+    fn test_roe_short() {
+        // 10x short: a 10% drop doubles the margin
+        let short = position(-10, 100, 100);
+        assert_eq!(short.roe_bps(90, DECIMALS).unwrap(), 10_000);
+        assert_eq!(short.roe_bps(105, DECIMALS).unwrap(), -5_000);
+    }
+
+    #[test]
+    fn test_compounding_position_margin_grows() {
+        let mut short = Position { auto_compound_funding: 1, ..position(-10, 100, 1_000) };
+        assert_eq!(short.apply_funding(50).unwrap(), 50);
+        assert_eq!(short.margin, 1_050);
+        assert_eq!(short.funding_payment, 0);
+    }
+
+    #[test]
+    fn test_extreme_funding_index_delta_overflows_cleanly() {
+        let max_long = Position { size: i128::MAX, last_funding_index: 0, ..Position::default() };
+        assert_eq!(max_long.pending_funding(i128::MAX / 2, DECIMALS), Err(ProgramError::ArithmeticOverflow));
+
+        // The delta itself can't be represented
+        let short = Position { size: -1, last_funding_index: i128::MIN, ..Position::default() };
+        assert_eq!(short.pending_funding(i128::MAX, DECIMALS), Err(ProgramError::ArithmeticOverflow));
+    }
+
+    #[test]
+    fn test_non_compounding_position_accrues_funding() {
+        let mut short = position(-10, 100, 1_000);
+        assert_eq!(short.apply_funding(50).unwrap(), 0);
+        assert_eq!(short.margin, 1_000);
+        assert_eq!(short.funding_payment, 50);
+
+        // Paid funding comes out of margin either way
+        assert_eq!(short.apply_funding(-30).unwrap(), -30);
+        assert_eq!(short.margin, 970);
+        assert_eq!(short.funding_payment, 50);
+    }
+
+    #[test]
+    fn test_liquidatable_below_maintenance() {
+        // 10 contracts at 100 with 100 margin, 500 bps maintenance
+        let long = position(10, 100, 100);
+        assert_eq!(long.maintenance_margin_required(100, 500, DECIMALS).unwrap(), 50);
+        assert!(!long.is_liquidatable(100, 0, 500, DECIMALS).unwrap());
+        // At 95: equity 50, requirement 47.5 rounded up to 48
+        assert!(!long.is_liquidatable(95, 0, 500, DECIMALS).unwrap());
+        // At 94: equity 40, requirement 47
+        assert!(long.is_liquidatable(94, 0, 500, DECIMALS).unwrap());
+        assert!(long.health_bps(94, 0, 500, DECIMALS).unwrap() < 10_000);
+    }
+
+    #[test]
+    fn test_unsettled_funding_counts_towards_liquidation() {
+        // At 95 the position is 2 over its 48 requirement, until it owes 3 of funding
+        let mut long = position(10, 100, 100);
+        let funding_index = Market::FUNDING_INDEX_PRECISION * 3 / 10;
+        assert_eq!(long.unsettled_funding(funding_index, DECIMALS).unwrap(), -3);
+        assert_eq!(long.equity_with_funding(95, funding_index, DECIMALS).unwrap(), 47);
+        assert!(!long.is_liquidatable(95, 0, 500, DECIMALS).unwrap());
+        assert!(long.is_liquidatable(95, funding_index, 500, DECIMALS).unwrap());
+        assert!(long.health_bps(95, funding_index, 500, DECIMALS).unwrap() < 10_000);
+
+        // Funding it received but hasn't compounded keeps it healthy
+        long.funding_payment = 10;
+        assert_eq!(long.unsettled_funding(funding_index, DECIMALS).unwrap(), 7);
+        assert!(!long.is_liquidatable(95, funding_index, 500, DECIMALS).unwrap());
+    }
+
+    #[test]
+    fn test_withdrawable_margin_healthy_position() {
+        // At 100: equity 200, requirement 50
+        let long = position(10, 100, 200);
+        assert_eq!(long.withdrawable_margin(100, 500, DECIMALS).unwrap(), 150);
+        // Unrealized profit isn't withdrawable, only margin
+        assert_eq!(long.withdrawable_margin(200, 500, DECIMALS).unwrap(), 200);
+    }
+
+    #[test]
+    fn test_withdrawable_margin_at_risk_position() {
+        // At 94: equity 40, requirement 47
+        let long = position(10, 100, 100);
+        assert_eq!(long.withdrawable_margin(94, 500, DECIMALS).unwrap(), 0);
+        // Exactly at maintenance
+        assert_eq!(long.withdrawable_margin(100, 10_000, DECIMALS).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_rounding_favors_the_protocol() {
+        // 3 contracts at 33 is 99 notional, 500 bps of it is 4.95
+        assert_eq!(position(3, 33, 100).maintenance_margin_required(33, 500, DECIMALS).unwrap(), 5);
+
+        // 3 long over an index move of 0.5 owes 1.5, charged as 2
+        let long = Position { last_funding_index: 0, ..position(3, 100, 100) };
+        assert_eq!(long.pending_funding(Market::FUNDING_INDEX_PRECISION / 2, DECIMALS).unwrap(), -2);
+        // The short side receives 1.5, credited as 1
+        let short = Position { last_funding_index: 0, ..position(-3, 100, 100) };
+        assert_eq!(short.pending_funding(Market::FUNDING_INDEX_PRECISION / 2, DECIMALS).unwrap(), 1);
+
+        // Closing 1 of 3 contracts with 10 accrued settles 3.33 as 3
+        let mut accrued = Position { funding_payment: 10, ..position(3, 100, 100) };
+        assert_eq!(accrued.settle_funding_share(1).unwrap(), 3);
+        assert_eq!(accrued.funding_payment, 7);
+    }
+
+    #[test]
+    fn test_liquidatable_around_exact_threshold() {
+        // 1_000 bps maintenance: requirement equals the price, equity is 10 * price - 900
+        let long = position(10, 100, 100);
+
+        // Just above: equity 110, requirement 101
+        assert!(!long.is_liquidatable(101, 0, 1_000, DECIMALS).unwrap());
+        // Exactly at: equity 100, requirement 100
+        assert_eq!(long.equity_at(100, DECIMALS).unwrap(), long.maintenance_margin_required(100, 1_000, DECIMALS).unwrap() as i128);
+        assert!(!long.is_liquidatable(100, 0, 1_000, DECIMALS).unwrap());
+        // Just below: equity 90, requirement 99
+        assert!(long.is_liquidatable(99, 0, 1_000, DECIMALS).unwrap());
+    }
+
+    #[test]
+    fn test_six_decimal_collateral_scales_pnl_and_requirement() {
+        // 10 long at $100 with $100 of 6-decimal margin, 500 bps maintenance
+        let long = position(10, 100_00000000, 100_000_000);
+        assert_eq!(long.maintenance_margin_required(100_00000000, 500, 6).unwrap(), 50_000_000);
+        // At $94: $60 loss, $40 equity under a $47 requirement
+        assert_eq!(long.unrealized_pnl_at(94_00000000, 6).unwrap(), -60_000_000);
+        assert!(long.is_liquidatable(94_00000000, 0, 500, 6).unwrap());
+        assert!(!long.is_liquidatable(95_00000000, 0, 500, 6).unwrap());
+    }
+
+    #[test]
+    fn test_requirement_beyond_u64_notional() {
+        // 1e11 contracts at $1_500 is 1.5e22 of notional, past u64 but not the i128 math here
+        let size = 100_000_000_000;
+        let price = 1500_00000000;
+        assert_eq!(
+            crate::instructions::calculate_position_value(size, price),
+            Err(ProgramError::ArithmeticOverflow)
+        );
+
+        let long = position(size, price, 8_000_000_000_000_000_000);
+        assert_eq!(long.maintenance_margin_required(price, 500, 6).unwrap(), 7_500_000_000_000_000_000);
+        assert!(!long.is_liquidatable(price, 0, 500, 6).unwrap());
+    }
+
+    #[test]
+    fn test_liquidation_price_brackets_the_threshold() {
+        // 10 long at 100 with 100 margin, 500 bps maintenance: 94.7, reported as 95
+        let long = position(10, 100, 100);
+        assert_eq!(long.liquidation_price(500, DECIMALS).unwrap(), 95);
+        assert!(!long.is_liquidatable(95, 0, 500, DECIMALS).unwrap());
+        assert!(long.is_liquidatable(94, 0, 500, DECIMALS).unwrap());
+
+        // The short mirror is liquidated above 104.76, reported as 104
+        let short = position(-10, 100, 100);
+        assert_eq!(short.liquidation_price(500, DECIMALS).unwrap(), 104);
+        assert!(!short.is_liquidatable(104, 0, 500, DECIMALS).unwrap());
+        assert!(short.is_liquidatable(105, 0, 500, DECIMALS).unwrap());
+
+        // A fully collateralized long can't be liquidated at a positive price
+        assert_eq!(position(10, 100, 1_000).liquidation_price(500, DECIMALS).unwrap(), 0);
+        assert_eq!(Position::default().liquidation_price(500, DECIMALS).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_liquidation_grace_needs_a_second_oracle_update() {
+        let mut position = Position { size: 10, entry_price: 100, margin: 10, is_active: 1, ..Default::default() };
+        assert!(!position.liquidation_grace_elapsed(1_000));
+
+        // Marked underwater at the update published at 1_000
+        position.track_underwater(true, 1_000);
+        assert!(!position.liquidation_grace_elapsed(1_000));
+
+        // Marked again later: the first sighting is kept
+        position.track_underwater(true, 1_005);
+        assert_eq!(position.underwater_since, 1_000);
+        assert!(position.liquidation_grace_elapsed(1_005));
+
+        // Recovery resets the window
+        position.track_underwater(false, 1_010);
+        assert!(!position.liquidation_grace_elapsed(1_020));
+    }
+
+    #[test]
+    fn test_health_orders_positions() {
+        let healthy = position(10, 100, 200);
+        let underwater = position(10, 100, 100);
+        assert!(underwater.health_bps(94, 0, 500, DECIMALS).unwrap() < healthy.health_bps(94, 0, 500, DECIMALS).unwrap());
+    }
+
+    #[test]
+    fn test_roe_zero_margin() {
+        assert_eq!(position(10, 100, 0).roe_bps(200, DECIMALS).unwrap(), 0);
     }
 }
@@ -1,32 +1,176 @@
-use pinocchio::{account_info::{AccountInfo, Ref, RefMut}, program_error::ProgramError, pubkey::Pubkey, *};
+use bytemuck::{Pod, Zeroable};
+use pinocchio::{account_info::{AccountInfo, Ref, RefMut}, program_error::ProgramError, pubkey::Pubkey};
 
-#[derive(Debug)]
+use crate::{errors::PerpError, states::{check_discriminator, write_discriminator, Position}};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, Pod, Zeroable)]
 pub struct UserAccount {
+    pub discriminator: [u8; 8], // UserAccount::DISCRIMINATOR once created
     pub owner: Pubkey, // Trader's wallet
     pub margin_balance: u64, // Deposited collateral (USDC)
-    pub open_positions: [Pubkey; 10] // References to Position accounts
+    pub open_positions: [Pubkey; 10], // References to Position accounts
+    pub deposit_time: i64, // Timestamp of the last collateral deposit
+    pub collateral_mint: Pubkey, // Mint margin_balance is held in, see bind_collateral_mint
 } 
 
 impl UserAccount {
-    pub const SIZE: usize = 32 + 8 + (10 * 32);
+    pub const SIZE: usize = 8 + 32 + 8 + (10 * 32) + 8 + 32;
+    pub const DISCRIMINATOR: [u8; 8] = *b"user_acc";
+
+    // Like Market and Position, accounts must be at least SIZE bytes; larger
+    // accounts are accepted so fields can be appended without breaking them.
 
-    pub fn from_account_info(account: &AccountInfo) -> Result<Ref<Self>, ProgramError> {
-        if account.data_len() != Self::SIZE {  
+    pub fn from_account_info(account: &AccountInfo) -> Result<Ref<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
             return Err(ProgramError::InvalidAccountData);
         }
 
-        Ok(Ref::map(account.try_borrow_data()?, |data| unsafe {
-            *(data.as_ptr() as *const &Self)
-        }))
+        let data = account.try_borrow_data()?;
+        check_discriminator(&data, &Self::DISCRIMINATOR)?;
+        Ref::filter_map(data, |data| bytemuck::try_from_bytes(&data[..Self::SIZE]).ok())
+            .map_err(|_| ProgramError::InvalidAccountData)
     }
 
-    pub fn from_account_info_mut(account: &AccountInfo) -> Result<RefMut<Self>, ProgramError> {
-        if account.data_len() != Self::SIZE {  
+    pub fn from_account_info_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
             return Err(ProgramError::InvalidAccountData);
         }
 
-        Ok(RefMut::map(account.try_borrow_mut_data()?, |data| unsafe {
-            &mut *(data.as_mut_ptr() as *mut Self)
-        }))
+        let data = account.try_borrow_mut_data()?;
+        check_discriminator(&data, &Self::DISCRIMINATOR)?;
+        RefMut::filter_map(data, |data| bytemuck::try_from_bytes_mut(&mut data[..Self::SIZE]).ok())
+            .map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Tags a just-created account as a user account and loads it, see write_discriminator.
+    pub fn init_from_account_info_mut(account: &AccountInfo) -> Result<RefMut<'_, Self>, ProgramError> {
+        if account.data_len() < Self::SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let mut data = account.try_borrow_mut_data()?;
+        write_discriminator(&mut data, &Self::DISCRIMINATOR)?;
+        RefMut::filter_map(data, |data| bytemuck::try_from_bytes_mut(&mut data[..Self::SIZE]).ok())
+            .map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Sets up a just-created account for `owner` with nothing deposited.
+    pub fn initialize(&mut self, owner: Pubkey) {
+        self.owner = owner;
+        self.margin_balance = 0;
+        self.open_positions = [Pubkey::default(); 10];
+        self.deposit_time = 0;
+        self.collateral_mint = Pubkey::default();
+    }
+
+    /// margin_balance is one balance across markets, so it is tied to the
+    /// mint it was credited in and can only be credited, spent or withdrawn
+    /// through markets of that mint. An empty balance takes on `mint`.
+    pub fn bind_collateral_mint(&mut self, mint: &Pubkey) -> Result<(), ProgramError> {
+        if self.collateral_mint == *mint {
+            return Ok(());
+        }
+        if self.margin_balance != 0 {
+            return Err(PerpError::CollateralMintMismatch.into());
+        }
+        self.collateral_mint = *mint;
+        Ok(())
+    }
+
+    /// Applies a change in position margin (e.g. settled funding) to the balance,
+    /// which includes locked margin.
+    pub fn apply_margin_delta(&mut self, delta: i64) -> Result<(), ProgramError> {
+        self.margin_balance = if delta < 0 {
+            self.margin_balance.saturating_sub(delta.unsigned_abs())
+        } else {
+            self.margin_balance
+                .checked_add(delta as u64)
+                .ok_or(ProgramError::ArithmeticOverflow)?
+        };
+        Ok(())
+    }
+
+    /// Margin committed to the active positions among `positions`.
+    pub fn locked_margin(&self, positions: &[&Position]) -> u64 {
+        positions
+            .iter()
+            .filter(|position| position.is_open())
+            .fold(0u64, |locked, position| locked.saturating_add(position.margin))
+    }
+
+    /// What's left of margin_balance once the margin locked in `positions` is
+    /// set aside, i.e. what can be withdrawn or put into a new position.
+    pub fn free_margin(&self, positions: &[&Position]) -> u64 {
+        self.margin_balance.saturating_sub(self.locked_margin(positions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pinocchio::program_error::ProgramError;
+
+    use super::UserAccount;
+    use crate::{errors::PerpError, states::{with_account_info, Position}};
+
+    #[test]
+    fn test_exactly_sized_user_account_loads() {
+        let mut data = [0u8; UserAccount::SIZE];
+        data[..8].copy_from_slice(&UserAccount::DISCRIMINATOR);
+        with_account_info(&data, |account| {
+            assert!(UserAccount::from_account_info(account).is_ok());
+            assert!(UserAccount::from_account_info_mut(account).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_oversized_user_account_loads() {
+        let mut data = [0u8; UserAccount::SIZE + 64];
+        data[..8].copy_from_slice(&UserAccount::DISCRIMINATOR);
+        with_account_info(&data, |account| {
+            assert!(UserAccount::from_account_info(account).is_ok());
+            assert!(UserAccount::from_account_info_mut(account).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_undersized_user_account_rejected() {
+        with_account_info(&[0u8; UserAccount::SIZE - 8], |account| {
+            assert!(matches!(UserAccount::from_account_info(account), Err(ProgramError::InvalidAccountData)));
+        });
+    }
+
+    #[test]
+    fn test_free_margin_excludes_active_positions() {
+        let user_account = UserAccount { discriminator: UserAccount::DISCRIMINATOR, owner: [1u8; 32], margin_balance: 1_000, open_positions: [[0u8; 32]; 10], deposit_time: 0, collateral_mint: [0u8; 32] };
+        let long = Position { margin: 300, is_active: 1, ..Position::default() };
+        let short = Position { margin: 200, is_active: 1, ..Position::default() };
+        let closed = Position { margin: 400, is_active: 0, ..Position::default() };
+
+        assert_eq!(user_account.locked_margin(&[&long, &short, &closed]), 500);
+        assert_eq!(user_account.free_margin(&[&long, &short, &closed]), 500);
+        assert_eq!(user_account.free_margin(&[]), 1_000);
+
+        // Locked margin past the balance leaves nothing free rather than underflowing
+        let large = Position { margin: 2_000, is_active: 1, ..Position::default() };
+        assert_eq!(user_account.free_margin(&[&long, &large]), 0);
+    }
+
+    #[test]
+    fn test_balance_bound_to_its_collateral_mint() {
+        const USDC: [u8; 32] = [5u8; 32];
+        const WSOL: [u8; 32] = [6u8; 32];
+
+        let mut user_account = UserAccount { margin_balance: 1_000, ..UserAccount::default() };
+        user_account.collateral_mint = USDC;
+        assert_eq!(user_account.bind_collateral_mint(&USDC), Ok(()));
+        // A USDC balance can't be spent or withdrawn through a wSOL market
+        assert_eq!(user_account.bind_collateral_mint(&WSOL), Err(PerpError::CollateralMintMismatch.into()));
+        assert_eq!(user_account.collateral_mint, USDC);
+
+        // Once emptied it can move to another mint
+        user_account.margin_balance = 0;
+        assert_eq!(user_account.bind_collateral_mint(&WSOL), Ok(()));
+        assert_eq!(user_account.collateral_mint, WSOL);
     }
-}
\ No newline at end of file
+}